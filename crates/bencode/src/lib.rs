@@ -0,0 +1,308 @@
+//! Bencoding, the serialisation format used by metainfo files, trackers and
+//! the extension protocol.
+//!
+//! Only `alloc` is needed without the default `std` feature.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum BencodeError {
+    #[error("unexpected end of input")]
+    UnexpectedEof,
+    #[error("unexpected byte {byte:#04x} at offset {offset}")]
+    UnexpectedByte { byte: u8, offset: usize },
+    #[error("invalid integer at offset {0}")]
+    InvalidInteger(usize),
+    #[error("invalid string length at offset {0}")]
+    InvalidLength(usize),
+    #[error("trailing data after value at offset {0}")]
+    TrailingData(usize),
+    #[error("nesting too deep at offset {0}")]
+    TooDeep(usize),
+    #[error("not canonically encoded")]
+    NotCanonical,
+}
+
+/// Maximum nesting of lists and dictionaries we are prepared to follow.
+const MAX_DEPTH: usize = 64;
+
+/// A decoded bencode value.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Value {
+    Integer(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes()
+            .and_then(|bytes| core::str::from_utf8(bytes).ok())
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_dict(&self) -> Option<&BTreeMap<Vec<u8>, Value>> {
+        match self {
+            Value::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    /// Looks up `key` if this is a dictionary.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.as_dict().and_then(|dict| dict.get(key.as_bytes()))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_into(&mut buf);
+        buf
+    }
+
+    pub fn encode_into(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Integer(i) => buf.extend_from_slice(format!("i{}e", i).as_bytes()),
+            Value::Bytes(bytes) => encode_bytes(bytes, buf),
+            Value::List(list) => {
+                buf.push(b'l');
+                for value in list {
+                    value.encode_into(buf);
+                }
+                buf.push(b'e');
+            }
+            Value::Dict(dict) => {
+                buf.push(b'd');
+                for (key, value) in dict {
+                    encode_bytes(key, buf);
+                    value.encode_into(buf);
+                }
+                buf.push(b'e');
+            }
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Integer(i)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Bytes(s.as_bytes().to_vec())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value::Bytes(bytes)
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Bytes(bytes) => match core::str::from_utf8(bytes) {
+                Ok(s) => write!(f, "{:?}", s),
+                Err(_) => write!(f, "<{} bytes>", bytes.len()),
+            },
+            Value::List(list) => f.debug_list().entries(list).finish(),
+            Value::Dict(dict) => f
+                .debug_map()
+                .entries(
+                    dict.iter()
+                        .map(|(k, v)| (String::from_utf8_lossy(k).into_owned(), v)),
+                )
+                .finish(),
+        }
+    }
+}
+
+fn encode_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    buf.extend_from_slice(bytes.len().to_string().as_bytes());
+    buf.push(b':');
+    buf.extend_from_slice(bytes);
+}
+
+/// Decodes exactly one value spanning the whole of `input`.
+pub fn decode(input: &[u8]) -> Result<Value, BencodeError> {
+    let (value, len) = decode_prefix(input)?;
+    if len != input.len() {
+        return Err(BencodeError::TrailingData(len));
+    }
+    Ok(value)
+}
+
+/// Decodes exactly one value spanning the whole of `input`, which must be
+/// in canonical form: dictionary keys sorted and unique, and no integer or
+/// length with a leading zero or plus sign.
+pub fn decode_canonical(input: &[u8]) -> Result<Value, BencodeError> {
+    let value = decode(input)?;
+    if value.encode() != input {
+        return Err(BencodeError::NotCanonical);
+    }
+    Ok(value)
+}
+
+/// Decodes one value from the start of `input`, returning it along with the
+/// number of bytes it occupied.
+pub fn decode_prefix(input: &[u8]) -> Result<(Value, usize), BencodeError> {
+    let mut decoder = Decoder { input, pos: 0 };
+    let value = decoder.value(0)?;
+    Ok((value, decoder.pos))
+}
+
+/// Returns the raw bytes of the value stored under `key` in the top-level
+/// dictionary `input`, exactly as they appear.
+///
+/// Hashes over sub-dictionaries (such as the info hash) must be computed
+/// over the original bytes rather than a re-encoding.
+pub fn raw_value<'a>(input: &'a [u8], key: &str) -> Result<Option<&'a [u8]>, BencodeError> {
+    let mut decoder = Decoder { input, pos: 0 };
+    decoder.expect(b'd')?;
+
+    while decoder.peek()? != b'e' {
+        let k = decoder.bytes()?;
+        let start = decoder.pos;
+        decoder.value(1)?;
+        if k == key.as_bytes() {
+            return Ok(Some(&input[start..decoder.pos]));
+        }
+    }
+
+    Ok(None)
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn peek(&self) -> Result<u8, BencodeError> {
+        self.input
+            .get(self.pos)
+            .copied()
+            .ok_or(BencodeError::UnexpectedEof)
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), BencodeError> {
+        match self.peek()? {
+            b if b == byte => {
+                self.pos += 1;
+                Ok(())
+            }
+            b => Err(BencodeError::UnexpectedByte {
+                byte: b,
+                offset: self.pos,
+            }),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, BencodeError> {
+        if depth > MAX_DEPTH {
+            return Err(BencodeError::TooDeep(self.pos));
+        }
+
+        match self.peek()? {
+            b'i' => self.integer().map(Value::Integer),
+            b'0'..=b'9' => self.bytes().map(|b| Value::Bytes(b.to_vec())),
+            b'l' => {
+                self.pos += 1;
+                let mut list = Vec::new();
+                while self.peek()? != b'e' {
+                    list.push(self.value(depth + 1)?);
+                }
+                self.pos += 1;
+                Ok(Value::List(list))
+            }
+            b'd' => {
+                self.pos += 1;
+                let mut dict = BTreeMap::new();
+                while self.peek()? != b'e' {
+                    let key = self.bytes()?.to_vec();
+                    let value = self.value(depth + 1)?;
+                    dict.insert(key, value);
+                }
+                self.pos += 1;
+                Ok(Value::Dict(dict))
+            }
+            byte => Err(BencodeError::UnexpectedByte {
+                byte,
+                offset: self.pos,
+            }),
+        }
+    }
+
+    fn integer(&mut self) -> Result<i64, BencodeError> {
+        let start = self.pos;
+        self.expect(b'i')?;
+        let end = self.input[self.pos..]
+            .iter()
+            .position(|&b| b == b'e')
+            .ok_or(BencodeError::UnexpectedEof)?;
+        let digits = &self.input[self.pos..self.pos + end];
+        let value = core::str::from_utf8(digits)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or(BencodeError::InvalidInteger(start))?;
+        self.pos += end + 1;
+        Ok(value)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], BencodeError> {
+        let start = self.pos;
+        let colon = self.input[self.pos..]
+            .iter()
+            .position(|&b| b == b':')
+            .ok_or(BencodeError::UnexpectedEof)?;
+        let len: usize = core::str::from_utf8(&self.input[self.pos..self.pos + colon])
+            .ok()
+            .filter(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|s| s.parse().ok())
+            .ok_or(BencodeError::InvalidLength(start))?;
+        self.pos += colon + 1;
+
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.input.len())
+            .ok_or(BencodeError::UnexpectedEof)?;
+        let bytes = &self.input[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,126 @@
+//! Properties of encoding and decoding, and agreement with `serde_bencode`
+//! on what bencode means.
+
+use std::collections::{BTreeMap, HashMap};
+
+use proptest::collection::{btree_map, vec};
+use proptest::prelude::*;
+
+use rainyday_bencode::{decode, decode_canonical, decode_prefix, Value};
+
+type Reference = serde_bencode::value::Value;
+
+/// Bytes that bencode gives a meaning to, so that mangled encodings stay
+/// close to valid ones.
+const SYNTAX: &[u8] = b"ilde:-+0123456789";
+
+fn value() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        any::<i64>().prop_map(Value::Integer),
+        vec(any::<u8>(), 0..16).prop_map(Value::Bytes),
+    ];
+    leaf.prop_recursive(4, 64, 8, |inner| {
+        prop_oneof![
+            vec(inner.clone(), 0..8).prop_map(Value::List),
+            btree_map(vec(any::<u8>(), 0..8), inner, 0..8).prop_map(Value::Dict),
+        ]
+    })
+}
+
+/// An encoding with a byte overwritten, one inserted, or its end cut off.
+fn mangled() -> impl Strategy<Value = Vec<u8>> {
+    (
+        value(),
+        any::<prop::sample::Index>(),
+        prop::sample::select(SYNTAX),
+        0..3,
+    )
+        .prop_map(|(value, at, byte, how)| {
+            let mut encoded = value.encode();
+            let at = at.index(encoded.len());
+            match how {
+                0 => encoded[at] = byte,
+                1 => encoded.insert(at, byte),
+                _ => encoded.truncate(at),
+            }
+            encoded
+        })
+}
+
+fn to_reference(value: &Value) -> Reference {
+    match value {
+        Value::Integer(i) => Reference::Int(*i),
+        Value::Bytes(bytes) => Reference::Bytes(bytes.clone()),
+        Value::List(list) => Reference::List(list.iter().map(to_reference).collect()),
+        Value::Dict(dict) => Reference::Dict(
+            dict.iter()
+                .map(|(key, value)| (key.clone(), to_reference(value)))
+                .collect::<HashMap<_, _>>(),
+        ),
+    }
+}
+
+/// What the reference makes of `input`, as one of our values. It ignores
+/// anything after the first value.
+fn reference_decode(input: &[u8]) -> Option<Value> {
+    fn convert(value: Reference) -> Value {
+        match value {
+            Reference::Int(i) => Value::Integer(i),
+            Reference::Bytes(bytes) => Value::Bytes(bytes),
+            Reference::List(list) => Value::List(list.into_iter().map(convert).collect()),
+            Reference::Dict(dict) => Value::Dict(
+                dict.into_iter()
+                    .map(|(key, value)| (key, convert(value)))
+                    .collect::<BTreeMap<_, _>>(),
+            ),
+        }
+    }
+    serde_bencode::from_bytes::<Reference>(input)
+        .ok()
+        .map(convert)
+}
+
+/// Where both implementations accept `input`, they must read the same
+/// value from it.
+fn agree_on(input: &[u8]) -> Result<(), TestCaseError> {
+    if let (Ok((ours, _)), Some(theirs)) = (decode_prefix(input), reference_decode(input)) {
+        prop_assert_eq!(ours, theirs);
+    }
+    if decode_canonical(input).is_ok() {
+        prop_assert!(
+            reference_decode(input).is_some(),
+            "reference rejects canonical input"
+        );
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn decoding_an_encoding_gives_back_the_value(value in value()) {
+        let encoded = value.encode();
+        prop_assert_eq!(decode(&encoded)?, value.clone());
+        prop_assert_eq!(decode_canonical(&encoded)?, value);
+    }
+
+    #[test]
+    fn encodes_as_the_reference_does(value in value()) {
+        let theirs = serde_bencode::to_bytes(&to_reference(&value)).unwrap();
+        prop_assert_eq!(value.encode(), theirs);
+    }
+
+    #[test]
+    fn decodes_our_encodings_as_the_reference_does(value in value()) {
+        prop_assert_eq!(reference_decode(&value.encode()), Some(value));
+    }
+
+    #[test]
+    fn agrees_with_the_reference_on_mangled_encodings(input in mangled()) {
+        agree_on(&input)?;
+    }
+
+    #[test]
+    fn agrees_with_the_reference_on_arbitrary_bytes(input in vec(any::<u8>(), 0..64)) {
+        agree_on(&input)?;
+    }
+}
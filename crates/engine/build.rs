@@ -0,0 +1,16 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc();
+}
+
+/// Generates the gRPC service from its schema, with a protoc that comes
+/// with the build so none needs to be installed.
+#[cfg(feature = "grpc")]
+fn grpc() {
+    let protoc = protoc_bin_vendored::protoc_bin_path().expect("no protoc for this platform");
+    std::env::set_var("PROTOC", protoc);
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/rainyday.proto"], &["proto"])
+        .expect("cannot compile proto/rainyday.proto");
+}
@@ -0,0 +1,109 @@
+//! A fixed-length set of piece indices, laid out as on the wire.
+
+/// Piece availability, most significant bit of the first byte first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bitfield {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Bitfield {
+    pub fn new(len: usize) -> Self {
+        Self {
+            bytes: vec![0; len.div_ceil(8)],
+            len,
+        }
+    }
+
+    pub fn full(len: usize) -> Self {
+        let mut bitfield = Self::new(len);
+        for i in 0..len {
+            bitfield.set(i);
+        }
+        bitfield
+    }
+
+    /// Builds a bitfield from its wire form, returning `None` if the length
+    /// is wrong or any spare trailing bit is set.
+    pub fn from_bytes(bytes: &[u8], len: usize) -> Option<Self> {
+        if bytes.len() != len.div_ceil(8) {
+            return None;
+        }
+
+        let bitfield = Self {
+            bytes: bytes.to_vec(),
+            len,
+        };
+        if (len..bytes.len() * 8).any(|i| bitfield.bit(i)) {
+            return None;
+        }
+
+        Some(bitfield)
+    }
+
+    /// Like [`Bitfield::from_bytes`], but clears spare trailing bits rather
+    /// than rejecting them.
+    pub fn from_bytes_masked(bytes: &[u8], len: usize) -> Option<Self> {
+        if bytes.len() != len.div_ceil(8) {
+            return None;
+        }
+
+        let mut bitfield = Self {
+            bytes: bytes.to_vec(),
+            len,
+        };
+        if !len.is_multiple_of(8) {
+            *bitfield.bytes.last_mut().unwrap() &= 0xff << (8 - len % 8);
+        }
+        Some(bitfield)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn has(&self, index: usize) -> bool {
+        index < self.len && self.bit(index)
+    }
+
+    pub fn set(&mut self, index: usize) {
+        if index < self.len {
+            self.bytes[index / 8] |= 0x80 >> (index % 8);
+        }
+    }
+
+    pub fn clear(&mut self, index: usize) {
+        if index < self.len {
+            self.bytes[index / 8] &= !(0x80 >> (index % 8));
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.bytes.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count() == self.len
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.bytes.iter().all(|&b| b == 0)
+    }
+
+    /// Iterates over the indices that are set.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(move |&i| self.bit(i))
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        self.bytes[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+}
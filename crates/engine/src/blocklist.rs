@@ -0,0 +1,220 @@
+//! IP blocklists: ranges of addresses we never connect to or accept peers
+//! from.
+//!
+//! Two common formats are read, optionally gzipped, and may be mixed:
+//!
+//! - PeerGuardian text (`.p2p`): `description:1.2.3.0-1.2.3.255`
+//! - eMule `ipfilter.dat`: `001.002.003.000 - 001.002.003.255 , 000 , description`,
+//!   where only ranges with an access level below 128 are blocked.
+
+use std::fs;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// eMule access levels at or above this let the range through.
+const DAT_ALLOW_LEVEL: u32 = 128;
+
+#[derive(Debug, Error)]
+pub enum BlocklistError {
+    #[error("cannot read blocklist {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// The `[blocklist]` section.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(default)]
+pub struct BlocklistConfig {
+    /// PeerGuardian `.p2p` or eMule `.dat` files, optionally gzipped.
+    pub files: Vec<PathBuf>,
+    /// Seconds between reloads of the files, or 0 to load them only once.
+    pub reload_interval: u64,
+}
+
+impl Default for BlocklistConfig {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            reload_interval: 24 * 60 * 60,
+        }
+    }
+}
+
+/// A set of IPv4 ranges that can be searched quickly. Cloning yields
+/// another handle to the same set, so reloads are seen everywhere.
+#[derive(Clone, Debug, Default)]
+pub struct Blocklist {
+    ranges: Arc<RwLock<Ranges>>,
+}
+
+/// Disjoint inclusive ranges, sorted by their start.
+#[derive(Debug, Default)]
+struct Ranges(Vec<(u32, u32)>);
+
+impl Blocklist {
+    /// Reads every file in `files` into one list.
+    pub fn load(files: &[PathBuf]) -> Result<Self, BlocklistError> {
+        let blocklist = Self::default();
+        blocklist.reload(files)?;
+        Ok(blocklist)
+    }
+
+    /// Reads the configured files, warning and blocking nothing if they
+    /// cannot be read.
+    pub fn from_config(config: &BlocklistConfig) -> Self {
+        Self::load(&config.files).unwrap_or_else(|e| {
+            warn!("not using a blocklist: {}", e);
+            Self::default()
+        })
+    }
+
+    /// Replaces the list with the current contents of `files`, leaving it
+    /// untouched if any cannot be read.
+    pub fn reload(&self, files: &[PathBuf]) -> Result<(), BlocklistError> {
+        let mut ranges = Vec::new();
+        for path in files {
+            let (read, skipped) = read_file(path, &mut ranges)?;
+            if skipped > 0 {
+                warn!("skipped {} malformed lines in {}", skipped, path.display());
+            }
+            info!("read {} ranges from blocklist {}", read, path.display());
+        }
+        *self.ranges.write().unwrap() = Ranges::new(ranges);
+        Ok(())
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V4(ip) => *ip,
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => ip,
+                None => return false,
+            },
+        };
+        self.ranges.read().unwrap().contains(u32::from(ip))
+    }
+
+    /// Number of disjoint ranges in the list.
+    pub fn len(&self) -> usize {
+        self.ranges.read().unwrap().0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Ranges {
+    /// Sorts `ranges` and merges those that overlap or touch.
+    fn new(mut ranges: Vec<(u32, u32)>) -> Self {
+        ranges.sort_unstable();
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+        for (first, last) in ranges {
+            match merged.last_mut() {
+                Some(prev) if first <= prev.1.saturating_add(1) => prev.1 = prev.1.max(last),
+                _ => merged.push((first, last)),
+            }
+        }
+        Self(merged)
+    }
+
+    fn contains(&self, ip: u32) -> bool {
+        let after = self.0.partition_point(|&(first, _)| first <= ip);
+        after > 0 && self.0[after - 1].1 >= ip
+    }
+}
+
+/// Appends the blocked ranges in `path` to `ranges`, returning how many
+/// were read and how many lines were skipped as malformed.
+fn read_file(path: &Path, ranges: &mut Vec<(u32, u32)>) -> Result<(usize, usize), BlocklistError> {
+    let io = |source| BlocklistError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let mut bytes = fs::read(path).map_err(io)?;
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut unpacked = Vec::new();
+        GzDecoder::new(&bytes[..])
+            .read_to_end(&mut unpacked)
+            .map_err(io)?;
+        bytes = unpacked;
+    }
+
+    let (mut read, mut skipped) = (0, 0);
+    for line in String::from_utf8_lossy(&bytes).lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+        match parse_line(line) {
+            Some(Some(range)) => {
+                ranges.push(range);
+                read += 1;
+            }
+            Some(None) => {}
+            None => skipped += 1,
+        }
+    }
+    Ok((read, skipped))
+}
+
+/// Parses one line of either format: `None` if it is malformed, and
+/// `Some(None)` if it is a `.dat` range that is allowed rather than
+/// blocked.
+fn parse_line(line: &str) -> Option<Option<(u32, u32)>> {
+    let range = if line.contains(',') {
+        let mut fields = line.split(',');
+        let range = fields.next()?;
+        let level: u32 = fields.next()?.trim().parse().ok()?;
+        if level >= DAT_ALLOW_LEVEL {
+            return Some(None);
+        }
+        range
+    } else {
+        // The description may itself contain colons; the range cannot.
+        line.rsplit_once(':').map_or(line, |(_, range)| range)
+    };
+
+    let (first, last) = range.split_once('-')?;
+    let (first, last) = (parse_ipv4(first)?, parse_ipv4(last)?);
+    (first <= last).then_some(Some((first, last)))
+}
+
+/// Parses a dotted quad, allowing the zero padding `.dat` files use.
+fn parse_ipv4(s: &str) -> Option<u32> {
+    let mut octets = [0u8; 4];
+    let mut parts = s.trim().split('.');
+    for octet in &mut octets {
+        *octet = parts.next()?.parse().ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(u32::from(Ipv4Addr::from(octets)))
+}
+
+/// Reloads `blocklist` from the configured files every `reload_interval`,
+/// keeping the old list if the files cannot be read.
+pub async fn reload_periodically(blocklist: Blocklist, config: BlocklistConfig) {
+    if config.reload_interval == 0 || config.files.is_empty() {
+        return;
+    }
+    let period = Duration::from_secs(config.reload_interval);
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+        if let Err(e) = blocklist.reload(&config.files) {
+            warn!("keeping the previous blocklist: {}", e);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! Reading packet captures, in the pcap and pcapng formats written by
+//! tcpdump and Wireshark, down to the TCP segments they hold.
+//!
+//! Only what debugging peer connections needs is understood: Ethernet,
+//! Linux cooked, loopback and raw IP captures, IPv4 and IPv6, and TCP.
+//! Other packets, and IP fragments, are skipped.
+
+pub mod tcp;
+
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use thiserror::Error;
+
+pub use tcp::{Connection, Direction, Reassembler};
+
+#[derive(Debug, Error)]
+pub enum CaptureError {
+    #[error("not a pcap or pcapng capture")]
+    UnknownFormat,
+    #[error("capture is cut off at offset {0}")]
+    Truncated(usize),
+    #[error("link type {0} is not supported")]
+    UnsupportedLinkType(u32),
+    #[error("packet from interface {0}, which was never described")]
+    UnknownInterface(usize),
+}
+
+/// Link types, as numbered by tcpdump.
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+const PROTOCOL_TCP: u8 = 6;
+
+/// TCP flags.
+pub const FIN: u8 = 0x01;
+pub const SYN: u8 = 0x02;
+pub const RST: u8 = 0x04;
+pub const ACK: u8 = 0x10;
+
+/// A TCP segment, as captured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    /// When it was captured, since the Unix epoch.
+    pub time: Duration,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub seq: u32,
+    pub flags: u8,
+    pub payload: Vec<u8>,
+}
+
+/// Every TCP segment in a capture, in the order captured.
+pub fn segments(capture: &[u8]) -> Result<Vec<Segment>, CaptureError> {
+    let mut segments = Vec::new();
+    let mut push = |time, link_type, frame: &[u8]| {
+        if let Some(segment) = decode_frame(time, link_type, frame) {
+            segments.push(segment);
+        }
+    };
+    match capture.get(..4) {
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => read_pcapng(capture, &mut push)?,
+        Some(_) => read_pcap(capture, &mut push)?,
+        None => return Err(CaptureError::UnknownFormat),
+    }
+    Ok(segments)
+}
+
+/// Reads fields of either byte order.
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn slice(&self, at: usize, len: usize) -> Result<&'a [u8], CaptureError> {
+        at.checked_add(len)
+            .and_then(|end| self.bytes.get(at..end))
+            .ok_or(CaptureError::Truncated(at))
+    }
+
+    fn u16(&self, at: usize) -> Result<u16, CaptureError> {
+        let bytes = self.slice(at, 2)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, at: usize) -> Result<u32, CaptureError> {
+        let bytes = self.slice(at, 4)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+}
+
+/// The classic format: a header, then each packet with a record header.
+fn read_pcap(
+    capture: &[u8],
+    push: &mut impl FnMut(Duration, u32, &[u8]),
+) -> Result<(), CaptureError> {
+    let magic = capture.get(..4).ok_or(CaptureError::UnknownFormat)?;
+    let (big_endian, nanos) = match magic {
+        [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+        [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+        _ => return Err(CaptureError::UnknownFormat),
+    };
+    let reader = Reader {
+        bytes: capture,
+        big_endian,
+    };
+    // The upper bits hold the length of any frame check sequence.
+    let link_type = reader.u32(20)? & 0x0fff_ffff;
+    check_link_type(link_type)?;
+
+    let mut at = 24;
+    while at < capture.len() {
+        let seconds = reader.u32(at)?;
+        let fraction = reader.u32(at + 4)?;
+        let len = reader.u32(at + 8)? as usize;
+        let frame = reader.slice(at + 16, len)?;
+        let time = Duration::from_secs(u64::from(seconds))
+            + if nanos {
+                Duration::from_nanos(u64::from(fraction))
+            } else {
+                Duration::from_micros(u64::from(fraction))
+            };
+        push(time, link_type, frame);
+        at += 16 + len;
+    }
+    Ok(())
+}
+
+/// The newer format: a sequence of blocks, describing interfaces as well as
+/// carrying packets.
+fn read_pcapng(
+    capture: &[u8],
+    push: &mut impl FnMut(Duration, u32, &[u8]),
+) -> Result<(), CaptureError> {
+    const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+    const INTERFACE_DESCRIPTION: u32 = 1;
+    const SIMPLE_PACKET: u32 = 3;
+    const ENHANCED_PACKET: u32 = 6;
+
+    // The link type of each interface of the current section, and how many
+    // of its timestamp units make a second.
+    let mut interfaces: Vec<(u32, u64)> = Vec::new();
+    let mut reader = Reader {
+        bytes: capture,
+        big_endian: false,
+    };
+    let mut at = 0;
+    while at < capture.len() {
+        if reader.slice(at, 4)? == [0x0a, 0x0d, 0x0d, 0x0a] {
+            reader.big_endian = match reader.slice(at + 8, 4)? {
+                [0x1a, 0x2b, 0x3c, 0x4d] => true,
+                [0x4d, 0x3c, 0x2b, 0x1a] => false,
+                _ => return Err(CaptureError::UnknownFormat),
+            };
+            interfaces.clear();
+        }
+        let kind = reader.u32(at)?;
+        let len = reader.u32(at + 4)? as usize;
+        if len < 12 || !len.is_multiple_of(4) {
+            return Err(CaptureError::Truncated(at));
+        }
+        let body = at + 8;
+        reader.slice(at, len)?;
+
+        match kind {
+            SECTION_HEADER => {}
+            INTERFACE_DESCRIPTION => {
+                let link_type = u32::from(reader.u16(body)?);
+                check_link_type(link_type)?;
+                let options = body + 8..at + len - 4;
+                interfaces.push((link_type, timestamp_units(reader, options)?));
+            }
+            ENHANCED_PACKET => {
+                let interface = reader.u32(body)? as usize;
+                let &(link_type, units) = interfaces
+                    .get(interface)
+                    .ok_or(CaptureError::UnknownInterface(interface))?;
+                let timestamp =
+                    u64::from(reader.u32(body + 4)?) << 32 | u64::from(reader.u32(body + 8)?);
+                let captured = reader.u32(body + 12)? as usize;
+                let frame = reader.slice(body + 20, captured)?;
+                let time = Duration::from_secs(timestamp / units)
+                    + Duration::from_nanos(
+                        ((timestamp % units) as u128 * 1_000_000_000 / units as u128) as u64,
+                    );
+                push(time, link_type, frame);
+            }
+            SIMPLE_PACKET => {
+                // Simple packets carry no timestamp, and come from the
+                // first interface.
+                let &(link_type, _) = interfaces
+                    .first()
+                    .ok_or(CaptureError::UnknownInterface(0))?;
+                let original = reader.u32(body)? as usize;
+                let captured = original.min(len - 16);
+                push(Duration::ZERO, link_type, reader.slice(body + 4, captured)?);
+            }
+            _ => {}
+        }
+        at += len;
+    }
+    Ok(())
+}
+
+/// The `if_tsresol` option of an interface: timestamps count in units of a
+/// negative power of ten, or of two if the top bit is set. Microseconds
+/// without it.
+fn timestamp_units(
+    reader: Reader<'_>,
+    options: std::ops::Range<usize>,
+) -> Result<u64, CaptureError> {
+    const END_OF_OPTIONS: u16 = 0;
+    const IF_TSRESOL: u16 = 9;
+
+    let mut at = options.start;
+    while at + 4 <= options.end {
+        let code = reader.u16(at)?;
+        let len = usize::from(reader.u16(at + 2)?);
+        match code {
+            END_OF_OPTIONS => break,
+            IF_TSRESOL if len == 1 => {
+                let resolution = reader.slice(at + 4, 1)?[0];
+                let exponent = u32::from(resolution & 0x7f);
+                let units = if resolution & 0x80 == 0 {
+                    10u64.checked_pow(exponent)
+                } else {
+                    1u64.checked_shl(exponent)
+                };
+                return units.ok_or(CaptureError::Truncated(at));
+            }
+            _ => {}
+        }
+        at += 4 + len.div_ceil(4) * 4;
+    }
+    Ok(1_000_000)
+}
+
+fn check_link_type(link_type: u32) -> Result<(), CaptureError> {
+    match link_type {
+        LINKTYPE_NULL | LINKTYPE_ETHERNET | LINKTYPE_RAW | LINKTYPE_LINUX_SLL | LINKTYPE_IPV4
+        | LINKTYPE_IPV6 | LINKTYPE_LINUX_SLL2 => Ok(()),
+        _ => Err(CaptureError::UnsupportedLinkType(link_type)),
+    }
+}
+
+/// The TCP segment in a captured frame, if there is one.
+fn decode_frame(time: Duration, link_type: u32, frame: &[u8]) -> Option<Segment> {
+    let packet = match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes(frame.get(12..14)?.try_into().ok()?);
+            let mut at = 14;
+            while ethertype == ETHERTYPE_VLAN {
+                ethertype = u16::from_be_bytes(frame.get(at + 2..at + 4)?.try_into().ok()?);
+                at += 4;
+            }
+            match ethertype {
+                ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => frame.get(at..)?,
+                _ => return None,
+            }
+        }
+        LINKTYPE_LINUX_SLL => frame.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => frame.get(20..)?,
+        // The address family, in the byte order of the capturing host.
+        LINKTYPE_NULL => frame.get(4..)?,
+        _ => frame,
+    };
+    let (src, dst, tcp) = match packet.first()? >> 4 {
+        4 => ipv4(packet)?,
+        6 => ipv6(packet)?,
+        _ => return None,
+    };
+
+    let offset = usize::from(tcp.get(12)? >> 4) * 4;
+    Some(Segment {
+        time,
+        src: SocketAddr::new(src, u16::from_be_bytes(tcp.get(0..2)?.try_into().ok()?)),
+        dst: SocketAddr::new(dst, u16::from_be_bytes(tcp.get(2..4)?.try_into().ok()?)),
+        seq: u32::from_be_bytes(tcp.get(4..8)?.try_into().ok()?),
+        flags: *tcp.get(13)?,
+        payload: tcp.get(offset..)?.to_vec(),
+    })
+}
+
+/// The addresses of an IPv4 packet carrying TCP, and its payload.
+fn ipv4(packet: &[u8]) -> Option<(IpAddr, IpAddr, &[u8])> {
+    let header = usize::from(packet[0] & 0x0f) * 4;
+    let total = usize::from(u16::from_be_bytes(packet.get(2..4)?.try_into().ok()?));
+    let fragment = u16::from_be_bytes(packet.get(6..8)?.try_into().ok()?);
+    // More fragments to come, or a fragment offset.
+    if fragment & 0x3fff != 0 || *packet.get(9)? != PROTOCOL_TCP {
+        return None;
+    }
+    let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+    let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+    // Ethernet pads short frames; the IP length says where the packet ends.
+    let payload = packet.get(header..total.min(packet.len()))?;
+    Some((
+        Ipv4Addr::from(src).into(),
+        Ipv4Addr::from(dst).into(),
+        payload,
+    ))
+}
+
+/// The addresses of an IPv6 packet carrying TCP, and its payload. Hop by
+/// hop, routing and destination options headers are skipped over.
+fn ipv6(packet: &[u8]) -> Option<(IpAddr, IpAddr, &[u8])> {
+    const HOP_BY_HOP: u8 = 0;
+    const ROUTING: u8 = 43;
+    const DESTINATION_OPTIONS: u8 = 60;
+
+    let len = usize::from(u16::from_be_bytes(packet.get(4..6)?.try_into().ok()?));
+    let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+    let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+    let mut next = *packet.get(6)?;
+    let mut payload = packet.get(40..(40 + len).min(packet.len()))?;
+    while matches!(next, HOP_BY_HOP | ROUTING | DESTINATION_OPTIONS) {
+        let header = (usize::from(*payload.get(1)?) + 1) * 8;
+        next = *payload.first()?;
+        payload = payload.get(header..)?;
+    }
+    if next != PROTOCOL_TCP {
+        return None;
+    }
+    Some((
+        Ipv6Addr::from(src).into(),
+        Ipv6Addr::from(dst).into(),
+        payload,
+    ))
+}
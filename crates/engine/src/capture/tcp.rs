@@ -0,0 +1,242 @@
+//! Putting the segments of TCP connections back into the byte streams that
+//! were sent.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use super::{Segment, ACK, FIN, RST, SYN};
+
+/// Which way data went, relative to the side that opened the connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// From the side that opened the connection.
+    Outbound,
+    /// Towards it.
+    Inbound,
+}
+
+impl Direction {
+    fn index(self) -> usize {
+        match self {
+            Direction::Outbound => 0,
+            Direction::Inbound => 1,
+        }
+    }
+}
+
+/// What happened on one direction of a connection, in order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Data(Vec<u8>),
+    /// Bytes that were sent but not captured.
+    Gap(u32),
+    /// The sender finished sending.
+    Fin,
+    /// The sender reset the connection.
+    Reset,
+}
+
+/// One direction of a connection.
+#[derive(Debug, Default)]
+struct Stream {
+    /// The sequence number of the first byte of data.
+    start: Option<u32>,
+    /// How far the stream has been put together, from `start`.
+    next: u32,
+    /// Segments that arrived ahead of `next`, by their offset.
+    pending: BTreeMap<u32, (Duration, Vec<u8>)>,
+    fin: Option<(Duration, u32)>,
+    finished: bool,
+}
+
+/// A TCP connection, and what was sent over it.
+#[derive(Debug)]
+pub struct Connection {
+    /// The side that opened the connection, or that sent first if its
+    /// opening was not captured.
+    pub initiator: SocketAddr,
+    pub acceptor: SocketAddr,
+    /// Whether the opening handshake was captured.
+    pub opened: bool,
+    pub events: Vec<(Duration, Direction, Event)>,
+    streams: [Stream; 2],
+}
+
+impl Connection {
+    fn new(initiator: SocketAddr, acceptor: SocketAddr) -> Self {
+        Connection {
+            initiator,
+            acceptor,
+            opened: false,
+            events: Vec::new(),
+            streams: Default::default(),
+        }
+    }
+
+    /// Whether `segment`, an opening, starts a connection other than this
+    /// one: a retransmitted opening has the same sequence number.
+    fn reopened_by(&self, segment: &Segment) -> bool {
+        let stream = if segment.src == self.initiator {
+            &self.streams[0]
+        } else {
+            &self.streams[1]
+        };
+        stream.start != Some(segment.seq.wrapping_add(1))
+    }
+
+    fn push(&mut self, direction: Direction, segment: Segment) {
+        let stream = &mut self.streams[direction.index()];
+        if stream.finished {
+            return;
+        }
+        if segment.flags & RST != 0 {
+            self.skip_gaps(direction);
+            let stream = &mut self.streams[direction.index()];
+            if !stream.finished {
+                stream.finished = true;
+                let time = self.events.last().map_or(segment.time, |event| event.0);
+                self.events
+                    .push((segment.time.max(time), direction, Event::Reset));
+            }
+            return;
+        }
+        let syn = segment.flags & SYN != 0;
+        let seq = segment.seq.wrapping_add(u32::from(syn));
+        let start = *stream.start.get_or_insert(seq);
+        let offset = seq.wrapping_sub(start);
+        // Retransmissions from before the capture began would otherwise
+        // read as data from almost four gigabytes on.
+        if offset > u32::MAX / 2 {
+            return;
+        }
+        if segment.flags & FIN != 0 {
+            let end = offset.wrapping_add(segment.payload.len() as u32);
+            stream.fin = Some((segment.time, end));
+        }
+        if !segment.payload.is_empty() {
+            stream
+                .pending
+                .entry(offset)
+                .or_insert((segment.time, segment.payload));
+        }
+        self.deliver(direction, segment.time);
+    }
+
+    /// Moves whatever now follows on from the stream so far into the events,
+    /// as of `now`.
+    fn deliver(&mut self, direction: Direction, now: Duration) {
+        let stream = &mut self.streams[direction.index()];
+        while let Some(entry) = stream.pending.first_entry() {
+            let offset = *entry.key();
+            if offset > stream.next {
+                break;
+            }
+            let (_, payload) = entry.remove();
+            let overlap = (stream.next - offset) as usize;
+            if overlap < payload.len() {
+                stream.next += (payload.len() - overlap) as u32;
+                let data = payload[overlap..].to_vec();
+                self.events.push((now, direction, Event::Data(data)));
+            }
+        }
+        if let Some((_, end)) = stream.fin {
+            if end <= stream.next {
+                stream.finished = true;
+                self.events.push((now, direction, Event::Fin));
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        self.skip_gaps(Direction::Outbound);
+        self.skip_gaps(Direction::Inbound);
+    }
+
+    /// Gives up on bytes that were never captured, so that what came after
+    /// them is not lost too. What this recovers is put at the end, so that
+    /// the events stay in the order they happened.
+    fn skip_gaps(&mut self, direction: Direction) {
+        loop {
+            let latest = self.events.last().map_or(Duration::ZERO, |event| event.0);
+            let stream = &mut self.streams[direction.index()];
+            if stream.finished {
+                break;
+            }
+            let missing = stream
+                .pending
+                .first_key_value()
+                .map(|(&offset, &(time, _))| (time, offset))
+                .or(stream.fin);
+            match missing {
+                Some((time, offset)) if offset > stream.next => {
+                    let len = offset - stream.next;
+                    stream.next = offset;
+                    let time = time.max(latest);
+                    self.events.push((time, direction, Event::Gap(len)));
+                    self.deliver(direction, time);
+                }
+                _ => break,
+            }
+        }
+    }
+}
+
+/// Sorts segments into connections and reassembles them.
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    connections: Vec<Connection>,
+    /// The latest connection between each pair of addresses, by the address
+    /// of its initiator and then its acceptor.
+    index: HashMap<(SocketAddr, SocketAddr), usize>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, segment: Segment) {
+        let (src, dst) = (segment.src, segment.dst);
+        let opening = segment.flags & (SYN | ACK) == SYN;
+        let found = match self.index.get(&(src, dst)) {
+            Some(&i) => Some((i, Direction::Outbound)),
+            None => self
+                .index
+                .get(&(dst, src))
+                .map(|&i| (i, Direction::Inbound)),
+        };
+        let (i, direction) = match found {
+            // A new connection between the same addresses as an old one.
+            Some((i, _)) if opening && self.connections[i].reopened_by(&segment) => {
+                self.open(src, dst)
+            }
+            Some(found) => found,
+            // The answer to an opening that was not captured.
+            None if segment.flags & (SYN | ACK) == SYN | ACK => {
+                let (i, _) = self.open(dst, src);
+                (i, Direction::Inbound)
+            }
+            None => self.open(src, dst),
+        };
+        let connection = &mut self.connections[i];
+        connection.opened |= opening;
+        connection.push(direction, segment);
+    }
+
+    fn open(&mut self, initiator: SocketAddr, acceptor: SocketAddr) -> (usize, Direction) {
+        let i = self.connections.len();
+        self.connections.push(Connection::new(initiator, acceptor));
+        self.index.insert((initiator, acceptor), i);
+        self.index.remove(&(acceptor, initiator));
+        (i, Direction::Outbound)
+    }
+
+    /// The connections, in the order they were first seen.
+    pub fn finish(mut self) -> Vec<Connection> {
+        for connection in &mut self.connections {
+            connection.finish();
+        }
+        self.connections
+    }
+}
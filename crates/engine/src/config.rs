@@ -0,0 +1,645 @@
+//! User configuration, loaded from a TOML, JSON or YAML file.
+
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use toml::{Table, Value};
+use tracing::{debug, warn};
+
+use crate::blocklist::BlocklistConfig;
+use crate::dht::BOOTSTRAP_NODES;
+use crate::hooks::Hooks;
+use crate::interface::{Endpoint, Interface};
+use crate::logfile::LoggingConfig;
+use crate::peer::encryption::EncryptionPolicy;
+use crate::peer::id::{PeerId, PeerIdError, DEFAULT_PREFIX};
+use crate::proxy::{ProxyConfig, ProxyError};
+use crate::schedule::Schedule;
+use crate::storage::file::DEFAULT_OPEN_FILES;
+use crate::storage::{AllocationMode, StorageBackend};
+
+/// Port tried first when none is configured.
+pub const DEFAULT_LISTEN_PORT: u16 = 6881;
+
+/// Table holding the named profiles, each a table of settings that
+/// override the rest of the file.
+const PROFILES_KEY: &str = "profile";
+
+/// Prefix of the environment variables that override configuration keys.
+pub const ENV_PREFIX: &str = "RAINYDAY_";
+
+/// Variables with that prefix that are not configuration keys: the console
+/// log filter, and those describing a torrent to the hooks it runs.
+const RESERVED_VARS: &[&str] = &[
+    "RAINYDAY_LOG",
+    "RAINYDAY_EVENT",
+    "RAINYDAY_INFO_HASH",
+    "RAINYDAY_NAME",
+    "RAINYDAY_PATH",
+    "RAINYDAY_SIZE",
+    "RAINYDAY_DONE",
+    "RAINYDAY_DOWNLOADED",
+    "RAINYDAY_UPLOADED",
+    "RAINYDAY_RATIO",
+    "RAINYDAY_ERROR",
+];
+
+/// Keys from before the configuration was split into sections, with the
+/// section and key that replace them.
+const LEGACY_KEYS: &[(&str, &str, &str)] = &[
+    ("listen_port", "network", "port"),
+    ("listen_port_range", "network", "port_range"),
+    ("encryption", "network", "encryption"),
+    ("max_peers", "network", "max_peers"),
+    ("max_connections", "network", "max_connections"),
+    ("keepalive_interval", "network", "keepalive_interval"),
+    ("peer_timeout", "network", "peer_timeout"),
+    ("download_dir", "storage", "download_dir"),
+    ("incomplete_dir", "storage", "incomplete_dir"),
+    ("part_suffix", "storage", "part_suffix"),
+    ("storage_backend", "storage", "backend"),
+    ("allocation", "storage", "allocation"),
+    ("direct_io", "storage", "direct_io"),
+    ("drop_cache_after_hash", "storage", "drop_cache_after_hash"),
+    ("dht", "dht", "enabled"),
+    ("max_download_rate", "limits", "download_rate"),
+    ("max_upload_rate", "limits", "upload_rate"),
+    ("disk_quota", "limits", "disk_quota"),
+];
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("could not read config file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid configuration: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("invalid configuration: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid configuration: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("invalid configuration: {0}")]
+    Proxy(#[from] ProxyError),
+    #[error("no profile named {0:?} in the configuration file")]
+    UnknownProfile(String),
+    #[error(
+        "invalid configuration: encryption = \"{0}\" needs encrypted connections, \
+         which rainyday cannot make yet; use \"disabled\""
+    )]
+    EncryptionUnsupported(EncryptionPolicy),
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Config {
+    /// Reject torrents, tracker responses and peer messages that deviate
+    /// from the specifications, instead of tolerating the common slips.
+    pub pedantic: bool,
+    /// Prefix of our peer ID, which trackers and peers use to identify the
+    /// client. Override it only if a tracker insists on a particular client.
+    pub peer_id_prefix: String,
+    /// Directory holding the session database, from which the daemon
+    /// restarts its torrents where they left off. Without one, nothing
+    /// about the session survives a restart.
+    pub state_dir: Option<PathBuf>,
+    /// Seconds between the daemon recording its torrents' progress in
+    /// `state_dir`, which bounds what a crash loses. 0 records it only at
+    /// shutdown.
+    pub save_interval: u64,
+    /// Unix socket the CLI uses to talk to a running rainyday. Defaults to
+    /// one in the user's runtime directory. On Windows, where the daemon
+    /// listens on loopback TCP instead, the file it writes its port to,
+    /// by default `control.port` in `state_dir`.
+    pub control_socket: Option<PathBuf>,
+    /// Address the daemon serves the gRPC control API on, in builds with
+    /// the `grpc` feature. Off unless set.
+    pub grpc_address: Option<SocketAddr>,
+    /// Address the daemon serves the REST API on. Off unless set.
+    pub http_address: Option<SocketAddr>,
+    /// Bearer token REST and gRPC clients must send. Without one, the daemon makes
+    /// one up each time it starts and writes it to `http-token` beside the
+    /// control socket, readable only by its user.
+    pub http_token: Option<String>,
+    /// Offer the daemon's D-Bus interface on the session bus.
+    pub dbus: bool,
+    /// Show a desktop notification when a download run from a terminal
+    /// finishes or fails.
+    pub notifications: bool,
+    pub network: NetworkConfig,
+    pub storage: StorageConfig,
+    pub dht: DhtConfig,
+    pub tracker: TrackerConfig,
+    pub limits: LimitsConfig,
+    /// Alternate rate limits for certain times of the week.
+    pub schedule: Schedule,
+    /// Addresses never to connect to or accept peers from.
+    pub blocklist: BlocklistConfig,
+    /// A proxy for peer and tracker connections.
+    pub proxy: ProxyConfig,
+    /// Commands run when torrents are added, finish, stop or fail.
+    pub hooks: Hooks,
+    /// Per-module log levels, and a file to log to.
+    pub logging: LoggingConfig,
+}
+
+/// The `[network]` section: how we talk to peers.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// Port to accept peer connections on, for every torrent; `0` for any
+    /// free port. Without one, 6881 is tried and any free port used if it
+    /// is taken.
+    pub port: Option<u16>,
+    /// Range such as `"6881-6999"` to pick the listen port from at random,
+    /// instead of `port`.
+    pub port_range: Option<PortRange>,
+    /// Keep using the port picked from `port_range` in later sessions,
+    /// rather than picking a new one at each start. Only the daemon, which
+    /// remembers it in `state_dir`, can.
+    pub keep_port: bool,
+    /// Addresses or interfaces to listen on, each with its own port and
+    /// announced to trackers separately, instead of `port` and
+    /// `port_range`.
+    pub listen: Vec<Endpoint>,
+    /// Where the host has a public IPv6 address as well, also listen on
+    /// IPv6 and announce over both, unless `listen` or `interface` is set.
+    pub dual_stack: bool,
+    /// When to use encrypted peer connections. Only `disabled` is accepted
+    /// until message stream encryption is implemented.
+    pub encryption: EncryptionPolicy,
+    /// Most peers to be connected to per torrent.
+    pub max_peers: usize,
+    /// Most peers to be connected to across all torrents.
+    pub max_connections: usize,
+    /// Seconds of send inactivity after which a keep-alive is sent.
+    pub keepalive_interval: u64,
+    /// Seconds of receive inactivity after which a peer is dropped.
+    pub peer_timeout: u64,
+    /// Ask the router to forward the listen port with UPnP.
+    pub upnp: bool,
+    /// Ask the router to forward the listen port with NAT-PMP or PCP.
+    pub natpmp: bool,
+    /// The router to ask with NAT-PMP and PCP, instead of the default
+    /// gateway.
+    pub gateway: Option<IpAddr>,
+    /// Interface, by name or address, to send and receive all traffic on.
+    /// Nothing is sent while it is missing.
+    pub interface: Option<Interface>,
+    /// Ports to open outgoing peer connections, UDP tracker requests and
+    /// DHT lookups from, instead of any.
+    pub source_ports: Option<PortRange>,
+}
+
+/// The `[storage]` section: where and how torrent data is kept.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Where finished downloads are kept.
+    pub download_dir: PathBuf,
+    /// Where downloads in progress are kept, if different. Torrents are
+    /// moved to `download_dir` when they finish.
+    pub incomplete_dir: Option<PathBuf>,
+    /// Suffix such as `.part` added to file names until the file has been
+    /// fully downloaded and verified, so other programs do not pick up
+    /// half-finished files.
+    pub part_suffix: Option<String>,
+    /// Whether torrent data is accessed through files or memory maps.
+    pub backend: StorageBackend,
+    /// How disk space for downloads is reserved. `full` writes zeroes over
+    /// each file where the system cannot reserve space, as on Windows.
+    pub allocation: AllocationMode,
+    /// Most file handles to keep open per torrent.
+    pub open_files: usize,
+    /// Write piece data with `O_DIRECT`, bypassing the page cache.
+    pub direct_io: bool,
+    /// Evict piece data from the page cache once it has been hashed.
+    pub drop_cache_after_hash: bool,
+}
+
+/// The `[dht]` section.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct DhtConfig {
+    /// Look for peers in the DHT as well as asking trackers.
+    pub enabled: bool,
+    /// `host:port` of the nodes lookups start from.
+    pub bootstrap_nodes: Vec<String>,
+}
+
+/// The `[tracker]` section.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct TrackerConfig {
+    /// How many peers to ask trackers for; without it, trackers decide.
+    pub num_want: Option<u32>,
+}
+
+/// The `[limits]` section: caps across all torrents.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Most bytes per second to download.
+    pub download_rate: Option<u64>,
+    /// Most bytes per second to upload.
+    pub upload_rate: Option<u64>,
+    /// Most bytes of torrent data to keep on disk. Torrents that would
+    /// exceed it are paused.
+    pub disk_quota: Option<u64>,
+    /// Most bytes of piece data to hold in memory while pieces are
+    /// downloaded, verified and written. No new pieces are started while
+    /// it is reached.
+    pub memory_budget: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            pedantic: false,
+            peer_id_prefix: DEFAULT_PREFIX.to_string(),
+            state_dir: None,
+            save_interval: 300,
+            control_socket: None,
+            grpc_address: None,
+            http_address: None,
+            http_token: None,
+            dbus: false,
+            notifications: false,
+            network: NetworkConfig::default(),
+            storage: StorageConfig::default(),
+            dht: DhtConfig::default(),
+            tracker: TrackerConfig::default(),
+            limits: LimitsConfig::default(),
+            schedule: Schedule::default(),
+            blocklist: BlocklistConfig::default(),
+            proxy: ProxyConfig::default(),
+            hooks: Hooks::default(),
+            logging: LoggingConfig::default(),
+        }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            port: None,
+            port_range: None,
+            keep_port: false,
+            listen: Vec::new(),
+            dual_stack: true,
+            encryption: EncryptionPolicy::default(),
+            max_peers: 50,
+            max_connections: 200,
+            keepalive_interval: 100,
+            peer_timeout: 180,
+            upnp: true,
+            natpmp: true,
+            gateway: None,
+            interface: None,
+            source_ports: None,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            download_dir: PathBuf::from("."),
+            incomplete_dir: None,
+            part_suffix: None,
+            backend: StorageBackend::default(),
+            allocation: AllocationMode::default(),
+            open_files: DEFAULT_OPEN_FILES,
+            direct_io: false,
+            drop_cache_after_hash: false,
+        }
+    }
+}
+
+impl Default for DhtConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bootstrap_nodes: BOOTSTRAP_NODES
+                .iter()
+                .map(|node| node.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl Config {
+    /// Generates the peer ID to use for a session.
+    pub fn peer_id(&self) -> Result<PeerId, PeerIdError> {
+        PeerId::generate(&self.peer_id_prefix)
+    }
+
+    /// Where the control socket lives.
+    pub fn control_socket(&self) -> PathBuf {
+        if let Some(path) = &self.control_socket {
+            return path.clone();
+        }
+        #[cfg(windows)]
+        return self
+            .state_dir
+            .clone()
+            .unwrap_or_else(|| env::temp_dir().join("rainyday"))
+            .join("control.port");
+        #[cfg(unix)]
+        match env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) => Path::new(&dir).join("rainyday").join("control.sock"),
+            None => {
+                // SAFETY: getuid cannot fail.
+                let uid = unsafe { libc::getuid() };
+                env::temp_dir()
+                    .join(format!("rainyday-{}", uid))
+                    .join("control.sock")
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Where the daemon writes the bearer token it made up, when none is
+    /// configured.
+    pub fn http_token_path(&self) -> PathBuf {
+        self.control_socket().with_file_name("http-token")
+    }
+}
+
+impl StorageConfig {
+    /// Where a newly added, unfinished torrent should be stored.
+    pub fn initial_dir(&self) -> &Path {
+        self.incomplete_dir.as_deref().unwrap_or(&self.download_dir)
+    }
+}
+
+/// An inclusive range of ports, written `6881-6999`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PortRange {
+    pub first: u16,
+    pub last: u16,
+}
+
+impl PortRange {
+    pub fn ports(&self) -> RangeInclusive<u16> {
+        self.first..=self.last
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid port range {:?}; expected e.g. 6881-6999", s);
+        let (first, last) = s.split_once('-').ok_or_else(invalid)?;
+        let first: u16 = first.trim().parse().map_err(|_| invalid())?;
+        let last: u16 = last.trim().parse().map_err(|_| invalid())?;
+        if first == 0 || first > last {
+            return Err(invalid());
+        }
+        Ok(Self { first, last })
+    }
+}
+
+impl TryFrom<String> for PortRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PortRange> for String {
+    fn from(range: PortRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.first, self.last)
+    }
+}
+
+impl Config {
+    /// Loads the file at `path`, if any, with the settings of `profile`
+    /// from its `[profile.<name>]` table applied, and then overrides from
+    /// `RAINYDAY_*` environment variables on top.
+    pub fn load(path: Option<&Path>, profile: Option<&str>) -> Result<Self, ConfigError> {
+        Self::load_with(path, profile, env::vars_os())
+    }
+
+    /// As [`Config::load`], with `vars` in place of the environment.
+    pub fn load_with(
+        path: Option<&Path>,
+        profile: Option<&str>,
+        vars: impl IntoIterator<Item = (OsString, OsString)>,
+    ) -> Result<Self, ConfigError> {
+        let mut table = match path {
+            Some(path) => read(path)?,
+            None => Table::new(),
+        };
+        upgrade(&mut table);
+        let mut overrides = overrides(vars);
+        let profiles = table.remove(PROFILES_KEY);
+        let profile_overrides = overrides.remove(PROFILES_KEY);
+        let sections = sections();
+        if let Some(name) = profile {
+            // Variables may set a profile's keys as well as the file.
+            let settings = profile_settings(profiles, name);
+            let settings_overrides = profile_settings(profile_overrides, name);
+            if settings.is_none() && settings_overrides.is_none() {
+                return Err(ConfigError::UnknownProfile(name.to_string()));
+            }
+            let mut settings = settings.unwrap_or_default();
+            upgrade(&mut settings);
+            if let Some(over) = settings_overrides {
+                override_with(&mut settings, over, &sections, "");
+            }
+            merge(&mut table, settings);
+        }
+        override_with(&mut table, overrides, &sections, "");
+        Self::from_table(table)
+    }
+
+    fn from_table(mut table: Table) -> Result<Self, ConfigError> {
+        upgrade(&mut table);
+        let config: Self = Value::Table(table).try_into()?;
+        config.proxy.validate()?;
+        if config.network.encryption != EncryptionPolicy::Disabled {
+            return Err(ConfigError::EncryptionUnsupported(
+                config.network.encryption,
+            ));
+        }
+        Ok(config)
+    }
+}
+
+/// Reads a configuration file, as JSON or YAML if its extension says so
+/// and as TOML otherwise.
+fn read(path: &Path) -> Result<Table, ConfigError> {
+    let text = fs::read_to_string(path)?;
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    Ok(match extension.to_ascii_lowercase().as_str() {
+        "json" => serde_json::from_str(&text)?,
+        "yaml" | "yml" => serde_yaml::from_str(&text)?,
+        _ => toml::from_str(&text)?,
+    })
+}
+
+impl TryFrom<&Path> for Config {
+    type Error = ConfigError;
+
+    fn try_from(path: &Path) -> Result<Self, Self::Error> {
+        Self::from_table(read(path)?)
+    }
+}
+
+/// Where the configuration file is looked for when none is given:
+/// `config.toml` in the platform's configuration directory for rainyday.
+pub fn default_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "rainyday").map(|dirs| dirs.config_dir().join("config.toml"))
+}
+
+/// Copies the keys of `over` into `base`, merging sections key by key
+/// rather than replacing them whole.
+fn merge(base: &mut Table, over: Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(over)) => merge(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// The table of `name` among `profiles`, if there is one.
+fn profile_settings(profiles: Option<Value>, name: &str) -> Option<Table> {
+    match profiles {
+        Some(Value::Table(mut profiles)) => match profiles.remove(name) {
+            Some(Value::Table(settings)) => Some(settings),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The keys named by `RAINYDAY_*` variables among `vars`. Sections are
+/// separated by a double underscore, so `RAINYDAY_NETWORK__PORT=6881` sets
+/// `port` in `[network]`, and keys from before there were sections are
+/// moved into theirs. Values are read as TOML, or as plain strings if they
+/// are not valid TOML.
+fn overrides(vars: impl IntoIterator<Item = (OsString, OsString)>) -> Table {
+    let mut table = Table::new();
+    for (name, value) in vars {
+        let (Some(name), Some(value)) = (name.to_str(), value.to_str()) else {
+            continue;
+        };
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        if RESERVED_VARS.contains(&name) {
+            continue;
+        }
+        let path: Vec<String> = key.split("__").map(str::to_lowercase).collect();
+        if path.iter().any(String::is_empty) {
+            continue;
+        }
+
+        let (key, sections) = path.split_last().unwrap();
+        let mut target = &mut table;
+        for section in sections {
+            let entry = target
+                .entry(section.as_str())
+                .or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            target = entry.as_table_mut().unwrap();
+        }
+        debug!("{} overrides the configuration file", name);
+        target.insert(key.clone(), env_value(value));
+    }
+    upgrade(&mut table);
+    table
+}
+
+/// Which keys are sections, as the tables of the default configuration.
+fn sections() -> Table {
+    match Value::try_from(Config::default()) {
+        Ok(Value::Table(table)) => table,
+        _ => Table::new(),
+    }
+}
+
+/// Copies `over` into `base` as [`merge`] does, except that a section,
+/// whether set in `base` or one of `sections`, is never replaced by a
+/// value. `path` names the table being merged, for warnings.
+fn override_with(base: &mut Table, over: Table, sections: &Table, path: &str) {
+    for (key, value) in over {
+        let name = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", path, key)
+        };
+        let section = sections.get(&key).and_then(Value::as_table);
+        let is_section = section.is_some() || base.get(&key).is_some_and(Value::is_table);
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(over)) => {
+                override_with(base, over, section.unwrap_or(&Table::new()), &name)
+            }
+            (_, value) if is_section && !value.is_table() => {
+                warn!(
+                    "ignoring an environment variable that sets section [{}]",
+                    name
+                );
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Reads an environment variable's value as a TOML value, falling back to
+/// the string itself.
+fn env_value(value: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+/// Moves keys written before the configuration had sections into their
+/// sections, so old files keep working. A key already set in its section
+/// wins.
+fn upgrade(table: &mut Table) {
+    for &(old, section, new) in LEGACY_KEYS {
+        // `dht` names both a legacy key and its section.
+        if table.get(old).is_none_or(Value::is_table) {
+            continue;
+        }
+        let value = table.remove(old).unwrap();
+        warn!(
+            "configuration key {} is deprecated; use {} in [{}]",
+            old, new, section
+        );
+        let section = table
+            .entry(section)
+            .or_insert_with(|| Value::Table(Table::new()));
+        if let Some(section) = section.as_table_mut() {
+            section.entry(new).or_insert(value);
+        }
+    }
+}
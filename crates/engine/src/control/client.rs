@@ -0,0 +1,32 @@
+//! Sending control requests to a running rainyday.
+
+use std::path::Path;
+
+use super::{transport, ControlError, Request, Response};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+/// Sends `request` to the process listening on `path` and waits for the
+/// answer. Errors reported by the other side become [`ControlError::Remote`].
+pub async fn request(path: &Path, request: &Request) -> Result<Response, ControlError> {
+    let stream = transport::connect(path)
+        .await
+        .map_err(|source| ControlError::Connect {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    let (reader, mut writer) = stream.into_split();
+
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+
+    let reply = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or(ControlError::NoResponse)?;
+    match serde_json::from_str(&reply)? {
+        Response::Error { message } => Err(ControlError::Remote(message)),
+        response => Ok(response),
+    }
+}
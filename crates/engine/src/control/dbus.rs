@@ -0,0 +1,209 @@
+//! A D-Bus interface on the session bus, for desktop integrations. It
+//! offers a few of the control requests as methods, and signals when
+//! torrents are added, finish or are removed.
+//!
+//! The service is `io.github.jmcph4.Rainyday`, with the interface
+//! `io.github.jmcph4.Rainyday1` at `/io/github/jmcph4/Rainyday`.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+use zbus::object_server::SignalEmitter;
+use zbus::{connection, fdo, interface};
+
+use super::server::handle;
+use super::{AddOptions, Request, Response, TorrentRef};
+use crate::event::Event;
+use crate::info_hash::InfoHash;
+use crate::session::Session;
+
+pub const BUS_NAME: &str = "io.github.jmcph4.Rainyday";
+pub const OBJECT_PATH: &str = "/io/github/jmcph4/Rainyday";
+
+/// A torrent's info hash, name, state, fraction done, bytes done and
+/// wanted, and download and upload rates.
+type Progress = (String, String, String, f64, u64, u64, u64, u64);
+
+/// Serves the interface until the task is dropped.
+pub async fn serve(session: Arc<Session>) -> zbus::Result<()> {
+    let mut events = session.events().subscribe();
+    let service = Service {
+        session: Arc::clone(&session),
+    };
+    let connection = connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, service)?
+        .build()
+        .await?;
+    debug!("D-Bus interface serving as {}", BUS_NAME);
+    let emitter = SignalEmitter::new(&connection, OBJECT_PATH)?;
+
+    loop {
+        let sent = match events.recv().await {
+            Ok(Event::TorrentAdded { info_hash, name }) => {
+                Service::torrent_added(&emitter, &info_hash.to_string(), &name).await
+            }
+            Ok(Event::TorrentFinished { info_hash }) => {
+                let name = name_of(&session, &info_hash).await;
+                Service::torrent_finished(&emitter, &info_hash.to_string(), &name).await
+            }
+            Ok(Event::TorrentRemoved { info_hash }) => {
+                Service::torrent_removed(&emitter, &info_hash.to_string()).await
+            }
+            Ok(_) | Err(RecvError::Lagged(_)) => Ok(()),
+            // The session has gone, but the bus name is kept until the
+            // daemon stops.
+            Err(RecvError::Closed) => std::future::pending().await,
+        };
+        if let Err(e) = sent {
+            warn!("cannot send D-Bus signal: {}", e);
+        }
+    }
+}
+
+/// The name of the torrent with `info_hash`, or the hash itself if it is
+/// no longer running.
+async fn name_of(session: &Arc<Session>, info_hash: &InfoHash) -> String {
+    let torrent = Some(info_hash.to_string());
+    match handle(Request::Status { torrent }, session).await {
+        Response::Status { mut torrents } if !torrents.is_empty() => torrents.remove(0).name,
+        _ => info_hash.to_string(),
+    }
+}
+
+struct Service {
+    session: Arc<Session>,
+}
+
+impl Service {
+    /// Carries out `request`, turning a failure into a D-Bus error.
+    async fn call(&self, request: Request) -> fdo::Result<Response> {
+        match handle(request, &self.session).await {
+            Response::Error { message } => Err(fdo::Error::Failed(message)),
+            response => Ok(response),
+        }
+    }
+
+    async fn done(&self, request: Request) -> fdo::Result<Vec<(String, String)>> {
+        match self.call(request).await? {
+            Response::Done { torrents } => Ok(torrents.into_iter().map(torrent_ref).collect()),
+            _ => Err(unexpected()),
+        }
+    }
+}
+
+#[interface(name = "io.github.jmcph4.Rainyday1")]
+impl Service {
+    /// Starts a torrent from an absolute path to a `.torrent` file, its URL
+    /// or a magnet link, saving it in `download_dir` or, if that is empty,
+    /// the default directory.
+    #[zbus(out_args("torrents"))]
+    async fn add(
+        &self,
+        torrent: String,
+        download_dir: String,
+    ) -> fdo::Result<Vec<(String, String)>> {
+        let options = AddOptions {
+            download_dir: Some(download_dir)
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from),
+            ..AddOptions::default()
+        };
+        self.done(Request::Add { torrent, options }).await
+    }
+
+    /// Stops the torrents matching `torrent` and forgets them.
+    #[zbus(out_args("torrents"))]
+    async fn remove(&self, torrent: String) -> fdo::Result<Vec<(String, String)>> {
+        self.done(Request::Remove { torrent }).await
+    }
+
+    #[zbus(out_args("torrents"))]
+    async fn pause(&self, torrent: String) -> fdo::Result<Vec<(String, String)>> {
+        self.done(Request::Pause { torrent }).await
+    }
+
+    #[zbus(out_args("torrents"))]
+    async fn resume(&self, torrent: String) -> fdo::Result<Vec<(String, String)>> {
+        self.done(Request::Resume { torrent }).await
+    }
+
+    /// Moves the data of the torrents matching `torrent` to the same place
+    /// under the absolute path `dir`, without stopping them.
+    #[zbus(out_args("torrents"))]
+    async fn move_data(&self, torrent: String, dir: String) -> fdo::Result<Vec<(String, String)>> {
+        let path = PathBuf::from(dir);
+        self.done(Request::Move { torrent, path }).await
+    }
+
+    /// Renames the single file or top-level directory of the one torrent
+    /// matching `torrent`.
+    #[zbus(out_args("torrents"))]
+    async fn rename(&self, torrent: String, name: String) -> fdo::Result<Vec<(String, String)>> {
+        self.done(Request::Rename { torrent, name }).await
+    }
+
+    /// Pauses every torrent, and those added later, until the session is
+    /// resumed.
+    #[zbus(out_args("torrents"))]
+    async fn pause_session(&self) -> fdo::Result<Vec<(String, String)>> {
+        self.done(Request::PauseSession).await
+    }
+
+    #[zbus(out_args("torrents"))]
+    async fn resume_session(&self) -> fdo::Result<Vec<(String, String)>> {
+        self.done(Request::ResumeSession).await
+    }
+
+    /// Progress of the torrents matching `torrent`, or of all if it is
+    /// empty.
+    #[zbus(out_args("torrents"))]
+    async fn status(&self, torrent: String) -> fdo::Result<Vec<Progress>> {
+        let torrent = Some(torrent).filter(|torrent| !torrent.is_empty());
+        match self.call(Request::Status { torrent }).await? {
+            Response::Status { torrents } => Ok(torrents
+                .into_iter()
+                .map(|status| {
+                    (
+                        status.info_hash.to_string(),
+                        status.name.clone(),
+                        status.state.to_string(),
+                        status.progress(),
+                        status.done,
+                        status.wanted,
+                        status.download_rate,
+                        status.upload_rate,
+                    )
+                })
+                .collect()),
+            _ => Err(unexpected()),
+        }
+    }
+
+    #[zbus(signal)]
+    async fn torrent_added(
+        emitter: &SignalEmitter<'_>,
+        info_hash: &str,
+        name: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn torrent_finished(
+        emitter: &SignalEmitter<'_>,
+        info_hash: &str,
+        name: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn torrent_removed(emitter: &SignalEmitter<'_>, info_hash: &str) -> zbus::Result<()>;
+}
+
+fn unexpected() -> fdo::Error {
+    fdo::Error::Failed("unexpected response to the request".to_owned())
+}
+
+fn torrent_ref(torrent: TorrentRef) -> (String, String) {
+    (torrent.info_hash.to_string(), torrent.name)
+}
@@ -0,0 +1,435 @@
+//! The gRPC control API, for services that would rather speak gRPC than
+//! JSON. Its schema is `proto/rainyday.proto`; each call is carried out as
+//! the matching control request. Clients send the same bearer token as
+//! REST clients, in `authorization` metadata.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Server;
+use tonic::Status as RpcStatus;
+use tracing::debug;
+
+use super::server::handle;
+use super::{AddOptions, Request, Response, Settings, TorrentRef};
+use crate::engine::{FileInfo, PeerInfo, State, Status, TrackerInfo, TrackerState};
+use crate::files::FilePriority;
+use crate::interface::Endpoint;
+use crate::portmap::{Mechanism, PortMapping};
+use crate::session::Session;
+
+use proto::control_server::{Control, ControlServer};
+
+/// Code generated from the schema.
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("rainyday.v1");
+}
+
+/// How often `WatchStatus` sends an update if not told otherwise.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// The most often `WatchStatus` sends updates.
+const MIN_WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+type RpcResult<T> = Result<tonic::Response<T>, RpcStatus>;
+
+/// Serves the API on `addr` until the task is dropped. Clients must send
+/// `token` as a bearer token.
+pub async fn serve(
+    addr: SocketAddr,
+    token: String,
+    session: Arc<Session>,
+) -> Result<(), tonic::transport::Error> {
+    debug!("gRPC API listening on {}", addr);
+    let expected = format!("Bearer {}", token);
+    let authorize = move |request: tonic::Request<()>| {
+        let given = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok());
+        if given == Some(expected.as_str()) {
+            Ok(request)
+        } else {
+            Err(RpcStatus::unauthenticated("a valid bearer token is needed"))
+        }
+    };
+    Server::builder()
+        .add_service(ControlServer::with_interceptor(
+            Service { session },
+            authorize,
+        ))
+        .serve(addr)
+        .await
+}
+
+struct Service {
+    session: Arc<Session>,
+}
+
+impl Service {
+    /// Carries out `request`, turning a failure into an error status.
+    async fn call(&self, request: Request) -> Result<Response, RpcStatus> {
+        match handle(request, &self.session).await {
+            Response::Error { message } => Err(RpcStatus::failed_precondition(message)),
+            response => Ok(response),
+        }
+    }
+
+    async fn done(&self, request: Request) -> RpcResult<proto::TorrentsReply> {
+        match self.call(request).await? {
+            Response::Done { torrents } => Ok(tonic::Response::new(proto::TorrentsReply {
+                torrents: torrents.into_iter().map(torrent_ref).collect(),
+            })),
+            _ => Err(unexpected()),
+        }
+    }
+
+    async fn status_reply(&self, torrent: Option<String>) -> Result<proto::StatusReply, RpcStatus> {
+        match self.call(Request::Status { torrent }).await? {
+            Response::Status { torrents } => Ok(proto::StatusReply {
+                torrents: torrents.into_iter().map(status).collect(),
+            }),
+            _ => Err(unexpected()),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Control for Service {
+    type WatchStatusStream = ReceiverStream<Result<proto::StatusReply, RpcStatus>>;
+
+    async fn add(
+        &self,
+        request: tonic::Request<proto::AddRequest>,
+    ) -> RpcResult<proto::TorrentsReply> {
+        let add = request.into_inner();
+        let options = AddOptions {
+            download_dir: add.download_dir.map(PathBuf::from),
+            download_rate: add.download_rate,
+            upload_rate: add.upload_rate,
+            seed_ratio: add.seed_ratio,
+            seed_time: add.seed_time,
+            sequential: add.sequential,
+        };
+        self.done(Request::Add {
+            torrent: add.torrent,
+            options,
+        })
+        .await
+    }
+
+    async fn remove(
+        &self,
+        request: tonic::Request<proto::TorrentFilter>,
+    ) -> RpcResult<proto::TorrentsReply> {
+        let torrent = request.into_inner().torrent;
+        self.done(Request::Remove { torrent }).await
+    }
+
+    async fn status(&self, request: tonic::Request<proto::Query>) -> RpcResult<proto::StatusReply> {
+        let torrent = request.into_inner().torrent;
+        self.status_reply(torrent).await.map(tonic::Response::new)
+    }
+
+    async fn watch_status(
+        &self,
+        request: tonic::Request<proto::WatchRequest>,
+    ) -> RpcResult<Self::WatchStatusStream> {
+        let watch = request.into_inner();
+        let period = match watch.interval_ms {
+            0 => WATCH_INTERVAL,
+            ms => Duration::from_millis(ms.into()).max(MIN_WATCH_INTERVAL),
+        };
+        let service = Service {
+            session: Arc::clone(&self.session),
+        };
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let reply = service.status_reply(watch.torrent.clone()).await;
+                // The client has gone once nobody is receiving.
+                if tx.send(reply).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn peers(&self, request: tonic::Request<proto::Query>) -> RpcResult<proto::PeersReply> {
+        let torrent = request.into_inner().torrent;
+        match self.call(Request::Peers { torrent }).await? {
+            Response::Peers { torrents } => Ok(tonic::Response::new(proto::PeersReply {
+                torrents: torrents
+                    .into_iter()
+                    .map(|torrent| proto::TorrentPeers {
+                        info_hash: torrent.info_hash.to_string(),
+                        name: torrent.name,
+                        peers: torrent.peers.into_iter().map(peer).collect(),
+                    })
+                    .collect(),
+            })),
+            _ => Err(unexpected()),
+        }
+    }
+
+    async fn trackers(
+        &self,
+        request: tonic::Request<proto::Query>,
+    ) -> RpcResult<proto::TrackersReply> {
+        let torrent = request.into_inner().torrent;
+        match self.call(Request::Trackers { torrent }).await? {
+            Response::Trackers { torrents } => Ok(tonic::Response::new(proto::TrackersReply {
+                torrents: torrents
+                    .into_iter()
+                    .map(|torrent| proto::TorrentTrackers {
+                        info_hash: torrent.info_hash.to_string(),
+                        name: torrent.name,
+                        trackers: torrent.trackers.into_iter().map(tracker).collect(),
+                    })
+                    .collect(),
+            })),
+            _ => Err(unexpected()),
+        }
+    }
+
+    async fn files(&self, request: tonic::Request<proto::Query>) -> RpcResult<proto::FilesReply> {
+        let torrent = request.into_inner().torrent;
+        match self.call(Request::Files { torrent }).await? {
+            Response::Files { torrents } => Ok(tonic::Response::new(proto::FilesReply {
+                torrents: torrents
+                    .into_iter()
+                    .map(|torrent| proto::TorrentFiles {
+                        info_hash: torrent.info_hash.to_string(),
+                        name: torrent.name,
+                        files: torrent.files.into_iter().map(file).collect(),
+                    })
+                    .collect(),
+            })),
+            _ => Err(unexpected()),
+        }
+    }
+
+    async fn announce(
+        &self,
+        request: tonic::Request<proto::TorrentFilter>,
+    ) -> RpcResult<proto::TorrentsReply> {
+        let torrent = request.into_inner().torrent;
+        self.done(Request::Announce { torrent }).await
+    }
+
+    async fn pause(
+        &self,
+        request: tonic::Request<proto::TorrentFilter>,
+    ) -> RpcResult<proto::TorrentsReply> {
+        let torrent = request.into_inner().torrent;
+        self.done(Request::Pause { torrent }).await
+    }
+
+    async fn resume(
+        &self,
+        request: tonic::Request<proto::TorrentFilter>,
+    ) -> RpcResult<proto::TorrentsReply> {
+        let torrent = request.into_inner().torrent;
+        self.done(Request::Resume { torrent }).await
+    }
+
+    async fn pause_session(
+        &self,
+        _: tonic::Request<proto::SessionRequest>,
+    ) -> RpcResult<proto::TorrentsReply> {
+        self.done(Request::PauseSession).await
+    }
+
+    async fn resume_session(
+        &self,
+        _: tonic::Request<proto::SessionRequest>,
+    ) -> RpcResult<proto::TorrentsReply> {
+        self.done(Request::ResumeSession).await
+    }
+
+    async fn session(
+        &self,
+        _: tonic::Request<proto::SessionRequest>,
+    ) -> RpcResult<proto::SessionReply> {
+        match self.call(Request::Session).await? {
+            Response::Session { session } => Ok(tonic::Response::new(proto::SessionReply {
+                port: session.port.map(u32::from),
+                paused: session.paused,
+                port_mappings: session
+                    .port_mappings
+                    .into_iter()
+                    .map(port_mapping)
+                    .collect(),
+                endpoints: session.endpoints.iter().map(Endpoint::to_string).collect(),
+                downloaded: session.downloaded,
+                uploaded: session.uploaded,
+            })),
+            _ => Err(unexpected()),
+        }
+    }
+
+    async fn recheck(
+        &self,
+        request: tonic::Request<proto::TorrentFilter>,
+    ) -> RpcResult<proto::TorrentsReply> {
+        let torrent = request.into_inner().torrent;
+        self.done(Request::Recheck { torrent }).await
+    }
+
+    async fn move_data(
+        &self,
+        request: tonic::Request<proto::MoveRequest>,
+    ) -> RpcResult<proto::TorrentsReply> {
+        let request = request.into_inner();
+        self.done(Request::Move {
+            torrent: request.torrent,
+            path: PathBuf::from(request.path),
+        })
+        .await
+    }
+
+    async fn rename(
+        &self,
+        request: tonic::Request<proto::RenameRequest>,
+    ) -> RpcResult<proto::TorrentsReply> {
+        let request = request.into_inner();
+        self.done(Request::Rename {
+            torrent: request.torrent,
+            name: request.name,
+        })
+        .await
+    }
+
+    async fn set(
+        &self,
+        request: tonic::Request<proto::SetRequest>,
+    ) -> RpcResult<proto::TorrentsReply> {
+        let set = request.into_inner();
+        let settings = Settings {
+            download_rate: set.download_rate,
+            upload_rate: set.upload_rate,
+            sequential: set.sequential,
+            file_priorities: set
+                .file_priorities
+                .map(|files| files.priorities().map(file_priority).collect()),
+        };
+        self.done(Request::Set {
+            torrent: set.torrent,
+            settings,
+        })
+        .await
+    }
+}
+
+fn unexpected() -> RpcStatus {
+    RpcStatus::internal("unexpected response to the request")
+}
+
+fn torrent_ref(torrent: TorrentRef) -> proto::TorrentRef {
+    proto::TorrentRef {
+        info_hash: torrent.info_hash.to_string(),
+        name: torrent.name,
+    }
+}
+
+fn port_mapping(mapping: PortMapping) -> proto::PortMapping {
+    let mechanism = match mapping.mechanism {
+        Mechanism::Upnp => proto::Mechanism::Upnp,
+        Mechanism::NatPmp => proto::Mechanism::NatPmp,
+        Mechanism::Pcp => proto::Mechanism::Pcp,
+    };
+    proto::PortMapping {
+        mechanism: mechanism.into(),
+        external: mapping.external.to_string(),
+    }
+}
+
+fn status(status: Status) -> proto::TorrentStatus {
+    let state = match status.state {
+        State::Downloading => proto::State::Downloading,
+        State::Seeding => proto::State::Seeding,
+        State::Paused => proto::State::Paused,
+        State::Checking => proto::State::Checking,
+        State::Error => proto::State::Error,
+    };
+    proto::TorrentStatus {
+        info_hash: status.info_hash.to_string(),
+        name: status.name,
+        state: state.into(),
+        done: status.done,
+        wanted: status.wanted,
+        downloaded: status.downloaded,
+        uploaded: status.uploaded,
+        download_rate: status.download_rate,
+        upload_rate: status.upload_rate,
+        peers: status.peers as u64,
+        seeds: status.seeds as u64,
+        blocked: status.blocked,
+        error: status.error,
+    }
+}
+
+fn peer(peer: PeerInfo) -> proto::Peer {
+    proto::Peer {
+        addr: peer.addr.to_string(),
+        client: peer.client,
+        incoming: peer.incoming,
+        encrypted: peer.encrypted,
+        am_choking: peer.am_choking,
+        am_interested: peer.am_interested,
+        peer_choking: peer.peer_choking,
+        peer_interested: peer.peer_interested,
+        progress: peer.progress,
+        download_rate: peer.download_rate,
+        upload_rate: peer.upload_rate,
+        downloaded: peer.downloaded,
+        uploaded: peer.uploaded,
+    }
+}
+
+fn tracker(tracker: TrackerInfo) -> proto::Tracker {
+    let (working, error) = match tracker.state {
+        TrackerState::Idle => (None, None),
+        TrackerState::Working => (Some(true), None),
+        TrackerState::Failed(message) => (Some(false), Some(message)),
+    };
+    proto::Tracker {
+        url: tracker.url,
+        working,
+        error,
+        peers: tracker.peers as u64,
+        seeders: tracker.seeders,
+        leechers: tracker.leechers,
+    }
+}
+
+fn file(file: FileInfo) -> proto::File {
+    let priority = match file.priority {
+        FilePriority::Skip => proto::FilePriority::Skip,
+        FilePriority::Low => proto::FilePriority::Low,
+        FilePriority::Normal => proto::FilePriority::Normal,
+        FilePriority::High => proto::FilePriority::High,
+    };
+    proto::File {
+        path: file.path.display().to_string(),
+        length: file.length,
+        done: file.done,
+        priority: priority.into(),
+    }
+}
+
+fn file_priority(priority: proto::FilePriority) -> FilePriority {
+    match priority {
+        proto::FilePriority::Skip => FilePriority::Skip,
+        proto::FilePriority::Low => FilePriority::Low,
+        proto::FilePriority::Normal => FilePriority::Normal,
+        proto::FilePriority::High => FilePriority::High,
+    }
+}
@@ -0,0 +1,156 @@
+//! Checks of the daemon's subsystems, for the `/healthz` and `/readyz`
+//! endpoints that container orchestrators probe. Liveness is probed often
+//! and must stay cheap; readiness may touch the disk and the network.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::join_all;
+use serde::Serialize;
+use tokio::net::lookup_host;
+use tokio::time::timeout;
+
+use crate::interface::Endpoint;
+use crate::session::Session;
+
+/// How long resolving the DHT bootstrap nodes may take, all together.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a torrent may take to answer before it is taken to be stuck.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The outcome of every check.
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+    /// Whether every check passed.
+    pub ready: bool,
+    pub checks: Vec<Check>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    /// What was found, or what went wrong.
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, result: Result<String, String>) -> Self {
+        let ok = result.is_ok();
+        let detail = result.unwrap_or_else(|e| e);
+        Self { name, ok, detail }
+    }
+}
+
+/// Checks that the daemon is listening for peers and that none of its
+/// torrents is stuck, without touching the disk or the network.
+pub async fn live(session: &Arc<Session>) -> Report {
+    let handles = session.handles();
+    let answers = join_all(
+        handles
+            .iter()
+            .map(|handle| timeout(ANSWER_TIMEOUT, handle.status())),
+    )
+    .await;
+    let stuck = answers.iter().filter(|answer| answer.is_err()).count();
+    let answering = if stuck == 0 {
+        Ok(format!("{} torrents answer", handles.len()))
+    } else {
+        Err(format!(
+            "{} of {} torrents did not answer within {:?}",
+            stuck,
+            handles.len(),
+            ANSWER_TIMEOUT
+        ))
+    };
+
+    let checks = vec![listener(session), Check::new("torrents", answering)];
+    Report {
+        ready: checks.iter().all(|check| check.ok),
+        checks,
+    }
+}
+
+/// Checks that the directories the daemon writes to are writable, that it
+/// is listening for peers, that the interface it is bound to is up, and,
+/// if the DHT is enabled, that its bootstrap nodes can be found.
+pub async fn check(session: &Arc<Session>) -> Report {
+    let config = session.config();
+    let mut checks = Vec::new();
+
+    let mut dirs = vec![config.storage.download_dir.as_path()];
+    dirs.extend(config.storage.incomplete_dir.as_deref());
+    dirs.extend(config.state_dir.as_deref());
+    for dir in dirs {
+        checks.push(Check::new("disk", writable(dir).await));
+    }
+
+    checks.push(listener(session));
+
+    if let Some(interface) = &config.network.interface {
+        checks.push(Check::new(
+            "interface",
+            interface
+                .check()
+                .map(|()| format!("interface {} is up", interface))
+                .map_err(|e| e.to_string()),
+        ));
+    }
+
+    if config.dht.enabled {
+        checks.push(Check::new(
+            "dht",
+            bootstrap_nodes(&config.dht.bootstrap_nodes).await,
+        ));
+    }
+
+    Report {
+        ready: checks.iter().all(|check| check.ok),
+        checks,
+    }
+}
+
+fn listener(session: &Session) -> Check {
+    let listening = match session.bound_endpoints().as_slice() {
+        [] => Err("not listening for peers".to_string()),
+        [endpoint] if session.config().network.listen.is_empty() => {
+            Ok(format!("listening for peers on port {}", endpoint.port))
+        }
+        endpoints => {
+            let endpoints: Vec<String> = endpoints.iter().map(Endpoint::to_string).collect();
+            Ok(format!("listening for peers on {}", endpoints.join(", ")))
+        }
+    };
+    Check::new("listener", listening)
+}
+
+/// Writes and removes a file in `dir`.
+async fn writable(dir: &Path) -> Result<String, String> {
+    let probe = dir.join(format!(".rainyday-health-{}", rand::random::<u32>()));
+    let written = tokio::fs::write(&probe, b"").await;
+    let _ = tokio::fs::remove_file(&probe).await;
+    match written {
+        Ok(()) => Ok(format!("{} is writable", dir.display())),
+        Err(e) => Err(format!("cannot write to {}: {}", dir.display(), e)),
+    }
+}
+
+/// Resolves the bootstrap nodes, which lookups need at least one of, all
+/// at once.
+async fn bootstrap_nodes(nodes: &[String]) -> Result<String, String> {
+    let lookups = join_all(nodes.iter().map(|node| async move {
+        lookup_host(node.as_str())
+            .await
+            .is_ok_and(|mut addrs| addrs.next().is_some())
+    }));
+    let resolved = timeout(RESOLVE_TIMEOUT, lookups)
+        .await
+        .map_or(0, |resolved| resolved.into_iter().filter(|&ok| ok).count());
+    let detail = format!("{} of {} bootstrap nodes resolve", resolved, nodes.len());
+    if resolved > 0 {
+        Ok(detail)
+    } else {
+        Err(detail)
+    }
+}
@@ -0,0 +1,278 @@
+//! The local control interface: a Unix socket, or loopback TCP on Windows,
+//! over which the CLI talks to a running rainyday process.
+//!
+//! Each request and response is one line of JSON. Other programs may also
+//! speak JSON-RPC 2.0 over the same socket; see [`rpc`]. The daemon can
+//! also serve a REST API over HTTP, in builds with the `grpc` feature a
+//! gRPC API, and in builds with the `dbus` feature a D-Bus interface.
+
+pub mod client;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod health;
+pub mod rest;
+pub mod rpc;
+pub mod server;
+pub mod transport;
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::config::Config;
+use crate::engine::{FileInfo, PeerInfo, Status, TrackerInfo};
+use crate::files::FilePriority;
+use crate::info_hash::InfoHash;
+use crate::interface::Endpoint;
+use crate::portmap::PortMapping;
+
+#[derive(Debug, Error)]
+pub enum ControlError {
+    #[error("cannot reach rainyday at {path}: {source}")]
+    Connect { path: PathBuf, source: io::Error },
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("malformed message: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("rainyday closed the connection without answering")]
+    NoResponse,
+    #[error("{0}")]
+    Remote(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// Start a torrent in a daemon: a path to a `.torrent` file, its URL,
+    /// or a magnet link.
+    Add {
+        torrent: String,
+        #[serde(default)]
+        options: AddOptions,
+    },
+    /// Stop the torrents matching `torrent` and forget them.
+    Remove {
+        torrent: String,
+    },
+    /// State and statistics of every torrent matching `torrent`, or of all.
+    Status {
+        torrent: Option<String>,
+    },
+    /// Connected peers of every torrent matching `torrent`, or of all.
+    Peers {
+        torrent: Option<String>,
+    },
+    /// Trackers of every torrent matching `torrent`, or of all.
+    Trackers {
+        torrent: Option<String>,
+    },
+    /// Files of every torrent matching `torrent`, or of all, and how much
+    /// of each is done.
+    Files {
+        torrent: Option<String>,
+    },
+    /// Announce the torrents matching `torrent` to their trackers now.
+    Announce {
+        torrent: String,
+    },
+    /// Stop the torrents matching `torrent`, keeping their data and state.
+    Pause {
+        torrent: String,
+    },
+    Resume {
+        torrent: String,
+    },
+    /// Verify the data of the torrents matching `torrent` again.
+    Recheck {
+        torrent: String,
+    },
+    /// Move the data of the torrents matching `torrent` to the same place
+    /// under the directory `path`, without stopping them.
+    Move {
+        torrent: String,
+        path: PathBuf,
+    },
+    /// Rename the single file or top-level directory of the one torrent
+    /// matching `torrent` to `name`, without stopping it.
+    Rename {
+        torrent: String,
+        name: String,
+    },
+    /// Change settings of the running torrents matching `torrent`.
+    Set {
+        torrent: String,
+        #[serde(default)]
+        settings: Settings,
+    },
+    /// Pause every torrent, and those added later, until the session is
+    /// resumed.
+    PauseSession,
+    /// Resume the torrents that pausing the session paused.
+    ResumeSession,
+    /// The state of the session as a whole.
+    Session,
+}
+
+/// Settings for a torrent being added that override the daemon's
+/// configuration for that torrent alone.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AddOptions {
+    /// Where to keep the download, instead of the download directory.
+    pub download_dir: Option<PathBuf>,
+    /// Most bytes per second to download, within the daemon's limit.
+    pub download_rate: Option<u64>,
+    /// Most bytes per second to upload, within the daemon's limit.
+    pub upload_rate: Option<u64>,
+    /// Share ratio at which to stop seeding.
+    pub seed_ratio: Option<f64>,
+    /// Seconds to seed for once complete.
+    pub seed_time: Option<u64>,
+    /// Download pieces in order rather than rarest first.
+    pub sequential: bool,
+}
+
+/// Settings of a running torrent to change. Those left out stay as they
+/// are.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Most bytes per second to download, within the daemon's limit, or 0
+    /// for no limit of the torrent's own.
+    pub download_rate: Option<u64>,
+    /// Most bytes per second to upload, within the daemon's limit, or 0
+    /// for no limit of the torrent's own.
+    pub upload_rate: Option<u64>,
+    /// Download pieces in order rather than rarest first.
+    pub sequential: Option<bool>,
+    /// Per-file priorities, indexed like the torrent's files. Files beyond
+    /// the end keep normal priority.
+    pub file_priorities: Option<Vec<FilePriority>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Response {
+    Status {
+        torrents: Vec<Status>,
+    },
+    Peers {
+        torrents: Vec<TorrentPeers>,
+    },
+    Trackers {
+        torrents: Vec<TorrentTrackers>,
+    },
+    Files {
+        torrents: Vec<TorrentFiles>,
+    },
+    Session {
+        session: SessionStatus,
+    },
+    /// The torrents a request was carried out for.
+    Done {
+        torrents: Vec<TorrentRef>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// The state of the session as a whole.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionStatus {
+    /// The port peers connect to, once the session listens.
+    pub port: Option<u16>,
+    /// Every address and port peers connect to, the main port first.
+    pub endpoints: Vec<Endpoint>,
+    /// Whether the whole session is paused.
+    pub paused: bool,
+    /// The forwards of the port routers have granted.
+    pub port_mappings: Vec<PortMapping>,
+    /// Bytes every torrent received since the session started.
+    #[serde(default)]
+    pub downloaded: u64,
+    /// Bytes every torrent sent since the session started.
+    #[serde(default)]
+    pub uploaded: u64,
+}
+
+/// Names a torrent in a response.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TorrentRef {
+    pub info_hash: InfoHash,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TorrentPeers {
+    pub info_hash: InfoHash,
+    pub name: String,
+    pub peers: Vec<PeerInfo>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TorrentTrackers {
+    pub info_hash: InfoHash,
+    pub name: String,
+    pub trackers: Vec<TrackerInfo>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TorrentFiles {
+    pub info_hash: InfoHash,
+    pub name: String,
+    pub files: Vec<FileInfo>,
+}
+
+/// Whether `filter`, as given on the command line, picks out `status`: it
+/// may be the torrent's name or a prefix of its info hash.
+pub fn matches(filter: Option<&str>, status: &Status) -> bool {
+    match filter {
+        None => true,
+        Some(filter) => {
+            status.name == filter
+                || (!filter.is_empty()
+                    && status
+                        .info_hash
+                        .to_string()
+                        .starts_with(&filter.to_ascii_lowercase()))
+        }
+    }
+}
+
+/// The bearer token clients of the daemon's network APIs must send: the
+/// configured one, or else a new one, written where the user can read it
+/// and, on Unix, nobody else can. On Windows the file takes the access
+/// rights of its directory.
+pub fn token(config: &Config) -> io::Result<String> {
+    if let Some(token) = &config.http_token {
+        return Ok(token.clone());
+    }
+    let token: String = rand::random::<[u8; 32]>()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let path = config.http_token_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Created afresh, so that an old file cannot lend it wider permissions.
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&path)?;
+    writeln!(file, "{}", token)?;
+    Ok(token)
+}
@@ -0,0 +1,557 @@
+//! A REST API over HTTP, for dashboards and scripts with no JSON-RPC
+//! client at hand. Torrents are addressed by name or a prefix of their
+//! info hash, as everywhere else, but must be picked out unambiguously.
+//!
+//! - `GET /api/v1/torrents`: the status of every torrent, a page at a time
+//! - `POST /api/v1/torrents`: add a torrent, either as
+//!   `{"torrent": "magnet:?...", "options": {...}}` or by uploading the
+//!   `.torrent` file as `application/x-bittorrent`, with the options as
+//!   query parameters
+//! - `GET /api/v1/torrents/{id}`: a torrent's status and trackers
+//! - `PATCH /api/v1/torrents/{id}`: change its settings
+//! - `DELETE /api/v1/torrents/{id}`: remove it
+//! - `POST /api/v1/torrents/{id}/{announce,pause,resume,recheck}`
+//! - `POST /api/v1/torrents/{id}/move`: move its data to `{"path": ...}`,
+//!   a directory within the download directory
+//! - `POST /api/v1/torrents/{id}/rename`: rename its file or top-level
+//!   directory to `{"name": ...}`
+//! - `GET /api/v1/torrents/{id}/{peers,files}`: a page at a time
+//! - `GET /api/v1/session`: the session's port, forwards and whether it
+//!   is paused
+//! - `POST /api/v1/session/{pause,resume}`: pause or resume every torrent
+//! - `GET /healthz`: whether the daemon is alive, and `GET /readyz`: the
+//!   [`health`](super::health) report, both answered without the token
+//!   and failing with 503 if a check does
+//!
+//! Lists take `offset` and `limit` query parameters.
+//!
+//! Web pages the user visits can send requests here too, so every request
+//! needs the bearer token, requests from other origins or naming the
+//! server by a host name other than `localhost` are refused, and nothing
+//! sent over HTTP may make the daemon read or write a path of its
+//! choosing: torrents are added as magnet links, URLs or uploads, into
+//! the download directory, and moved only within it.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Component, PathBuf};
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::extract::{Path, Query, Request as HttpRequest, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response as HttpResponse};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::net::TcpListener;
+
+use super::health;
+use super::server::{handle, start};
+use super::{AddOptions, Request, Response, SessionStatus, Settings, TorrentRef};
+use crate::engine::{FileInfo, PeerInfo, Status, TrackerInfo};
+use crate::magnet;
+use crate::metainfo::Metainfo;
+use crate::session::Session;
+
+/// Items in a page unless the client asks for fewer.
+const DEFAULT_PAGE: usize = 50;
+const MAX_PAGE: usize = 500;
+/// Largest `.torrent` file that may be uploaded.
+const MAX_UPLOAD: usize = 16 * 1024 * 1024;
+const TORRENT_TYPE: &str = "application/x-bittorrent";
+const JSON_TYPE: &str = "application/json";
+
+/// Serves the API on `addr` until the task is dropped. Clients must send
+/// `token` as a bearer token.
+pub async fn serve(addr: SocketAddr, token: String, session: Arc<Session>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let api = Api {
+        session,
+        token: Arc::from(token),
+    };
+    axum::serve(listener, router(api)).await
+}
+
+#[derive(Clone)]
+struct Api {
+    session: Arc<Session>,
+    token: Arc<str>,
+}
+
+fn router(api: Api) -> Router {
+    Router::new()
+        .route("/api/v1/torrents", get(list).post(add))
+        .route(
+            "/api/v1/torrents/{id}",
+            get(detail).patch(set).delete(remove),
+        )
+        .route("/api/v1/torrents/{id}/peers", get(peers))
+        .route("/api/v1/torrents/{id}/files", get(files))
+        .route("/api/v1/torrents/{id}/move", post(move_data))
+        .route("/api/v1/torrents/{id}/rename", post(rename))
+        .route("/api/v1/torrents/{id}/{action}", post(action))
+        .route("/api/v1/session", get(session))
+        .route("/api/v1/session/{action}", post(session_action))
+        .layer(middleware::from_fn_with_state(api.clone(), authorize))
+        .layer(axum::extract::DefaultBodyLimit::max(MAX_UPLOAD))
+        // Probes have no token to send.
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .layer(middleware::from_fn(same_origin))
+        .with_state(api)
+}
+
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> HttpResponse {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+/// Which part of a list to return.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+struct PageQuery {
+    offset: usize,
+    limit: usize,
+}
+
+impl Default for PageQuery {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            limit: DEFAULT_PAGE,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Page<T> {
+    /// Items in the whole list.
+    total: usize,
+    offset: usize,
+    limit: usize,
+    items: Vec<T>,
+}
+
+impl<T> Page<T> {
+    fn new(items: Vec<T>, query: PageQuery) -> Self {
+        let limit = query.limit.min(MAX_PAGE);
+        Self {
+            total: items.len(),
+            offset: query.offset,
+            limit,
+            items: items.into_iter().skip(query.offset).take(limit).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Detail {
+    #[serde(flatten)]
+    status: Status,
+    trackers: Vec<TrackerInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AddBody {
+    torrent: String,
+    #[serde(default)]
+    options: AddOptions,
+}
+
+#[derive(Debug, Deserialize)]
+struct MoveBody {
+    /// Relative to the download directory.
+    path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct RenameBody {
+    name: String,
+}
+
+async fn authorize(
+    State(api): State<Api>,
+    request: HttpRequest,
+    next: Next,
+) -> Result<HttpResponse, ApiError> {
+    let given = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if given != Some(&*api.token) {
+        return Err(ApiError(
+            StatusCode::UNAUTHORIZED,
+            "a valid bearer token is needed".to_owned(),
+        ));
+    }
+    Ok(next.run(request).await)
+}
+
+/// Refuses requests that a web page on another origin sent, or that reached
+/// us through a name of the page's choosing (DNS rebinding).
+async fn same_origin(request: HttpRequest, next: Next) -> Result<HttpResponse, ApiError> {
+    let forbidden = |message: &str| ApiError(StatusCode::FORBIDDEN, message.to_owned());
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok());
+    if !host.is_some_and(is_local_host) {
+        return Err(forbidden(
+            "the server must be addressed as localhost or by IP address",
+        ));
+    }
+    if let Some(origin) = request.headers().get(header::ORIGIN) {
+        let origin = origin_authority(origin);
+        if origin.is_none() || origin != host {
+            return Err(forbidden("requests from other origins are not allowed"));
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Whether `host`, a `Host` header, names the server as `localhost` or by
+/// an IP address, rather than by a name a web page could point anywhere.
+fn is_local_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        // An IPv6 address, with or without a port.
+        Some(rest) => rest.split(']').next().unwrap_or(""),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok()
+}
+
+/// The host and port of an `Origin` header, which is `null` for pages
+/// that have none.
+fn origin_authority(origin: &HeaderValue) -> Option<&str> {
+    let origin = origin.to_str().ok()?;
+    origin
+        .strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"))
+}
+
+impl Api {
+    /// Carries out `request`, taking a failure as the client's fault.
+    async fn call(&self, request: Request) -> Result<Response, ApiError> {
+        match handle(request, &self.session).await {
+            Response::Error { message } => Err(ApiError(StatusCode::BAD_REQUEST, message)),
+            response => Ok(response),
+        }
+    }
+
+    async fn statuses(&self, torrent: Option<String>) -> Result<Vec<Status>, ApiError> {
+        match self.call(Request::Status { torrent }).await? {
+            Response::Status { torrents } => Ok(torrents),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// The one torrent `id` picks out.
+    async fn resolve(&self, id: &str) -> Result<Status, ApiError> {
+        let mut found = self.statuses(Some(id.to_owned())).await?;
+        match found.len() {
+            0 => Err(ApiError(
+                StatusCode::NOT_FOUND,
+                format!("no torrent matches {:?}", id),
+            )),
+            1 => Ok(found.remove(0)),
+            n => Err(ApiError(
+                StatusCode::CONFLICT,
+                format!("{:?} matches {} torrents", id, n),
+            )),
+        }
+    }
+
+    /// Carries out a request that acts on the torrent `id` picks out.
+    async fn act(
+        &self,
+        id: &str,
+        request: impl FnOnce(String) -> Request,
+    ) -> ApiResult<TorrentRef> {
+        let status = self.resolve(id).await?;
+        match self.call(request(status.info_hash.to_string())).await? {
+            Response::Done { mut torrents } if !torrents.is_empty() => Ok(Json(torrents.remove(0))),
+            _ => Err(unexpected()),
+        }
+    }
+}
+
+/// Whether `torrent` names something fetched from the network, rather
+/// than a file the daemon would read.
+fn is_remote(torrent: &str) -> bool {
+    torrent.starts_with(magnet::SCHEME)
+        || torrent.starts_with("http://")
+        || torrent.starts_with("https://")
+}
+
+fn unexpected() -> ApiError {
+    ApiError(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "unexpected response to the request".to_owned(),
+    )
+}
+
+async fn list(State(api): State<Api>, Query(page): Query<PageQuery>) -> ApiResult<Page<Status>> {
+    Ok(Json(Page::new(api.statuses(None).await?, page)))
+}
+
+async fn add(
+    State(api): State<Api>,
+    Query(options): Query<AddOptions>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<TorrentRef>), ApiError> {
+    let bad_request = |message: String| ApiError(StatusCode::BAD_REQUEST, message);
+    let forbidden = |message: &str| ApiError(StatusCode::FORBIDDEN, message.to_owned());
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let uploaded = content_type.starts_with(TORRENT_TYPE);
+    if !uploaded && !content_type.starts_with(JSON_TYPE) {
+        return Err(ApiError(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("send {} or {}", JSON_TYPE, TORRENT_TYPE),
+        ));
+    }
+
+    let added = if uploaded {
+        if options.download_dir.is_some() {
+            return Err(forbidden("download_dir cannot be set over HTTP"));
+        }
+        let pedantic = api.session.config().pedantic;
+        let metainfo = Metainfo::parse(&body, pedantic)
+            .map_err(|e| bad_request(format!("invalid torrent: {}", e)))?;
+        start(&api.session, metainfo, &options)
+            .await
+            .map_err(|e| bad_request(e.to_string()))?
+    } else {
+        let add: AddBody = serde_json::from_slice(&body)
+            .map_err(|e| bad_request(format!("invalid request: {}", e)))?;
+        if add.options.download_dir.is_some() {
+            return Err(forbidden("download_dir cannot be set over HTTP"));
+        }
+        if !is_remote(&add.torrent) {
+            return Err(forbidden(
+                "only magnet links and http(s) URLs can be added over HTTP; upload .torrent files",
+            ));
+        }
+        match api
+            .call(Request::Add {
+                torrent: add.torrent,
+                options: add.options,
+            })
+            .await?
+        {
+            Response::Done { mut torrents } if !torrents.is_empty() => torrents.remove(0),
+            _ => return Err(unexpected()),
+        }
+    };
+    Ok((StatusCode::CREATED, Json(added)))
+}
+
+async fn detail(State(api): State<Api>, Path(id): Path<String>) -> ApiResult<Detail> {
+    let status = api.resolve(&id).await?;
+    let torrent = Some(status.info_hash.to_string());
+    let trackers = match api.call(Request::Trackers { torrent }).await? {
+        Response::Trackers { mut torrents } if !torrents.is_empty() => torrents.remove(0).trackers,
+        _ => Vec::new(),
+    };
+    Ok(Json(Detail { status, trackers }))
+}
+
+async fn set(
+    State(api): State<Api>,
+    Path(id): Path<String>,
+    Json(settings): Json<Settings>,
+) -> ApiResult<TorrentRef> {
+    api.act(&id, |torrent| Request::Set { torrent, settings })
+        .await
+}
+
+async fn remove(State(api): State<Api>, Path(id): Path<String>) -> ApiResult<TorrentRef> {
+    api.act(&id, |torrent| Request::Remove { torrent }).await
+}
+
+async fn move_data(
+    State(api): State<Api>,
+    Path(id): Path<String>,
+    Json(body): Json<MoveBody>,
+) -> ApiResult<TorrentRef> {
+    if !stays_inside(&body.path) {
+        return Err(ApiError(
+            StatusCode::FORBIDDEN,
+            "data can only be moved within the download directory over HTTP".to_owned(),
+        ));
+    }
+    let path = api.session.config().storage.download_dir.join(body.path);
+    api.act(&id, |torrent| Request::Move { torrent, path })
+        .await
+}
+
+async fn rename(
+    State(api): State<Api>,
+    Path(id): Path<String>,
+    Json(body): Json<RenameBody>,
+) -> ApiResult<TorrentRef> {
+    let name = body.name;
+    api.act(&id, |torrent| Request::Rename { torrent, name })
+        .await
+}
+
+/// Whether `path`, taken as relative to a directory, stays inside it.
+fn stays_inside(path: &std::path::Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+async fn action(
+    State(api): State<Api>,
+    Path((id, action)): Path<(String, String)>,
+) -> ApiResult<TorrentRef> {
+    let request: fn(String) -> Request = match action.as_str() {
+        "announce" => |torrent| Request::Announce { torrent },
+        "pause" => |torrent| Request::Pause { torrent },
+        "resume" => |torrent| Request::Resume { torrent },
+        "recheck" => |torrent| Request::Recheck { torrent },
+        _ => {
+            return Err(ApiError(
+                StatusCode::NOT_FOUND,
+                format!("no action {:?}", action),
+            ))
+        }
+    };
+    api.act(&id, request).await
+}
+
+async fn session(State(api): State<Api>) -> ApiResult<SessionStatus> {
+    match api.call(Request::Session).await? {
+        Response::Session { session } => Ok(Json(session)),
+        _ => Err(unexpected()),
+    }
+}
+
+async fn session_action(
+    State(api): State<Api>,
+    Path(action): Path<String>,
+) -> ApiResult<Vec<TorrentRef>> {
+    let request = match action.as_str() {
+        "pause" => Request::PauseSession,
+        "resume" => Request::ResumeSession,
+        _ => {
+            return Err(ApiError(
+                StatusCode::NOT_FOUND,
+                format!("no action {:?}", action),
+            ))
+        }
+    };
+    match api.call(request).await? {
+        Response::Done { torrents } => Ok(Json(torrents)),
+        _ => Err(unexpected()),
+    }
+}
+
+async fn healthz(State(api): State<Api>) -> (StatusCode, Json<health::Report>) {
+    report(health::live(&api.session).await)
+}
+
+async fn readyz(State(api): State<Api>) -> (StatusCode, Json<health::Report>) {
+    report(health::check(&api.session).await)
+}
+
+fn report(report: health::Report) -> (StatusCode, Json<health::Report>) {
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+async fn peers(
+    State(api): State<Api>,
+    Path(id): Path<String>,
+    Query(page): Query<PageQuery>,
+) -> ApiResult<Page<PeerInfo>> {
+    let torrent = Some(api.resolve(&id).await?.info_hash.to_string());
+    match api.call(Request::Peers { torrent }).await? {
+        Response::Peers { mut torrents } if !torrents.is_empty() => {
+            Ok(Json(Page::new(torrents.remove(0).peers, page)))
+        }
+        _ => Err(unexpected()),
+    }
+}
+
+async fn files(
+    State(api): State<Api>,
+    Path(id): Path<String>,
+    Query(page): Query<PageQuery>,
+) -> ApiResult<Page<FileInfo>> {
+    let torrent = Some(api.resolve(&id).await?.info_hash.to_string());
+    match api.call(Request::Files { torrent }).await? {
+        Response::Files { mut torrents } if !torrents.is_empty() => {
+            Ok(Json(Page::new(torrents.remove(0).files, page)))
+        }
+        _ => Err(unexpected()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_hosts_no_web_page_can_rebind() {
+        for host in [
+            "localhost",
+            "LOCALHOST:8080",
+            "127.0.0.1:8080",
+            "[::1]:8080",
+            "[::1]",
+        ] {
+            assert!(is_local_host(host), "{}", host);
+        }
+        for host in [
+            "",
+            "evil.example",
+            "evil.example:8080",
+            "127.0.0.1.nip.io:8080",
+        ] {
+            assert!(!is_local_host(host), "{}", host);
+        }
+    }
+
+    #[test]
+    fn reads_the_authority_of_an_origin() {
+        let origin = |value| origin_authority(&HeaderValue::from_static(value)).map(str::to_owned);
+        assert_eq!(origin("http://127.0.0.1:8080").unwrap(), "127.0.0.1:8080");
+        assert_eq!(origin("https://localhost").unwrap(), "localhost");
+        assert_eq!(origin("null"), None);
+    }
+
+    #[test]
+    fn adds_only_what_is_fetched_from_the_network() {
+        assert!(is_remote(
+            "magnet:?xt=urn:btih:0000000000000000000000000000000000000000"
+        ));
+        assert!(is_remote("https://example.com/debian.torrent"));
+        assert!(!is_remote("/tmp/debian.torrent"));
+        assert!(!is_remote("file:///tmp/debian.torrent"));
+    }
+
+    #[test]
+    fn moves_only_within_the_download_directory() {
+        assert!(stays_inside(std::path::Path::new("films/2024")));
+        assert!(stays_inside(std::path::Path::new("./films")));
+        assert!(!stays_inside(std::path::Path::new("/etc")));
+        assert!(!stays_inside(std::path::Path::new("films/../../etc")));
+    }
+}
@@ -0,0 +1,154 @@
+//! JSON-RPC 2.0 over the control socket, for programs other than the CLI.
+//!
+//! A line holding a JSON-RPC request, or a batch of them, is answered in
+//! kind. Methods are named after the control requests and take their
+//! fields as named parameters:
+//!
+//! ```json
+//! {"jsonrpc": "2.0", "id": 1, "method": "set", "params": {"torrent": "debian", "settings": {"upload_rate": 50000}}}
+//! ```
+//!
+//! The result holds the fields of the control response, such as
+//! `{"torrents": [...]}`, and a request that cannot be carried out is
+//! answered with an error whose code is [`FAILED`].
+
+use std::sync::Arc;
+
+use serde_json::{json, Map, Value};
+
+use super::server::handle;
+use super::{Request, Response};
+use crate::session::Session;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The request was understood but could not be carried out, e.g. because
+/// no torrent matches.
+pub const FAILED: i64 = -32000;
+
+/// Every method, one for each kind of control request.
+pub const METHODS: &[&str] = &[
+    "add",
+    "remove",
+    "status",
+    "peers",
+    "trackers",
+    "files",
+    "announce",
+    "pause",
+    "resume",
+    "recheck",
+    "move",
+    "rename",
+    "set",
+    "pause_session",
+    "resume_session",
+    "session",
+];
+
+/// Whether `message` is meant as JSON-RPC rather than a plain control
+/// request.
+pub fn is_rpc(message: &Value) -> bool {
+    message.is_array() || message.get("jsonrpc").is_some()
+}
+
+/// The answer to a line that claimed to be JSON-RPC but is not JSON.
+pub fn parse_error(e: serde_json::Error) -> Value {
+    error(Value::Null, PARSE_ERROR, format!("parse error: {}", e))
+}
+
+/// Answers a request or a batch of them, or gives `None` if no answer is
+/// due because they were all notifications.
+pub async fn answer(message: Value, session: &Arc<Session>) -> Option<Value> {
+    match message {
+        Value::Array(batch) if batch.is_empty() => {
+            Some(error(Value::Null, INVALID_REQUEST, "empty batch"))
+        }
+        Value::Array(batch) => {
+            let mut replies = Vec::new();
+            for call in batch {
+                replies.extend(answer_one(call, session).await);
+            }
+            (!replies.is_empty()).then_some(Value::Array(replies))
+        }
+        call => answer_one(call, session).await,
+    }
+}
+
+async fn answer_one(call: Value, session: &Arc<Session>) -> Option<Value> {
+    let mut call = match call {
+        Value::Object(call) => call,
+        _ => {
+            return Some(error(
+                Value::Null,
+                INVALID_REQUEST,
+                "a request must be an object",
+            ))
+        }
+    };
+    let id = call.remove("id");
+    let invalid = |message: &str| {
+        Some(error(
+            id.clone().unwrap_or_default(),
+            INVALID_REQUEST,
+            message,
+        ))
+    };
+    if call.get("jsonrpc") != Some(&json!("2.0")) {
+        return invalid("jsonrpc must be \"2.0\"");
+    }
+    let method = match call.get("method").and_then(Value::as_str) {
+        Some(method) => method.to_owned(),
+        None => return invalid("method must be a string"),
+    };
+
+    let outcome = match call.remove("params") {
+        _ if !METHODS.contains(&method.as_str()) => {
+            Err((METHOD_NOT_FOUND, format!("no method {:?}", method)))
+        }
+        None => call_method(method, Map::new(), session).await,
+        Some(Value::Object(params)) => call_method(method, params, session).await,
+        Some(_) => Err((
+            INVALID_PARAMS,
+            "params must be an object of named parameters".to_owned(),
+        )),
+    };
+
+    // Notifications are carried out but never answered.
+    let id = id?;
+    Some(match outcome {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => error(id, code, message),
+    })
+}
+
+async fn call_method(
+    method: String,
+    mut params: Map<String, Value>,
+    session: &Arc<Session>,
+) -> Result<Value, (i64, String)> {
+    params.insert("command".to_owned(), Value::String(method));
+    let request: Request = serde_json::from_value(Value::Object(params))
+        .map_err(|e| (INVALID_PARAMS, format!("invalid params: {}", e)))?;
+    match handle(request, session).await {
+        Response::Error { message } => Err((FAILED, message)),
+        response => {
+            let mut result = serde_json::to_value(response)
+                .map_err(|e| (FAILED, format!("cannot encode the result: {}", e)))?;
+            if let Some(result) = result.as_object_mut() {
+                result.remove("result");
+            }
+            Ok(result)
+        }
+    }
+}
+
+fn error(id: Value, code: i64, message: impl Into<String>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message.into() },
+    })
+}
@@ -0,0 +1,463 @@
+//! Answering control requests on behalf of the running torrents.
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tracing::{debug, info, warn};
+
+use super::transport::{self, Listener as Socket, Stream};
+use super::{
+    matches, rpc, AddOptions, Request, Response, SessionStatus, TorrentFiles, TorrentPeers,
+    TorrentRef, TorrentTrackers,
+};
+use crate::engine::{Handle, Mode, Options, SeedGoal};
+use crate::input;
+use crate::magnet::{self, Magnet};
+use crate::metainfo::Metainfo;
+use crate::session::{Session, SessionError};
+use crate::storage::sanitize::sanitize_component;
+
+/// Listens on `path` and answers requests about the torrents in `session`
+/// until the task is dropped. Fails only if the socket cannot be bound.
+pub async fn serve(path: PathBuf, session: Arc<Session>) -> io::Result<()> {
+    listen(path).await?.serve(session).await
+}
+
+/// A bound control socket, removed when dropped.
+pub struct Listener {
+    listener: Socket,
+    _cleanup: RemoveOnDrop,
+}
+
+/// Binds the control socket at `path`, so that clients can connect as soon
+/// as this returns.
+pub async fn listen(path: PathBuf) -> io::Result<Listener> {
+    let listener = transport::bind(&path).await?;
+    debug!("control socket listening on {}", path.display());
+    Ok(Listener {
+        listener,
+        _cleanup: RemoveOnDrop(path),
+    })
+}
+
+impl Listener {
+    /// Answers requests about the torrents in `session` until the task is
+    /// dropped.
+    pub async fn serve(self, session: Arc<Session>) -> io::Result<()> {
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, _)) => {
+                    let session = Arc::clone(&session);
+                    tokio::spawn(async move {
+                        if let Err(e) = connection(stream, &session).await {
+                            debug!("control connection failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("failed to accept control connection: {}", e),
+            }
+        }
+    }
+}
+
+async fn connection(stream: Stream, session: &Arc<Session>) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let reply = match serde_json::from_str::<serde_json::Value>(&line) {
+            Ok(message) if rpc::is_rpc(&message) => match rpc::answer(message, session).await {
+                Some(reply) => reply,
+                None => continue,
+            },
+            Ok(message) => serde_json::to_value(match serde_json::from_value(message) {
+                Ok(request) => handle(request, session).await,
+                Err(e) => error(format!("invalid request: {}", e)),
+            })?,
+            Err(e) if line.contains("\"jsonrpc\"") => rpc::parse_error(e),
+            Err(e) => serde_json::to_value(error(format!("invalid request: {}", e)))?,
+        };
+        let mut reply = serde_json::to_vec(&reply)?;
+        reply.push(b'\n');
+        writer.write_all(&reply).await?;
+    }
+    Ok(())
+}
+
+pub(super) async fn handle(request: Request, session: &Arc<Session>) -> Response {
+    let torrents = &session.handles();
+    match request {
+        Request::Add { torrent, options } => add(session, torrent, options).await,
+        Request::Remove { torrent } => {
+            let matching = matching(&torrent, torrents).await;
+            for (_, torrent) in &matching {
+                session.remove(&torrent.info_hash).await;
+                if let Err(e) = session.forget(&torrent.info_hash) {
+                    warn!(
+                        "cannot remove {} from the session store: {}",
+                        torrent.name, e
+                    );
+                }
+            }
+            done(&torrent, matching)
+        }
+        Request::Status { torrent } => {
+            let mut result = Vec::new();
+            for handle in torrents {
+                match handle.status().await {
+                    Some(status) if matches(torrent.as_deref(), &status) => result.push(status),
+                    _ => {}
+                }
+            }
+            Response::Status { torrents: result }
+        }
+        Request::Peers { torrent } => {
+            let mut result = Vec::new();
+            for handle in torrents {
+                let status = match handle.status().await {
+                    Some(status) if matches(torrent.as_deref(), &status) => status,
+                    _ => continue,
+                };
+                result.push(TorrentPeers {
+                    info_hash: status.info_hash,
+                    name: status.name,
+                    peers: handle.peers().await.unwrap_or_default(),
+                });
+            }
+            Response::Peers { torrents: result }
+        }
+        Request::Trackers { torrent } => {
+            let mut result = Vec::new();
+            for handle in torrents {
+                let status = match handle.status().await {
+                    Some(status) if matches(torrent.as_deref(), &status) => status,
+                    _ => continue,
+                };
+                result.push(TorrentTrackers {
+                    info_hash: status.info_hash,
+                    name: status.name,
+                    trackers: handle.trackers().await.unwrap_or_default(),
+                });
+            }
+            Response::Trackers { torrents: result }
+        }
+        Request::Files { torrent } => {
+            let mut result = Vec::new();
+            for handle in torrents {
+                let status = match handle.status().await {
+                    Some(status) if matches(torrent.as_deref(), &status) => status,
+                    _ => continue,
+                };
+                result.push(TorrentFiles {
+                    info_hash: status.info_hash,
+                    name: status.name,
+                    files: handle.files().await.unwrap_or_default(),
+                });
+            }
+            Response::Files { torrents: result }
+        }
+        Request::Announce { torrent } => {
+            let matching = matching(&torrent, torrents).await;
+            for (handle, _) in &matching {
+                handle.announce().await;
+            }
+            done(&torrent, matching)
+        }
+        Request::Pause { torrent } => {
+            let matching = matching(&torrent, torrents).await;
+            for (handle, torrent) in &matching {
+                handle.pause().await;
+                session.release(&torrent.info_hash);
+            }
+            done(&torrent, matching)
+        }
+        Request::Resume { torrent } => {
+            let matching = matching(&torrent, torrents).await;
+            for (handle, torrent) in &matching {
+                handle.resume().await;
+                session.release(&torrent.info_hash);
+            }
+            done(&torrent, matching)
+        }
+        Request::Recheck { torrent } => {
+            let matching = matching(&torrent, torrents).await;
+            for (handle, _) in &matching {
+                handle.recheck().await;
+            }
+            done(&torrent, matching)
+        }
+        Request::Move { torrent, path } => {
+            if !path.is_absolute() {
+                return error(format!("{} is not an absolute path", path.display()));
+            }
+            let matching = matching(&torrent, torrents).await;
+            for (_, torrent) in &matching {
+                if let Err(e) = session.move_torrent(&torrent.info_hash, path.clone()).await {
+                    return error(format!("cannot move {}: {}", torrent.name, e));
+                }
+            }
+            done(&torrent, matching)
+        }
+        Request::Rename { torrent, name } => {
+            let name = match sanitize_component(&name) {
+                Ok(clean) if clean == name => name,
+                Ok(_) => return error(format!("{:?} is not a portable file name", name)),
+                Err(e) => return error(e),
+            };
+            let matching = matching(&torrent, torrents).await;
+            if matching.len() > 1 {
+                return error(format!(
+                    "{:?} matches {} torrents; rename one at a time",
+                    torrent,
+                    matching.len()
+                ));
+            }
+            for (_, torrent) in &matching {
+                if let Err(e) = session
+                    .rename_torrent(&torrent.info_hash, name.clone())
+                    .await
+                {
+                    return error(format!("cannot rename {}: {}", torrent.name, e));
+                }
+            }
+            done(&torrent, matching)
+        }
+        Request::Set { torrent, settings } => {
+            let matching = matching(&torrent, torrents).await;
+            for (handle, _) in &matching {
+                handle.configure(settings.clone()).await;
+            }
+            done(&torrent, matching)
+        }
+        Request::PauseSession => {
+            let paused = session.pause().await;
+            info!("paused the session");
+            Response::Done {
+                torrents: refs(&paused).await,
+            }
+        }
+        Request::ResumeSession => {
+            let resumed = session.resume().await;
+            info!("resumed the session");
+            Response::Done {
+                torrents: refs(&resumed).await,
+            }
+        }
+        Request::Session => {
+            let transferred = session.transferred();
+            Response::Session {
+                session: SessionStatus {
+                    port: session.bound_port(),
+                    endpoints: session.bound_endpoints(),
+                    paused: session.is_paused(),
+                    port_mappings: session.port_mappings(),
+                    downloaded: transferred.downloaded,
+                    uploaded: transferred.uploaded,
+                },
+            }
+        }
+    }
+}
+
+/// Loads and starts a torrent in a daemon. Magnet links are answered
+/// straight away, as their metadata may take a while to arrive.
+async fn add(session: &Arc<Session>, torrent: String, options: AddOptions) -> Response {
+    if !session.is_daemon() {
+        return error("torrents can only be added to `rainyday daemon`");
+    }
+    if torrent == "-" {
+        return error("the daemon cannot read a torrent from standard input");
+    }
+
+    if torrent.starts_with(magnet::SCHEME) {
+        let magnet: Magnet = match torrent.parse() {
+            Ok(magnet) => magnet,
+            Err(e) => return error(e),
+        };
+        let info_hash = magnet.info_hash;
+        let added = TorrentRef {
+            info_hash,
+            name: magnet.name.unwrap_or_else(|| info_hash.to_string()),
+        };
+        let session = Arc::clone(session);
+        tokio::spawn(async move {
+            let started = match input::load(&torrent, session.config(), session.peer_id()).await {
+                Ok(metainfo) => start(&session, metainfo, &options)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = started {
+                warn!("cannot add {}: {}", torrent, e);
+            }
+        });
+        return Response::Done {
+            torrents: vec![added],
+        };
+    }
+
+    let metainfo = match input::load(&torrent, session.config(), session.peer_id()).await {
+        Ok(metainfo) => metainfo,
+        Err(e) => return error(e),
+    };
+    match start(session, metainfo, &options).await {
+        Ok(added) => Response::Done {
+            torrents: vec![added],
+        },
+        Err(e) => error(e),
+    }
+}
+
+/// Starts seeding or downloading a torrent in the daemon, and records it
+/// in the session store.
+pub(super) async fn start(
+    session: &Arc<Session>,
+    metainfo: Metainfo,
+    add: &AddOptions,
+) -> Result<TorrentRef, SessionError> {
+    let options = Options {
+        download_dir: add.download_dir.clone(),
+        download_rate: add.download_rate,
+        upload_rate: add.upload_rate,
+        sequential: add.sequential,
+        ..Options::default()
+    };
+    let goal = SeedGoal {
+        ratio: add.seed_ratio,
+        time: add.seed_time.map(Duration::from_secs),
+    };
+    let metainfo = Arc::new(metainfo);
+    let (added, _) = launch(session, Arc::clone(&metainfo), &options, goal).await?;
+    info!("added {}", added.name);
+    if let Err(e) = session.remember(&metainfo, &options, goal) {
+        warn!("cannot record {} in the session store: {}", added.name, e);
+    }
+    Ok(added)
+}
+
+/// Starts every torrent in the session store again, with the settings and
+/// pieces it had when the daemon last stopped. Torrents that were paused
+/// stay paused.
+pub async fn restore(session: &Arc<Session>) {
+    let records = match session.records() {
+        Ok(records) => records,
+        Err(e) => {
+            warn!("cannot restore the session: {}", e);
+            return;
+        }
+    };
+    for record in records {
+        let metainfo = match Metainfo::parse(&record.metainfo, session.config().pedantic) {
+            Ok(metainfo) => metainfo,
+            Err(e) => {
+                warn!("cannot restore {}: {}", record.info_hash, e);
+                continue;
+            }
+        };
+        let options = Options {
+            name: record.options.name,
+            file_priorities: record.options.file_priorities,
+            download_dir: Some(record.options.save_path),
+            download_rate: record.options.download_rate,
+            upload_rate: record.options.upload_rate,
+            sequential: record.options.sequential,
+            have: record.resume.map(|resume| resume.have),
+        };
+        let goal = record.options.seed_goal;
+        match launch(session, Arc::new(metainfo), &options, goal).await {
+            Ok((added, handle)) if record.options.paused => {
+                handle.pause().await;
+                session.release(&added.info_hash);
+                info!("restored {}, paused", added.name);
+            }
+            Ok((added, _)) => info!("restored {}", added.name),
+            Err(e) => warn!("cannot restore {}: {}", record.info_hash, e),
+        }
+    }
+}
+
+/// Starts a torrent in the daemon, logging how it ends.
+async fn launch(
+    session: &Arc<Session>,
+    metainfo: Arc<Metainfo>,
+    options: &Options,
+    goal: SeedGoal,
+) -> Result<(TorrentRef, Handle), SessionError> {
+    let added = TorrentRef {
+        info_hash: metainfo.info_hash,
+        name: metainfo.info.name.clone(),
+    };
+    let handle = session.add(metainfo, options, Mode::Seed(goal)).await?;
+
+    let (session, info_hash) = (Arc::clone(session), added.info_hash);
+    tokio::spawn(async move {
+        match session.wait(&info_hash).await {
+            Some(Ok(_)) => {
+                if let Err(e) = session.stopped(&info_hash) {
+                    warn!(%info_hash, "cannot record that the torrent stopped: {}", e);
+                }
+            }
+            Some(Err(e)) => warn!(%info_hash, "torrent stopped: {}", e),
+            None => {}
+        }
+    });
+    Ok((added, handle))
+}
+
+fn error(message: impl ToString) -> Response {
+    Response::Error {
+        message: message.to_string(),
+    }
+}
+
+/// The running torrents that `filter` picks out.
+async fn matching<'a>(filter: &str, torrents: &'a [Handle]) -> Vec<(&'a Handle, TorrentRef)> {
+    let mut result = Vec::new();
+    for handle in torrents {
+        match handle.status().await {
+            Some(status) if matches(Some(filter), &status) => result.push((
+                handle,
+                TorrentRef {
+                    info_hash: status.info_hash,
+                    name: status.name,
+                },
+            )),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Names the torrents `handles` run.
+async fn refs(handles: &[Handle]) -> Vec<TorrentRef> {
+    let mut result = Vec::new();
+    for handle in handles {
+        if let Some(status) = handle.status().await {
+            result.push(TorrentRef {
+                info_hash: status.info_hash,
+                name: status.name,
+            });
+        }
+    }
+    result
+}
+
+/// The response to a request that acted on the torrents matching `filter`.
+fn done(filter: &str, matching: Vec<(&Handle, TorrentRef)>) -> Response {
+    if matching.is_empty() {
+        return error(format!("no torrent matches {:?}", filter));
+    }
+    Response::Done {
+        torrents: matching.into_iter().map(|(_, torrent)| torrent).collect(),
+    }
+}
+
+struct RemoveOnDrop(PathBuf);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
@@ -0,0 +1,58 @@
+//! What the control interface runs over: a Unix socket at the configured
+//! path, or on Windows a TCP socket on the loopback address, whose port is
+//! written to that path instead.
+
+use std::io;
+use std::path::Path;
+
+#[cfg(windows)]
+pub use tokio::net::{TcpListener as Listener, TcpStream as Stream};
+#[cfg(unix)]
+pub use tokio::net::{UnixListener as Listener, UnixStream as Stream};
+
+/// Connects to the process listening at `path`.
+#[cfg(unix)]
+pub async fn connect(path: &Path) -> io::Result<Stream> {
+    Stream::connect(path).await
+}
+
+/// Connects to the process whose port is written at `path`.
+#[cfg(windows)]
+pub async fn connect(path: &Path) -> io::Result<Stream> {
+    let port: u16 = std::fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Stream::connect((std::net::Ipv4Addr::LOCALHOST, port)).await
+}
+
+/// Listens at `path`, replacing what a process that is no longer running
+/// left there but refusing to take over from one that is.
+pub async fn bind(path: &Path) -> io::Result<Listener> {
+    if path.exists() {
+        if connect(path).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another rainyday is already listening",
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    listen(path).await
+}
+
+#[cfg(unix)]
+async fn listen(path: &Path) -> io::Result<Listener> {
+    Listener::bind(path)
+}
+
+#[cfg(windows)]
+async fn listen(path: &Path) -> io::Result<Listener> {
+    let listener = Listener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).await?;
+    let port = listener.local_addr()?.port();
+    crate::fsutil::write_atomic(path, format!("{}\n", port).as_bytes())?;
+    Ok(listener)
+}
@@ -0,0 +1,219 @@
+//! Building `.torrent` files from data on disk.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+use crate::bencode::Value;
+use crate::hash;
+
+/// Smallest and largest piece lengths chosen automatically.
+const MIN_PIECE_LENGTH: u32 = 16 * 1024;
+const MAX_PIECE_LENGTH: u32 = 16 * 1024 * 1024;
+/// Roughly how many pieces an automatically sized torrent should have.
+const TARGET_PIECES: u64 = 1500;
+
+#[derive(Debug, Error)]
+pub enum CreateError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("{0} contains no files")]
+    Empty(PathBuf),
+    #[error("piece length must be a power of two of at least 16 KiB")]
+    InvalidPieceLength,
+    #[error("{0} has no usable file name")]
+    InvalidName(PathBuf),
+}
+
+/// Everything about a new torrent other than its data.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CreateOptions {
+    /// Tracker URLs grouped in tiers.
+    pub trackers: Vec<Vec<String>>,
+    pub web_seeds: Vec<String>,
+    /// Chosen from the total size when absent.
+    pub piece_length: Option<u32>,
+    pub private: bool,
+    pub comment: Option<String>,
+    /// Overrides the file or directory name.
+    pub name: Option<String>,
+}
+
+/// Hashes the file or directory at `path` and returns the encoded
+/// `.torrent` describing it.
+pub fn create(path: &Path, options: &CreateOptions) -> Result<Vec<u8>, CreateError> {
+    let name = match &options.name {
+        Some(name) => name.clone(),
+        None => path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| CreateError::InvalidName(path.to_path_buf()))?
+            .to_string(),
+    };
+
+    let single_file = path.is_file();
+    let files = if single_file {
+        vec![(PathBuf::new(), fs::metadata(path)?.len())]
+    } else {
+        let mut files = Vec::new();
+        collect_files(path, Path::new(""), &mut files)?;
+        files.sort();
+        if files.is_empty() {
+            return Err(CreateError::Empty(path.to_path_buf()));
+        }
+        files
+    };
+
+    let total: u64 = files.iter().map(|(_, length)| length).sum();
+    let piece_length = match options.piece_length {
+        Some(length) if length >= MIN_PIECE_LENGTH && length.is_power_of_two() => length,
+        Some(_) => return Err(CreateError::InvalidPieceLength),
+        None => auto_piece_length(total),
+    };
+
+    let mut hasher = PieceHasher::new(piece_length);
+    for (relative, _) in &files {
+        let mut file = if single_file {
+            File::open(path)?
+        } else {
+            File::open(path.join(relative))?
+        };
+        hasher.update_from(&mut file)?;
+    }
+    let pieces = hasher.finish();
+
+    let mut info = BTreeMap::new();
+    info.insert(b"name".to_vec(), Value::from(name.as_str()));
+    info.insert(
+        b"piece length".to_vec(),
+        Value::Integer(piece_length as i64),
+    );
+    info.insert(b"pieces".to_vec(), Value::Bytes(pieces));
+    if options.private {
+        info.insert(b"private".to_vec(), Value::Integer(1));
+    }
+    if single_file {
+        info.insert(b"length".to_vec(), Value::Integer(total as i64));
+    } else {
+        let entries = files
+            .iter()
+            .map(|(relative, length)| {
+                let mut entry = BTreeMap::new();
+                entry.insert(b"length".to_vec(), Value::Integer(*length as i64));
+                let components = relative
+                    .components()
+                    .map(|c| Value::from(c.as_os_str().to_string_lossy().as_ref()))
+                    .collect();
+                entry.insert(b"path".to_vec(), Value::List(components));
+                Value::Dict(entry)
+            })
+            .collect();
+        info.insert(b"files".to_vec(), Value::List(entries));
+    }
+
+    let mut root = BTreeMap::new();
+    if let Some(first) = options.trackers.iter().flatten().next() {
+        root.insert(b"announce".to_vec(), Value::from(first.as_str()));
+    }
+    if options.trackers.iter().flatten().count() > 1 {
+        let tiers = options
+            .trackers
+            .iter()
+            .map(|tier| Value::List(tier.iter().map(|url| Value::from(url.as_str())).collect()))
+            .collect();
+        root.insert(b"announce-list".to_vec(), Value::List(tiers));
+    }
+    if !options.web_seeds.is_empty() {
+        let urls = options
+            .web_seeds
+            .iter()
+            .map(|url| Value::from(url.as_str()))
+            .collect();
+        root.insert(b"url-list".to_vec(), Value::List(urls));
+    }
+    if let Some(comment) = &options.comment {
+        root.insert(b"comment".to_vec(), Value::from(comment.as_str()));
+    }
+    root.insert(
+        b"created by".to_vec(),
+        Value::from(concat!("rainyday/", env!("CARGO_PKG_VERSION"))),
+    );
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    root.insert(b"creation date".to_vec(), Value::Integer(now));
+    root.insert(b"info".to_vec(), Value::Dict(info));
+
+    Ok(Value::Dict(root).encode())
+}
+
+/// Picks the power of two giving roughly [`TARGET_PIECES`] pieces.
+fn auto_piece_length(total: u64) -> u32 {
+    let ideal = (total / TARGET_PIECES).max(1);
+    let length = ideal.next_power_of_two().min(MAX_PIECE_LENGTH as u64) as u32;
+    length.max(MIN_PIECE_LENGTH)
+}
+
+/// Lists the regular files under `dir`, relative to the torrent root.
+fn collect_files(
+    dir: &Path,
+    relative: &Path,
+    files: &mut Vec<(PathBuf, u64)>,
+) -> Result<(), CreateError> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        let meta = fs::metadata(entry.path())?;
+        if meta.is_dir() {
+            collect_files(&entry.path(), &path, files)?;
+        } else if meta.is_file() {
+            files.push((path, meta.len()));
+        }
+    }
+    Ok(())
+}
+
+/// Hashes a stream of bytes in pieces, regardless of file boundaries.
+struct PieceHasher {
+    piece_length: usize,
+    buffer: Vec<u8>,
+    pieces: Vec<u8>,
+}
+
+impl PieceHasher {
+    fn new(piece_length: u32) -> Self {
+        Self {
+            piece_length: piece_length as usize,
+            buffer: Vec::with_capacity(piece_length as usize),
+            pieces: Vec::new(),
+        }
+    }
+
+    fn update_from(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        loop {
+            let start = self.buffer.len();
+            self.buffer.resize(self.piece_length, 0);
+            let n = reader.read(&mut self.buffer[start..])?;
+            self.buffer.truncate(start + n);
+            if n == 0 {
+                return Ok(());
+            }
+            if self.buffer.len() == self.piece_length {
+                self.pieces.extend_from_slice(&hash::sha1(&self.buffer));
+                self.buffer.clear();
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if !self.buffer.is_empty() {
+            self.pieces.extend_from_slice(&hash::sha1(&self.buffer));
+        }
+        self.pieces
+    }
+}
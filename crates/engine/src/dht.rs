@@ -0,0 +1,188 @@
+//! A minimal DHT client (BEP 5), used to find peers for torrents added by
+//! magnet link.
+//!
+//! Only outgoing `get_peers` lookups are made; rainyday does not yet answer
+//! queries or keep a routing table between lookups.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::net::lookup_host;
+use tokio::time::{timeout_at, Instant};
+use tracing::debug;
+
+use crate::bencode::{self, Value};
+use crate::config::PortRange;
+use crate::info_hash::InfoHash;
+use crate::interface::{self, Interface};
+use crate::tracker::compact_peers_v4;
+
+/// Well-known nodes to start lookups from.
+pub const BOOTSTRAP_NODES: &[&str] = &[
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+];
+
+/// Nodes queried at once in each round of a lookup.
+const ALPHA: usize = 8;
+/// How long each round waits for answers.
+const ROUND_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_ROUNDS: usize = 10;
+/// A lookup stops early once it has found this many peers.
+const WANTED_PEERS: usize = 50;
+/// Length of a node in the compact `nodes` format: ID, IPv4 address, port.
+const COMPACT_NODE_LEN: usize = 26;
+
+#[derive(Debug, Error)]
+pub enum DhtError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("none of the bootstrap nodes could be resolved")]
+    NoBootstrapNodes,
+}
+
+/// Looks up peers for `info_hash`, starting from `bootstrap`, from
+/// `interface` if there is one and a port in `source_ports` if given.
+pub async fn get_peers(
+    info_hash: &InfoHash,
+    bootstrap: &[impl AsRef<str>],
+    interface: Option<&Interface>,
+    source_ports: Option<PortRange>,
+) -> Result<Vec<SocketAddr>, DhtError> {
+    let socket = interface::udp(interface, source_ports, false).await?;
+    let node_id: [u8; 20] = rand::random();
+
+    let mut start = Vec::new();
+    for host in bootstrap {
+        let host = host.as_ref();
+        match lookup_host(host).await {
+            Ok(addrs) => start.extend(addrs.filter(SocketAddr::is_ipv4)),
+            Err(e) => debug!(host, "cannot resolve bootstrap node: {}", e),
+        }
+    }
+    if start.is_empty() {
+        return Err(DhtError::NoBootstrapNodes);
+    }
+
+    // Nodes still to ask, closest to the info hash first.
+    let mut candidates: BTreeMap<[u8; 20], SocketAddr> = BTreeMap::new();
+    let mut queried = HashSet::new();
+    let mut peers = Vec::new();
+    let mut transaction = 0u16;
+
+    for round in 0..MAX_ROUNDS {
+        let targets: Vec<SocketAddr> = if round == 0 {
+            start.clone()
+        } else {
+            candidates
+                .values()
+                .filter(|addr| !queried.contains(*addr))
+                .take(ALPHA)
+                .copied()
+                .collect()
+        };
+        if targets.is_empty() {
+            break;
+        }
+
+        let mut pending = HashMap::new();
+        for addr in targets {
+            queried.insert(addr);
+            transaction = transaction.wrapping_add(1);
+            let tid = transaction.to_be_bytes();
+            let query = get_peers_query(&node_id, info_hash, &tid);
+            if let Err(e) = socket.send_to(&query, addr).await {
+                debug!(%addr, "DHT query failed: {}", e);
+                continue;
+            }
+            pending.insert(tid.to_vec(), addr);
+        }
+
+        let deadline = Instant::now() + ROUND_TIMEOUT;
+        let mut buf = [0u8; 2048];
+        while !pending.is_empty() {
+            let (len, from) = match timeout_at(deadline, socket.recv_from(&mut buf)).await {
+                Ok(Ok(received)) => received,
+                Ok(Err(e)) => {
+                    debug!("DHT receive failed: {}", e);
+                    continue;
+                }
+                Err(_) => break,
+            };
+            let response = match bencode::decode(&buf[..len]) {
+                Ok(response) => response,
+                Err(_) => continue,
+            };
+            let tid = response
+                .get("t")
+                .and_then(Value::as_bytes)
+                .unwrap_or_default();
+            if pending.get(tid) != Some(&from) {
+                continue;
+            }
+            pending.remove(tid);
+
+            let body = match response.get("r") {
+                Some(body) => body,
+                None => continue,
+            };
+            if let Some(values) = body.get("values").and_then(Value::as_list) {
+                for value in values.iter().filter_map(Value::as_bytes) {
+                    peers.extend(compact_peers_v4(value));
+                }
+            }
+            if let Some(nodes) = body.get("nodes").and_then(Value::as_bytes) {
+                for node in nodes.chunks_exact(COMPACT_NODE_LEN) {
+                    let mut id = [0u8; 20];
+                    id.copy_from_slice(&node[..20]);
+                    if let Some(&addr) = compact_peers_v4(&node[20..]).first() {
+                        candidates.insert(distance(&id, info_hash), addr);
+                    }
+                }
+            }
+        }
+
+        peers.sort_unstable();
+        peers.dedup();
+        debug!(
+            round,
+            peers = peers.len(),
+            nodes = candidates.len(),
+            "DHT lookup"
+        );
+        if peers.len() >= WANTED_PEERS {
+            break;
+        }
+    }
+
+    Ok(peers)
+}
+
+fn get_peers_query(node_id: &[u8; 20], info_hash: &InfoHash, tid: &[u8]) -> Vec<u8> {
+    let mut args = BTreeMap::new();
+    args.insert(b"id".to_vec(), Value::Bytes(node_id.to_vec()));
+    args.insert(
+        b"info_hash".to_vec(),
+        Value::Bytes(info_hash.as_bytes().to_vec()),
+    );
+
+    let mut query = BTreeMap::new();
+    query.insert(b"a".to_vec(), Value::Dict(args));
+    query.insert(b"q".to_vec(), Value::from("get_peers"));
+    query.insert(b"t".to_vec(), Value::Bytes(tid.to_vec()));
+    query.insert(b"y".to_vec(), Value::from("q"));
+    Value::Dict(query).encode()
+}
+
+/// XOR distance between a node and the info hash being looked up.
+fn distance(id: &[u8; 20], info_hash: &InfoHash) -> [u8; 20] {
+    let mut distance = [0u8; 20];
+    for (i, byte) in distance.iter_mut().enumerate() {
+        *byte = id[i] ^ info_hash.as_bytes()[i];
+    }
+    distance
+}
@@ -0,0 +1,123 @@
+//! Changing a `.torrent` file's trackers, web seeds and other details
+//! without touching the data it describes.
+
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+
+use crate::bencode::{self, BencodeError, Value};
+use crate::metainfo::{self, Metainfo, MetainfoError};
+
+#[derive(Debug, Error)]
+pub enum EditError {
+    #[error("{0}")]
+    Metainfo(#[from] MetainfoError),
+    #[error("metainfo is not valid bencode: {0}")]
+    Bencode(#[from] BencodeError),
+    #[error("changing the private flag changes the info hash; use --force to do it anyway")]
+    ChangesInfoHash,
+}
+
+/// The changes to make. Anything left at its default is kept as it is.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Edit {
+    /// Tiers of tracker URLs to add after the existing ones.
+    pub add_trackers: Vec<Vec<String>>,
+    /// Tracker URLs to remove from every tier.
+    pub remove_trackers: Vec<String>,
+    pub add_web_seeds: Vec<String>,
+    pub remove_web_seeds: Vec<String>,
+    /// A new comment; an empty one removes it.
+    pub comment: Option<String>,
+    /// A new value for the private flag, which is part of the info
+    /// dictionary.
+    pub private: Option<bool>,
+    /// Allow changes that alter the info hash.
+    pub force: bool,
+}
+
+/// Applies `edit` to the `.torrent` file in `bytes`, returning the new
+/// file. Keys rainyday does not know are kept, and the info dictionary is
+/// copied byte for byte unless `edit` changes it.
+pub fn edit(bytes: &[u8], edit: &Edit) -> Result<Vec<u8>, EditError> {
+    let metainfo = Metainfo::from_bytes(bytes)?;
+    let mut root = match bencode::decode(bytes)? {
+        Value::Dict(root) => root,
+        _ => unreachable!("metainfo was parsed from a dictionary"),
+    };
+
+    let mut tiers = metainfo.trackers();
+    for tier in &mut tiers {
+        tier.retain(|url| !edit.remove_trackers.contains(url));
+    }
+    tiers.extend(edit.add_trackers.iter().cloned());
+    tiers.retain(|tier| !tier.is_empty());
+    set_trackers(&mut root, &tiers);
+
+    let mut web_seeds = metainfo.url_list.clone();
+    web_seeds.retain(|url| !edit.remove_web_seeds.contains(url));
+    for url in &edit.add_web_seeds {
+        if !web_seeds.contains(url) {
+            web_seeds.push(url.clone());
+        }
+    }
+    root.remove(b"url-list".as_slice());
+    if !web_seeds.is_empty() {
+        root.insert(b"url-list".to_vec(), strings(&web_seeds));
+    }
+
+    match edit.comment.as_deref() {
+        Some("") => {
+            root.remove(b"comment".as_slice());
+        }
+        Some(comment) => {
+            root.insert(b"comment".to_vec(), Value::from(comment));
+        }
+        None => {}
+    }
+
+    let mut info_bytes = metainfo.info_bytes;
+    match edit.private {
+        Some(private) if private != metainfo.info.private => {
+            if !edit.force {
+                return Err(EditError::ChangesInfoHash);
+            }
+            let mut info = match bencode::decode(&info_bytes)? {
+                Value::Dict(info) => info,
+                _ => unreachable!("info was parsed as a dictionary"),
+            };
+            if private {
+                info.insert(b"private".to_vec(), Value::Integer(1));
+            } else {
+                info.remove(b"private".as_slice());
+            }
+            info_bytes = Value::Dict(info).encode();
+        }
+        _ => {}
+    }
+
+    Ok(metainfo::encode(&root, &info_bytes))
+}
+
+/// Writes `tiers` the way `create` would: `announce` for the first URL,
+/// and an announce list only when there is more than one.
+fn set_trackers(root: &mut BTreeMap<Vec<u8>, Value>, tiers: &[Vec<String>]) {
+    root.remove(b"announce".as_slice());
+    root.remove(b"announce-list".as_slice());
+    if let Some(first) = tiers.iter().flatten().next() {
+        root.insert(b"announce".to_vec(), Value::from(first.as_str()));
+    }
+    if tiers.iter().flatten().count() > 1 {
+        let tiers = tiers.iter().map(|tier| strings(tier)).collect();
+        root.insert(b"announce-list".to_vec(), Value::List(tiers));
+    }
+}
+
+fn strings(values: &[String]) -> Value {
+    Value::List(
+        values
+            .iter()
+            .map(|value| Value::from(value.as_str()))
+            .collect(),
+    )
+}
@@ -0,0 +1,1999 @@
+//! Drives a single torrent: announcing, connecting to peers, requesting and
+//! serving blocks, and writing verified pieces to disk.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep_until, timeout, Instant};
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use crate::bitfield::Bitfield;
+use crate::blocklist::Blocklist;
+use crate::config::Config;
+use crate::control::Settings;
+use crate::event::{Event, EventBus};
+use crate::files::FilePriority;
+use crate::hooks::Hook;
+use crate::info_hash::InfoHash;
+use crate::interface::{self, Endpoint};
+use crate::limits::{ConnectionPermit, Limits};
+use crate::metainfo::Metainfo;
+use crate::peer::connection::{self, dial};
+use crate::peer::fast::{allowed_fast_set, availability_message, ALLOWED_FAST_COUNT};
+use crate::peer::holepunch;
+use crate::peer::state::PeerState;
+use crate::peer::task::{self, Timeouts};
+use crate::peer::transport::{PeerStream, PeerTransport};
+use crate::peer::PeerId;
+use crate::pool::BLOCKS;
+use crate::protocol::extension::{ExtendedHandshake, HANDSHAKE_ID};
+use crate::protocol::holepunch::{
+    HolepunchMessage, EXTENSION_NAME as HOLEPUNCH_EXTENSION, LOCAL_ID as HOLEPUNCH_ID,
+};
+use crate::protocol::metadata::{self, MetadataMessage};
+use crate::protocol::{BlockRequest, Handshake, Message, MessageCodec};
+use crate::rate::RateMeter;
+use crate::session::move_completed;
+use crate::stats::Counters;
+use crate::storage::{part, space, FileStorage, IoHints, Layout, MovableStorage, StorageError};
+use crate::swarm::{ExternalIp, Swarm};
+use crate::torrent::{PieceOutcome, Torrent};
+use crate::tracker::{Announce, AnnounceEvent, AnnounceResponse, Tracker, TrackerError};
+use crate::verify::{recheck, verify_piece_async};
+
+/// Requests kept outstanding with each peer.
+const PIPELINE_DEPTH: usize = 16;
+/// Peers we upload to at once.
+const UPLOAD_SLOTS: usize = 4;
+/// How often to top up connections from the known peers.
+const DIAL_INTERVAL: Duration = Duration::from_secs(5);
+/// How long dialling a peer or its incoming handshake may take.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for the final announces before giving up on them.
+const STOP_ANNOUNCE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Messages buffered between the engine and each connection.
+const CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Debug, Error)]
+pub enum EngineError {
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
+    #[error("paused: {0}")]
+    DiskFull(StorageError),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("cannot set up the tracker client: {0}")]
+    Tracker(#[from] TrackerError),
+}
+
+/// What a finished download amounted to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Summary {
+    /// Directory the data ended up in.
+    pub save_path: PathBuf,
+    /// Whether every wanted piece was downloaded.
+    pub complete: bool,
+    pub downloaded: u64,
+    pub uploaded: u64,
+}
+
+/// Something that happened outside the engine's own task.
+enum Input {
+    /// A peer connected to the session's listener and asked for this
+    /// torrent.
+    Incoming {
+        stream: PeerStream,
+        addr: SocketAddr,
+        handshake: Handshake,
+    },
+    Connected {
+        addr: SocketAddr,
+        handshake: Handshake,
+        outbound: mpsc::Sender<Message>,
+        incoming: bool,
+    },
+    Message {
+        addr: SocketAddr,
+        message: Message,
+    },
+    Disconnected {
+        addr: SocketAddr,
+        reason: Option<String>,
+    },
+    DialFailed {
+        addr: SocketAddr,
+    },
+    AddPeers(Vec<SocketAddr>),
+    /// The trackers that answered, one per listen endpoint at most, and
+    /// the errors from those tried before them that answered for none.
+    Announced(Vec<(String, AnnounceResponse)>, Vec<(String, TrackerError)>),
+    Status(oneshot::Sender<Status>),
+    Peers(oneshot::Sender<Vec<PeerInfo>>),
+    Trackers(oneshot::Sender<Vec<TrackerInfo>>),
+    Pieces(oneshot::Sender<Bitfield>),
+    /// Flush the data, then answer with the pieces it holds.
+    Checkpoint(oneshot::Sender<Bitfield>),
+    Files(oneshot::Sender<Vec<FileInfo>>),
+    Configure(Settings),
+    Announce,
+    Pause,
+    Resume,
+    Recheck,
+    Rechecked(Result<Bitfield, StorageError>),
+    /// Move the data to the same place under another directory, answering
+    /// once it is there.
+    Move(PathBuf, oneshot::Sender<Result<(), StorageError>>),
+    /// Rename the top-level file or directory, answering once it is done.
+    Rename(String, oneshot::Sender<Result<(), StorageError>>),
+    /// Stop, answering once stopped with the pieces the flushed data holds.
+    Shutdown(Option<oneshot::Sender<Bitfield>>),
+}
+
+struct Peer {
+    state: PeerState,
+    /// Held for as long as the peer is connected.
+    _permit: ConnectionPermit,
+    outbound: mpsc::Sender<Message>,
+    incoming: bool,
+    transferred: Counters,
+    download_rate: RateMeter,
+    upload_rate: RateMeter,
+}
+
+/// When the engine stops of its own accord.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Stop as soon as every wanted piece is on disk.
+    Download,
+    /// Keep serving peers once finished, until the goal is met or the
+    /// engine is shut down.
+    Seed(SeedGoal),
+}
+
+/// Choices made for one torrent rather than for the whole client.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    /// Name of the top-level file or directory, instead of the torrent's.
+    pub name: Option<String>,
+    /// Per-file priorities, indexed like the torrent's files. Empty means
+    /// every file is wanted.
+    pub file_priorities: Vec<FilePriority>,
+    /// Where to keep the finished download, instead of the configured
+    /// download directory.
+    pub download_dir: Option<PathBuf>,
+    /// Most bytes per second this torrent may download, within the
+    /// session's limit.
+    pub download_rate: Option<u64>,
+    /// Most bytes per second this torrent may upload, within the session's
+    /// limit.
+    pub upload_rate: Option<u64>,
+    /// Download pieces in order rather than rarest first.
+    pub sequential: bool,
+    /// Pieces an earlier run verified, trusted instead of checking the
+    /// data on disk again.
+    pub have: Option<Bitfield>,
+}
+
+impl Options {
+    /// The configuration this torrent runs with.
+    fn configure(&self, config: &Config) -> Config {
+        let mut config = config.clone();
+        if let Some(dir) = &self.download_dir {
+            config.storage.download_dir = dir.clone();
+        }
+        config
+    }
+}
+
+/// How much seeding is enough. Whichever limit is reached first ends it;
+/// with neither set, seeding goes on until shut down.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SeedGoal {
+    /// Share ratio to reach, as reported by [`Status::ratio`].
+    pub ratio: Option<f64>,
+    /// How long to seed for once the download is complete.
+    pub time: Option<Duration>,
+}
+
+impl SeedGoal {
+    pub fn is_met(&self, ratio: Option<f64>, seeded: Duration) -> bool {
+        let by_ratio = match (self.ratio, ratio) {
+            (Some(goal), Some(ratio)) => ratio >= goal,
+            _ => false,
+        };
+        let by_time = self.time.is_some_and(|goal| seeded >= goal);
+        by_ratio || by_time
+    }
+}
+
+/// What a torrent is doing at the moment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Downloading,
+    Seeding,
+    Paused,
+    /// Verifying the data on disk.
+    Checking,
+    /// Stopped by an error, such as the disk filling up.
+    Error,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            State::Downloading => "downloading",
+            State::Seeding => "seeding",
+            State::Paused => "paused",
+            State::Checking => "checking",
+            State::Error => "error",
+        })
+    }
+}
+
+/// A snapshot of a running torrent.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Status {
+    pub info_hash: InfoHash,
+    pub name: String,
+    pub state: State,
+    /// Bytes of wanted data verified and on disk.
+    pub done: u64,
+    /// Bytes of data wanted in total.
+    pub wanted: u64,
+    pub downloaded: u64,
+    pub uploaded: u64,
+    /// Bytes per second, averaged over the last few seconds.
+    pub download_rate: u64,
+    pub upload_rate: u64,
+    pub peers: usize,
+    /// Connected peers that have every piece.
+    pub seeds: usize,
+    /// Peers refused because their address is on the blocklist.
+    #[serde(default)]
+    pub blocked: u64,
+    /// What stopped the torrent, when `state` is `Error`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Status {
+    /// Fraction of the wanted data that is done, from 0 to 1.
+    pub fn progress(&self) -> f64 {
+        if self.wanted == 0 {
+            1.0
+        } else {
+            self.done as f64 / self.wanted as f64
+        }
+    }
+
+    /// Time left at the current download rate, if it is moving at all.
+    pub fn eta(&self) -> Option<Duration> {
+        let left = self.wanted.saturating_sub(self.done);
+        if left == 0 {
+            return Some(Duration::ZERO);
+        }
+        if self.download_rate == 0 {
+            return None;
+        }
+        Some(Duration::from_secs(left.div_ceil(self.download_rate)))
+    }
+
+    /// Bytes uploaded per byte downloaded. A torrent that was seeded from
+    /// data already on disk is measured against the data it has instead.
+    pub fn ratio(&self) -> Option<f64> {
+        let base = if self.downloaded > 0 {
+            self.downloaded
+        } else {
+            self.done
+        };
+        if base == 0 {
+            None
+        } else {
+            Some(self.uploaded as f64 / base as f64)
+        }
+    }
+}
+
+/// A connected peer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub addr: SocketAddr,
+    pub client: Option<String>,
+    /// The peer connected to us rather than the other way round.
+    pub incoming: bool,
+    pub encrypted: bool,
+    pub am_choking: bool,
+    pub am_interested: bool,
+    pub peer_choking: bool,
+    pub peer_interested: bool,
+    /// Fraction of the torrent the peer has, from 0 to 1.
+    pub progress: f64,
+    /// Bytes per second we receive from the peer.
+    pub download_rate: u64,
+    /// Bytes per second we send to the peer.
+    pub upload_rate: u64,
+    /// Bytes received from the peer since it connected.
+    #[serde(default)]
+    pub downloaded: u64,
+    /// Bytes sent to the peer since it connected.
+    #[serde(default)]
+    pub uploaded: u64,
+}
+
+impl PeerInfo {
+    /// The connection's state in the compact letters most clients use:
+    /// `D`/`d` downloading or wanting to, `U`/`u` likewise for uploading,
+    /// `I` incoming and `E` encrypted.
+    pub fn flags(&self) -> String {
+        let mut flags = String::new();
+        if self.am_interested {
+            flags.push(if self.peer_choking { 'd' } else { 'D' });
+        }
+        if self.peer_interested {
+            flags.push(if self.am_choking { 'u' } else { 'U' });
+        }
+        if self.incoming {
+            flags.push('I');
+        }
+        if self.encrypted {
+            flags.push('E');
+        }
+        flags
+    }
+}
+
+/// How announcing to one tracker has gone.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackerState {
+    /// Not announced to yet, or only backups of it were needed.
+    Idle,
+    Working,
+    Failed(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackerInfo {
+    pub url: String,
+    pub state: TrackerState,
+    /// Peers returned by the last successful announce.
+    pub peers: usize,
+    pub seeders: Option<u32>,
+    pub leechers: Option<u32>,
+}
+
+/// One of a torrent's files.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileInfo {
+    /// Path within the torrent.
+    pub path: PathBuf,
+    pub length: u64,
+    /// Bytes of the file in pieces that are verified and on disk.
+    pub done: u64,
+    pub priority: FilePriority,
+}
+
+/// Controls a torrent running in the background.
+#[derive(Clone, Debug)]
+pub struct Handle {
+    input: mpsc::Sender<Input>,
+}
+
+impl Handle {
+    /// Whether the torrent is still running.
+    pub fn is_running(&self) -> bool {
+        !self.input.is_closed()
+    }
+
+    /// The torrent's current status, or `None` once it has stopped.
+    pub async fn status(&self) -> Option<Status> {
+        let (tx, rx) = oneshot::channel();
+        self.input.send(Input::Status(tx)).await.ok()?;
+        rx.await.ok()
+    }
+
+    pub async fn peers(&self) -> Option<Vec<PeerInfo>> {
+        let (tx, rx) = oneshot::channel();
+        self.input.send(Input::Peers(tx)).await.ok()?;
+        rx.await.ok()
+    }
+
+    pub async fn trackers(&self) -> Option<Vec<TrackerInfo>> {
+        let (tx, rx) = oneshot::channel();
+        self.input.send(Input::Trackers(tx)).await.ok()?;
+        rx.await.ok()
+    }
+
+    /// The pieces verified and on disk.
+    pub async fn pieces(&self) -> Option<Bitfield> {
+        let (tx, rx) = oneshot::channel();
+        self.input.send(Input::Pieces(tx)).await.ok()?;
+        rx.await.ok()
+    }
+
+    /// Flushes the torrent's data to disk, returning the pieces it now
+    /// safely holds.
+    pub async fn checkpoint(&self) -> Option<Bitfield> {
+        let (tx, rx) = oneshot::channel();
+        self.input.send(Input::Checkpoint(tx)).await.ok()?;
+        rx.await.ok()
+    }
+
+    pub async fn files(&self) -> Option<Vec<FileInfo>> {
+        let (tx, rx) = oneshot::channel();
+        self.input.send(Input::Files(tx)).await.ok()?;
+        rx.await.ok()
+    }
+
+    /// Changes the torrent's settings while it runs.
+    pub async fn configure(&self, settings: Settings) {
+        let _ = self.input.send(Input::Configure(settings)).await;
+    }
+
+    /// Asks the trackers for peers now rather than when they next expect
+    /// to hear from us.
+    pub async fn announce(&self) {
+        let _ = self.input.send(Input::Announce).await;
+    }
+
+    /// Asks the torrent to disconnect from its peers and tell the trackers
+    /// it has stopped, keeping everything it has downloaded.
+    pub async fn pause(&self) {
+        let _ = self.input.send(Input::Pause).await;
+    }
+
+    pub async fn resume(&self) {
+        let _ = self.input.send(Input::Resume).await;
+    }
+
+    /// Adds peers to dial, as if a tracker had returned them.
+    pub async fn add_peers(&self, peers: Vec<SocketAddr>) {
+        let _ = self.input.send(Input::AddPeers(peers)).await;
+    }
+
+    /// Asks the torrent to verify its data on disk again, e.g. after files
+    /// were restored from a backup.
+    pub async fn recheck(&self) {
+        let _ = self.input.send(Input::Recheck).await;
+    }
+
+    /// Moves the torrent's data to the same place under `dir`, holding off
+    /// disk I/O until it is there. `None` if the torrent has stopped.
+    pub async fn move_to(&self, dir: PathBuf) -> Option<Result<(), StorageError>> {
+        let (tx, rx) = oneshot::channel();
+        self.input.send(Input::Move(dir, tx)).await.ok()?;
+        rx.await.ok()
+    }
+
+    /// Renames the torrent's single file or top-level directory.
+    pub async fn rename(&self, name: String) -> Option<Result<(), StorageError>> {
+        let (tx, rx) = oneshot::channel();
+        self.input.send(Input::Rename(name, tx)).await.ok()?;
+        rx.await.ok()
+    }
+
+    /// Asks the torrent to announce that it is leaving and stop.
+    pub async fn shutdown(&self) {
+        let _ = self.input.send(Input::Shutdown(None)).await;
+    }
+
+    /// Stops the torrent as [`shutdown`](Self::shutdown) does, but waits
+    /// for it to, returning the pieces its data holds once flushed. `None`
+    /// if it had already stopped, or failed to stop cleanly.
+    pub async fn stop(&self) -> Option<Bitfield> {
+        let (tx, rx) = oneshot::channel();
+        self.input.send(Input::Shutdown(Some(tx))).await.ok()?;
+        rx.await.ok()
+    }
+
+    /// Hands over an incoming connection whose handshake asked for this
+    /// torrent. Our side of the handshake has not been sent yet.
+    pub(crate) async fn incoming(
+        &self,
+        stream: PeerStream,
+        addr: SocketAddr,
+        handshake: Handshake,
+    ) {
+        let incoming = Input::Incoming {
+            stream,
+            addr,
+            handshake,
+        };
+        let _ = self.input.send(incoming).await;
+    }
+}
+
+/// What the torrents of one session have in common.
+#[derive(Clone, Debug)]
+pub struct Shared {
+    pub events: EventBus,
+    /// The port the session accepts peer connections on.
+    pub port: u16,
+    /// Every address and port the session accepts peer connections on.
+    pub endpoints: Vec<Endpoint>,
+    pub limits: Limits,
+    pub blocklist: Blocklist,
+    /// What the session transferred, which each torrent's transfers are
+    /// added to.
+    pub counters: Arc<Counters>,
+    /// Our public address, as far as the session knows it.
+    pub external_ip: ExternalIp,
+    /// The peer ID every torrent of the session handshakes with.
+    pub peer_id: PeerId,
+    /// How peers are dialled.
+    pub transport: Arc<dyn PeerTransport>,
+    /// Faults to inject into each torrent's storage.
+    #[cfg(feature = "faults")]
+    pub faults: Option<Arc<crate::faults::Faults>>,
+}
+
+/// Starts running the torrent described by `metainfo` in the background.
+/// It runs until `mode` says to stop or it is shut down through the handle.
+pub async fn start(
+    metainfo: Arc<Metainfo>,
+    config: &Config,
+    options: &Options,
+    shared: &Shared,
+    mode: Mode,
+) -> Result<(Handle, JoinHandle<Result<Summary, EngineError>>), EngineError> {
+    let mut engine = Engine::new(metainfo, config, options, shared).await?;
+    let handle = Handle {
+        input: engine.input_tx.clone(),
+    };
+    engine.hook(Hook::Added, None);
+    let task = tokio::spawn(async move {
+        let result = engine.run(mode).await;
+        match &result {
+            Ok(_) => engine.hook(Hook::Stopped, None),
+            Err(e) => engine.hook(Hook::Error, Some(e.to_string())),
+        }
+        result
+    });
+    Ok((handle, task))
+}
+
+struct Engine {
+    config: Config,
+    torrent: Torrent,
+    storage: Arc<MovableStorage>,
+    events: EventBus,
+    /// One per listen endpoint, if several are configured.
+    announcers: Vec<Announcer>,
+    /// How peers are dialled.
+    transport: Arc<dyn PeerTransport>,
+    ours: Handshake,
+    port: u16,
+    limits: Limits,
+    blocklist: Blocklist,
+    /// Peers refused because their address is on the blocklist.
+    blocked: u64,
+    /// Our public address, which dial candidates are ranked against.
+    external_ip: ExternalIp,
+    swarm: Swarm,
+    /// Ordered by address, so that peers are served and requested from in
+    /// the same order from one run to the next.
+    peers: BTreeMap<SocketAddr, Peer>,
+    dialing: HashSet<SocketAddr>,
+    input_tx: mpsc::Sender<Input>,
+    input_rx: mpsc::Receiver<Input>,
+    next_announce: Option<Instant>,
+    transferred: Arc<Counters>,
+    download_rate: RateMeter,
+    upload_rate: RateMeter,
+    trackers: Vec<TrackerInfo>,
+    /// Whether a recheck is running.
+    checking: bool,
+}
+
+impl Engine {
+    async fn new(
+        metainfo: Arc<Metainfo>,
+        config: &Config,
+        options: &Options,
+        shared: &Shared,
+    ) -> Result<Self, EngineError> {
+        let config = &options.configure(config);
+        let events = shared.events.clone();
+        let info = &metainfo.info;
+        let mut layout = Layout::new(info);
+        if let Some(name) = &options.name {
+            layout.rename_root(name);
+        }
+
+        // Pick up where an earlier run left off, wherever it left the data.
+        let root = if space::used(&config.storage.download_dir, &layout) > 0 {
+            config.storage.download_dir.clone()
+        } else {
+            config.storage.initial_dir().to_path_buf()
+        };
+        if let Some(suffix) = &config.storage.part_suffix {
+            part::apply_suffix(&root, &mut layout, suffix);
+        }
+        let have = match &options.have {
+            _ if space::used(&root, &layout) == 0 => Bitfield::new(info.num_pieces()),
+            Some(have) if have.len() == info.num_pieces() => {
+                debug!("trusting {} pieces verified earlier", have.count());
+                have.clone()
+            }
+            _ => {
+                info!("checking existing data in {}", root.display());
+                let existing = FileStorage::open_read_only(&root, layout.clone());
+                recheck(&existing, info, |_, _| {})?
+            }
+        };
+
+        space::ensure(&root, &layout, config.limits.disk_quota)?;
+        let storage = MovableStorage::open(
+            config.storage.backend,
+            &root,
+            layout,
+            config.storage.allocation,
+            IoHints::from(config),
+        )?;
+        #[cfg(feature = "faults")]
+        let storage = match &shared.faults {
+            Some(faults) => storage.with_faults(Arc::clone(faults)),
+            None => storage,
+        };
+        let storage = Arc::new(storage);
+        if let Some(suffix) = &config.storage.part_suffix {
+            part::finish_files(&storage, suffix, &have)?;
+        }
+
+        let mut torrent = Torrent::new(Arc::clone(&metainfo), Arc::new(events.clone()));
+        for piece in have.iter() {
+            torrent.picker.piece_complete(piece as u32);
+        }
+        if !options.file_priorities.is_empty() {
+            torrent.set_file_priorities(&options.file_priorities);
+        }
+        torrent.picker.set_sequential(options.sequential);
+        torrent
+            .picker
+            .set_memory_budget(shared.limits.memory.clone());
+
+        let mut ours = Handshake::new(metainfo.info_hash, shared.peer_id);
+        ours.set_fast();
+        ours.set_extensions();
+
+        events.publish(Event::TorrentAdded {
+            info_hash: metainfo.info_hash,
+            name: info.name.clone(),
+        });
+
+        let (input_tx, input_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        Ok(Self {
+            config: config.clone(),
+            torrent,
+            storage,
+            events,
+            announcers: Announcer::for_endpoints(config, shared)?,
+            transport: Arc::clone(&shared.transport),
+            ours,
+            port: shared.port,
+            limits: shared
+                .limits
+                .for_torrent(options.download_rate, options.upload_rate),
+            blocklist: shared.blocklist.clone(),
+            blocked: 0,
+            external_ip: shared.external_ip.clone(),
+            swarm: Swarm::new(config.network.max_peers),
+            peers: BTreeMap::new(),
+            dialing: HashSet::new(),
+            input_tx,
+            input_rx,
+            next_announce: None,
+            transferred: Arc::new(shared.counters.child()),
+            download_rate: RateMeter::default(),
+            upload_rate: RateMeter::default(),
+            trackers: metainfo
+                .trackers()
+                .into_iter()
+                .flatten()
+                .map(|url| TrackerInfo {
+                    url,
+                    state: TrackerState::Idle,
+                    peers: 0,
+                    seeders: None,
+                    leechers: None,
+                })
+                .collect(),
+            checking: false,
+        })
+    }
+
+    async fn run(&mut self, mode: Mode) -> Result<Summary, EngineError> {
+        let mut finished = self.torrent.picker.is_finished();
+        if finished && mode == Mode::Download {
+            return self.finish();
+        }
+        let mut seeding_since = Instant::now();
+
+        if self.torrent.metainfo.trackers().is_empty() {
+            warn!("torrent has no trackers; waiting for peers to connect");
+        }
+        self.announce(Some(AnnounceEvent::Started));
+
+        let mut dial_timer = interval(DIAL_INTERVAL);
+        let mut stopped = None;
+        loop {
+            if !finished && self.torrent.picker.is_finished() {
+                finished = true;
+                if mode == Mode::Download {
+                    self.final_announce(AnnounceEvent::Completed).await;
+                    let summary = self.finish()?;
+                    self.hook(Hook::Finished, None);
+                    return Ok(summary);
+                }
+                self.settle()?;
+                self.hook(Hook::Finished, None);
+                self.announce(Some(AnnounceEvent::Completed));
+                seeding_since = Instant::now();
+            }
+
+            let announce_at = self.next_announce;
+            tokio::select! {
+                input = self.input_rx.recv() => match input {
+                    Some(Input::Shutdown(reply)) => {
+                        stopped = reply;
+                        break;
+                    }
+                    Some(input) => self.handle(input).await?,
+                    // We hold a sender ourselves, so the channel never closes.
+                    None => {}
+                },
+                _ = dial_timer.tick() => {
+                    if let (true, Mode::Seed(goal)) = (finished, mode) {
+                        let ratio = self.status().ratio();
+                        if goal.is_met(ratio, seeding_since.elapsed()) {
+                            info!("seeding goal reached");
+                            break;
+                        }
+                    }
+                    self.dial_more();
+                    self.request_from_all();
+                }
+                _ = sleep_until(announce_at.unwrap_or_else(Instant::now)),
+                    if announce_at.is_some() =>
+                {
+                    self.next_announce = None;
+                    self.announce(None);
+                }
+            }
+        }
+
+        self.peers.clear();
+        self.final_announce(AnnounceEvent::Stopped).await;
+        let summary = self.finish()?;
+        if let Some(reply) = stopped {
+            let _ = reply.send(self.torrent.picker.have().clone());
+        }
+        Ok(summary)
+    }
+
+    /// Flushes the data, and once the download is done moves it into place.
+    /// Safe to call repeatedly.
+    fn settle(&mut self) -> Result<(), EngineError> {
+        self.storage.get().flush()?;
+        if !self.torrent.picker.is_finished() {
+            return Ok(());
+        }
+        if let Some(suffix) = &self.config.storage.part_suffix {
+            part::finish_files(&self.storage, suffix, self.torrent.picker.have())?;
+        }
+        move_completed(&self.storage, &self.config)?;
+        Ok(())
+    }
+
+    /// Runs the user's command for `hook`, if any.
+    fn hook(&mut self, hook: Hook, error: Option<String>) {
+        if self.config.hooks.command(hook).is_none() {
+            return;
+        }
+        let mut status = self.status();
+        if error.is_some() {
+            status.error = error;
+        }
+        self.config.hooks.run(hook, &status, &self.data_path());
+    }
+
+    /// The torrent's single file or top-level directory.
+    fn data_path(&self) -> PathBuf {
+        let root = self.storage.root();
+        let storage = self.storage.get();
+        match storage.layout().files.first() {
+            Some(file) => root.join(file.path.iter().next().unwrap_or_default()),
+            None => root,
+        }
+    }
+
+    fn finish(&mut self) -> Result<Summary, EngineError> {
+        self.settle()?;
+        let transferred = self.transferred.snapshot();
+        Ok(Summary {
+            save_path: self.storage.root(),
+            complete: self.torrent.picker.is_finished(),
+            downloaded: transferred.downloaded,
+            uploaded: transferred.uploaded,
+        })
+    }
+
+    async fn handle(&mut self, input: Input) -> Result<(), EngineError> {
+        match input {
+            Input::Incoming {
+                stream,
+                addr,
+                handshake,
+            } => self.accept(stream, addr, handshake),
+            Input::Connected {
+                addr,
+                handshake,
+                outbound,
+                incoming,
+            } => self.connected(addr, handshake, outbound, incoming),
+            Input::Message { addr, message } => self.message(addr, message).await?,
+            Input::Disconnected { addr, reason } => {
+                if self.peers.contains_key(&addr) {
+                    debug!(%addr, ?reason, "peer disconnected");
+                    self.drop_peer(&addr, reason);
+                }
+            }
+            Input::DialFailed { addr } => {
+                self.dialing.remove(&addr);
+                self.swarm.mark_unreachable(addr, Instant::now().into_std());
+                self.request_rendezvous();
+            }
+            Input::AddPeers(peers) => {
+                for addr in peers {
+                    self.swarm.add_peer(addr);
+                }
+                self.dial_more();
+            }
+            Input::Announced(successes, errors) => self.announced(successes, errors),
+            Input::Status(reply) => {
+                let _ = reply.send(self.status());
+            }
+            Input::Peers(reply) => {
+                let _ = reply.send(self.peer_infos());
+            }
+            Input::Trackers(reply) => {
+                let _ = reply.send(self.trackers.clone());
+            }
+            Input::Pieces(reply) => {
+                let _ = reply.send(self.torrent.picker.have().clone());
+            }
+            Input::Checkpoint(reply) => {
+                self.storage.get().flush()?;
+                let _ = reply.send(self.torrent.picker.have().clone());
+            }
+            Input::Files(reply) => {
+                let _ = reply.send(self.file_infos());
+            }
+            Input::Configure(settings) => self.configure(settings),
+            Input::Announce if self.torrent.is_paused() => {}
+            Input::Announce => {
+                info!("announcing on request");
+                self.next_announce = None;
+                self.announce(None);
+            }
+            Input::Pause => self.pause(),
+            Input::Resume => self.resume(),
+            Input::Recheck => self.recheck(),
+            Input::Rechecked(result) => self.rechecked(result)?,
+            Input::Move(dir, reply) => {
+                info!("moving data to {}", dir.display());
+                let storage = Arc::clone(&self.storage);
+                let moved = tokio::task::spawn_blocking(move || storage.move_to(&dir))
+                    .await
+                    .expect("move task panicked");
+                let _ = reply.send(moved);
+            }
+            Input::Rename(name, reply) => {
+                info!("renaming data to {}", name);
+                let storage = Arc::clone(&self.storage);
+                let renamed = tokio::task::spawn_blocking(move || storage.rename_root(&name))
+                    .await
+                    .expect("rename task panicked");
+                let _ = reply.send(renamed);
+            }
+            Input::Shutdown(_) => {}
+        }
+        Ok(())
+    }
+
+    fn status(&mut self) -> Status {
+        let picker = &self.torrent.picker;
+        let (mut done, mut wanted) = (0, 0);
+        for piece in (0..picker.num_pieces() as u32).filter(|&piece| picker.is_wanted(piece)) {
+            let size = picker.piece_size(piece) as u64;
+            wanted += size;
+            if picker.have().has(piece as usize) {
+                done += size;
+            }
+        }
+        let state = if self.torrent.error().is_some() {
+            State::Error
+        } else if self.torrent.is_paused() {
+            State::Paused
+        } else if self.checking {
+            State::Checking
+        } else if picker.is_finished() {
+            State::Seeding
+        } else {
+            State::Downloading
+        };
+
+        let transferred = self.transferred.snapshot();
+        Status {
+            info_hash: self.torrent.metainfo.info_hash,
+            name: self.torrent.metainfo.info.name.clone(),
+            state,
+            done,
+            wanted,
+            downloaded: transferred.downloaded,
+            uploaded: transferred.uploaded,
+            download_rate: self.download_rate.rate(),
+            upload_rate: self.upload_rate.rate(),
+            peers: self.peers.len(),
+            seeds: self
+                .peers
+                .values()
+                .filter(|peer| peer.state.has.is_full())
+                .count(),
+            blocked: self.blocked,
+            error: self.torrent.error().map(str::to_string),
+        }
+    }
+
+    fn peer_infos(&mut self) -> Vec<PeerInfo> {
+        let num_pieces = self.torrent.picker.num_pieces().max(1);
+        let mut peers: Vec<PeerInfo> = self
+            .peers
+            .iter_mut()
+            .map(|(addr, peer)| {
+                let transferred = peer.transferred.snapshot();
+                PeerInfo {
+                    addr: *addr,
+                    client: peer.state.client.as_ref().map(ToString::to_string),
+                    incoming: peer.incoming,
+                    encrypted: false,
+                    am_choking: peer.state.am_choking,
+                    am_interested: peer.state.am_interested,
+                    peer_choking: peer.state.peer_choking,
+                    peer_interested: peer.state.peer_interested,
+                    progress: peer.state.has.count() as f64 / num_pieces as f64,
+                    download_rate: peer.download_rate.rate(),
+                    upload_rate: peer.upload_rate.rate(),
+                    downloaded: transferred.downloaded,
+                    uploaded: transferred.uploaded,
+                }
+            })
+            .collect();
+        peers.sort_by_key(|peer| peer.addr);
+        peers
+    }
+
+    fn file_infos(&self) -> Vec<FileInfo> {
+        let picker = &self.torrent.picker;
+        let layout = Layout::new(&self.torrent.metainfo.info);
+        let piece_length = layout.piece_length as u64;
+        layout
+            .files
+            .iter()
+            .enumerate()
+            .map(|(index, slot)| {
+                let end = slot.offset + slot.length;
+                let mut done = 0;
+                if slot.length > 0 {
+                    for piece in slot.offset / piece_length..=(end - 1) / piece_length {
+                        if picker.have().has(piece as usize) {
+                            let start = piece * piece_length;
+                            let stop = start + picker.piece_size(piece as u32) as u64;
+                            done += stop.min(end) - start.max(slot.offset);
+                        }
+                    }
+                }
+                FileInfo {
+                    path: slot.path.clone(),
+                    length: slot.length,
+                    done,
+                    priority: self.torrent.file_priority(index),
+                }
+            })
+            .collect()
+    }
+
+    fn configure(&mut self, settings: Settings) {
+        let limit = |rate| Some(rate).filter(|&rate| rate > 0);
+        if let Some(rate) = settings.download_rate {
+            self.limits.torrent_download().set_rate(limit(rate));
+        }
+        if let Some(rate) = settings.upload_rate {
+            self.limits.torrent_upload().set_rate(limit(rate));
+        }
+        if let Some(sequential) = settings.sequential {
+            self.torrent.picker.set_sequential(sequential);
+        }
+        if let Some(priorities) = settings.file_priorities {
+            self.torrent.set_file_priorities(&priorities);
+            let addrs: Vec<SocketAddr> = self.peers.keys().copied().collect();
+            for addr in &addrs {
+                self.update_interest(addr);
+                self.request_more(addr);
+            }
+        }
+        info!("settings changed on request");
+    }
+
+    fn connected(
+        &mut self,
+        addr: SocketAddr,
+        handshake: Handshake,
+        outbound: mpsc::Sender<Message>,
+        incoming: bool,
+    ) {
+        self.dialing.remove(&addr);
+        if self.torrent.is_paused()
+            || self.peers.contains_key(&addr)
+            || self.torrent.is_banned(&addr.ip())
+            || handshake.peer_id == self.ours.peer_id
+        {
+            // Dropping `outbound` closes the connection.
+            return;
+        }
+        if self.config.pedantic && !handshake.reserved_bits_assigned() {
+            debug!(%addr, reserved = ?handshake.reserved, "peer set unassigned reserved bits");
+            return;
+        }
+        if self.peers.len() >= self.swarm.max_connections() {
+            // Make room by dropping the peer the BEP 40 ranking likes least,
+            // if this one outranks it.
+            self.update_local_addr();
+            match self.swarm.outranked_by(addr) {
+                Some(evicted) => {
+                    debug!(%addr, %evicted, "replacing a peer of lower priority");
+                    self.drop_peer(
+                        &evicted,
+                        Some("replaced by a peer of higher priority".into()),
+                    );
+                }
+                None => return,
+            }
+        }
+        let permit = match self.limits.connection() {
+            Some(permit) => permit,
+            None => return,
+        };
+
+        let fast = self.ours.supports_fast() && handshake.supports_fast();
+        let pedantic = self.config.pedantic;
+        let mut state = PeerState::new(self.torrent.picker.num_pieces(), fast, pedantic);
+        state.identify(&handshake.peer_id);
+
+        let mut greeting = Vec::new();
+        if handshake.supports_extensions() {
+            let extended = ExtendedHandshake {
+                version: Some(format!("rainyday {}", env!("CARGO_PKG_VERSION"))),
+                port: Some(self.port),
+                your_ip: Some(addr.ip()),
+                request_queue: Some(CHANNEL_CAPACITY as u32),
+                extensions: BTreeMap::from([
+                    (metadata::EXTENSION_NAME.to_string(), metadata::LOCAL_ID),
+                    (HOLEPUNCH_EXTENSION.to_string(), HOLEPUNCH_ID),
+                ]),
+                metadata_size: Some(self.torrent.metainfo.info_bytes.len() as u64),
+            };
+            greeting.push(Message::Extended {
+                id: HANDSHAKE_ID,
+                payload: extended.to_bytes(),
+            });
+        }
+        greeting.extend(availability_message(self.torrent.picker.have(), fast));
+        if fast {
+            // Pieces the peer may have even while we choke it, so that a
+            // newcomer can get started (BEP 6).
+            let allowed = allowed_fast_set(
+                addr.ip(),
+                &self.torrent.metainfo.info_hash,
+                self.torrent.picker.num_pieces() as u32,
+                ALLOWED_FAST_COUNT,
+            );
+            state.granted_fast.extend(&allowed);
+            greeting.extend(allowed.into_iter().map(Message::AllowedFast));
+        }
+
+        self.swarm.mark_connected(addr);
+        self.peers.insert(
+            addr,
+            Peer {
+                state,
+                _permit: permit,
+                outbound,
+                incoming,
+                transferred: self.transferred.child(),
+                download_rate: RateMeter::default(),
+                upload_rate: RateMeter::default(),
+            },
+        );
+        info!(%addr, client = ?self.peers[&addr].state.client, "peer connected");
+        self.events.publish(Event::PeerConnected {
+            info_hash: self.torrent.metainfo.info_hash,
+            addr,
+        });
+
+        for message in greeting {
+            self.send(&addr, message);
+        }
+    }
+
+    async fn message(&mut self, addr: SocketAddr, message: Message) -> Result<(), EngineError> {
+        let peer = match self.peers.get_mut(&addr) {
+            Some(peer) => peer,
+            None => return Ok(()),
+        };
+        // Whether a block answers one of our requests, which handling it
+        // forgets.
+        let requested = match &message {
+            Message::Piece {
+                piece,
+                offset,
+                data,
+            } => peer.state.pending.contains(&BlockRequest {
+                piece: *piece,
+                offset: *offset,
+                length: data.len() as u32,
+            }),
+            _ => false,
+        };
+        let released = match self.torrent.peer_message(&mut peer.state, &message) {
+            Ok(released) => released,
+            Err(e) => {
+                debug!(%addr, "dropping peer: {}", e);
+                self.drop_peer(&addr, Some(e.to_string()));
+                return Ok(());
+            }
+        };
+        for request in &released {
+            self.torrent.picker.release(request);
+        }
+        if let Message::Extended {
+            id: HANDSHAKE_ID, ..
+        } = &message
+        {
+            let extensions = peer.state.extensions.as_ref();
+            if let Some(ip) = extensions.and_then(|e| e.your_ip) {
+                self.external_ip.suggest(ip);
+            }
+            if extensions
+                .and_then(|e| e.id_of(HOLEPUNCH_EXTENSION))
+                .is_some()
+            {
+                self.swarm.set_relay(addr);
+            }
+        }
+
+        match message {
+            Message::Interested => self.unchoke_some(),
+            Message::NotInterested if !peer.state.am_choking => {
+                peer.state.am_choking = true;
+                self.send(&addr, Message::Choke);
+                self.unchoke_some();
+            }
+            Message::Request(request) => self.serve(addr, request).await?,
+            Message::Extended {
+                id: HANDSHAKE_ID, ..
+            } => self.request_rendezvous(),
+            Message::Extended {
+                id: metadata::LOCAL_ID,
+                payload,
+            } => self.serve_metadata(&addr, &payload),
+            Message::Extended {
+                id: HOLEPUNCH_ID,
+                payload,
+            } => self.holepunch(addr, &payload),
+            Message::Piece {
+                piece,
+                offset,
+                data,
+            } => {
+                peer.transferred.record_download(data.len() as u64);
+                self.download_rate.record(data.len() as u64);
+                peer.download_rate.record(data.len() as u64);
+                if !requested {
+                    debug!(%addr, piece, offset, "ignoring unrequested block");
+                } else if let Some(blocks) =
+                    self.torrent.block_received(addr.ip(), piece, offset, data)
+                {
+                    self.piece_downloaded(piece, blocks).await?;
+                }
+            }
+            _ => {}
+        }
+
+        self.update_interest(&addr);
+        self.request_more(&addr);
+        Ok(())
+    }
+
+    /// Verifies a fully downloaded piece and writes it to disk.
+    async fn piece_downloaded(
+        &mut self,
+        piece: u32,
+        blocks: Vec<Bytes>,
+    ) -> Result<(), EngineError> {
+        let expected = match self.torrent.metainfo.info.piece_hash(piece) {
+            Some(hash) => *hash,
+            None => return Ok(()),
+        };
+        let span = self.torrent.piece_span(piece);
+        let (valid, blocks) = verify_piece_async(expected, blocks)
+            .instrument(info_span!(parent: &span, "verify"))
+            .await;
+
+        if valid {
+            let storage = Arc::clone(&self.storage);
+            let written = tokio::task::spawn_blocking(move || {
+                let _span = info_span!(parent: &span, "write").entered();
+                let storage = storage.get();
+                storage.write_blocks(piece, &blocks)?;
+                let len = blocks.iter().map(|block| block.len() as u64).sum();
+                storage.drop_cache(storage.layout().piece_offset(piece), len)
+            })
+            .await
+            .expect("disk write task panicked");
+            if let Err(e) = written {
+                self.torrent.abandon_piece(piece);
+                if self.torrent.storage_failed(&e) {
+                    return Err(EngineError::DiskFull(e));
+                }
+                return Err(e.into());
+            }
+        }
+
+        match self.torrent.finish_piece(piece, valid) {
+            PieceOutcome::Verified { have } => {
+                let addrs: Vec<SocketAddr> = self.peers.keys().copied().collect();
+                for addr in addrs {
+                    self.send(&addr, have.clone());
+                    self.update_interest(&addr);
+                }
+                if let Some(suffix) = &self.config.storage.part_suffix {
+                    part::finish_files(&self.storage, suffix, self.torrent.picker.have())?;
+                }
+            }
+            PieceOutcome::Failed { banned, .. } => {
+                let addrs: Vec<SocketAddr> = self
+                    .peers
+                    .keys()
+                    .filter(|addr| banned.contains(&addr.ip()))
+                    .copied()
+                    .collect();
+                for addr in addrs {
+                    self.drop_peer(&addr, Some("sent corrupt data".to_string()));
+                }
+            }
+        }
+        // The piece's memory is free for others to be started.
+        self.request_from_all();
+        Ok(())
+    }
+
+    fn pause(&mut self) {
+        if self.torrent.is_paused() {
+            return;
+        }
+        info!("pausing on request");
+        self.torrent.pause();
+        let addrs: Vec<SocketAddr> = self.peers.keys().copied().collect();
+        for addr in addrs {
+            self.drop_peer(&addr, Some("torrent paused".to_string()));
+        }
+        self.next_announce = None;
+        self.announce(Some(AnnounceEvent::Stopped));
+    }
+
+    fn resume(&mut self) {
+        if !self.torrent.is_paused() {
+            return;
+        }
+        info!("resuming on request");
+        self.torrent.resume();
+        self.announce(Some(AnnounceEvent::Started));
+        self.dial_more();
+    }
+
+    /// Starts hashing the data on disk in the background. Peers are still
+    /// served meanwhile; the result replaces what we believe we have.
+    fn recheck(&mut self) {
+        if self.checking {
+            return;
+        }
+        info!("rechecking data on request");
+        self.checking = true;
+        let storage = Arc::clone(&self.storage);
+        let metainfo = Arc::clone(&self.torrent.metainfo);
+        let input = self.input_tx.clone();
+        tokio::spawn(async move {
+            let result = tokio::task::spawn_blocking(move || {
+                recheck(&*storage.get(), &metainfo.info, |_, _| {})
+            })
+            .await
+            .expect("recheck task panicked");
+            let _ = input.send(Input::Rechecked(result)).await;
+        });
+    }
+
+    fn rechecked(&mut self, result: Result<Bitfield, StorageError>) -> Result<(), EngineError> {
+        self.checking = false;
+        let valid = match result {
+            Ok(valid) => valid,
+            Err(e) => {
+                warn!("recheck failed: {}", e);
+                return Ok(());
+            }
+        };
+
+        let mut gained = Vec::new();
+        for piece in 0..valid.len() {
+            match (self.torrent.picker.have().has(piece), valid.has(piece)) {
+                (true, false) => self.torrent.picker.piece_failed(piece as u32),
+                (false, true) => {
+                    self.torrent.picker.piece_complete(piece as u32);
+                    gained.push(piece as u32);
+                }
+                _ => {}
+            }
+        }
+        info!(
+            valid = valid.count(),
+            pieces = valid.len(),
+            "recheck finished"
+        );
+
+        let addrs: Vec<SocketAddr> = self.peers.keys().copied().collect();
+        for addr in addrs {
+            for &piece in &gained {
+                self.send(&addr, Message::Have(piece));
+            }
+            self.update_interest(&addr);
+        }
+        if let Some(suffix) = &self.config.storage.part_suffix {
+            part::finish_files(&self.storage, suffix, self.torrent.picker.have())?;
+        }
+        self.events.publish(Event::Rechecked {
+            info_hash: self.torrent.metainfo.info_hash,
+            valid: valid.count(),
+            pieces: valid.len(),
+        });
+        Ok(())
+    }
+
+    /// Answers a block request if we have the piece and the peer may ask.
+    async fn serve(&mut self, addr: SocketAddr, request: BlockRequest) -> Result<(), EngineError> {
+        let peer = &self.peers[&addr];
+        let in_range = request.piece < self.torrent.picker.num_pieces() as u32
+            && request.offset as u64 + request.length as u64
+                <= self.torrent.picker.piece_size(request.piece) as u64;
+        if !in_range
+            || !peer
+                .state
+                .should_serve(&request, self.torrent.picker.have())
+        {
+            if peer.state.fast {
+                self.send(&addr, Message::Reject(request));
+            }
+            return Ok(());
+        }
+
+        let storage = Arc::clone(&self.storage);
+        let offset = storage.get().layout().piece_offset(request.piece) + request.offset as u64;
+        let data = tokio::task::spawn_blocking(move || {
+            let mut data = BLOCKS.zeroed(request.length as usize);
+            storage.get().read_into(offset, &mut data).map(|()| data)
+        })
+        .await
+        .expect("disk read task panicked")?;
+
+        self.upload_rate.record(data.len() as u64);
+        match self.peers.get_mut(&addr) {
+            Some(peer) => {
+                peer.transferred.record_upload(data.len() as u64);
+                peer.upload_rate.record(data.len() as u64);
+            }
+            None => self.transferred.record_upload(data.len() as u64),
+        }
+        self.send(
+            &addr,
+            Message::Piece {
+                piece: request.piece,
+                offset: request.offset,
+                data: data.into_bytes(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Answers a peer's request for part of the info dictionary.
+    fn serve_metadata(&mut self, addr: &SocketAddr, payload: &[u8]) {
+        let piece = match MetadataMessage::from_bytes(payload) {
+            Ok(MetadataMessage::Request { piece }) => piece,
+            _ => return,
+        };
+        let id = match self.peers[addr]
+            .state
+            .extensions
+            .as_ref()
+            .and_then(|extensions| extensions.id_of(metadata::EXTENSION_NAME))
+        {
+            Some(id) => id,
+            None => return,
+        };
+
+        let info = &self.torrent.metainfo.info_bytes;
+        let start = piece as usize * metadata::PIECE_SIZE;
+        let reply = if start < info.len() {
+            let end = (start + metadata::PIECE_SIZE).min(info.len());
+            MetadataMessage::Data {
+                piece,
+                total_size: info.len() as u64,
+                data: info[start..end].to_vec(),
+            }
+        } else {
+            MetadataMessage::Reject { piece }
+        };
+        self.send(
+            addr,
+            Message::Extended {
+                id,
+                payload: reply.to_bytes(),
+            },
+        );
+    }
+
+    fn holepunch(&mut self, addr: SocketAddr, payload: &[u8]) {
+        match HolepunchMessage::from_bytes(payload) {
+            Ok(HolepunchMessage::Rendezvous(target)) => {
+                // Where the target can reach the initiator: its address
+                // with the port it listens on.
+                let port = self.peers[&addr]
+                    .state
+                    .extensions
+                    .as_ref()
+                    .and_then(|extensions| extensions.port);
+                let initiator = SocketAddr::new(addr.ip(), port.unwrap_or_else(|| addr.port()));
+                let to = self
+                    .connection_to(target)
+                    .map(|to| (to, self.holepunch_id(&to).is_some()));
+                for (to, message) in holepunch::relay(addr, initiator, target, to) {
+                    self.send_holepunch(&to, message);
+                }
+            }
+            Ok(HolepunchMessage::Connect(target)) => {
+                let known = self.connection_to(target).is_some() || self.dialing.contains(&target);
+                if known
+                    || self.blocklist.contains(&target.ip())
+                    || self.torrent.is_banned(&target.ip())
+                {
+                    return;
+                }
+                debug!(%addr, %target, "holepunching");
+                self.swarm.add_peer(target);
+                self.dial(target, true);
+            }
+            Ok(HolepunchMessage::Error(target, error)) => {
+                debug!(%addr, %target, ?error, "rendezvous refused");
+            }
+            Err(e) => debug!(%addr, "bad holepunch message: {}", e),
+        }
+    }
+
+    /// Asks relays to introduce us to the peers we could not dial.
+    fn request_rendezvous(&mut self) {
+        for (relay, message) in holepunch::rendezvous_requests(&mut self.swarm) {
+            self.send_holepunch(&relay, message);
+        }
+    }
+
+    /// The connection to the peer at `addr`, whether we dialled it there or
+    /// it dialled us and listens there.
+    fn connection_to(&self, addr: SocketAddr) -> Option<SocketAddr> {
+        if self.peers.contains_key(&addr) {
+            return Some(addr);
+        }
+        self.peers.iter().find_map(|(connection, peer)| {
+            let port = peer.state.extensions.as_ref()?.port?;
+            (connection.ip() == addr.ip() && port == addr.port()).then_some(*connection)
+        })
+    }
+
+    fn holepunch_id(&self, addr: &SocketAddr) -> Option<u8> {
+        self.peers
+            .get(addr)?
+            .state
+            .extensions
+            .as_ref()?
+            .id_of(HOLEPUNCH_EXTENSION)
+    }
+
+    fn send_holepunch(&mut self, addr: &SocketAddr, message: HolepunchMessage) {
+        if let Some(id) = self.holepunch_id(addr) {
+            let payload = message.to_bytes();
+            self.send(addr, Message::Extended { id, payload });
+        }
+    }
+
+    /// Tells the peer whether it has anything we still want.
+    fn update_interest(&mut self, addr: &SocketAddr) {
+        let picker = &self.torrent.picker;
+        let peer = match self.peers.get_mut(addr) {
+            Some(peer) => peer,
+            None => return,
+        };
+        let interested = peer
+            .state
+            .has
+            .iter()
+            .any(|piece| !picker.have().has(piece) && picker.is_wanted(piece as u32));
+
+        if interested != peer.state.am_interested {
+            peer.state.am_interested = interested;
+            let message = if interested {
+                Message::Interested
+            } else {
+                Message::NotInterested
+            };
+            self.send(addr, message);
+        }
+    }
+
+    /// Keeps the peer's request pipeline full.
+    fn request_more(&mut self, addr: &SocketAddr) {
+        let peer = match self.peers.get_mut(addr) {
+            Some(peer) => peer,
+            None => return,
+        };
+        if !peer.state.am_interested {
+            return;
+        }
+
+        let mut requests = Vec::new();
+        while peer.state.pending.len() < PIPELINE_DEPTH {
+            match self.torrent.picker.pick(&peer.state) {
+                Some(request) => {
+                    peer.state.pending.insert(request);
+                    requests.push(request);
+                }
+                None => break,
+            }
+        }
+        for request in requests {
+            self.send(addr, Message::Request(request));
+        }
+    }
+
+    /// Tops up every peer's requests, as those held back by the memory
+    /// budget may now fit.
+    fn request_from_all(&mut self) {
+        let addrs: Vec<SocketAddr> = self.peers.keys().copied().collect();
+        for addr in addrs {
+            self.request_more(&addr);
+        }
+    }
+
+    /// Unchokes interested peers while upload slots are free.
+    fn unchoke_some(&mut self) {
+        let unchoked = self.peers.values().filter(|p| !p.state.am_choking).count();
+        let candidates: Vec<SocketAddr> = self
+            .peers
+            .iter()
+            .filter(|(_, p)| p.state.am_choking && p.state.peer_interested)
+            .map(|(addr, _)| *addr)
+            .take(UPLOAD_SLOTS.saturating_sub(unchoked))
+            .collect();
+        for addr in candidates {
+            if let Some(peer) = self.peers.get_mut(&addr) {
+                peer.state.am_choking = false;
+            }
+            self.send(&addr, Message::Unchoke);
+        }
+    }
+
+    /// Queues a message for a peer, dropping the peer if it is not keeping
+    /// up with what we send it.
+    fn send(&mut self, addr: &SocketAddr, message: Message) {
+        let full = match self.peers.get(addr) {
+            Some(peer) => peer.outbound.try_send(message).is_err(),
+            None => return,
+        };
+        if full {
+            self.drop_peer(addr, Some("send queue full".to_string()));
+        }
+    }
+
+    fn drop_peer(&mut self, addr: &SocketAddr, reason: Option<String>) {
+        let peer = match self.peers.remove(addr) {
+            Some(peer) => peer,
+            None => return,
+        };
+        self.torrent.picker.peer_lost(&peer.state.has);
+        for request in &peer.state.pending {
+            self.torrent.picker.release(request);
+        }
+        self.swarm.mark_disconnected(addr);
+        if !peer.state.am_choking {
+            self.unchoke_some();
+        }
+        self.events.publish(Event::PeerDisconnected {
+            info_hash: self.torrent.metainfo.info_hash,
+            addr: *addr,
+            reason,
+        });
+
+        // Other peers may now be able to take over its requests.
+        let addrs: Vec<SocketAddr> = self.peers.keys().copied().collect();
+        for addr in addrs {
+            self.request_more(&addr);
+        }
+    }
+
+    /// Ranks peers against our public address, once it is known.
+    fn update_local_addr(&mut self) {
+        if let Some(ip) = self.external_ip.get() {
+            self.swarm.set_local_addr(SocketAddr::new(ip, self.port));
+        }
+    }
+
+    /// Dials known peers while connection slots are free, those of highest
+    /// BEP 40 priority first.
+    fn dial_more(&mut self) {
+        if self.torrent.is_paused() {
+            return;
+        }
+        self.update_local_addr();
+        self.swarm.retry_unreachable(Instant::now().into_std());
+        let slots = self
+            .swarm
+            .max_connections()
+            .saturating_sub(self.peers.len() + self.dialing.len())
+            .min(
+                self.limits
+                    .free_connections()
+                    .saturating_sub(self.dialing.len()),
+            );
+        for addr in self.swarm.dial_candidates() {
+            if self.blocklist.contains(&addr.ip()) {
+                debug!(%addr, "not dialing blocklisted peer");
+                self.swarm.remove_peer(&addr);
+                self.blocked += 1;
+            }
+        }
+        let candidates: Vec<SocketAddr> = self
+            .swarm
+            .dial_candidates()
+            .into_iter()
+            .filter(|addr| !self.dialing.contains(addr) && !self.torrent.is_banned(&addr.ip()))
+            .take(slots)
+            .collect();
+
+        for addr in candidates {
+            self.dial(addr, false);
+        }
+    }
+
+    /// Dials `addr`, or with `punch` opens a connection to it while it
+    /// opens one to us.
+    fn dial(&mut self, addr: SocketAddr, punch: bool) {
+        self.dialing.insert(addr);
+        let ours = self.ours;
+        let policy = self.config.network.encryption;
+        let port = self.port;
+        let transport = Arc::clone(&self.transport);
+        let timeouts = Timeouts::from(&self.config);
+        let limits = self.limits.clone();
+        let codec = MessageCodec::new(self.config.pedantic);
+        let input = self.input_tx.clone();
+        tokio::spawn(async move {
+            let attempt = async {
+                if punch {
+                    connection::punch(addr, port, &ours, &*transport).await
+                } else {
+                    dial(addr, &ours, policy, &*transport).await
+                }
+            };
+            match timeout(CONNECT_TIMEOUT, attempt).await {
+                Ok(Ok((stream, handshake))) => {
+                    run_connection(
+                        addr, stream, handshake, false, timeouts, limits, codec, input,
+                    )
+                    .await
+                }
+                Ok(Err(e)) => {
+                    debug!(%addr, "dial failed: {}", e);
+                    let _ = input.send(Input::DialFailed { addr }).await;
+                }
+                Err(_) => {
+                    debug!(%addr, "dial timed out");
+                    let _ = input.send(Input::DialFailed { addr }).await;
+                }
+            }
+        });
+    }
+
+    /// Completes the handshake of an incoming connection.
+    fn accept(&mut self, mut stream: PeerStream, addr: SocketAddr, handshake: Handshake) {
+        if self.torrent.is_paused() || self.torrent.is_banned(&addr.ip()) {
+            return;
+        }
+        if self.blocklist.contains(&addr.ip()) {
+            debug!(%addr, "refusing blocklisted peer");
+            self.blocked += 1;
+            return;
+        }
+        let ours = self.ours;
+        let timeouts = Timeouts::from(&self.config);
+        let limits = self.limits.clone();
+        let codec = MessageCodec::new(self.config.pedantic);
+        let input = self.input_tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = tokio::io::AsyncWriteExt::write_all(&mut stream, &ours.to_bytes()).await
+            {
+                return debug!(%addr, "incoming handshake failed: {}", e);
+            }
+            run_connection(
+                addr, stream, handshake, true, timeouts, limits, codec, input,
+            )
+            .await
+        });
+    }
+
+    /// Announces in the background; the responses arrive as an input.
+    fn announce(&self, event: Option<AnnounceEvent>) {
+        let announcers = self.announcers.clone();
+        let tiers = self.torrent.metainfo.trackers();
+        let announce = self.announce_params(event);
+        let input = self.input_tx.clone();
+        tokio::spawn(async move {
+            let (successes, errors) = announce_all(&announcers, &tiers, &announce).await;
+            let _ = input.send(Input::Announced(successes, errors)).await;
+        });
+    }
+
+    /// Announces and waits, briefly, for the trackers to acknowledge.
+    async fn final_announce(&self, event: AnnounceEvent) {
+        let tiers = self.torrent.metainfo.trackers();
+        let announce = self.announce_params(Some(event));
+        let _ = timeout(
+            STOP_ANNOUNCE_TIMEOUT,
+            announce_all(&self.announcers, &tiers, &announce),
+        )
+        .await;
+    }
+
+    fn announce_params(&self, event: Option<AnnounceEvent>) -> Announce {
+        let picker = &self.torrent.picker;
+        let left = (0..picker.num_pieces() as u32)
+            .filter(|&piece| !picker.have().has(piece as usize))
+            .map(|piece| picker.piece_size(piece) as u64)
+            .sum();
+        let transferred = self.transferred.snapshot();
+        Announce {
+            info_hash: self.torrent.metainfo.info_hash,
+            peer_id: self.ours.peer_id,
+            port: self.port,
+            uploaded: transferred.uploaded,
+            downloaded: transferred.downloaded,
+            left,
+            event,
+            num_want: self.config.tracker.num_want,
+            ipv4: None,
+            ipv6: None,
+        }
+    }
+
+    fn announced(
+        &mut self,
+        successes: Vec<(String, AnnounceResponse)>,
+        errors: Vec<(String, TrackerError)>,
+    ) {
+        let info_hash = self.torrent.metainfo.info_hash;
+        for (url, error) in errors {
+            info!(url = %url, "announce failed: {}", error);
+            if let Some(tracker) = self.trackers.iter_mut().find(|t| t.url == url) {
+                tracker.state = TrackerState::Failed(error.to_string());
+            }
+            self.events.publish(Event::TrackerError {
+                info_hash,
+                url,
+                message: error.to_string(),
+            });
+        }
+
+        let mut interval = None;
+        for (url, response) in successes {
+            info!(url = %url, peers = response.peers.len(), "announced");
+            if let Some(warning) = &response.warning {
+                warn!(url = %url, "tracker warning: {}", warning);
+            }
+            if let Some(ip) = response.external_ip {
+                self.external_ip.suggest(ip);
+            }
+            for addr in &response.peers {
+                self.swarm.add_peer(*addr);
+            }
+            if let Some(tracker) = self.trackers.iter_mut().find(|t| t.url == url) {
+                tracker.state = TrackerState::Working;
+                tracker.peers = response.peers.len();
+                tracker.seeders = response.seeders;
+                tracker.leechers = response.leechers;
+            }
+            self.events.publish(Event::TrackerAnnounced {
+                info_hash,
+                url,
+                peers: response.peers.len(),
+            });
+            self.dial_more();
+            // Announce again when the most impatient tracker wants to.
+            let next = response
+                .interval
+                .max(response.min_interval.unwrap_or_default());
+            interval = Some(interval.map_or(next, |interval: Duration| interval.min(next)));
+        }
+        // If no tracker answered, try again soon rather than waiting a full
+        // interval.
+        let interval = interval.unwrap_or(Duration::from_secs(60));
+        if !self.torrent.is_paused() {
+            self.next_announce = Some(Instant::now() + interval);
+        }
+    }
+}
+
+/// Announces one listen endpoint to the trackers: its port, from its
+/// address.
+#[derive(Clone)]
+struct Announcer {
+    tracker: Tracker,
+    port: u16,
+    /// Whether this is one of the announces over IPv4 and IPv6 that
+    /// `dual_stack` makes, which also tell trackers our public addresses.
+    dual_stack: bool,
+}
+
+impl Announcer {
+    /// One for each endpoint if `listen` configures them or the session
+    /// listens on both IPv4 and IPv6, and otherwise one for the session's
+    /// port. Announces through a proxy would all come from the proxy, so
+    /// are not made over both.
+    fn for_endpoints(config: &Config, shared: &Shared) -> Result<Vec<Self>, EngineError> {
+        let tracker = |interface| {
+            Tracker::new()
+                .with_pedantic(config.pedantic)
+                .with_source_ports(config.network.source_ports)
+                .with_network(&config.proxy, interface)
+        };
+        let listen = &config.network.listen;
+        let dual_stack = listen.is_empty() && shared.endpoints.len() > 1;
+        if listen.is_empty() && (!dual_stack || config.proxy.proxies_trackers()) {
+            return Ok(vec![Self {
+                tracker: tracker(config.network.interface.as_ref())?,
+                port: shared.port,
+                dual_stack: false,
+            }]);
+        }
+        shared
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                Ok(Self {
+                    tracker: tracker(Some(&endpoint.interface))?,
+                    port: endpoint.port,
+                    dual_stack,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Announces every endpoint, each with its own port, merging the answers.
+async fn announce_all(
+    announcers: &[Announcer],
+    tiers: &[Vec<String>],
+    announce: &Announce,
+) -> (Vec<(String, AnnounceResponse)>, Vec<(String, TrackerError)>) {
+    let (ipv4, ipv6) = if announcers.iter().any(|announcer| announcer.dual_stack) {
+        interface::public_addresses()
+    } else {
+        (None, None)
+    };
+    let results = join_all(announcers.iter().map(|announcer| async move {
+        let announce = Announce {
+            port: announcer.port,
+            ipv4,
+            ipv6,
+            ..announce.clone()
+        };
+        announcer.tracker.announce_tiers(tiers, &announce).await
+    }))
+    .await;
+
+    // A tracker that answered for several endpoints is reported once, with
+    // every peer it gave.
+    let mut successes: Vec<(String, AnnounceResponse)> = Vec::new();
+    let mut errors = Vec::new();
+    for (success, failures) in results {
+        if let Some((url, response)) = success {
+            match successes.iter_mut().find(|(answered, _)| *answered == url) {
+                Some((_, merged)) => merged.merge(response),
+                None => successes.push((url, response)),
+            }
+        }
+        errors.extend(failures);
+    }
+    // A tracker that answered for one endpoint is working, and one that
+    // failed for several is reported once.
+    let mut reported = HashSet::new();
+    errors.retain(|(url, _)| {
+        !successes.iter().any(|(answered, _)| answered == url) && reported.insert(url.clone())
+    });
+    (successes, errors)
+}
+
+/// Runs an established connection, relaying its traffic to the engine.
+#[allow(clippy::too_many_arguments)]
+async fn run_connection(
+    addr: SocketAddr,
+    stream: PeerStream,
+    handshake: Handshake,
+    incoming: bool,
+    timeouts: Timeouts,
+    limits: Limits,
+    codec: MessageCodec,
+    input: mpsc::Sender<Input>,
+) {
+    let (outbound_tx, outbound_rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let (inbound_tx, mut inbound_rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let connected = Input::Connected {
+        addr,
+        handshake,
+        outbound: outbound_tx,
+        incoming,
+    };
+    if input.send(connected).await.is_err() {
+        return;
+    }
+
+    let connection = task::run(stream, outbound_rx, inbound_tx, timeouts, limits, codec);
+    tokio::pin!(connection);
+    // Polling in a fixed order, rather than tokio's random one, keeps
+    // connections behaving the same from one run to the next.
+    let result = loop {
+        tokio::select! {
+            biased;
+            result = &mut connection => break result,
+            Some(message) = inbound_rx.recv() => {
+                if input.send(Input::Message { addr, message }).await.is_err() {
+                    return;
+                }
+            }
+        }
+    };
+
+    while let Ok(message) = inbound_rx.try_recv() {
+        if input.send(Input::Message { addr, message }).await.is_err() {
+            return;
+        }
+    }
+    let reason = result.err().map(|e| e.to_string());
+    let _ = input.send(Input::Disconnected { addr, reason }).await;
+}
@@ -0,0 +1,136 @@
+//! Session-wide notifications, published by every subsystem and consumed by
+//! anything that reports on progress.
+
+use std::net::SocketAddr;
+
+#[cfg(feature = "tokio")]
+use tokio::sync::broadcast;
+
+use crate::info_hash::InfoHash;
+
+/// Number of events a slow subscriber may fall behind before it starts
+/// missing them.
+#[cfg(feature = "tokio")]
+const DEFAULT_CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    TorrentAdded {
+        info_hash: InfoHash,
+        name: String,
+    },
+    TorrentFinished {
+        info_hash: InfoHash,
+    },
+    TorrentRemoved {
+        info_hash: InfoHash,
+    },
+    /// The torrent was paused because its data no longer fits on disk or
+    /// within the quota.
+    DiskFull {
+        info_hash: InfoHash,
+        message: String,
+    },
+    PeerConnected {
+        info_hash: InfoHash,
+        addr: SocketAddr,
+    },
+    PeerDisconnected {
+        info_hash: InfoHash,
+        addr: SocketAddr,
+        reason: Option<String>,
+    },
+    PieceCompleted {
+        info_hash: InfoHash,
+        piece: u32,
+    },
+    /// A piece failed its hash check and was discarded for re-download.
+    /// `failures` counts how often this piece has failed so far.
+    PieceFailed {
+        info_hash: InfoHash,
+        piece: u32,
+        failures: u32,
+    },
+    TrackerAnnounced {
+        info_hash: InfoHash,
+        url: String,
+        peers: usize,
+    },
+    TrackerError {
+        info_hash: InfoHash,
+        url: String,
+        message: String,
+    },
+    /// A forced recheck of the data on disk finished, finding `valid` of
+    /// the torrent's `pieces` intact.
+    Rechecked {
+        info_hash: InfoHash,
+        valid: usize,
+        pieces: usize,
+    },
+}
+
+impl Event {
+    /// The torrent the event concerns.
+    pub fn info_hash(&self) -> &InfoHash {
+        match self {
+            Event::TorrentAdded { info_hash, .. }
+            | Event::TorrentFinished { info_hash }
+            | Event::TorrentRemoved { info_hash }
+            | Event::DiskFull { info_hash, .. }
+            | Event::PeerConnected { info_hash, .. }
+            | Event::PeerDisconnected { info_hash, .. }
+            | Event::PieceCompleted { info_hash, .. }
+            | Event::PieceFailed { info_hash, .. }
+            | Event::TrackerAnnounced { info_hash, .. }
+            | Event::TrackerError { info_hash, .. }
+            | Event::Rechecked { info_hash, .. } => info_hash,
+        }
+    }
+}
+
+/// Somewhere events are published to: the session's [`EventBus`], or
+/// whatever an application embedding the library passes them on to.
+pub trait Publish: Send + Sync {
+    fn publish(&self, event: Event);
+}
+
+/// A broadcast channel every subsystem publishes its events to.
+///
+/// Cloning the bus is cheap and yields another handle to the same channel.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Debug)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+#[cfg(feature = "tokio")]
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    /// Publishes an event. Having no subscribers is not an error.
+    pub fn publish(&self, event: Event) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Publish for EventBus {
+    fn publish(&self, event: Event) {
+        EventBus::publish(self, event);
+    }
+}
@@ -0,0 +1,303 @@
+//! Faults injected into peer connections and storage on demand, so that the
+//! code handling them can be tested. Built with the `faults` feature.
+//!
+//! A [`Faults`] is shared by a test and the wrappers here, which pass
+//! everything through untouched until the test asks for a fault. The engine
+//! takes the wrapped transport as [`Shared::transport`] and wraps its
+//! storage itself when given [`Shared::faults`].
+//!
+//! [`Shared::transport`]: crate::engine::Shared::transport
+//! [`Shared::faults`]: crate::engine::Shared::faults
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::future::{self, BoxFuture};
+use futures::FutureExt;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::peer::transport::{PeerStream, PeerTransport};
+use crate::protocol::handshake::HANDSHAKE_LEN;
+use crate::storage::{DiskMetrics, Layout, Storage, StorageError};
+
+/// The id of a `piece` message.
+const PIECE: u8 = 7;
+/// Bytes of a `piece` message's payload before its block: the piece index
+/// and the offset.
+const PIECE_HEADER_LEN: usize = 8;
+
+/// Faults waiting to be injected. Each one asked for happens once, to the
+/// next connection, block or disk operation it applies to.
+#[derive(Debug, Default)]
+pub struct Faults {
+    connect_timeouts: AtomicUsize,
+    read_limit: AtomicUsize,
+    corrupt_blocks: AtomicUsize,
+    read_errors: AtomicUsize,
+    write_errors: AtomicUsize,
+}
+
+impl Faults {
+    /// Makes the next `count` connections hang instead of connecting, so
+    /// that dialling them times out.
+    pub fn time_out_connects(&self, count: usize) {
+        self.connect_timeouts.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Cuts every read from a peer to at most `len` bytes, however much has
+    /// arrived. Zero lets reads be as long as they are again.
+    pub fn shorten_reads(&self, len: usize) {
+        self.read_limit.store(len, Ordering::Relaxed);
+    }
+
+    /// Flips the first byte of each of the next `count` blocks received
+    /// from peers.
+    pub fn corrupt_blocks(&self, count: usize) {
+        self.corrupt_blocks.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Fails the next `count` reads from disk.
+    pub fn fail_reads(&self, count: usize) {
+        self.read_errors.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Fails the next `count` writes to disk.
+    pub fn fail_writes(&self, count: usize) {
+        self.write_errors.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Whether every fault asked for has happened. Shortened reads go on
+    /// until turned off, so are not counted.
+    pub fn is_spent(&self) -> bool {
+        [
+            &self.connect_timeouts,
+            &self.corrupt_blocks,
+            &self.read_errors,
+            &self.write_errors,
+        ]
+        .iter()
+        .all(|pending| pending.load(Ordering::Relaxed) == 0)
+    }
+
+    /// Takes one of the faults counted by `pending`, if any are left.
+    fn take(pending: &AtomicUsize) -> bool {
+        pending
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok()
+    }
+}
+
+/// The error an injected disk fault fails with.
+fn injected() -> StorageError {
+    StorageError::Io(io::Error::other("injected fault"))
+}
+
+/// A transport whose connections suffer the faults asked for.
+#[derive(Debug)]
+pub struct FaultyTransport {
+    inner: Arc<dyn PeerTransport>,
+    faults: Arc<Faults>,
+}
+
+impl FaultyTransport {
+    pub fn new(inner: Arc<dyn PeerTransport>, faults: Arc<Faults>) -> Self {
+        Self { inner, faults }
+    }
+}
+
+impl PeerTransport for FaultyTransport {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<PeerStream>> {
+        if Faults::take(&self.faults.connect_timeouts) {
+            return future::pending().boxed();
+        }
+        let faults = Arc::clone(&self.faults);
+        self.inner
+            .connect(addr)
+            .map(|stream| stream.map(|stream| Box::new(FaultyStream::new(stream, faults)) as _))
+            .boxed()
+    }
+}
+
+/// A connection that shortens reads and corrupts blocks when asked to.
+///
+/// Blocks are found by following the messages as they are read, which
+/// works because connections are never encrypted.
+pub struct FaultyStream {
+    inner: PeerStream,
+    faults: Arc<Faults>,
+    framing: Framing,
+}
+
+impl FaultyStream {
+    pub fn new(inner: PeerStream, faults: Arc<Faults>) -> Self {
+        Self {
+            inner,
+            faults,
+            framing: Framing::default(),
+        }
+    }
+}
+
+impl AsyncRead for FaultyStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let limit = match this.faults.read_limit.load(Ordering::Relaxed) {
+            0 => buf.remaining(),
+            limit => limit.min(buf.remaining()),
+        };
+        let mut short = ReadBuf::new(buf.initialize_unfilled_to(limit));
+        let poll = Pin::new(&mut this.inner).poll_read(cx, &mut short);
+        let read = short.filled().len();
+        buf.advance(read);
+
+        let start = buf.filled().len() - read;
+        for byte in &mut buf.filled_mut()[start..] {
+            if this.framing.next(*byte) && Faults::take(&this.faults.corrupt_blocks) {
+                *byte ^= 0xff;
+            }
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for FaultyStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Where the bytes read so far leave off in the peer's side of the
+/// conversation.
+#[derive(Debug)]
+struct Framing {
+    /// Bytes of the handshake still to come.
+    handshake: usize,
+    /// The length prefix and id of the next message, as far as read.
+    header: [u8; 5],
+    header_len: usize,
+    /// Bytes of the current message's payload read, and still to come.
+    payload_read: usize,
+    payload_left: usize,
+    /// Whether the current message is a `piece`.
+    piece: bool,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self {
+            handshake: HANDSHAKE_LEN,
+            header: [0; 5],
+            header_len: 0,
+            payload_read: 0,
+            payload_left: 0,
+            piece: false,
+        }
+    }
+}
+
+impl Framing {
+    /// Follows one more byte, returning whether it is the first of a block.
+    fn next(&mut self, byte: u8) -> bool {
+        if self.handshake > 0 {
+            self.handshake -= 1;
+            return false;
+        }
+        if self.payload_left > 0 {
+            let at = self.payload_read;
+            self.payload_read += 1;
+            self.payload_left -= 1;
+            return self.piece && at == PIECE_HEADER_LEN;
+        }
+
+        self.header[self.header_len] = byte;
+        self.header_len += 1;
+        let len = u32::from_be_bytes([
+            self.header[0],
+            self.header[1],
+            self.header[2],
+            self.header[3],
+        ]) as usize;
+        if self.header_len == 4 && len == 0 {
+            // A keep-alive, which has no id.
+            self.header_len = 0;
+        } else if self.header_len == 5 {
+            self.header_len = 0;
+            self.piece = byte == PIECE;
+            self.payload_read = 0;
+            self.payload_left = len - 1;
+        }
+        false
+    }
+}
+
+/// Storage whose reads and writes fail when asked to.
+pub struct FaultyStorage {
+    inner: Box<dyn Storage>,
+    faults: Arc<Faults>,
+}
+
+impl FaultyStorage {
+    pub fn new(inner: Box<dyn Storage>, faults: Arc<Faults>) -> Self {
+        Self { inner, faults }
+    }
+}
+
+impl Storage for FaultyStorage {
+    fn layout(&self) -> &Layout {
+        self.inner.layout()
+    }
+
+    fn metrics(&self) -> &DiskMetrics {
+        self.inner.metrics()
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), StorageError> {
+        if Faults::take(&self.faults.write_errors) {
+            return Err(injected());
+        }
+        self.inner.write(offset, data)
+    }
+
+    fn read_into(&self, offset: u64, buf: &mut [u8]) -> Result<(), StorageError> {
+        if Faults::take(&self.faults.read_errors) {
+            return Err(injected());
+        }
+        self.inner.read_into(offset, buf)
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        self.inner.flush()
+    }
+
+    fn drop_cache(&self, offset: u64, len: u64) -> Result<(), StorageError> {
+        self.inner.drop_cache(offset, len)
+    }
+
+    fn write_blocks(&self, piece: u32, blocks: &[Bytes]) -> Result<(), StorageError> {
+        if Faults::take(&self.faults.write_errors) {
+            return Err(injected());
+        }
+        self.inner.write_blocks(piece, blocks)
+    }
+}
@@ -0,0 +1,139 @@
+//! Choosing which of a torrent's files to download.
+
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::metainfo::Info;
+use crate::storage::Layout;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SelectionError {
+    #[error("invalid file index or range {0:?}")]
+    InvalidIndex(String),
+    #[error("file index {index} is out of range; the torrent has {count} files")]
+    OutOfRange { index: usize, count: usize },
+    #[error("no files selected")]
+    NothingSelected,
+}
+
+/// How eagerly a file is downloaded. Higher priorities are picked first;
+/// within a priority level pieces are still picked rarest first.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilePriority {
+    /// Do not download the file.
+    Skip,
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl FilePriority {
+    pub fn is_wanted(self) -> bool {
+        self != FilePriority::Skip
+    }
+}
+
+/// The priority of each piece: the highest priority of any file it
+/// overlaps.
+///
+/// A piece straddling a wanted and a skipped file is wanted, since the
+/// wanted file cannot be completed without it. Files missing from
+/// `priorities` default to normal priority.
+pub fn piece_priorities(
+    layout: &Layout,
+    priorities: &[FilePriority],
+    num_pieces: usize,
+) -> Vec<FilePriority> {
+    let mut pieces = vec![FilePriority::Skip; num_pieces];
+    let piece_length = layout.piece_length as u64;
+
+    for (index, slot) in layout.files.iter().enumerate() {
+        let priority = priorities.get(index).copied().unwrap_or_default();
+        if !priority.is_wanted() || slot.length == 0 {
+            continue;
+        }
+
+        let first = slot.offset / piece_length;
+        let last = (slot.offset + slot.length - 1) / piece_length;
+        for piece in &mut pieces[first as usize..=last as usize] {
+            *piece = (*piece).max(priority);
+        }
+    }
+
+    pieces
+}
+
+/// A choice of files by index and by glob pattern over their paths within
+/// the torrent. With no indices or `include` patterns every file is chosen,
+/// before `exclude` takes some away again.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Selection {
+    pub indices: Vec<usize>,
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+}
+
+impl Selection {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty() && self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// The priority of each of `info`'s files: normal if chosen, skipped
+    /// otherwise.
+    pub fn priorities(&self, info: &Info) -> Result<Vec<FilePriority>, SelectionError> {
+        let count = info.files.len();
+        if let Some(&index) = self.indices.iter().find(|&&index| index >= count) {
+            return Err(SelectionError::OutOfRange { index, count });
+        }
+
+        let everything = self.indices.is_empty() && self.include.is_empty();
+        let priorities: Vec<FilePriority> = info
+            .files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| {
+                let path = file.path_buf();
+                let chosen = everything
+                    || self.indices.contains(&index)
+                    || self.include.iter().any(|p| p.matches_path(&path));
+                if chosen && !self.exclude.iter().any(|p| p.matches_path(&path)) {
+                    FilePriority::Normal
+                } else {
+                    FilePriority::Skip
+                }
+            })
+            .collect();
+
+        if !priorities.iter().any(|p| p.is_wanted()) {
+            return Err(SelectionError::NothingSelected);
+        }
+        Ok(priorities)
+    }
+}
+
+/// Parses a list of file indices such as `0,2,5-7`.
+pub fn parse_indices(list: &str) -> Result<Vec<usize>, SelectionError> {
+    let mut indices = Vec::new();
+    for item in list.split(',').map(str::trim) {
+        let invalid = || SelectionError::InvalidIndex(item.to_string());
+        match item.split_once('-') {
+            Some((first, last)) => {
+                let first: usize = first.trim().parse().map_err(|_| invalid())?;
+                let last: usize = last.trim().parse().map_err(|_| invalid())?;
+                if first > last {
+                    return Err(invalid());
+                }
+                indices.extend(first..=last);
+            }
+            None => indices.push(item.parse().map_err(|_| invalid())?),
+        }
+    }
+    indices.sort_unstable();
+    indices.dedup();
+    Ok(indices)
+}
@@ -0,0 +1,60 @@
+//! SHA-1, which identifies torrents and verifies their pieces, from the
+//! backend the build selects.
+//!
+//! By default it comes from the `sha1` crate, which uses the SHA extensions
+//! of x86 processors when they are present. The `openssl` feature takes it
+//! from OpenSSL instead, whose assembly also uses ARMv8's cryptography
+//! extensions and otherwise NEON or AVX2.
+
+/// Which implementation hashes, for reporting.
+#[cfg(not(feature = "openssl"))]
+pub const BACKEND: &str = "the sha1 crate";
+#[cfg(feature = "openssl")]
+pub const BACKEND: &str = "OpenSSL";
+
+/// The SHA-1 hash of `data`.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// Hashes data given in parts.
+#[derive(Clone)]
+pub struct Sha1 {
+    #[cfg(not(feature = "openssl"))]
+    inner: sha1::Sha1,
+    #[cfg(feature = "openssl")]
+    inner: openssl::sha::Sha1,
+}
+
+impl Sha1 {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(not(feature = "openssl"))]
+            inner: <sha1::Sha1 as sha1::Digest>::new(),
+            #[cfg(feature = "openssl")]
+            inner: openssl::sha::Sha1::new(),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        #[cfg(not(feature = "openssl"))]
+        sha1::Digest::update(&mut self.inner, data);
+        #[cfg(feature = "openssl")]
+        self.inner.update(data);
+    }
+
+    pub fn finish(self) -> [u8; 20] {
+        #[cfg(not(feature = "openssl"))]
+        return sha1::Digest::finalize(self.inner).into();
+        #[cfg(feature = "openssl")]
+        return self.inner.finish();
+    }
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
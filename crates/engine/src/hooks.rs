@@ -0,0 +1,118 @@
+//! User commands run when something happens to a torrent, such as unpacking
+//! a finished download or telling a media library to rescan.
+//!
+//! Each command is run by `sh -c` with details of the torrent in the
+//! environment:
+//!
+//! | Variable              | Value                                          |
+//! |-----------------------|------------------------------------------------|
+//! | `RAINYDAY_EVENT`      | `added`, `finished`, `stopped` or `error`      |
+//! | `RAINYDAY_INFO_HASH`  | The info hash, in hex                          |
+//! | `RAINYDAY_NAME`       | The torrent's name                             |
+//! | `RAINYDAY_PATH`       | The torrent's file or top-level directory      |
+//! | `RAINYDAY_SIZE`       | Bytes of data wanted                           |
+//! | `RAINYDAY_DONE`       | Bytes of wanted data on disk                   |
+//! | `RAINYDAY_DOWNLOADED` | Bytes downloaded this session                  |
+//! | `RAINYDAY_UPLOADED`   | Bytes uploaded this session                    |
+//! | `RAINYDAY_RATIO`      | Upload ratio, if anything has been transferred |
+//! | `RAINYDAY_ERROR`      | What went wrong, for `error` only              |
+
+use std::fmt;
+use std::path::Path;
+use std::process::Stdio;
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+use crate::engine::Status;
+
+/// The commands to run, configured under `[hooks]`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Hooks {
+    /// Run once a torrent has been checked and started.
+    pub added: Option<String>,
+    /// Run when a download completes and its data has been moved into
+    /// place.
+    pub finished: Option<String>,
+    /// Run when a torrent stops without error.
+    pub stopped: Option<String>,
+    /// Run when a torrent stops because of an error.
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hook {
+    Added,
+    Finished,
+    Stopped,
+    Error,
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Hook::Added => "added",
+            Hook::Finished => "finished",
+            Hook::Stopped => "stopped",
+            Hook::Error => "error",
+        })
+    }
+}
+
+impl Hooks {
+    pub fn command(&self, hook: Hook) -> Option<&str> {
+        match hook {
+            Hook::Added => self.added.as_deref(),
+            Hook::Finished => self.finished.as_deref(),
+            Hook::Stopped => self.stopped.as_deref(),
+            Hook::Error => self.error.as_deref(),
+        }
+    }
+
+    /// Starts the command for `hook`, if one is configured. The command
+    /// runs in the background; a failure is only logged.
+    pub fn run(&self, hook: Hook, status: &Status, path: &Path) {
+        let command = match self.command(hook) {
+            Some(command) => command,
+            None => return,
+        };
+
+        let mut child = Command::new("sh");
+        child
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .env("RAINYDAY_EVENT", hook.to_string())
+            .env("RAINYDAY_INFO_HASH", status.info_hash.to_string())
+            .env("RAINYDAY_NAME", &status.name)
+            .env("RAINYDAY_PATH", path)
+            .env("RAINYDAY_SIZE", status.wanted.to_string())
+            .env("RAINYDAY_DONE", status.done.to_string())
+            .env("RAINYDAY_DOWNLOADED", status.downloaded.to_string())
+            .env("RAINYDAY_UPLOADED", status.uploaded.to_string());
+        if let Some(ratio) = status.ratio() {
+            child.env("RAINYDAY_RATIO", format!("{:.3}", ratio));
+        }
+        if let Some(error) = &status.error {
+            child.env("RAINYDAY_ERROR", error);
+        }
+
+        let mut child = match child.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                warn!("cannot run {} hook: {}", hook, e);
+                return;
+            }
+        };
+        debug!(%hook, command, "running hook");
+        tokio::spawn(async move {
+            match child.wait().await {
+                Ok(exit) if exit.success() => {}
+                Ok(exit) => warn!("{} hook failed: {}", hook, exit),
+                Err(e) => warn!("{} hook failed: {}", hook, e),
+            }
+        });
+    }
+}
@@ -0,0 +1,358 @@
+//! Reading the resume data other clients keep, so their torrents can be
+//! moved into a rainyday session without checking the data again.
+//!
+//! qBittorrent keeps libtorrent's `<info hash>.fastresume` files next to
+//! the `.torrent` files in its `BT_backup` directory; newer versions may
+//! leave out the `.torrent` file and keep the info dictionary in the
+//! resume file instead. Transmission keeps `.resume` files in `resume/`
+//! and the `.torrent` files by the same name in `torrents/`.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+use crate::bencode::{self, BencodeError, Value};
+use crate::bitfield::Bitfield;
+use crate::engine::SeedGoal;
+use crate::files::FilePriority;
+use crate::info_hash::InfoHash;
+use crate::metainfo::{Metainfo, MetainfoError};
+use crate::resume::ResumeData;
+use crate::session::store::{TorrentOptions, TorrentStats};
+use crate::session::TorrentRecord;
+
+#[derive(Debug, Error)]
+pub enum ImportError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("resume data is not valid bencode: {0}")]
+    Bencode(#[from] BencodeError),
+    #[error("{0}")]
+    Metainfo(#[from] MetainfoError),
+    #[error("no .torrent file was found for the resume data")]
+    MissingTorrent,
+    #[error("resume data field `{0}` is missing or invalid")]
+    InvalidField(&'static str),
+    #[error("resume data is for {resume}, but the .torrent file is for {torrent}")]
+    Mismatch { resume: InfoHash, torrent: InfoHash },
+}
+
+/// A client whose resume data can be imported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Client {
+    QBittorrent,
+    Transmission,
+}
+
+impl FromStr for Client {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "qbittorrent" | "libtorrent" => Ok(Client::QBittorrent),
+            "transmission" => Ok(Client::Transmission),
+            _ => Err(format!(
+                "unknown client {:?}; expected qbittorrent or transmission",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Client::QBittorrent => "qbittorrent",
+            Client::Transmission => "transmission",
+        })
+    }
+}
+
+/// Reads every torrent `client` keeps resume data for in `dir`, pairing
+/// each resume file with its `.torrent` file. Each resume file is read on
+/// its own, so one that cannot be read does not stop the rest.
+pub fn read_dir(
+    client: Client,
+    dir: &Path,
+) -> io::Result<Vec<(PathBuf, Result<TorrentRecord, ImportError>)>> {
+    let (resume_dir, torrent_dir, extension) = match client {
+        Client::QBittorrent => (dir.to_path_buf(), dir.to_path_buf(), "fastresume"),
+        Client::Transmission => {
+            // Given the configuration directory or the resume directory.
+            let resume_dir = Some(dir.join("resume"))
+                .filter(|resume| resume.is_dir())
+                .unwrap_or_else(|| dir.to_path_buf());
+            let torrent_dir = resume_dir
+                .parent()
+                .map(|parent| parent.join("torrents"))
+                .filter(|torrents| torrents.is_dir())
+                .unwrap_or_else(|| resume_dir.clone());
+            (resume_dir, torrent_dir, "resume")
+        }
+    };
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(&resume_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    paths.retain(|path| path.extension().and_then(|e| e.to_str()) == Some(extension));
+    paths.sort();
+
+    Ok(paths
+        .into_iter()
+        .map(|path| {
+            // Transmission's names have dots in them, so the extension is
+            // appended rather than set.
+            let mut torrent = path
+                .file_stem()
+                .map(|stem| torrent_dir.join(stem))
+                .unwrap_or_default();
+            torrent.as_mut_os_string().push(".torrent");
+            let record = match client {
+                Client::QBittorrent => fastresume(&path, &torrent),
+                Client::Transmission => transmission(&path, &torrent),
+            };
+            (path, record)
+        })
+        .collect())
+}
+
+/// Reads a libtorrent `.fastresume` file, as qBittorrent writes them.
+fn fastresume(path: &Path, torrent: &Path) -> Result<TorrentRecord, ImportError> {
+    let bytes = fs::read(path)?;
+    let resume = bencode::decode(&bytes)?;
+    let metainfo_bytes = if torrent.is_file() {
+        fs::read(torrent)?
+    } else {
+        embedded_metainfo(&bytes, &resume)?.ok_or(ImportError::MissingTorrent)?
+    };
+    let metainfo = Metainfo::from_bytes(&metainfo_bytes)?;
+    if let Some(hash) = resume.get("info-hash").and_then(Value::as_bytes) {
+        let resume_hash = <[u8; 20]>::try_from(hash)
+            .map(InfoHash)
+            .map_err(|_| ImportError::InvalidField("info-hash"))?;
+        if resume_hash != metainfo.info_hash {
+            return Err(ImportError::Mismatch {
+                resume: resume_hash,
+                torrent: metainfo.info_hash,
+            });
+        }
+    }
+
+    let info = &metainfo.info;
+    let int = |key: &str| resume.get(key).and_then(Value::as_int);
+    // Negative limits mean none, or the client's global one.
+    let positive = |key: &str| int(key).filter(|&n| n > 0).map(|n| n as u64);
+    let save_path = resume
+        .get("save_path")
+        .and_then(Value::as_str)
+        .map(PathBuf::from)
+        .ok_or(ImportError::InvalidField("save_path"))?;
+    // One byte per piece, the lowest bit set if the piece was verified.
+    let have = resume
+        .get("pieces")
+        .and_then(Value::as_bytes)
+        .filter(|pieces| pieces.len() == info.num_pieces())
+        .map(|pieces| {
+            let mut have = Bitfield::new(pieces.len());
+            for (piece, _) in pieces.iter().enumerate().filter(|(_, &b)| b & 1 != 0) {
+                have.set(piece);
+            }
+            have
+        });
+    let priorities: Vec<i64> = list(&resume, "file_priority")
+        .iter()
+        .filter_map(Value::as_int)
+        .collect();
+
+    let options = TorrentOptions {
+        save_path,
+        name: None,
+        paused: int("paused") == Some(1),
+        file_priorities: libtorrent_priorities(info.files.len(), &priorities),
+        download_rate: positive("download_rate_limit"),
+        upload_rate: positive("upload_rate_limit"),
+        seed_goal: SeedGoal {
+            ratio: int("qBt-ratioLimit")
+                .filter(|&n| n >= 0)
+                .map(|n| n as f64 / 1000.0),
+            time: int("qBt-seedingTimeLimit")
+                .filter(|&n| n >= 0)
+                .map(|minutes| Duration::from_secs(minutes as u64 * 60)),
+        },
+        sequential: int("sequential_download") == Some(1),
+    };
+    let stats = TorrentStats {
+        uploaded: positive("total_uploaded").unwrap_or(0),
+        downloaded: positive("total_downloaded").unwrap_or(0),
+        added_at: int("added_time").unwrap_or_else(unix_time),
+        completed_at: int("completed_time").filter(|&time| time > 0),
+    };
+    Ok(record(metainfo, metainfo_bytes, options, stats, have))
+}
+
+/// A `.torrent` file made from the info dictionary and trackers kept in a
+/// resume file, if it has them.
+fn embedded_metainfo(bytes: &[u8], resume: &Value) -> Result<Option<Vec<u8>>, ImportError> {
+    let Some(info) = bencode::raw_value(bytes, "info")? else {
+        return Ok(None);
+    };
+    let mut metainfo = b"d".to_vec();
+    let tiers = list(resume, "trackers");
+    let first = tiers
+        .first()
+        .and_then(Value::as_list)
+        .and_then(<[Value]>::first);
+    if let Some(url) = first {
+        metainfo.extend_from_slice(b"8:announce");
+        url.encode_into(&mut metainfo);
+        metainfo.extend_from_slice(b"13:announce-list");
+        Value::List(tiers.to_vec()).encode_into(&mut metainfo);
+    }
+    metainfo.extend_from_slice(b"4:info");
+    metainfo.extend_from_slice(info);
+    metainfo.push(b'e');
+    Ok(Some(metainfo))
+}
+
+/// Reads a Transmission `.resume` file.
+fn transmission(path: &Path, torrent: &Path) -> Result<TorrentRecord, ImportError> {
+    let resume = bencode::decode(&fs::read(path)?)?;
+    if !torrent.is_file() {
+        return Err(ImportError::MissingTorrent);
+    }
+    let metainfo_bytes = fs::read(torrent)?;
+    let metainfo = Metainfo::from_bytes(&metainfo_bytes)?;
+
+    let info = &metainfo.info;
+    let int = |key: &str| resume.get(key).and_then(Value::as_int);
+    let count = |key: &str| int(key).filter(|&n| n > 0).map_or(0, |n| n as u64);
+    let speed_limit = |key: &str| {
+        let limit = resume.get(key)?;
+        if limit.get("use-speed-limit").and_then(Value::as_int) != Some(1) {
+            return None;
+        }
+        let rate = limit.get("speed-Bps").and_then(Value::as_int)?;
+        u64::try_from(rate).ok().filter(|&rate| rate > 0)
+    };
+    let save_path = resume
+        .get("destination")
+        .and_then(Value::as_str)
+        .map(PathBuf::from)
+        .ok_or(ImportError::InvalidField("destination"))?;
+    let have = resume.get("progress").and_then(|progress| {
+        if progress.get("have").and_then(Value::as_bytes) == Some(b"all") {
+            return Some(Bitfield::full(info.num_pieces()));
+        }
+        let pieces = progress.get("pieces").and_then(Value::as_bytes)?;
+        Bitfield::from_bytes_masked(pieces, info.num_pieces())
+            .or_else(|| (pieces == b"all").then(|| Bitfield::full(info.num_pieces())))
+    });
+    let dnd: Vec<i64> = list(&resume, "dnd")
+        .iter()
+        .filter_map(Value::as_int)
+        .collect();
+    let priorities: Vec<i64> = list(&resume, "priority")
+        .iter()
+        .filter_map(Value::as_int)
+        .collect();
+    let file_priorities = if dnd.is_empty() && priorities.is_empty() {
+        Vec::new()
+    } else {
+        (0..info.files.len())
+            .map(|file| match dnd.get(file) {
+                Some(1) => FilePriority::Skip,
+                _ => match priorities.get(file) {
+                    Some(p) if *p < 0 => FilePriority::Low,
+                    Some(p) if *p > 0 => FilePriority::High,
+                    _ => FilePriority::Normal,
+                },
+            })
+            .collect()
+    };
+    let ratio = resume.get("ratio-limit").and_then(|limit| {
+        // Mode 1 is a limit of the torrent's own; 0 is the global one.
+        if limit.get("ratio-mode").and_then(Value::as_int) != Some(1) {
+            return None;
+        }
+        limit.get("ratio-limit")?.as_str()?.parse().ok()
+    });
+
+    let options = TorrentOptions {
+        save_path,
+        name: resume
+            .get("name")
+            .and_then(Value::as_str)
+            .filter(|name| *name != info.name)
+            .map(str::to_string),
+        paused: int("paused") == Some(1),
+        file_priorities,
+        download_rate: speed_limit("speed-limit-down"),
+        upload_rate: speed_limit("speed-limit-up"),
+        seed_goal: SeedGoal { ratio, time: None },
+        sequential: int("sequential_download") == Some(1),
+    };
+    let stats = TorrentStats {
+        uploaded: count("uploaded"),
+        downloaded: count("downloaded"),
+        added_at: int("added-date").unwrap_or_else(unix_time),
+        completed_at: int("done-date").filter(|&time| time > 0),
+    };
+    Ok(record(metainfo, metainfo_bytes, options, stats, have))
+}
+
+fn record(
+    metainfo: Metainfo,
+    metainfo_bytes: Vec<u8>,
+    options: TorrentOptions,
+    stats: TorrentStats,
+    have: Option<Bitfield>,
+) -> TorrentRecord {
+    let resume = have.map(|have| ResumeData {
+        info_hash: metainfo.info_hash,
+        save_path: options.save_path.clone(),
+        have,
+        uploaded: stats.uploaded,
+        downloaded: stats.downloaded,
+    });
+    TorrentRecord {
+        info_hash: metainfo.info_hash,
+        metainfo: metainfo_bytes,
+        options,
+        stats,
+        resume,
+    }
+}
+
+fn list<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    value.get(key).and_then(Value::as_list).unwrap_or_default()
+}
+
+/// Priorities for each of `files` from libtorrent's, which run from 0,
+/// skip, to 7; qBittorrent writes 1 for normal and 6 and 7 for high. The
+/// list may be shorter than the files, and if it is empty every file is
+/// wanted.
+fn libtorrent_priorities(files: usize, priorities: &[i64]) -> Vec<FilePriority> {
+    if priorities.is_empty() {
+        return Vec::new();
+    }
+    (0..files)
+        .map(|file| match priorities.get(file) {
+            Some(i64::MIN..=0) => FilePriority::Skip,
+            Some(6..) => FilePriority::High,
+            _ => FilePriority::Normal,
+        })
+        .collect()
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,104 @@
+//! Turning a torrent given on the command line or over the control
+//! interface into metainfo.
+
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+use reqwest::redirect::Policy;
+use thiserror::Error;
+use tokio::io::AsyncReadExt;
+use tracing::info;
+
+use crate::config::Config;
+use crate::interface::Interface;
+use crate::magnet::{self, Magnet, MagnetError};
+use crate::metainfo::{Metainfo, MetainfoError};
+use crate::peer::metadata::{self, MetadataError};
+use crate::peer::PeerId;
+
+/// Largest `.torrent` file we are willing to download or read.
+const MAX_TORRENT_SIZE: u64 = 32 * 1024 * 1024;
+const MAX_REDIRECTS: usize = 10;
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Error)]
+pub enum InputError {
+    #[error("{0}")]
+    Metainfo(#[from] MetainfoError),
+    #[error("{0}")]
+    Magnet(#[from] MagnetError),
+    #[error("cannot fetch metadata: {0}")]
+    Metadata(#[from] MetadataError),
+    #[error("cannot download torrent: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("{0} is larger than {MAX_TORRENT_SIZE} bytes")]
+    TooLarge(String),
+}
+
+/// Loads the torrent named by `input`: a magnet link, whose metadata is
+/// fetched from peers, who know us as `peer_id`, the HTTP(S) URL of a
+/// `.torrent` file, a path to one, or `-` to read one from standard input.
+pub async fn load(input: &str, config: &Config, peer_id: PeerId) -> Result<Metainfo, InputError> {
+    if input.starts_with(magnet::SCHEME) {
+        let magnet: Magnet = input.parse()?;
+        info!(
+            "fetching metadata for {}",
+            magnet
+                .name
+                .as_deref()
+                .unwrap_or(&magnet.info_hash.to_string())
+        );
+        return Ok(metadata::fetch(&magnet, config, peer_id).await?);
+    }
+
+    if input == "-" {
+        let mut bytes = Vec::new();
+        tokio::io::stdin()
+            .take(MAX_TORRENT_SIZE + 1)
+            .read_to_end(&mut bytes)
+            .await?;
+        if bytes.len() as u64 > MAX_TORRENT_SIZE {
+            return Err(InputError::TooLarge("standard input".to_string()));
+        }
+        return Ok(Metainfo::parse(&bytes, config.pedantic)?);
+    }
+
+    if input.starts_with("http://") || input.starts_with("https://") {
+        return Ok(Metainfo::parse(
+            &download(input, config.network.interface.as_ref()).await?,
+            config.pedantic,
+        )?);
+    }
+
+    let bytes = fs::read(input).map_err(MetainfoError::Io)?;
+    Ok(Metainfo::parse(&bytes, config.pedantic)?)
+}
+
+/// Downloads a `.torrent` file, following redirects and refusing anything
+/// implausibly large.
+async fn download(url: &str, interface: Option<&Interface>) -> Result<Vec<u8>, InputError> {
+    let mut client = reqwest::Client::builder()
+        .redirect(Policy::limited(MAX_REDIRECTS))
+        .timeout(DOWNLOAD_TIMEOUT);
+    if let Some(interface) = interface {
+        client = interface.configure(client)?;
+    }
+    let client = client.build()?;
+    let mut response = client.get(url).send().await?.error_for_status()?;
+
+    let too_large = || InputError::TooLarge(url.to_string());
+    if response.content_length().unwrap_or(0) > MAX_TORRENT_SIZE {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() as u64 > MAX_TORRENT_SIZE {
+            return Err(too_large());
+        }
+    }
+    Ok(body)
+}
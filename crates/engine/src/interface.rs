@@ -0,0 +1,499 @@
+//! Keeping all traffic on one network interface, as configured by
+//! `interface` under `[network]`, typically a VPN's.
+//!
+//! Binding fails closed: when the interface or address is missing, sockets
+//! cannot be opened and nothing is sent, rather than going out by the
+//! default route. On Linux, sockets are bound to the device itself, so
+//! connections through it fail if it goes away; elsewhere they are bound
+//! to its address.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use socket2::SockRef;
+use tokio::net::{lookup_host, TcpListener, TcpSocket, TcpStream, ToSocketAddrs, UdpSocket};
+
+use crate::config::PortRange;
+
+/// Longest interface name the kernel accepts.
+const MAX_NAME_LEN: usize = 15;
+/// Connections a listening socket queues before they are accepted.
+const BACKLOG: u32 = 1024;
+
+/// An interface to send and receive all traffic on, named either by its
+/// device name, such as `wg0`, or by one of its addresses.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Interface {
+    Address(IpAddr),
+    Device(String),
+}
+
+impl Interface {
+    /// Checks that the interface exists and, if named by address, that the
+    /// address is still assigned.
+    pub fn check(&self) -> io::Result<()> {
+        match self {
+            Self::Address(ip) => {
+                if addresses()?.iter().any(|(_, address)| address == ip) {
+                    Ok(())
+                } else {
+                    Err(io::Error::new(
+                        io::ErrorKind::AddrNotAvailable,
+                        format!("no interface has the address {}", ip),
+                    ))
+                }
+            }
+            Self::Device(name) => {
+                if addresses()?.iter().any(|(device, _)| device == name) {
+                    Ok(())
+                } else {
+                    Err(missing(name))
+                }
+            }
+        }
+    }
+
+    /// The address to send from to reach IPv6 or IPv4 addresses.
+    pub fn address(&self, ipv6: bool) -> io::Result<IpAddr> {
+        match self {
+            Self::Address(ip) if ip.is_ipv6() == ipv6 => Ok(*ip),
+            Self::Address(ip) => Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("{} cannot reach {} addresses", ip, family(ipv6)),
+            )),
+            Self::Device(name) => {
+                let addresses = addresses()?;
+                let mut found = addresses
+                    .iter()
+                    .filter(|(device, _)| device == name)
+                    .peekable();
+                if found.peek().is_none() {
+                    return Err(missing(name));
+                }
+                // Link-local IPv6 addresses cannot reach the internet.
+                found
+                    .map(|(_, ip)| *ip)
+                    .filter(|ip| ip.is_ipv6() == ipv6)
+                    .min_by_key(is_link_local)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::AddrNotAvailable,
+                            format!("interface {} has no {} address", name, family(ipv6)),
+                        )
+                    })
+            }
+        }
+    }
+
+    /// Whether the interface is named by an IPv6 address, and so only
+    /// reaches IPv6 addresses.
+    pub fn is_ipv6(&self) -> bool {
+        matches!(self, Self::Address(ip) if ip.is_ipv6())
+    }
+
+    /// Whether `addr` can be reached from the interface, as far as can be
+    /// told without trying.
+    pub fn reaches(&self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::Address(ip) => ip.is_ipv6() == addr.is_ipv6(),
+            Self::Device(_) => true,
+        }
+    }
+
+    fn tcp_socket(&self, ipv6: bool, port: u16) -> io::Result<TcpSocket> {
+        let socket = if ipv6 {
+            let socket = TcpSocket::new_v6()?;
+            // Otherwise listening on an IPv6 port takes the IPv4 port too.
+            SockRef::from(&socket).set_only_v6(true)?;
+            socket
+        } else {
+            TcpSocket::new_v4()?
+        };
+        // As `TcpListener::bind` does, so a restart can listen on the same
+        // port at once.
+        #[cfg(unix)]
+        socket.set_reuseaddr(true)?;
+        let ip = match self {
+            #[cfg(target_os = "linux")]
+            Self::Device(name) => {
+                socket.bind_device(Some(name.as_bytes()))?;
+                unspecified(ipv6)
+            }
+            _ => self.address(ipv6)?,
+        };
+        socket.bind(SocketAddr::new(ip, port))?;
+        Ok(socket)
+    }
+
+    async fn udp_socket(&self, ipv6: bool, port: u16) -> io::Result<UdpSocket> {
+        match self {
+            #[cfg(target_os = "linux")]
+            Self::Device(name) => {
+                let socket = UdpSocket::bind((unspecified(ipv6), port)).await?;
+                socket.bind_device(Some(name.as_bytes()))?;
+                Ok(socket)
+            }
+            _ => UdpSocket::bind((self.address(ipv6)?, port)).await,
+        }
+    }
+
+    /// Has the HTTP client send from the interface.
+    pub fn configure(&self, client: reqwest::ClientBuilder) -> io::Result<reqwest::ClientBuilder> {
+        Ok(match self {
+            Self::Address(ip) => client.local_address(*ip),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Self::Device(name) => client.interface(name),
+            #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+            Self::Device(_) => client.local_address(self.address(false)?),
+        })
+    }
+}
+
+impl FromStr for Interface {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = s.parse() {
+            return Ok(Self::Address(ip));
+        }
+        if s.is_empty()
+            || s.len() > MAX_NAME_LEN
+            || s.contains(|c: char| c.is_whitespace() || c == '/')
+        {
+            return Err(format!(
+                "invalid interface {:?}; expected a name such as wg0 or an address",
+                s
+            ));
+        }
+        Ok(Self::Device(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for Interface {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Interface> for String {
+    fn from(interface: Interface) -> Self {
+        interface.to_string()
+    }
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Address(ip) => write!(f, "{}", ip),
+            Self::Device(name) => f.write_str(name),
+        }
+    }
+}
+
+/// An address or interface and a port to listen for peers on, written
+/// `0.0.0.0:6881`, `[::]:6882` or `wg0:51413`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Endpoint {
+    pub interface: Interface,
+    /// `0` for any free port.
+    pub port: u16,
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Self {
+                interface: Interface::Address(addr.ip()),
+                port: addr.port(),
+            });
+        }
+        let invalid = || {
+            format!(
+                "invalid listen endpoint {:?}; expected e.g. 0.0.0.0:6881, [::]:6881 or wg0:6881",
+                s
+            )
+        };
+        let (interface, port) = s.rsplit_once(':').ok_or_else(invalid)?;
+        Ok(Self {
+            interface: interface.parse().map_err(|_| invalid())?,
+            port: port.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl TryFrom<String> for Endpoint {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Endpoint> for String {
+    fn from(endpoint: Endpoint) -> Self {
+        endpoint.to_string()
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.interface {
+            Interface::Address(ip) => write!(f, "{}", SocketAddr::new(*ip, self.port)),
+            Interface::Device(name) => write!(f, "{}:{}", name, self.port),
+        }
+    }
+}
+
+/// Opens a TCP connection to `addr`, from `interface` if there is one,
+/// and from a port in `source_ports` if given.
+pub async fn connect(
+    interface: Option<&Interface>,
+    source_ports: Option<PortRange>,
+    addr: impl ToSocketAddrs,
+) -> io::Result<TcpStream> {
+    if interface.is_none() && source_ports.is_none() {
+        return TcpStream::connect(addr).await;
+    }
+    let mut last_error = None;
+    for addr in lookup_host(addr).await? {
+        let attempt = match source_ports {
+            Some(range) => connect_in(interface, range, addr).await,
+            None => connect_from(interface, 0, addr).await,
+        };
+        match attempt {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to")))
+}
+
+/// Connects to `addr` from the first free port in `range`.
+async fn connect_in(
+    interface: Option<&Interface>,
+    range: PortRange,
+    addr: SocketAddr,
+) -> io::Result<TcpStream> {
+    for port in shuffled(range) {
+        let socket = match tcp_socket(interface, addr.is_ipv6(), port) {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            socket => socket?,
+        };
+        match socket.connect(addr).await {
+            // Another connection from this port goes to `addr` already.
+            Err(e) if e.kind() == io::ErrorKind::AddrNotAvailable => continue,
+            attempt => return attempt,
+        }
+    }
+    Err(all_taken(range))
+}
+
+async fn connect_from(
+    interface: Option<&Interface>,
+    port: u16,
+    addr: SocketAddr,
+) -> io::Result<TcpStream> {
+    tcp_socket(interface, addr.is_ipv6(), port)?
+        .connect(addr)
+        .await
+}
+
+/// A TCP socket bound to `port`, on `interface` if there is one.
+fn tcp_socket(interface: Option<&Interface>, ipv6: bool, port: u16) -> io::Result<TcpSocket> {
+    if let Some(interface) = interface {
+        return interface.tcp_socket(ipv6, port);
+    }
+    let socket = if ipv6 {
+        TcpSocket::new_v6()?
+    } else {
+        TcpSocket::new_v4()?
+    };
+    // So a source port whose last connection is in TIME_WAIT can be used
+    // again.
+    #[cfg(unix)]
+    socket.set_reuseaddr(true)?;
+    socket.bind(SocketAddr::new(unspecified(ipv6), port))?;
+    Ok(socket)
+}
+
+/// Listens for TCP connections on `port`, on `interface` if there is one,
+/// and otherwise on every IPv4 address.
+pub async fn listen(interface: Option<&Interface>, port: u16) -> io::Result<TcpListener> {
+    match interface {
+        Some(interface) => interface
+            .tcp_socket(interface.is_ipv6(), port)?
+            .listen(BACKLOG),
+        None => TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).await,
+    }
+}
+
+/// Opens a UDP socket for reaching IPv6 or IPv4 addresses, on `interface`
+/// if there is one, and on a port in `source_ports` if given, or otherwise
+/// any port.
+pub async fn udp(
+    interface: Option<&Interface>,
+    source_ports: Option<PortRange>,
+    ipv6: bool,
+) -> io::Result<UdpSocket> {
+    let Some(range) = source_ports else {
+        return udp_from(interface, ipv6, 0).await;
+    };
+    for port in shuffled(range) {
+        match udp_from(interface, ipv6, port).await {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            attempt => return attempt,
+        }
+    }
+    Err(all_taken(range))
+}
+
+async fn udp_from(interface: Option<&Interface>, ipv6: bool, port: u16) -> io::Result<UdpSocket> {
+    match interface {
+        Some(interface) => interface.udp_socket(ipv6, port).await,
+        None => UdpSocket::bind((unspecified(ipv6), port)).await,
+    }
+}
+
+/// Every port in `range`, starting from a random one, so that sockets
+/// opened one after another rarely try the same ports first.
+fn shuffled(range: PortRange) -> impl Iterator<Item = u16> {
+    let len = u32::from(range.last - range.first) + 1;
+    let start = rand::thread_rng().gen_range(0..len);
+    (0..len).map(move |i| range.first + ((start + i) % len) as u16)
+}
+
+fn all_taken(range: PortRange) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!("every source port in {} is in use", range),
+    )
+}
+
+/// The addresses of every interface, with their device names.
+#[cfg(unix)]
+pub fn addresses() -> io::Result<Vec<(String, IpAddr)>> {
+    use std::ffi::CStr;
+
+    let mut list: *mut libc::ifaddrs = std::ptr::null_mut();
+    // Safety: `list` is a valid out pointer, freed below.
+    if unsafe { libc::getifaddrs(&mut list) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut found = Vec::new();
+    let mut entry = list;
+    while !entry.is_null() {
+        // Safety: `entry` points into the list, which is still allocated;
+        // names are NUL-terminated and addresses are sized by family.
+        unsafe {
+            let ifa = &*entry;
+            let name = CStr::from_ptr(ifa.ifa_name).to_string_lossy().into_owned();
+            let ip = match ifa.ifa_addr.as_ref().map(|addr| addr.sa_family as i32) {
+                Some(libc::AF_INET) => {
+                    let addr = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                    Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                        addr.sin_addr.s_addr,
+                    ))))
+                }
+                Some(libc::AF_INET6) => {
+                    let addr = &*(ifa.ifa_addr as *const libc::sockaddr_in6);
+                    Some(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)))
+                }
+                _ => None,
+            };
+            if let Some(ip) = ip {
+                found.push((name, ip));
+            }
+            entry = ifa.ifa_next;
+        }
+    }
+    // Safety: `list` came from `getifaddrs` and is freed once.
+    unsafe { libc::freeifaddrs(list) };
+    Ok(found)
+}
+
+/// A public IPv4 and IPv6 address of this host, if it has them: ones
+/// peers on the internet can reach without a NAT in between, as far as the
+/// address tells.
+pub fn public_addresses() -> (Option<Ipv4Addr>, Option<Ipv6Addr>) {
+    let addresses = addresses().unwrap_or_default();
+    let ipv4 = addresses.iter().find_map(|(_, ip)| match ip {
+        IpAddr::V4(ip) if is_public_v4(ip) => Some(*ip),
+        _ => None,
+    });
+    let ipv6 = addresses.iter().find_map(|(_, ip)| match ip {
+        IpAddr::V6(ip) if is_public_v6(ip) => Some(*ip),
+        _ => None,
+    });
+    (ipv4, ipv6)
+}
+
+/// Interfaces cannot be listed here, so only addresses can name one.
+#[cfg(not(unix))]
+pub fn addresses() -> io::Result<Vec<(String, IpAddr)>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "interfaces cannot be named on this platform; use an address",
+    ))
+}
+
+fn missing(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("interface {} does not exist or has no address", name),
+    )
+}
+
+fn family(ipv6: bool) -> &'static str {
+    if ipv6 {
+        "IPv6"
+    } else {
+        "IPv4"
+    }
+}
+
+fn unspecified(ipv6: bool) -> IpAddr {
+    if ipv6 {
+        Ipv6Addr::UNSPECIFIED.into()
+    } else {
+        Ipv4Addr::UNSPECIFIED.into()
+    }
+}
+
+fn is_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+fn is_public_v4(ip: &Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    // 100.64.0.0/10 is shared by carrier-grade NATs.
+    let shared = first == 100 && second & 0xc0 == 64;
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || shared)
+}
+
+fn is_public_v6(ip: &Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // Global unicast addresses are 2000::/3, except for the documentation
+    // prefix.
+    segments[0] & 0xe000 == 0x2000 && segments[..2] != [0x2001, 0x0db8]
+}
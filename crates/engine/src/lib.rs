@@ -0,0 +1,67 @@
+//! A minimalist BitTorrent client library.
+//!
+//! The client itself runs on Tokio and is built with the `tokio` feature,
+//! on by default. Without it the rest compiles on its own, with no async
+//! runtime: metainfo and magnet links, piece picking, verification and
+//! storage. Bencode and the peer wire protocol are crates of their own,
+//! `rainyday-bencode` and `rainyday-proto`, re-exported here as [`bencode`]
+//! and [`protocol`].
+
+pub mod bitfield;
+#[cfg(feature = "tokio")]
+pub mod blocklist;
+pub mod capture;
+#[cfg(feature = "tokio")]
+pub mod config;
+#[cfg(feature = "tokio")]
+pub mod control;
+pub mod create;
+#[cfg(feature = "tokio")]
+pub mod dht;
+pub mod edit;
+#[cfg(feature = "tokio")]
+pub mod engine;
+pub mod event;
+#[cfg(feature = "faults")]
+pub mod faults;
+pub mod files;
+pub mod fsutil;
+pub mod hash;
+#[cfg(feature = "tokio")]
+pub mod hooks;
+#[cfg(feature = "tokio")]
+pub mod import;
+#[cfg(feature = "tokio")]
+pub mod input;
+#[cfg(feature = "tokio")]
+pub mod interface;
+#[cfg(feature = "tokio")]
+pub mod limits;
+pub mod logfile;
+pub mod magnet;
+pub mod memory;
+pub mod metainfo;
+pub mod peer;
+pub mod picker;
+pub mod pool;
+#[cfg(feature = "tokio")]
+pub mod portmap;
+#[cfg(feature = "tokio")]
+pub mod proxy;
+pub mod rate;
+pub mod resume;
+#[cfg(feature = "tokio")]
+pub mod schedule;
+#[cfg(feature = "tokio")]
+pub mod session;
+pub mod stats;
+pub mod storage;
+pub mod swarm;
+pub mod torrent;
+#[cfg(feature = "tokio")]
+pub mod tracker;
+pub mod verify;
+
+pub use rainyday_bencode as bencode;
+pub use rainyday_proto as protocol;
+pub use rainyday_proto::info_hash;
@@ -0,0 +1,142 @@
+//! Limits shared by every torrent in a session.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{sleep, Instant};
+
+use crate::config::Config;
+use crate::memory::MemoryBudget;
+
+/// Proof that a peer connection fits within the session's limit. The slot
+/// is freed when the permit is dropped.
+pub type ConnectionPermit = OwnedSemaphorePermit;
+
+/// Cloning yields another handle to the same limits.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    connections: Arc<Semaphore>,
+    pub download: RateLimit,
+    pub upload: RateLimit,
+    pub memory: MemoryBudget,
+    /// A single torrent's own limits, applied on top of the session's.
+    torrent_download: RateLimit,
+    torrent_upload: RateLimit,
+}
+
+impl Limits {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            connections: Arc::new(Semaphore::new(config.network.max_connections)),
+            download: RateLimit::new(config.limits.download_rate),
+            upload: RateLimit::new(config.limits.upload_rate),
+            memory: MemoryBudget::new(config.limits.memory_budget),
+            torrent_download: RateLimit::new(None),
+            torrent_upload: RateLimit::new(None),
+        }
+    }
+
+    /// The session's limits for one torrent, which may also be held to
+    /// rates of its own.
+    pub fn for_torrent(&self, download: Option<u64>, upload: Option<u64>) -> Self {
+        Self {
+            torrent_download: RateLimit::new(download),
+            torrent_upload: RateLimit::new(upload),
+            ..self.clone()
+        }
+    }
+
+    /// This torrent's own download limit.
+    pub fn torrent_download(&self) -> &RateLimit {
+        &self.torrent_download
+    }
+
+    /// This torrent's own upload limit.
+    pub fn torrent_upload(&self) -> &RateLimit {
+        &self.torrent_upload
+    }
+
+    /// Waits until `bytes` more may be downloaded.
+    pub async fn acquire_download(&self, bytes: usize) {
+        self.download.acquire(bytes).await;
+        self.torrent_download.acquire(bytes).await;
+    }
+
+    /// Waits until `bytes` more may be uploaded.
+    pub async fn acquire_upload(&self, bytes: usize) {
+        self.upload.acquire(bytes).await;
+        self.torrent_upload.acquire(bytes).await;
+    }
+
+    /// Takes a connection slot, if any is free.
+    pub fn connection(&self) -> Option<ConnectionPermit> {
+        Arc::clone(&self.connections).try_acquire_owned().ok()
+    }
+
+    /// Connection slots not in use by any torrent.
+    pub fn free_connections(&self) -> usize {
+        self.connections.available_permits()
+    }
+}
+
+/// A token bucket holding up to a second's worth of bytes. Cloning yields
+/// another handle to the same bucket.
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes per second, or `None` for no limit.
+    rate: Option<u64>,
+    /// Bytes that may be sent now; negative when callers are waiting.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimit {
+    pub fn new(rate: Option<u64>) -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                rate,
+                tokens: rate.unwrap_or(0) as f64,
+                refilled: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn rate(&self) -> Option<u64> {
+        self.bucket.lock().unwrap().rate
+    }
+
+    /// Changes the limit, taking effect for the next transfer.
+    pub fn set_rate(&self, rate: Option<u64>) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.rate = rate;
+        bucket.tokens = bucket.tokens.min(rate.unwrap_or(0) as f64);
+    }
+
+    /// Accounts for `bytes` about to be transferred, waiting until the
+    /// limit allows it.
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let rate = match bucket.rate {
+                Some(rate) if rate > 0 => rate as f64,
+                _ => return,
+            };
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+            bucket.refilled = now;
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        };
+        sleep(wait).await;
+    }
+}
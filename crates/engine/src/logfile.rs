@@ -0,0 +1,190 @@
+//! Logging to a file as well as the console: the `[logging]` section, and
+//! a writer that starts a new file once the current one grows too large or
+//! a day has passed.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::level_filters::LevelFilter;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The `[logging]` section.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Levels for modules or subsystems, such as `tracker = "debug"`, on
+    /// the console and in the file alike.
+    pub levels: BTreeMap<String, Level>,
+    /// File to write JSON lines to, as well as logging to the console.
+    pub file: Option<PathBuf>,
+    /// Least severe messages written to the file.
+    pub file_level: Level,
+    /// Bytes after which a new file is started, or 0 for no limit.
+    pub max_size: u64,
+    /// Start a new file at midnight UTC.
+    pub daily: bool,
+    /// Earlier files to keep, as `rainyday.log.1` and so on.
+    pub keep: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            levels: BTreeMap::new(),
+            file: None,
+            file_level: Level(LevelFilter::INFO),
+            max_size: 10 * 1024 * 1024,
+            daily: false,
+            keep: 5,
+        }
+    }
+}
+
+/// A level such as `"info"`, or `"off"`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Level(pub LevelFilter);
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Level).map_err(|_| {
+            format!(
+                "invalid level {:?}; expected off, error, warn, info, debug or trace",
+                s
+            )
+        })
+    }
+}
+
+impl TryFrom<String> for Level {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Level> for String {
+    fn from(level: Level) -> Self {
+        level.to_string()
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // LevelFilter displays in upper case; configuration files use lower.
+        f.write_str(&self.0.to_string().to_ascii_lowercase())
+    }
+}
+
+/// A log file that is moved aside as `<name>.1`, shifting older ones up,
+/// when it is due to be rotated.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    daily: bool,
+    keep: usize,
+    file: File,
+    size: u64,
+    /// Day the current file was started on, counted from the epoch.
+    day: u64,
+}
+
+impl RotatingFile {
+    /// Opens the file named in `config`, appending to it unless it is
+    /// already due to be rotated.
+    pub fn open(path: &Path, config: &LoggingConfig) -> io::Result<Self> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let file = append(path)?;
+        let metadata = file.metadata()?;
+        let day = metadata.modified().map(day_of).unwrap_or_else(|_| today());
+        let mut log = Self {
+            path: path.to_path_buf(),
+            max_size: config.max_size,
+            daily: config.daily,
+            keep: config.keep,
+            file,
+            size: metadata.len(),
+            day,
+        };
+        if log.is_due(0) {
+            log.rotate()?;
+        }
+        Ok(log)
+    }
+
+    /// Whether writing `len` more bytes calls for a new file first.
+    fn is_due(&self, len: usize) -> bool {
+        let too_big = self.max_size > 0 && self.size > 0 && self.size + len as u64 > self.max_size;
+        let too_old = self.daily && self.day != today();
+        too_big || too_old
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let numbered = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = numbered(n);
+                if from.exists() {
+                    fs::rename(&from, numbered(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+        self.file = append(&self.path)?;
+        self.size = 0;
+        self.day = today();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.is_due(buf.len()) {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn day_of(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() / SECONDS_PER_DAY)
+}
+
+fn today() -> u64 {
+    day_of(SystemTime::now())
+}
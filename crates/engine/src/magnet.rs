@@ -0,0 +1,173 @@
+//! Magnet links (BEP 9).
+
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::info_hash::InfoHash;
+use crate::metainfo::Metainfo;
+
+/// Prefix identifying a magnet link.
+pub const SCHEME: &str = "magnet:?";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum MagnetError {
+    #[error("not a magnet link")]
+    NotMagnet,
+    #[error("magnet link has no BitTorrent info hash")]
+    MissingInfoHash,
+    #[error("invalid info hash {0:?} in magnet link")]
+    InvalidInfoHash(String),
+}
+
+/// The parts of a magnet link rainyday understands.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Magnet {
+    pub info_hash: InfoHash,
+    /// Display name, shown until the metadata arrives.
+    pub name: Option<String>,
+    pub trackers: Vec<String>,
+    pub web_seeds: Vec<String>,
+}
+
+impl Magnet {
+    /// The magnet link for an existing torrent.
+    pub fn from_metainfo(metainfo: &Metainfo) -> Self {
+        Self {
+            info_hash: metainfo.info_hash,
+            name: Some(metainfo.info.name.clone()),
+            trackers: metainfo.trackers().into_iter().flatten().collect(),
+            web_seeds: metainfo.url_list.clone(),
+        }
+    }
+}
+
+impl FromStr for Magnet {
+    type Err = MagnetError;
+
+    /// Parses a magnet link. Parameters other than `xt`, `dn`, `tr` and
+    /// `ws` are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let query = s.strip_prefix(SCHEME).ok_or(MagnetError::NotMagnet)?;
+
+        let mut info_hash = None;
+        let mut name = None;
+        let mut trackers = Vec::new();
+        let mut web_seeds = Vec::new();
+        for pair in query.split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode(value);
+            // Numbered forms such as `tr.1` are treated like the plain key.
+            match key.split('.').next().unwrap_or(key) {
+                "xt" => {
+                    if let Some(hash) = value.strip_prefix("urn:btih:") {
+                        info_hash = Some(
+                            parse_info_hash(hash)
+                                .ok_or_else(|| MagnetError::InvalidInfoHash(hash.to_string()))?,
+                        );
+                    }
+                }
+                "dn" => name = Some(value),
+                "tr" => trackers.push(value),
+                "ws" => web_seeds.push(value),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            info_hash: info_hash.ok_or(MagnetError::MissingInfoHash)?,
+            name,
+            trackers,
+            web_seeds,
+        })
+    }
+}
+
+/// Accepts both the hex and the older base32 form of the info hash.
+fn parse_info_hash(s: &str) -> Option<InfoHash> {
+    match s.len() {
+        40 => InfoHash::from_hex(s),
+        32 => {
+            let mut bytes = [0u8; 20];
+            let mut bits = 0u64;
+            let mut count = 0;
+            let mut out = 0;
+            for c in s.bytes() {
+                let digit = match c.to_ascii_uppercase() {
+                    c @ b'A'..=b'Z' => c - b'A',
+                    c @ b'2'..=b'7' => c - b'2' + 26,
+                    _ => return None,
+                };
+                bits = (bits << 5) | digit as u64;
+                count += 5;
+                if count >= 8 {
+                    count -= 8;
+                    bytes[out] = (bits >> count) as u8;
+                    out += 1;
+                }
+            }
+            Some(InfoHash(bytes))
+        }
+        _ => None,
+    }
+}
+
+/// Percent-encodes raw bytes, leaving only RFC 3986 unreserved characters.
+pub(crate) fn percent_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 3);
+    for &b in bytes {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// Undoes percent-encoding, also treating `+` as a space.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => match bytes.get(i + 1..i + 3).and_then(hex_byte) {
+                Some(byte) => {
+                    out.push(byte);
+                    i += 3;
+                    continue;
+                }
+                None => out.push(b'%'),
+            },
+            b'+' => out.push(b' '),
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn hex_byte(digits: &[u8]) -> Option<u8> {
+    if !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+}
+
+impl fmt::Display for Magnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "magnet:?xt=urn:btih:{}", self.info_hash)?;
+        if let Some(name) = &self.name {
+            write!(f, "&dn={}", percent_encode(name.as_bytes()))?;
+        }
+        for tracker in &self.trackers {
+            write!(f, "&tr={}", percent_encode(tracker.as_bytes()))?;
+        }
+        for seed in &self.web_seeds {
+            write!(f, "&ws={}", percent_encode(seed.as_bytes()))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+//! A budget for the piece data held in memory, shared by every torrent.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Bytes of piece data that may be held in memory at once, reserved a piece
+/// at a time. Cloning yields another handle to the same budget.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBudget {
+    /// `None` for no limit.
+    max: Option<u64>,
+    used: Arc<AtomicU64>,
+}
+
+impl MemoryBudget {
+    pub fn new(max: Option<u64>) -> Self {
+        Self {
+            max,
+            used: Arc::default(),
+        }
+    }
+
+    /// Reserves `bytes` until the reservation is dropped, if they fit. So
+    /// that a budget smaller than a piece cannot stall every download, a
+    /// reservation is always granted while nothing else is reserved.
+    pub fn reserve(&self, bytes: u64) -> Option<MemoryReservation> {
+        if let Some(max) = self.max {
+            self.used
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                    (used == 0 || used + bytes <= max).then_some(used + bytes)
+                })
+                .ok()?;
+        } else {
+            self.used.fetch_add(bytes, Ordering::AcqRel);
+        }
+        Some(MemoryReservation {
+            used: Arc::clone(&self.used),
+            bytes,
+        })
+    }
+}
+
+/// Bytes taken from a [`MemoryBudget`], given back when dropped.
+#[derive(Debug)]
+pub struct MemoryReservation {
+    used: Arc<AtomicU64>,
+    bytes: u64,
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.used.fetch_sub(self.bytes, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,426 @@
+//! Parsing of `.torrent` metainfo files (BEP 3, BEP 12, BEP 19).
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::bencode::{self, BencodeError, Value};
+use crate::hash;
+use crate::info_hash::InfoHash;
+use crate::storage::sanitize::{disambiguate, sanitize_component, sanitize_path, PathError};
+
+#[derive(Debug, Error)]
+pub enum MetainfoError {
+    #[error("could not read metainfo file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("metainfo is not valid bencode: {0}")]
+    Bencode(#[from] BencodeError),
+    #[error("metainfo is missing the `{0}` field")]
+    MissingField(&'static str),
+    #[error("metainfo field `{0}` is invalid")]
+    InvalidField(&'static str),
+    #[error("metainfo contains an unsafe path: {0}")]
+    UnsafePath(#[from] PathError),
+    #[error("metainfo field `{0}` does not follow the specification")]
+    OffSpec(&'static str),
+}
+
+/// One file of a torrent, with its path relative to the torrent's root.
+///
+/// Paths are sanitized while parsing, so they are always safe to join onto
+/// a download directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileEntry {
+    pub path: Vec<String>,
+    pub length: u64,
+}
+
+impl FileEntry {
+    pub fn path_buf(&self) -> PathBuf {
+        self.path.iter().collect()
+    }
+}
+
+/// The contents of the info dictionary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Info {
+    pub name: String,
+    pub piece_length: u32,
+    pub pieces: Vec<[u8; 20]>,
+    pub private: bool,
+    pub files: Vec<FileEntry>,
+    /// Whether the torrent is a single file rather than a directory.
+    pub single_file: bool,
+}
+
+impl Info {
+    pub fn total_length(&self) -> u64 {
+        self.files.iter().map(|file| file.length).sum()
+    }
+
+    pub fn num_pieces(&self) -> usize {
+        self.pieces.len()
+    }
+
+    pub fn piece_hash(&self, piece: u32) -> Option<&[u8; 20]> {
+        self.pieces.get(piece as usize)
+    }
+
+    /// Length of `piece` in bytes; only the last piece may be short.
+    pub fn piece_size(&self, piece: u32) -> u32 {
+        let start = piece as u64 * self.piece_length as u64;
+        self.total_length()
+            .saturating_sub(start)
+            .min(self.piece_length as u64) as u32
+    }
+}
+
+/// A parsed `.torrent` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metainfo {
+    pub announce: Option<String>,
+    pub announce_list: Vec<Vec<String>>,
+    pub comment: Option<String>,
+    pub created_by: Option<String>,
+    pub creation_date: Option<i64>,
+    pub url_list: Vec<String>,
+    pub info: Info,
+    pub info_hash: InfoHash,
+    /// The info dictionary exactly as it appeared in the file.
+    pub info_bytes: Vec<u8>,
+}
+
+impl Metainfo {
+    /// Parses a `.torrent` file, tolerating the deviations from the
+    /// specification that torrent makers commonly produce.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MetainfoError> {
+        Self::parse(bytes, false)
+    }
+
+    /// Parses a `.torrent` file. If `pedantic` is set, the file must be
+    /// canonically encoded and every field it has must be of the type the
+    /// specifications give it.
+    pub fn parse(bytes: &[u8], pedantic: bool) -> Result<Self, MetainfoError> {
+        let root = if pedantic {
+            bencode::decode_canonical(bytes)?
+        } else {
+            bencode::decode(bytes)?
+        };
+        if root.as_dict().is_none() {
+            return Err(MetainfoError::InvalidField("root"));
+        }
+        if pedantic {
+            lint(&root)?;
+        }
+
+        let info_bytes = bencode::raw_value(bytes, "info")?
+            .ok_or(MetainfoError::MissingField("info"))?
+            .to_vec();
+        let info = parse_info(root.get("info").unwrap())?;
+        let info_hash = InfoHash(hash::sha1(&info_bytes));
+
+        let announce_list = root
+            .get("announce-list")
+            .and_then(Value::as_list)
+            .map(|tiers| {
+                tiers
+                    .iter()
+                    .filter_map(Value::as_list)
+                    .map(|tier| tier.iter().filter_map(string).collect::<Vec<_>>())
+                    .filter(|tier| !tier.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let url_list = match root.get("url-list") {
+            Some(Value::List(urls)) => urls.iter().filter_map(string).collect(),
+            Some(url) => string(url).into_iter().collect(),
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            announce: root.get("announce").and_then(string),
+            announce_list,
+            comment: root.get("comment").and_then(string),
+            created_by: root.get("created by").and_then(string),
+            creation_date: root.get("creation date").and_then(Value::as_int),
+            url_list,
+            info,
+            info_hash,
+            info_bytes,
+        })
+    }
+
+    /// Builds a metainfo around an info dictionary fetched from peers, for
+    /// torrents added by magnet link.
+    pub fn from_info_bytes(
+        info_bytes: Vec<u8>,
+        trackers: Vec<Vec<String>>,
+        url_list: Vec<String>,
+    ) -> Result<Self, MetainfoError> {
+        let info = parse_info(&bencode::decode(&info_bytes)?)?;
+        Ok(Self {
+            announce: trackers.first().and_then(|tier| tier.first()).cloned(),
+            announce_list: trackers,
+            comment: None,
+            created_by: None,
+            creation_date: None,
+            url_list,
+            info,
+            info_hash: InfoHash(hash::sha1(&info_bytes)),
+            info_bytes,
+        })
+    }
+
+    /// Encodes the metainfo as a `.torrent` file. The info dictionary is
+    /// written exactly as it was read, so the info hash is unchanged.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut root = BTreeMap::new();
+        if let Some(announce) = &self.announce {
+            root.insert(b"announce".to_vec(), Value::from(announce.as_str()));
+        }
+        if !self.announce_list.is_empty() {
+            let tiers = self
+                .announce_list
+                .iter()
+                .map(|tier| Value::List(tier.iter().map(|url| Value::from(url.as_str())).collect()))
+                .collect();
+            root.insert(b"announce-list".to_vec(), Value::List(tiers));
+        }
+        if let Some(comment) = &self.comment {
+            root.insert(b"comment".to_vec(), Value::from(comment.as_str()));
+        }
+        if let Some(created_by) = &self.created_by {
+            root.insert(b"created by".to_vec(), Value::from(created_by.as_str()));
+        }
+        if let Some(date) = self.creation_date {
+            root.insert(b"creation date".to_vec(), Value::Integer(date));
+        }
+        if !self.url_list.is_empty() {
+            let urls = self
+                .url_list
+                .iter()
+                .map(|url| Value::from(url.as_str()))
+                .collect();
+            root.insert(b"url-list".to_vec(), Value::List(urls));
+        }
+        encode(&root, &self.info_bytes)
+    }
+
+    /// Tracker URLs grouped in tiers, falling back to `announce` when there
+    /// is no announce list.
+    pub fn trackers(&self) -> Vec<Vec<String>> {
+        if !self.announce_list.is_empty() {
+            self.announce_list.clone()
+        } else {
+            self.announce.iter().map(|url| vec![url.clone()]).collect()
+        }
+    }
+}
+
+impl TryFrom<&Path> for Metainfo {
+    type Error = MetainfoError;
+
+    fn try_from(path: &Path) -> Result<Self, Self::Error> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+/// Encodes the top-level dictionary of a `.torrent` file, with `info_bytes`
+/// spliced in verbatim as its `info` entry. Any `info` entry in `root` is
+/// ignored.
+pub fn encode(root: &BTreeMap<Vec<u8>, Value>, info_bytes: &[u8]) -> Vec<u8> {
+    let mut out = vec![b'd'];
+    let mut info = Some(info_bytes);
+    for (key, value) in root {
+        if key.as_slice() == b"info" {
+            continue;
+        }
+        if key.as_slice() > b"info".as_slice() {
+            if let Some(info_bytes) = info.take() {
+                write_info(&mut out, info_bytes);
+            }
+        }
+        Value::Bytes(key.clone()).encode_into(&mut out);
+        value.encode_into(&mut out);
+    }
+    if let Some(info_bytes) = info {
+        write_info(&mut out, info_bytes);
+    }
+    out.push(b'e');
+    out
+}
+
+fn write_info(out: &mut Vec<u8>, info_bytes: &[u8]) {
+    Value::from("info").encode_into(out);
+    out.extend_from_slice(info_bytes);
+}
+
+/// Rejects fields that the lenient parser would skip over or coerce.
+fn lint(root: &Value) -> Result<(), MetainfoError> {
+    let is_str = |value: &Value| value.as_str().is_some();
+    let all_str = |value: &Value| value.as_list().is_some_and(|list| list.iter().all(is_str));
+
+    if root.get("announce").is_some_and(|url| !is_str(url)) {
+        return Err(MetainfoError::OffSpec("announce"));
+    }
+    if let Some(tiers) = root.get("announce-list") {
+        let valid = tiers
+            .as_list()
+            .is_some_and(|tiers| tiers.iter().all(all_str));
+        if !valid {
+            return Err(MetainfoError::OffSpec("announce-list"));
+        }
+    }
+    if root
+        .get("url-list")
+        .is_some_and(|urls| !is_str(urls) && !all_str(urls))
+    {
+        return Err(MetainfoError::OffSpec("url-list"));
+    }
+    if root
+        .get("creation date")
+        .is_some_and(|date| date.as_int().is_none())
+    {
+        return Err(MetainfoError::OffSpec("creation date"));
+    }
+
+    let info = root
+        .get("info")
+        .ok_or(MetainfoError::MissingField("info"))?;
+    if info.get("name").is_some_and(|name| !is_str(name)) {
+        return Err(MetainfoError::OffSpec("name"));
+    }
+    if info
+        .get("private")
+        .is_some_and(|private| !matches!(private.as_int(), Some(0 | 1)))
+    {
+        return Err(MetainfoError::OffSpec("private"));
+    }
+    for file in info.get("files").and_then(Value::as_list).unwrap_or(&[]) {
+        let valid = file
+            .get("path")
+            .and_then(Value::as_list)
+            .is_some_and(|path| {
+                path.iter()
+                    .all(|component| component.as_str().is_some_and(|c| !c.is_empty()))
+            });
+        if !valid {
+            return Err(MetainfoError::OffSpec("path"));
+        }
+    }
+    Ok(())
+}
+
+fn string(value: &Value) -> Option<String> {
+    value
+        .as_bytes()
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn parse_info(info: &Value) -> Result<Info, MetainfoError> {
+    if info.as_dict().is_none() {
+        return Err(MetainfoError::InvalidField("info"));
+    }
+
+    let name = info
+        .get("name.utf-8")
+        .or_else(|| info.get("name"))
+        .and_then(string)
+        .ok_or(MetainfoError::MissingField("name"))?;
+    let name = sanitize_component(&name)?;
+
+    let piece_length = info
+        .get("piece length")
+        .ok_or(MetainfoError::MissingField("piece length"))?
+        .as_int()
+        .and_then(|len| u32::try_from(len).ok())
+        .filter(|&len| len > 0)
+        .ok_or(MetainfoError::InvalidField("piece length"))?;
+
+    let pieces = info
+        .get("pieces")
+        .ok_or(MetainfoError::MissingField("pieces"))?
+        .as_bytes()
+        .filter(|pieces| pieces.len() % 20 == 0)
+        .ok_or(MetainfoError::InvalidField("pieces"))?
+        .chunks_exact(20)
+        .map(|chunk| {
+            let mut hash = [0u8; 20];
+            hash.copy_from_slice(chunk);
+            hash
+        })
+        .collect();
+
+    let (files, single_file) = match (info.get("length"), info.get("files")) {
+        (Some(length), None) => {
+            let length = length
+                .as_int()
+                .and_then(|len| u64::try_from(len).ok())
+                .ok_or(MetainfoError::InvalidField("length"))?;
+            (
+                vec![FileEntry {
+                    path: vec![name.clone()],
+                    length,
+                }],
+                true,
+            )
+        }
+        (None, Some(files)) => (parse_files(files)?, false),
+        (Some(_), Some(_)) => return Err(MetainfoError::InvalidField("files")),
+        (None, None) => return Err(MetainfoError::MissingField("length")),
+    };
+
+    let private = info.get("private").and_then(Value::as_int) == Some(1);
+
+    let info = Info {
+        name,
+        piece_length,
+        pieces,
+        private,
+        files,
+        single_file,
+    };
+
+    let expected = info.total_length().div_ceil(info.piece_length as u64);
+    if info.pieces.len() as u64 != expected {
+        return Err(MetainfoError::InvalidField("pieces"));
+    }
+
+    Ok(info)
+}
+
+fn parse_files(files: &Value) -> Result<Vec<FileEntry>, MetainfoError> {
+    let mut files = files
+        .as_list()
+        .ok_or(MetainfoError::InvalidField("files"))?
+        .iter()
+        .map(|file| {
+            let length = file
+                .get("length")
+                .and_then(Value::as_int)
+                .and_then(|len| u64::try_from(len).ok())
+                .ok_or(MetainfoError::InvalidField("length"))?;
+            let path = file
+                .get("path.utf-8")
+                .or_else(|| file.get("path"))
+                .and_then(Value::as_list)
+                .ok_or(MetainfoError::MissingField("path"))?
+                .iter()
+                .map(|component| string(component).ok_or(MetainfoError::InvalidField("path")))
+                .collect::<Result<Vec<_>, _>>()?;
+            if path.is_empty() {
+                return Err(MetainfoError::InvalidField("path"));
+            }
+            Ok(FileEntry {
+                path: sanitize_path(&path)?,
+                length,
+            })
+        })
+        .collect::<Result<Vec<_>, MetainfoError>>()?;
+    disambiguate(files.iter_mut().map(|file| &mut file.path));
+    Ok(files)
+}
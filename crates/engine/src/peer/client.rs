@@ -0,0 +1,191 @@
+//! Identifying the software a remote peer is running.
+
+use std::fmt;
+
+use super::PeerId;
+
+/// Azureus-style two-letter client codes, as used in `-XX1234-` peer IDs.
+const AZUREUS_CLIENTS: &[(&str, &str)] = &[
+    ("AG", "Ares"),
+    ("AZ", "Vuze"),
+    ("BC", "BitComet"),
+    ("BI", "BiglyBT"),
+    ("BT", "BitTorrent"),
+    ("BW", "BitWombat"),
+    ("DE", "Deluge"),
+    ("FD", "Free Download Manager"),
+    ("FL", "Folx"),
+    ("FW", "FrostWire"),
+    ("HL", "Halite"),
+    ("KT", "KTorrent"),
+    ("LT", "libtorrent (Rasterbar)"),
+    ("lt", "libTorrent (rakshasa)"),
+    ("LW", "LimeWire"),
+    ("PI", "PicoTorrent"),
+    ("qB", "qBittorrent"),
+    ("RD", "rainyday"),
+    ("SD", "Thunder"),
+    ("TL", "Tribler"),
+    ("TR", "Transmission"),
+    ("UM", "µTorrent Mac"),
+    ("UT", "µTorrent"),
+    ("UW", "µTorrent Web"),
+    ("WD", "WebTorrent Desktop"),
+    ("WW", "WebTorrent"),
+    ("XL", "Xunlei"),
+];
+
+/// Shadow-style single-letter client codes, as used in `S58B-----` peer IDs.
+const SHADOW_CLIENTS: &[(u8, &str)] = &[
+    (b'A', "ABC"),
+    (b'O', "Osprey Permaseed"),
+    (b'Q', "BTQueue"),
+    (b'R', "Tribler"),
+    (b'S', "Shadow's client"),
+    (b'T', "BitTornado"),
+    (b'U', "UPnP NAT Bit Torrent"),
+];
+
+/// A remote client's name and, where it could be determined, its version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl ClientInfo {
+    /// Decodes a peer ID following the Azureus, Shadow or Mainline
+    /// conventions. Returns `None` for IDs that follow none of them.
+    pub fn from_peer_id(id: &PeerId) -> Option<Self> {
+        let id = id.as_bytes();
+        azureus(id).or_else(|| mainline(id)).or_else(|| shadow(id))
+    }
+
+    /// Interprets the free-form `v` string of an extension handshake, such
+    /// as `qBittorrent/4.3.9` or `Transmission 3.00`.
+    pub fn from_version_string(v: &str) -> Self {
+        let v = v.trim();
+        let split = v
+            .rfind([' ', '/'])
+            .filter(|&i| v[i + 1..].starts_with(|c: char| c.is_ascii_digit()));
+
+        match split {
+            Some(i) => Self {
+                name: v[..i].trim().to_string(),
+                version: Some(v[i + 1..].to_string()),
+            },
+            None => Self {
+                name: v.to_string(),
+                version: None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ClientInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{} {}", self.name, version),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// `-XXVVVV-`: two-letter client code and four version characters.
+fn azureus(id: &[u8; 20]) -> Option<ClientInfo> {
+    if id[0] != b'-' || id[7] != b'-' {
+        return None;
+    }
+
+    let code = std::str::from_utf8(&id[1..3]).ok()?;
+    if !code.bytes().all(|b| b.is_ascii_alphabetic()) {
+        return None;
+    }
+    let digits = id[3..7]
+        .iter()
+        .map(|&c| version_digit(c))
+        .collect::<Option<Vec<_>>>()?;
+
+    let name = AZUREUS_CLIENTS
+        .iter()
+        .find(|(known, _)| *known == code)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| format!("Unknown ({})", code));
+    let version = match code {
+        // Transmission packs major.minor into the first three digits.
+        "TR" if digits[0] == 0 => format!("{}.{}{}", digits[1], digits[2], digits[3]),
+        "TR" => format!("{}.{}{}", digits[0], digits[1], digits[2]),
+        _ => digits
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>()
+            .join("."),
+    };
+
+    Some(ClientInfo {
+        name,
+        version: Some(version),
+    })
+}
+
+/// `M4-3-6--`: Mainline's letter followed by dash-separated version numbers.
+fn mainline(id: &[u8; 20]) -> Option<ClientInfo> {
+    if !matches!(id[0], b'M' | b'Q') || id[1] == b'-' {
+        return None;
+    }
+
+    let head = std::str::from_utf8(&id[1..8]).ok()?;
+    let parts: Vec<&str> = head.trim_end_matches('-').split('-').collect();
+    let numeric = |p: &&str| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit());
+    if parts.len() != 3 || !parts.iter().all(numeric) {
+        return None;
+    }
+
+    Some(ClientInfo {
+        name: if id[0] == b'M' {
+            "BitTorrent (Mainline)"
+        } else {
+            "Queen Bee"
+        }
+        .to_string(),
+        version: Some(parts.join(".")),
+    })
+}
+
+/// `S58B-----`: one letter followed by up to five version characters.
+fn shadow(id: &[u8; 20]) -> Option<ClientInfo> {
+    let name = SHADOW_CLIENTS
+        .iter()
+        .find(|(code, _)| *code == id[0])
+        .map(|(_, name)| name)?;
+
+    let version: Vec<u8> = id[1..6]
+        .iter()
+        .take_while(|&&c| c != b'-')
+        .map(|&c| version_digit(c))
+        .collect::<Option<_>>()?;
+    if version.is_empty() || id[1 + version.len()..9].iter().any(|&c| c != b'-') {
+        return None;
+    }
+
+    Some(ClientInfo {
+        name: name.to_string(),
+        version: Some(
+            version
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+                .join("."),
+        ),
+    })
+}
+
+fn version_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'A'..=b'Z' => Some(c - b'A' + 10),
+        b'a'..=b'z' => Some(c - b'a' + 36),
+        b'.' => Some(62),
+        _ => None,
+    }
+}
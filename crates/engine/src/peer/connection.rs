@@ -0,0 +1,92 @@
+//! Establishing connections with peers, in either direction.
+
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::{debug, instrument};
+
+use super::encryption::{EncryptionPolicy, HandshakeKind};
+use super::task::handshake;
+use super::transport::{PeerStream, PeerTransport};
+use super::PeerError;
+use crate::protocol::handshake::HANDSHAKE_LEN;
+use crate::protocol::Handshake;
+
+/// Dials `addr` and performs the handshake, trying each handshake kind the
+/// policy allows in turn.
+#[instrument(name = "handshake", skip_all, fields(%addr, incoming = false))]
+pub async fn dial(
+    addr: SocketAddr,
+    ours: &Handshake,
+    policy: EncryptionPolicy,
+    transport: &dyn PeerTransport,
+) -> Result<(PeerStream, Handshake), PeerError> {
+    let mut last_error = PeerError::EncryptionUnsupported;
+
+    for &kind in policy.outgoing() {
+        let attempt = match kind {
+            HandshakeKind::Plaintext => dial_plaintext(addr, ours, transport).await,
+            HandshakeKind::Encrypted => Err(PeerError::EncryptionUnsupported),
+        };
+
+        match attempt {
+            Ok(connection) => return Ok(connection),
+            Err(e) => {
+                debug!(%addr, ?kind, "handshake failed: {}", e);
+                last_error = e;
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
+async fn dial_plaintext(
+    addr: SocketAddr,
+    ours: &Handshake,
+    transport: &dyn PeerTransport,
+) -> Result<(PeerStream, Handshake), PeerError> {
+    let mut stream = transport.connect(addr).await?;
+    let theirs = handshake(&mut stream, ours).await?;
+    Ok((stream, theirs))
+}
+
+/// Opens a connection to `addr` as a relay told us to (BEP 55), from our
+/// listen `port` where the transport can, and performs the handshake.
+#[instrument(name = "handshake", skip_all, fields(%addr, incoming = false, punched = true))]
+pub async fn punch(
+    addr: SocketAddr,
+    port: u16,
+    ours: &Handshake,
+    transport: &dyn PeerTransport,
+) -> Result<(PeerStream, Handshake), PeerError> {
+    let mut stream = transport.punch(addr, port).await?;
+    let theirs = handshake(&mut stream, ours).await?;
+    Ok((stream, theirs))
+}
+
+/// Reads the handshake of an incoming connection, enforcing the policy.
+///
+/// The caller replies with its own handshake once it has checked that the
+/// info hash belongs to a torrent it serves.
+pub async fn accept<S>(stream: &mut S, policy: EncryptionPolicy) -> Result<Handshake, PeerError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = [0u8; HANDSHAKE_LEN];
+    stream.read_exact(&mut buf[..20]).await?;
+
+    let mut prefix = [0u8; 20];
+    prefix.copy_from_slice(&buf[..20]);
+    let kind = HandshakeKind::detect(&prefix);
+
+    if !policy.accepts(kind) {
+        return Err(PeerError::EncryptionPolicy(kind));
+    }
+    if kind == HandshakeKind::Encrypted {
+        return Err(PeerError::EncryptionUnsupported);
+    }
+
+    stream.read_exact(&mut buf[20..]).await?;
+    Ok(Handshake::from_bytes(&buf)?)
+}
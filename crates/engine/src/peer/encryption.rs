@@ -0,0 +1,81 @@
+//! Policy deciding when connections use message stream encryption (MSE).
+//!
+//! MSE itself is not implemented yet, so the configuration accepts only
+//! [`EncryptionPolicy::Disabled`]; the other policies fail every
+//! encrypted handshake with [`PeerError::EncryptionUnsupported`].
+//!
+//! [`PeerError::EncryptionUnsupported`]: super::PeerError::EncryptionUnsupported
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::handshake::PROTOCOL;
+
+/// How a connection's handshake is carried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakeKind {
+    Plaintext,
+    Encrypted,
+}
+
+impl HandshakeKind {
+    /// Classifies an incoming connection from its first 20 bytes. Anything
+    /// other than the plaintext protocol header must be an MSE key exchange.
+    pub fn detect(prefix: &[u8; 20]) -> Self {
+        if prefix[0] as usize == PROTOCOL.len() && &prefix[1..] == PROTOCOL {
+            HandshakeKind::Plaintext
+        } else {
+            HandshakeKind::Encrypted
+        }
+    }
+}
+
+/// Session-wide stance on encrypted connections.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EncryptionPolicy {
+    /// Never encrypt, and refuse encrypted incoming connections.
+    #[default]
+    Disabled,
+    /// Dial in plaintext, but accept encrypted incoming connections.
+    AllowIncoming,
+    /// Try an encrypted handshake first and fall back to plaintext.
+    PreferOutgoing,
+    /// Only ever use encrypted connections, in both directions.
+    Require,
+}
+
+impl fmt::Display for EncryptionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            EncryptionPolicy::Disabled => "disabled",
+            EncryptionPolicy::AllowIncoming => "allow-incoming",
+            EncryptionPolicy::PreferOutgoing => "prefer-outgoing",
+            EncryptionPolicy::Require => "require",
+        })
+    }
+}
+
+impl EncryptionPolicy {
+    /// The handshakes to attempt, in order, when dialling a peer.
+    pub fn outgoing(self) -> &'static [HandshakeKind] {
+        match self {
+            EncryptionPolicy::Disabled | EncryptionPolicy::AllowIncoming => {
+                &[HandshakeKind::Plaintext]
+            }
+            EncryptionPolicy::PreferOutgoing => {
+                &[HandshakeKind::Encrypted, HandshakeKind::Plaintext]
+            }
+            EncryptionPolicy::Require => &[HandshakeKind::Encrypted],
+        }
+    }
+
+    /// Whether an incoming connection using `kind` may proceed.
+    pub fn accepts(self, kind: HandshakeKind) -> bool {
+        match kind {
+            HandshakeKind::Plaintext => self != EncryptionPolicy::Require,
+            HandshakeKind::Encrypted => self != EncryptionPolicy::Disabled,
+        }
+    }
+}
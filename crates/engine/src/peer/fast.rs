@@ -0,0 +1,66 @@
+//! Fast extension (BEP 6) helpers.
+
+use std::net::IpAddr;
+
+use crate::bitfield::Bitfield;
+use crate::hash;
+use crate::info_hash::InfoHash;
+use crate::protocol::Message;
+
+/// Number of allowed-fast pieces we grant each peer.
+pub const ALLOWED_FAST_COUNT: usize = 10;
+
+/// Computes the canonical allowed-fast set for a peer at `ip`.
+///
+/// Only IPv4 is specified by BEP 6, so IPv6 peers get an empty set. At most
+/// `num_pieces` indices are returned.
+pub fn allowed_fast_set(ip: IpAddr, info_hash: &InfoHash, num_pieces: u32, k: usize) -> Vec<u32> {
+    let ip = match ip {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => ip,
+            None => return Vec::new(),
+        },
+    };
+
+    let k = k.min(num_pieces as usize);
+    let mut set = Vec::with_capacity(k);
+    let mut x = Vec::with_capacity(24);
+    x.extend_from_slice(&(u32::from(ip) & 0xffff_ff00).to_be_bytes());
+    x.extend_from_slice(info_hash.as_bytes());
+
+    while set.len() < k {
+        x = hash::sha1(&x).to_vec();
+        for chunk in x.chunks_exact(4) {
+            if set.len() == k {
+                break;
+            }
+            let y = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            let index = y % num_pieces;
+            if !set.contains(&index) {
+                set.push(index);
+            }
+        }
+    }
+
+    set
+}
+
+/// Chooses the message announcing our pieces right after the handshake.
+///
+/// With the fast extension a full or empty bitfield is replaced by the
+/// compact HaveAll/HaveNone; without it an empty bitfield may be skipped
+/// entirely.
+pub fn availability_message(have: &Bitfield, fast: bool) -> Option<Message> {
+    if fast && have.is_full() {
+        Some(Message::HaveAll)
+    } else if have.is_none() {
+        if fast {
+            Some(Message::HaveNone)
+        } else {
+            None
+        }
+    } else {
+        Some(Message::Bitfield(have.as_bytes().to_vec()))
+    }
+}
@@ -0,0 +1,102 @@
+//! Reaching peers behind NATs by having a mutually connected peer relay a
+//! rendezvous (BEP 55).
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::time::{sleep, timeout};
+
+use crate::protocol::holepunch::{HolepunchError, HolepunchMessage};
+use crate::swarm::Swarm;
+
+/// How long to keep retrying a simultaneous open after a connect message.
+const PUNCH_WINDOW: Duration = Duration::from_secs(10);
+
+/// Delay between connection attempts within the punch window.
+const PUNCH_RETRY: Duration = Duration::from_millis(500);
+
+/// Handles a rendezvous request while acting as relay: `initiator`, whom
+/// we are connected to over `from`, asks to be introduced to `target`,
+/// whom we may be connected to over `to`, a connection that does or does
+/// not support holepunching. Returns the messages to send, each with the
+/// connection to send it over.
+pub fn relay(
+    from: SocketAddr,
+    initiator: SocketAddr,
+    target: SocketAddr,
+    to: Option<(SocketAddr, bool)>,
+) -> Vec<(SocketAddr, HolepunchMessage)> {
+    let error = match to {
+        _ if target == initiator || to.map(|(to, _)| to) == Some(from) => HolepunchError::NoSelf,
+        None => HolepunchError::NotConnected,
+        Some((_, false)) => HolepunchError::NoSupport,
+        Some((to, true)) => {
+            return vec![
+                (from, HolepunchMessage::Connect(target)),
+                (to, HolepunchMessage::Connect(initiator)),
+            ]
+        }
+    };
+    vec![(from, HolepunchMessage::Error(target, error))]
+}
+
+/// Rendezvous requests worth sending: one for each time a peer could not
+/// be dialled, addressed to a connected relay.
+pub fn rendezvous_requests(swarm: &mut Swarm) -> Vec<(SocketAddr, HolepunchMessage)> {
+    swarm
+        .take_holepunch_targets()
+        .into_iter()
+        .map(|(relay, target)| (relay, HolepunchMessage::Rendezvous(target)))
+        .collect()
+}
+
+/// Performs our half of a TCP simultaneous open with `target`.
+///
+/// Both sides dial each other at roughly the same time after receiving the
+/// relay's connect message, so the outgoing SYNs open the NAT mappings the
+/// other side's SYNs need. Binding to our listen port keeps the mapping the
+/// same one the relay observed.
+pub async fn punch(listen: SocketAddr, target: SocketAddr) -> io::Result<TcpStream> {
+    let attempt = async {
+        loop {
+            let socket = if target.is_ipv4() {
+                TcpSocket::new_v4()?
+            } else {
+                TcpSocket::new_v6()?
+            };
+            socket.set_reuseaddr(true)?;
+            #[cfg(unix)]
+            socket.set_reuseport(true)?;
+            socket.bind(SocketAddr::new(socket_ip(listen, target), listen.port()))?;
+
+            match socket.connect(target).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) if is_transient(&e) => sleep(PUNCH_RETRY).await,
+                Err(e) => return Err(e),
+            }
+        }
+    };
+
+    timeout(PUNCH_WINDOW, attempt)
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+}
+
+/// Binds to the unspecified address of the target's family unless the
+/// listen address already matches it.
+fn socket_ip(listen: SocketAddr, target: SocketAddr) -> std::net::IpAddr {
+    match (listen.ip(), target.ip()) {
+        (ip, t) if ip.is_ipv4() == t.is_ipv4() => ip,
+        (_, t) if t.is_ipv4() => std::net::Ipv4Addr::UNSPECIFIED.into(),
+        _ => std::net::Ipv6Addr::UNSPECIFIED.into(),
+    }
+}
+
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset | io::ErrorKind::TimedOut
+    )
+}
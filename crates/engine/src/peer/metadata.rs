@@ -0,0 +1,308 @@
+//! Fetching a torrent's info dictionary from peers (BEP 9), so it can be
+//! downloaded from nothing more than a magnet link.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use thiserror::Error;
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout};
+use tokio_util::codec::Framed;
+use tracing::{debug, info};
+
+use super::connection::dial;
+use super::encryption::EncryptionPolicy;
+use super::transport::{self, PeerTransport};
+use super::{PeerError, PeerId};
+use crate::blocklist::Blocklist;
+use crate::config::{Config, DEFAULT_LISTEN_PORT};
+use crate::dht;
+use crate::hash;
+use crate::magnet::Magnet;
+use crate::metainfo::{Metainfo, MetainfoError};
+use crate::protocol::extension::{ExtendedHandshake, HANDSHAKE_ID};
+use crate::protocol::metadata::{self, MetadataMessage, EXTENSION_NAME, LOCAL_ID, PIECE_SIZE};
+use crate::protocol::{Handshake, Message, MessageCodec, ProtocolError};
+use crate::tracker::{Announce, AnnounceEvent, Tracker, TrackerError};
+
+/// Largest info dictionary we accept from a peer.
+pub const MAX_METADATA_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Peers asked for the metadata at the same time.
+const PARALLEL_PEERS: usize = 8;
+/// How long a single peer gets to hand over the whole info dictionary.
+const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Wait between rounds of peer discovery when no peer could help.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Error)]
+pub enum MetadataError {
+    #[error("{0}")]
+    Peer(#[from] PeerError),
+    #[error("peer does not support metadata exchange")]
+    Unsupported,
+    #[error("peer claims the metadata is {0} bytes, which is too large")]
+    TooLarge(u64),
+    #[error("peer refused to send metadata piece {0}")]
+    Rejected(u32),
+    #[error("metadata does not match the info hash")]
+    HashMismatch,
+    #[error("peer closed the connection")]
+    Closed,
+    #[error("invalid metadata: {0}")]
+    Metainfo(#[from] MetainfoError),
+    #[error("cannot set up the tracker client: {0}")]
+    Tracker(#[from] TrackerError),
+}
+
+impl From<ProtocolError> for MetadataError {
+    fn from(error: ProtocolError) -> Self {
+        MetadataError::Peer(error.into())
+    }
+}
+
+/// Finds peers for `magnet` through its trackers and the DHT, and fetches
+/// the info dictionary from the first that has it, introducing ourselves
+/// as `peer_id`. Keeps looking until it succeeds.
+pub async fn fetch(
+    magnet: &Magnet,
+    config: &Config,
+    peer_id: PeerId,
+) -> Result<Metainfo, MetadataError> {
+    let mut ours = Handshake::new(magnet.info_hash, peer_id);
+    ours.set_extensions();
+    let tracker = Tracker::new()
+        .with_pedantic(config.pedantic)
+        .with_source_ports(config.network.source_ports)
+        .with_network(&config.proxy, config.network.interface.as_ref())?;
+    let blocklist = Blocklist::from_config(&config.blocklist);
+    let transport = transport::from_config(config);
+    let tiers: Vec<Vec<String>> = magnet
+        .trackers
+        .iter()
+        .map(|url| vec![url.clone()])
+        .collect();
+
+    loop {
+        let mut peers = discover(&tracker, &tiers, &ours, magnet, config).await;
+        peers.retain(|addr| !blocklist.contains(&addr.ip()));
+        info!(peers = peers.len(), "asking peers for metadata");
+
+        let mut attempts = JoinSet::new();
+        let mut queue = peers.into_iter();
+        for addr in queue.by_ref().take(PARALLEL_PEERS) {
+            attempts.spawn(attempt(
+                addr,
+                ours,
+                config.network.encryption,
+                Arc::clone(&transport),
+            ));
+        }
+        while let Some(result) = attempts.join_next().await {
+            if let Ok(Ok(info_bytes)) = result {
+                let trackers = tiers.clone();
+                let web_seeds = magnet.web_seeds.clone();
+                return Ok(Metainfo::from_info_bytes(info_bytes, trackers, web_seeds)?);
+            }
+            if let Some(addr) = queue.next() {
+                attempts.spawn(attempt(
+                    addr,
+                    ours,
+                    config.network.encryption,
+                    Arc::clone(&transport),
+                ));
+            }
+        }
+
+        info!(
+            "no peer had the metadata; trying again in {:?}",
+            RETRY_INTERVAL
+        );
+        sleep(RETRY_INTERVAL).await;
+    }
+}
+
+/// Asks the trackers and, if enabled, the DHT for peers.
+async fn discover(
+    tracker: &Tracker,
+    tiers: &[Vec<String>],
+    ours: &Handshake,
+    magnet: &Magnet,
+    config: &Config,
+) -> Vec<SocketAddr> {
+    let announce = Announce {
+        info_hash: magnet.info_hash,
+        peer_id: ours.peer_id,
+        port: match config.network.listen.first() {
+            Some(endpoint) => endpoint.port,
+            None => config.network.port.unwrap_or(DEFAULT_LISTEN_PORT),
+        },
+        uploaded: 0,
+        downloaded: 0,
+        // The size is unknown until the metadata arrives; claiming to need
+        // something keeps trackers from treating us as a seeder.
+        left: PIECE_SIZE as u64,
+        event: Some(AnnounceEvent::Started),
+        num_want: config.tracker.num_want,
+        ipv4: None,
+        ipv6: None,
+    };
+    let from_trackers = async {
+        match tracker.announce_tiers(tiers, &announce).await {
+            (Some((_, response)), _) => response.peers,
+            (None, errors) => {
+                for (url, error) in errors {
+                    debug!(url = %url, "announce failed: {}", error);
+                }
+                Vec::new()
+            }
+        }
+    };
+    let from_dht = async {
+        if !config.dht.enabled || config.proxy.blocks_dht() {
+            return Vec::new();
+        }
+        let network = &config.network;
+        let (interface, source_ports) = (network.interface.as_ref(), network.source_ports);
+        dht::get_peers(
+            &magnet.info_hash,
+            &config.dht.bootstrap_nodes,
+            interface,
+            source_ports,
+        )
+        .await
+        .unwrap_or_else(|e| {
+            debug!("DHT lookup failed: {}", e);
+            Vec::new()
+        })
+    };
+
+    let (mut peers, from_dht) = tokio::join!(from_trackers, from_dht);
+    peers.extend(from_dht);
+    peers.sort_unstable();
+    peers.dedup();
+    peers
+}
+
+async fn attempt(
+    addr: SocketAddr,
+    ours: Handshake,
+    policy: EncryptionPolicy,
+    transport: Arc<dyn PeerTransport>,
+) -> Result<Vec<u8>, MetadataError> {
+    let fetch = fetch_from_peer(addr, &ours, policy, &*transport);
+    let result = match timeout(PEER_TIMEOUT, fetch).await {
+        Ok(result) => result,
+        Err(_) => Err(PeerError::Timeout.into()),
+    };
+    if let Err(e) = &result {
+        debug!(%addr, "metadata fetch failed: {}", e);
+    }
+    result
+}
+
+/// Fetches and verifies the info dictionary from a single peer.
+pub async fn fetch_from_peer(
+    addr: SocketAddr,
+    ours: &Handshake,
+    policy: EncryptionPolicy,
+    transport: &dyn PeerTransport,
+) -> Result<Vec<u8>, MetadataError> {
+    let (stream, theirs) = dial(addr, ours, policy, transport).await?;
+    if !theirs.supports_extensions() {
+        return Err(MetadataError::Unsupported);
+    }
+
+    let mut framed = Framed::new(stream, MessageCodec::default());
+    let handshake = ExtendedHandshake {
+        extensions: BTreeMap::from([(EXTENSION_NAME.to_string(), LOCAL_ID)]),
+        version: Some(format!("rainyday {}", env!("CARGO_PKG_VERSION"))),
+        ..ExtendedHandshake::default()
+    };
+    framed
+        .send(Message::Extended {
+            id: HANDSHAKE_ID,
+            payload: handshake.to_bytes(),
+        })
+        .await?;
+
+    let mut their_id = None;
+    let mut size = 0;
+    let mut pieces: Vec<Option<Vec<u8>>> = Vec::new();
+
+    while let Some(message) = framed.next().await {
+        match message? {
+            Message::Extended {
+                id: HANDSHAKE_ID,
+                payload,
+            } => {
+                let handshake = ExtendedHandshake::from_bytes(&payload)?;
+                let id = handshake
+                    .id_of(EXTENSION_NAME)
+                    .ok_or(MetadataError::Unsupported)?;
+                size = handshake.metadata_size.ok_or(MetadataError::Unsupported)?;
+                if size == 0 || size > MAX_METADATA_SIZE {
+                    return Err(MetadataError::TooLarge(size));
+                }
+
+                their_id = Some(id);
+                pieces = vec![None; metadata::num_pieces(size) as usize];
+                for piece in 0..pieces.len() as u32 {
+                    let request = MetadataMessage::Request { piece };
+                    framed
+                        .send(Message::Extended {
+                            id,
+                            payload: request.to_bytes(),
+                        })
+                        .await?;
+                }
+            }
+            Message::Extended {
+                id: LOCAL_ID,
+                payload,
+            } => match MetadataMessage::from_bytes(&payload)? {
+                MetadataMessage::Data { piece, data, .. } => {
+                    let expected = (size - piece as u64 * PIECE_SIZE as u64).min(PIECE_SIZE as u64);
+                    match pieces.get_mut(piece as usize) {
+                        Some(slot) if data.len() as u64 == expected => *slot = Some(data),
+                        _ => {
+                            return Err(PeerError::ProtocolViolation(
+                                "metadata piece has the wrong size",
+                            )
+                            .into())
+                        }
+                    }
+                    if pieces.iter().all(Option::is_some) {
+                        return assemble(pieces, ours);
+                    }
+                }
+                MetadataMessage::Reject { piece } => return Err(MetadataError::Rejected(piece)),
+                MetadataMessage::Request { piece } => {
+                    if let Some(id) = their_id {
+                        let reject = MetadataMessage::Reject { piece };
+                        framed
+                            .send(Message::Extended {
+                                id,
+                                payload: reject.to_bytes(),
+                            })
+                            .await?;
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    Err(MetadataError::Closed)
+}
+
+fn assemble(pieces: Vec<Option<Vec<u8>>>, ours: &Handshake) -> Result<Vec<u8>, MetadataError> {
+    let info_bytes: Vec<u8> = pieces.into_iter().flatten().flatten().collect();
+    if hash::sha1(&info_bytes) != *ours.info_hash.as_bytes() {
+        return Err(MetadataError::HashMismatch);
+    }
+    Ok(info_bytes)
+}
@@ -0,0 +1,44 @@
+//! Peer-level logic shared by the connection tasks and the swarm.
+
+pub mod client;
+#[cfg(feature = "tokio")]
+pub mod connection;
+pub mod encryption;
+pub mod fast;
+#[cfg(feature = "tokio")]
+pub mod holepunch;
+#[cfg(feature = "tokio")]
+pub mod metadata;
+pub mod priority;
+pub mod state;
+#[cfg(feature = "tokio")]
+pub mod task;
+#[cfg(feature = "tokio")]
+pub mod transport;
+
+pub use id::PeerId;
+pub use priority::canonical_priority;
+pub use rainyday_proto::peer_id as id;
+
+use thiserror::Error;
+
+use crate::protocol::ProtocolError;
+use encryption::HandshakeKind;
+
+#[derive(Debug, Error)]
+pub enum PeerError {
+    #[error("protocol error: {0}")]
+    Protocol(#[from] ProtocolError),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("peer handshake was for a different torrent")]
+    InfoHashMismatch,
+    #[error("protocol violation: {0}")]
+    ProtocolViolation(&'static str),
+    #[error("{0:?} handshake refused by the encryption policy")]
+    EncryptionPolicy(HandshakeKind),
+    #[error("encrypted connections are not supported yet")]
+    EncryptionUnsupported,
+    #[error("peer timed out")]
+    Timeout,
+}
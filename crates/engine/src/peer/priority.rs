@@ -0,0 +1,77 @@
+//! Canonical peer priority as described in BEP 40.
+//!
+//! Both ends of a prospective connection compute the same priority, which
+//! lets a swarm converge on a stable, well-connected topology instead of one
+//! shaped by whoever happened to dial first.
+
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+const V4_MASKS: [[u8; 4]; 3] = [
+    [0xff, 0xff, 0x55, 0x55],
+    [0xff, 0xff, 0xff, 0x55],
+    [0xff, 0xff, 0xff, 0xff],
+];
+
+const V6_MASKS: [[u8; 16]; 3] = [
+    [
+        0xff, 0xff, 0xff, 0xff, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55,
+        0x55,
+    ],
+    [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55,
+        0x55,
+    ],
+    [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55,
+        0x55,
+    ],
+];
+
+/// Computes the BEP 40 priority of a connection between `a` and `b`.
+///
+/// The result is symmetric, so it does not matter which endpoint is ours.
+/// Higher values should be preferred when deciding whom to connect to.
+pub fn canonical_priority(a: SocketAddr, b: SocketAddr) -> u32 {
+    let (a, b) = if a <= b { (a, b) } else { (b, a) };
+
+    if a.ip() == b.ip() {
+        let mut buf = [0u8; 4];
+        buf[..2].copy_from_slice(&a.port().to_be_bytes());
+        buf[2..].copy_from_slice(&b.port().to_be_bytes());
+        return crc32c::crc32c(&buf);
+    }
+
+    match (a.ip(), b.ip()) {
+        (IpAddr::V4(x), IpAddr::V4(y)) => masked_priority(&x.octets(), &y.octets(), &V4_MASKS, 2),
+        (x, y) => masked_priority(&to_v6(x).octets(), &to_v6(y).octets(), &V6_MASKS, 4),
+    }
+}
+
+/// Picks a mask based on how long a prefix the two addresses share, applies
+/// it to both and hashes the concatenation.
+fn masked_priority<const N: usize>(
+    a: &[u8; N],
+    b: &[u8; N],
+    masks: &[[u8; N]; 3],
+    prefix: usize,
+) -> u32 {
+    let mask = if a[..prefix] != b[..prefix] {
+        &masks[0]
+    } else if a[..=prefix] != b[..=prefix] {
+        &masks[1]
+    } else {
+        &masks[2]
+    };
+
+    let mut buf = Vec::with_capacity(2 * N);
+    buf.extend(a.iter().zip(mask.iter()).map(|(x, m)| x & m));
+    buf.extend(b.iter().zip(mask.iter()).map(|(x, m)| x & m));
+    crc32c::crc32c(&buf)
+}
+
+fn to_v6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
@@ -0,0 +1,178 @@
+//! What we know about a single connected peer.
+
+use std::collections::HashSet;
+
+use super::client::ClientInfo;
+use super::{PeerError, PeerId};
+use crate::bitfield::Bitfield;
+use crate::picker::BLOCK_SIZE;
+use crate::protocol::extension::{ExtendedHandshake, HANDSHAKE_ID};
+use crate::protocol::{BlockRequest, Message};
+
+/// Choke/interest flags, advertised pieces and outstanding requests for one
+/// connection.
+#[derive(Clone, Debug)]
+pub struct PeerState {
+    pub am_choking: bool,
+    pub am_interested: bool,
+    pub peer_choking: bool,
+    pub peer_interested: bool,
+    /// Both sides advertised the fast extension.
+    pub fast: bool,
+    /// Pieces the peer has told us it has.
+    pub has: Bitfield,
+    /// Pieces the peer lets us request even while it chokes us.
+    pub allowed_fast: HashSet<u32>,
+    /// Pieces we let the peer request even while we choke it.
+    pub granted_fast: HashSet<u32>,
+    /// Pieces the peer suggested we download.
+    pub suggested: Vec<u32>,
+    /// Requests we have sent that are still unanswered.
+    pub pending: HashSet<BlockRequest>,
+    /// The peer's extension handshake, once received.
+    pub extensions: Option<ExtendedHandshake>,
+    /// The remote client, as far as we could tell.
+    pub client: Option<ClientInfo>,
+    /// Treat off-spec messages as protocol violations rather than making
+    /// the best of them.
+    pub pedantic: bool,
+}
+
+impl PeerState {
+    pub fn new(num_pieces: usize, fast: bool, pedantic: bool) -> Self {
+        Self {
+            am_choking: true,
+            am_interested: false,
+            peer_choking: true,
+            peer_interested: false,
+            fast,
+            has: Bitfield::new(num_pieces),
+            allowed_fast: HashSet::new(),
+            granted_fast: HashSet::new(),
+            suggested: Vec::new(),
+            pending: HashSet::new(),
+            extensions: None,
+            client: None,
+            pedantic,
+        }
+    }
+
+    /// Guesses the remote client from its peer ID, unless its extension
+    /// handshake already told us more reliably.
+    pub fn identify(&mut self, peer_id: &PeerId) {
+        if self.client.is_none() {
+            self.client = ClientInfo::from_peer_id(peer_id);
+        }
+    }
+
+    /// Whether we may currently request blocks of `piece` from this peer.
+    pub fn can_request(&self, piece: u32) -> bool {
+        self.has.has(piece as usize)
+            && (!self.peer_choking || (self.fast && self.allowed_fast.contains(&piece)))
+    }
+
+    /// Whether a request from the peer should be served rather than rejected.
+    /// Requests for more than a block are refused, as other clients do, so
+    /// that no peer can have us read and hold more at once.
+    pub fn should_serve(&self, request: &BlockRequest, have: &Bitfield) -> bool {
+        request.length <= BLOCK_SIZE
+            && have.has(request.piece as usize)
+            && (!self.am_choking || self.granted_fast.contains(&request.piece))
+    }
+
+    /// Applies a message received from the peer.
+    ///
+    /// Returns the requests that the message cancelled, so the caller can
+    /// hand them back to the picker.
+    pub fn handle(&mut self, message: &Message) -> Result<Vec<BlockRequest>, PeerError> {
+        let num_pieces = self.has.len();
+
+        match message {
+            Message::Choke => {
+                self.peer_choking = true;
+                // Without the fast extension a choke implicitly rejects
+                // everything; with it the peer sends explicit rejects.
+                if !self.fast {
+                    return Ok(self.pending.drain().collect());
+                }
+            }
+            Message::Unchoke => self.peer_choking = false,
+            Message::Interested => self.peer_interested = true,
+            Message::NotInterested => self.peer_interested = false,
+            Message::Have(piece) if self.pedantic && *piece as usize >= num_pieces => {
+                return Err(PeerError::ProtocolViolation(
+                    "have for a piece out of range",
+                ));
+            }
+            Message::Have(piece) => self.has.set(*piece as usize),
+            Message::Bitfield(bytes) => {
+                let bitfield = if self.pedantic {
+                    Bitfield::from_bytes(bytes, num_pieces)
+                } else {
+                    Bitfield::from_bytes_masked(bytes, num_pieces)
+                };
+                self.has = bitfield.ok_or(PeerError::ProtocolViolation("malformed bitfield"))?;
+            }
+            Message::Piece {
+                piece,
+                offset,
+                data,
+            } => {
+                self.pending.remove(&BlockRequest {
+                    piece: *piece,
+                    offset: *offset,
+                    length: data.len() as u32,
+                });
+            }
+            Message::HaveAll | Message::HaveNone => {
+                self.require_fast()?;
+                self.has = if *message == Message::HaveAll {
+                    Bitfield::full(num_pieces)
+                } else {
+                    Bitfield::new(num_pieces)
+                };
+            }
+            Message::Reject(request) => {
+                self.require_fast()?;
+                if self.pending.remove(request) {
+                    return Ok(vec![*request]);
+                }
+            }
+            Message::AllowedFast(piece) => {
+                self.require_fast()?;
+                if (*piece as usize) < num_pieces {
+                    self.allowed_fast.insert(*piece);
+                }
+            }
+            Message::Suggest(piece) => {
+                self.require_fast()?;
+                if (*piece as usize) < num_pieces && !self.suggested.contains(piece) {
+                    self.suggested.push(*piece);
+                }
+            }
+            Message::Extended {
+                id: HANDSHAKE_ID,
+                payload,
+            } => {
+                let handshake = ExtendedHandshake::from_bytes(payload)?;
+                if let Some(version) = &handshake.version {
+                    self.client = Some(ClientInfo::from_version_string(version));
+                }
+                self.extensions = Some(handshake);
+            }
+            _ => {}
+        }
+
+        Ok(Vec::new())
+    }
+
+    fn require_fast(&self) -> Result<(), PeerError> {
+        if self.fast {
+            Ok(())
+        } else {
+            Err(PeerError::ProtocolViolation(
+                "fast extension message without negotiating it",
+            ))
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! The per-connection task that pumps messages between a socket and the
+//! rest of the client.
+
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+use tokio_util::codec::Framed;
+use tracing::{debug, trace};
+
+use super::PeerError;
+use crate::config::Config;
+use crate::limits::Limits;
+use crate::protocol::handshake::HANDSHAKE_LEN;
+use crate::protocol::{Handshake, Message, MessageCodec};
+
+/// Liveness timers applied to every connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Send a keep-alive after this long without sending anything.
+    pub keepalive: Duration,
+    /// Drop the peer after this long without receiving anything.
+    pub idle: Duration,
+}
+
+impl From<&Config> for Timeouts {
+    fn from(config: &Config) -> Self {
+        Self {
+            keepalive: Duration::from_secs(config.network.keepalive_interval),
+            idle: Duration::from_secs(config.network.peer_timeout),
+        }
+    }
+}
+
+/// Exchanges handshakes, returning the remote side's.
+pub async fn handshake<S>(stream: &mut S, ours: &Handshake) -> Result<Handshake, PeerError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(&ours.to_bytes()).await?;
+
+    let mut buf = [0u8; HANDSHAKE_LEN];
+    stream.read_exact(&mut buf).await?;
+    let theirs = Handshake::from_bytes(&buf)?;
+
+    if theirs.info_hash != ours.info_hash {
+        return Err(PeerError::InfoHashMismatch);
+    }
+
+    Ok(theirs)
+}
+
+/// Runs a connection until either side closes it or it times out.
+///
+/// Messages received from the peer are forwarded on `inbound` (keep-alives
+/// are absorbed here), and messages arriving on `outbound` are written to
+/// the socket. Block data in either direction counts against `limits`.
+pub async fn run<S>(
+    stream: S,
+    mut outbound: mpsc::Receiver<Message>,
+    inbound: mpsc::Sender<Message>,
+    timeouts: Timeouts,
+    limits: Limits,
+    codec: MessageCodec,
+) -> Result<(), PeerError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut framed = Framed::new(stream, codec);
+    let mut last_sent = Instant::now();
+    let mut last_received = Instant::now();
+
+    loop {
+        tokio::select! {
+            frame = framed.next() => match frame {
+                Some(Ok(message)) => {
+                    last_received = Instant::now();
+                    trace!(?message, "received");
+                    if let Message::Piece { data, .. } = &message {
+                        limits.acquire_download(data.len()).await;
+                    }
+                    if message != Message::KeepAlive && inbound.send(message).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                Some(Err(e)) => return Err(e.into()),
+                None => return Ok(()),
+            },
+            message = outbound.recv() => match message {
+                Some(message) => {
+                    if let Message::Piece { data, .. } = &message {
+                        limits.acquire_upload(data.len()).await;
+                    }
+                    framed.send(message).await?;
+                    last_sent = Instant::now();
+                }
+                None => return Ok(()),
+            },
+            _ = sleep_until(last_sent + timeouts.keepalive) => {
+                framed.send(Message::KeepAlive).await?;
+                last_sent = Instant::now();
+            }
+            _ = sleep_until(last_received + timeouts.idle) => {
+                debug!("peer idle for {:?}, disconnecting", timeouts.idle);
+                return Err(PeerError::Timeout);
+            }
+        }
+    }
+}
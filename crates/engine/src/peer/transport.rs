@@ -0,0 +1,230 @@
+//! How connections to peers are opened: directly, or tunnelled through the
+//! proxy configured under `[proxy]`.
+//!
+//! Peers are dialled by address, so tunnelling through a proxy leaves no
+//! DNS lookups behind; only the proxy's own host name is resolved here.
+//!
+//! Connections are handed on as [`PeerStream`]s rather than sockets, so a
+//! transport can also carry them over something other than TCP, such as
+//! the in-memory network of a simulation.
+
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
+
+use crate::config::{Config, PortRange};
+use crate::interface::{self, Interface};
+use crate::peer::holepunch;
+use crate::proxy::ProxyKind;
+
+/// Longest response header we accept from an HTTP proxy's `CONNECT`.
+const MAX_CONNECT_RESPONSE: usize = 8 * 1024;
+
+/// A way of reaching peers.
+pub trait PeerTransport: fmt::Debug + Send + Sync {
+    /// Opens a connection to the peer at `addr`.
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<PeerStream>>;
+
+    /// Opens a connection to the peer at `addr` while it opens one to us,
+    /// after a relay introduced us (BEP 55). `port` is the one we listen
+    /// on. Transports that cannot take part just connect.
+    fn punch(&self, addr: SocketAddr, port: u16) -> BoxFuture<'_, io::Result<PeerStream>> {
+        let _ = port;
+        self.connect(addr)
+    }
+}
+
+/// Bytes to and from a peer.
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> Stream for S {}
+
+/// A connection to a peer, whichever transport opened it.
+pub type PeerStream = Box<dyn Stream>;
+
+/// The transport `config` asks for: through the proxy if peers are
+/// proxied, and otherwise direct, in either case from the configured
+/// interface.
+pub fn from_config(config: &Config) -> Arc<dyn PeerTransport> {
+    let proxy = &config.proxy;
+    let interface = config.network.interface.clone();
+    let source_ports = config.network.source_ports;
+    if !proxy.proxies_peers() {
+        return Arc::new(Direct {
+            interface,
+            source_ports,
+        });
+    }
+    let (host, port) = proxy.address();
+    let server = Server {
+        host: host.to_string(),
+        port,
+        interface,
+        source_ports,
+    };
+    let credentials = proxy.username.clone().map(|username| Credentials {
+        username,
+        password: proxy.password.clone().unwrap_or_default(),
+    });
+    match proxy.kind {
+        ProxyKind::Socks5 => Arc::new(Socks5 {
+            server,
+            credentials,
+        }),
+        _ => Arc::new(HttpTunnel {
+            server,
+            credentials,
+        }),
+    }
+}
+
+/// Connects straight to peers.
+#[derive(Clone, Debug, Default)]
+pub struct Direct {
+    pub interface: Option<Interface>,
+    pub source_ports: Option<PortRange>,
+}
+
+impl PeerTransport for Direct {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<PeerStream>> {
+        async move {
+            let stream =
+                interface::connect(self.interface.as_ref(), self.source_ports, addr).await?;
+            Ok(Box::new(stream) as PeerStream)
+        }
+        .boxed()
+    }
+
+    fn punch(&self, addr: SocketAddr, port: u16) -> BoxFuture<'_, io::Result<PeerStream>> {
+        if self.interface.is_some() || self.source_ports.is_some() {
+            return self.connect(addr);
+        }
+        async move {
+            let listen = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port);
+            match holepunch::punch(listen, addr).await {
+                Ok(stream) => Ok(Box::new(stream) as PeerStream),
+                // The listener holds the port without sharing it.
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => self.connect(addr).await,
+                Err(e) => Err(e),
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Where a proxy listens.
+#[derive(Clone)]
+struct Server {
+    host: String,
+    port: u16,
+    /// The interface the proxy is reached from.
+    interface: Option<Interface>,
+    source_ports: Option<PortRange>,
+}
+
+impl Server {
+    async fn connect(&self) -> io::Result<TcpStream> {
+        let address = (self.host.as_str(), self.port);
+        interface::connect(self.interface.as_ref(), self.source_ports, address).await
+    }
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+#[derive(Clone)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+/// Keeps the password out of logs.
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Tunnels peer connections through a SOCKS5 proxy, authenticating with a
+/// username and password if given.
+#[derive(Clone, Debug)]
+pub struct Socks5 {
+    server: Server,
+    credentials: Option<Credentials>,
+}
+
+impl PeerTransport for Socks5 {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<PeerStream>> {
+        async move {
+            let proxy = self.server.connect().await?;
+            let stream = match &self.credentials {
+                Some(Credentials { username, password }) => {
+                    Socks5Stream::connect_with_password_and_socket(proxy, addr, username, password)
+                        .await
+                }
+                None => Socks5Stream::connect_with_socket(proxy, addr).await,
+            };
+            match stream {
+                Ok(stream) => Ok(Box::new(stream.into_inner()) as PeerStream),
+                Err(e) => Err(io::Error::other(format!("SOCKS5 proxy: {}", e))),
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Tunnels peer connections through an HTTP proxy with `CONNECT`.
+#[derive(Clone, Debug)]
+pub struct HttpTunnel {
+    server: Server,
+    credentials: Option<Credentials>,
+}
+
+impl PeerTransport for HttpTunnel {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<PeerStream>> {
+        async move {
+            let mut stream = self.server.connect().await?;
+            let mut request = format!("CONNECT {addr} HTTP/1.1\r\nHost: {addr}\r\n");
+            if let Some(Credentials { username, password }) = &self.credentials {
+                let token = BASE64.encode(format!("{}:{}", username, password));
+                request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+            }
+            request.push_str("\r\n");
+            stream.write_all(request.as_bytes()).await?;
+
+            // Read byte by byte so nothing the peer sends after the header
+            // is swallowed.
+            let mut response = Vec::new();
+            while !response.ends_with(b"\r\n\r\n") {
+                if response.len() >= MAX_CONNECT_RESPONSE {
+                    return Err(io::Error::other("HTTP proxy response too long"));
+                }
+                response.push(stream.read_u8().await?);
+            }
+            let status = String::from_utf8_lossy(&response);
+            let status = status.lines().next().unwrap_or_default();
+            match status.split_whitespace().nth(1) {
+                Some(code) if code.starts_with('2') => Ok(Box::new(stream) as PeerStream),
+                _ => Err(io::Error::other(format!(
+                    "HTTP proxy refused the connection: {}",
+                    status
+                ))),
+            }
+        }
+        .boxed()
+    }
+}
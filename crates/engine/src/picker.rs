@@ -0,0 +1,373 @@
+//! Decides which blocks to request from which peer.
+
+use std::collections::HashMap;
+
+use crate::bitfield::Bitfield;
+use crate::files::FilePriority;
+use crate::memory::{MemoryBudget, MemoryReservation};
+use crate::peer::state::PeerState;
+use crate::protocol::BlockRequest;
+
+/// Size of the blocks pieces are requested in.
+pub const BLOCK_SIZE: u32 = 16 * 1024;
+
+#[derive(Debug)]
+struct PartialPiece {
+    requested: Vec<bool>,
+    received: Vec<bool>,
+    /// Held from when the piece is started until it is written or given up.
+    _memory: MemoryReservation,
+}
+
+/// How many connected peers have each piece, with the pieces that could
+/// be started grouped by priority and then by that count, so that the
+/// rarest is found without looking at every piece of a large torrent.
+#[derive(Clone, Debug)]
+struct Availability {
+    counts: Vec<u32>,
+    /// Indexed by priority, then by count.
+    buckets: Vec<Vec<Vec<u32>>>,
+    /// Where each piece is in `buckets`, if it is there.
+    slots: Vec<Option<Slot>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Slot {
+    priority: FilePriority,
+    index: usize,
+}
+
+impl Availability {
+    fn new(num_pieces: usize) -> Self {
+        Self {
+            counts: vec![0; num_pieces],
+            buckets: Vec::new(),
+            slots: vec![None; num_pieces],
+        }
+    }
+
+    /// Makes `piece` one that can be picked, at `priority`.
+    fn insert(&mut self, piece: u32, priority: FilePriority) {
+        self.remove(piece);
+        let count = self.counts[piece as usize] as usize;
+        let level = priority as usize;
+        if self.buckets.len() <= level {
+            self.buckets.resize_with(level + 1, Vec::new);
+        }
+        let by_count = &mut self.buckets[level];
+        if by_count.len() <= count {
+            by_count.resize_with(count + 1, Vec::new);
+        }
+        self.slots[piece as usize] = Some(Slot {
+            priority,
+            index: by_count[count].len(),
+        });
+        by_count[count].push(piece);
+    }
+
+    /// Stops `piece` from being picked.
+    fn remove(&mut self, piece: u32) {
+        let Some(slot) = self.slots[piece as usize].take() else {
+            return;
+        };
+        let count = self.counts[piece as usize] as usize;
+        let bucket = &mut self.buckets[slot.priority as usize][count];
+        bucket.swap_remove(slot.index);
+        if let Some(&moved) = bucket.get(slot.index) {
+            if let Some(moved) = &mut self.slots[moved as usize] {
+                moved.index = slot.index;
+            }
+        }
+    }
+
+    fn add(&mut self, piece: u32) {
+        let slot = self.slots[piece as usize];
+        self.remove(piece);
+        self.counts[piece as usize] += 1;
+        if let Some(slot) = slot {
+            self.insert(piece, slot.priority);
+        }
+    }
+
+    fn subtract(&mut self, piece: u32) {
+        let slot = self.slots[piece as usize];
+        self.remove(piece);
+        let count = &mut self.counts[piece as usize];
+        *count = count.saturating_sub(1);
+        if let Some(slot) = slot {
+            self.insert(piece, slot.priority);
+        }
+    }
+
+    /// The rarest of the highest-priority pieces that `usable` accepts.
+    fn rarest(&self, usable: impl Fn(u32) -> bool) -> Option<u32> {
+        self.buckets
+            .iter()
+            .rev()
+            .flatten()
+            .flatten()
+            .copied()
+            .find(|&piece| usable(piece))
+    }
+}
+
+/// Rarest-first block picker for a single torrent.
+#[derive(Debug)]
+pub struct Picker {
+    piece_length: u32,
+    total_length: u64,
+    have: Bitfield,
+    /// Derived from the priorities of the files each piece overlaps.
+    priorities: Vec<FilePriority>,
+    availability: Availability,
+    partial: HashMap<u32, PartialPiece>,
+    /// Start pieces in order rather than rarest first.
+    sequential: bool,
+    /// What pieces in progress are held to.
+    memory: MemoryBudget,
+}
+
+impl Picker {
+    pub fn new(piece_length: u32, total_length: u64) -> Self {
+        let num_pieces = total_length.div_ceil(piece_length as u64) as usize;
+        let mut availability = Availability::new(num_pieces);
+        for piece in 0..num_pieces as u32 {
+            availability.insert(piece, FilePriority::Normal);
+        }
+        Self {
+            piece_length,
+            total_length,
+            have: Bitfield::new(num_pieces),
+            priorities: vec![FilePriority::Normal; num_pieces],
+            availability,
+            partial: HashMap::new(),
+            sequential: false,
+            memory: MemoryBudget::default(),
+        }
+    }
+
+    /// Starts new pieces in order, so the beginning of each file arrives
+    /// first, instead of rarest first. Priorities still come first.
+    pub fn set_sequential(&mut self, sequential: bool) {
+        self.sequential = sequential;
+    }
+
+    /// Holds pieces in progress to `budget`: while it is used up, only
+    /// pieces already started are requested from.
+    pub fn set_memory_budget(&mut self, budget: MemoryBudget) {
+        self.memory = budget;
+    }
+
+    pub fn num_pieces(&self) -> usize {
+        self.have.len()
+    }
+
+    pub fn have(&self) -> &Bitfield {
+        &self.have
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.have.is_full()
+    }
+
+    /// Sets each piece's priority. Skipped pieces are never picked, and any
+    /// of them already in progress are abandoned.
+    pub fn set_priorities(&mut self, priorities: Vec<FilePriority>) {
+        self.partial
+            .retain(|piece, _| priorities[*piece as usize].is_wanted());
+        self.priorities = priorities;
+        for piece in 0..self.num_pieces() as u32 {
+            self.update_availability(piece);
+        }
+    }
+
+    pub fn priority(&self, piece: u32) -> FilePriority {
+        self.priorities[piece as usize]
+    }
+
+    pub fn is_wanted(&self, piece: u32) -> bool {
+        self.priority(piece).is_wanted()
+    }
+
+    /// Whether `piece` has been started and is not yet verified.
+    pub fn is_in_progress(&self, piece: u32) -> bool {
+        self.partial.contains_key(&piece)
+    }
+
+    /// Whether every wanted piece has been downloaded.
+    pub fn is_finished(&self) -> bool {
+        (0..self.num_pieces()).all(|piece| self.have.has(piece) || !self.is_wanted(piece as u32))
+    }
+
+    /// Length of `piece` in bytes; only the last piece may be short.
+    pub fn piece_size(&self, piece: u32) -> u32 {
+        let start = piece as u64 * self.piece_length as u64;
+        (self.total_length - start).min(self.piece_length as u64) as u32
+    }
+
+    pub fn num_blocks(&self, piece: u32) -> usize {
+        self.piece_size(piece).div_ceil(BLOCK_SIZE) as usize
+    }
+
+    fn block(&self, piece: u32, block: usize) -> BlockRequest {
+        let offset = block as u32 * BLOCK_SIZE;
+        BlockRequest {
+            piece,
+            offset,
+            length: (self.piece_size(piece) - offset).min(BLOCK_SIZE),
+        }
+    }
+
+    /// Whether `piece` could be started: wanted, and neither had nor in
+    /// progress.
+    fn is_startable(&self, piece: u32) -> bool {
+        !self.have.has(piece as usize)
+            && self.is_wanted(piece)
+            && !self.partial.contains_key(&piece)
+    }
+
+    /// Indexes `piece` for picking if it can be started, or stops it being
+    /// picked if not.
+    fn update_availability(&mut self, piece: u32) {
+        if self.is_startable(piece) {
+            self.availability.insert(piece, self.priority(piece));
+        } else {
+            self.availability.remove(piece);
+        }
+    }
+
+    /// How many connected peers have `piece`.
+    pub fn availability(&self, piece: u32) -> u32 {
+        self.availability.counts[piece as usize]
+    }
+
+    pub fn peer_has(&mut self, piece: u32) {
+        if (piece as usize) < self.num_pieces() {
+            self.availability.add(piece);
+        }
+    }
+
+    /// Counts the pieces a peer's new bitfield adds to what it `had`, and
+    /// forgets those it no longer has.
+    pub fn peer_bitfield(&mut self, had: &Bitfield, has: &Bitfield) {
+        for piece in 0..has.len().min(self.num_pieces()) {
+            match (had.has(piece), has.has(piece)) {
+                (false, true) => self.availability.add(piece as u32),
+                (true, false) => self.availability.subtract(piece as u32),
+                _ => {}
+            }
+        }
+    }
+
+    /// Forgets a disconnected peer's contribution to piece availability.
+    pub fn peer_lost(&mut self, has: &Bitfield) {
+        for piece in has.iter() {
+            self.availability.subtract(piece as u32);
+        }
+    }
+
+    /// Picks the next block to request from `peer`. Higher-priority pieces
+    /// come first; within a priority, pieces already in progress are
+    /// finished before the rarest new piece, or in sequential mode the
+    /// first, is started. A new piece is only started if it fits in the
+    /// memory budget.
+    ///
+    /// While the peer chokes us only its allowed-fast pieces are considered.
+    pub fn pick(&mut self, peer: &PeerState) -> Option<BlockRequest> {
+        let mut in_progress: Vec<u32> = self
+            .partial
+            .keys()
+            .copied()
+            .filter(|&piece| peer.can_request(piece))
+            .collect();
+        in_progress.sort_unstable_by_key(|&piece| (std::cmp::Reverse(self.priority(piece)), piece));
+
+        let fresh = if self.sequential {
+            (0..self.num_pieces() as u32)
+                .filter(|&piece| self.is_startable(piece) && peer.can_request(piece))
+                .min_by_key(|&piece| (std::cmp::Reverse(self.priority(piece)), piece))
+        } else {
+            self.availability.rarest(|piece| peer.can_request(piece))
+        };
+        let fresh_priority = fresh.map(|piece| self.priority(piece));
+
+        for &piece in &in_progress {
+            if Some(self.priority(piece)) < fresh_priority {
+                break;
+            }
+            if let Some(request) = self.next_block(piece) {
+                return Some(request);
+            }
+        }
+
+        let piece = fresh?;
+        let Some(memory) = self.memory.reserve(self.piece_size(piece) as u64) else {
+            // Keep to the pieces already started until memory is freed.
+            return in_progress
+                .into_iter()
+                .find_map(|piece| self.next_block(piece));
+        };
+
+        let num_blocks = self.num_blocks(piece);
+        let mut partial = PartialPiece {
+            requested: vec![false; num_blocks],
+            received: vec![false; num_blocks],
+            _memory: memory,
+        };
+        partial.requested[0] = true;
+        self.partial.insert(piece, partial);
+        self.availability.remove(piece);
+        Some(self.block(piece, 0))
+    }
+
+    /// The first block of `piece`, which is in progress, not yet requested.
+    fn next_block(&mut self, piece: u32) -> Option<BlockRequest> {
+        let partial = self.partial.get_mut(&piece)?;
+        let block = partial.requested.iter().position(|r| !r)?;
+        partial.requested[block] = true;
+        Some(self.block(piece, block))
+    }
+
+    /// Makes a block requestable again after it was rejected, cancelled or
+    /// its peer went away.
+    pub fn release(&mut self, request: &BlockRequest) {
+        if let Some(partial) = self.partial.get_mut(&request.piece) {
+            let block = (request.offset / BLOCK_SIZE) as usize;
+            if let Some(requested) = partial.requested.get_mut(block) {
+                if !partial.received[block] {
+                    *requested = false;
+                }
+            }
+        }
+    }
+
+    /// Records a received block, returning `true` once every block of its
+    /// piece has arrived.
+    pub fn block_received(&mut self, request: &BlockRequest) -> bool {
+        match self.partial.get_mut(&request.piece) {
+            Some(partial) => {
+                let block = (request.offset / BLOCK_SIZE) as usize;
+                if block < partial.received.len() {
+                    partial.requested[block] = true;
+                    partial.received[block] = true;
+                }
+                partial.received.iter().all(|&r| r)
+            }
+            None => false,
+        }
+    }
+
+    /// Marks a piece as verified and stored.
+    pub fn piece_complete(&mut self, piece: u32) {
+        self.partial.remove(&piece);
+        self.have.set(piece as usize);
+        self.availability.remove(piece);
+    }
+
+    /// Discards a piece that failed verification so it is downloaded again.
+    pub fn piece_failed(&mut self, piece: u32) {
+        self.partial.remove(&piece);
+        self.have.clear(piece as usize);
+        self.update_availability(piece);
+    }
+}
@@ -0,0 +1,161 @@
+//! Reusable buffers for block data, so that a fast transfer does not
+//! allocate and free a buffer for every 16 KiB block it receives or sends.
+//!
+//! Blocks are taken from the shared [`BLOCKS`] pool as they are read from
+//! disk for peers, and go back to it once sent. Blocks received from peers
+//! need no buffer of their own, as they share the frame they came in.
+
+use std::fmt;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+use crate::picker::BLOCK_SIZE;
+
+/// Idle buffers the pool keeps for reuse, 16 MiB of blocks; more are freed.
+pub const MAX_IDLE: usize = 1024;
+
+/// The pool block data is kept in.
+pub static BLOCKS: BufferPool = BufferPool::new(BLOCK_SIZE as usize, MAX_IDLE);
+
+/// Buffers of one size, handed out and taken back.
+pub struct BufferPool {
+    size: usize,
+    max_idle: usize,
+    idle: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    pub const fn new(size: usize, max_idle: usize) -> Self {
+        Self {
+            size,
+            max_idle,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// A buffer holding a copy of `data`.
+    pub fn copy_from(&'static self, data: &[u8]) -> Buffer {
+        let mut buffer = self.take(data.len());
+        buffer.data.extend_from_slice(data);
+        buffer
+    }
+
+    /// A buffer of `len` zeroes, to be filled.
+    pub fn zeroed(&'static self, len: usize) -> Buffer {
+        let mut buffer = self.take(len);
+        buffer.data.resize(len, 0);
+        buffer
+    }
+
+    /// An empty buffer with room for `len` bytes. Ones bigger than the
+    /// pool's are allocated for the purpose and not pooled.
+    fn take(&'static self, len: usize) -> Buffer {
+        if len > self.size {
+            return Buffer {
+                data: Vec::with_capacity(len),
+                pool: None,
+            };
+        }
+        let data = self.idle.lock().unwrap().pop();
+        Buffer {
+            data: data.unwrap_or_else(|| Vec::with_capacity(self.size)),
+            pool: Some(self),
+        }
+    }
+
+    fn give_back(&self, mut data: Vec<u8>) {
+        if data.capacity() != self.size {
+            return;
+        }
+        data.clear();
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.max_idle {
+            idle.push(data);
+        }
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("size", &self.size)
+            .field("idle", &self.idle.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// Bytes in a buffer that goes back to its pool when dropped.
+pub struct Buffer {
+    data: Vec<u8>,
+    pool: Option<&'static BufferPool>,
+}
+
+impl Buffer {
+    /// Shares the buffer as [`Bytes`], which give it back to the pool once
+    /// the last of them is dropped.
+    pub fn into_bytes(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl AsRef<[u8]> for Buffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool {
+            pool.give_back(mem::take(&mut self.data));
+        }
+    }
+}
+
+/// Copies into a buffer from the same pool.
+impl Clone for Buffer {
+    fn clone(&self) -> Self {
+        match self.pool {
+            Some(pool) => pool.copy_from(&self.data),
+            None => Self::from(self.data.clone()),
+        }
+    }
+}
+
+/// Wraps bytes that did not come from a pool, and are freed when dropped.
+impl From<Vec<u8>> for Buffer {
+    fn from(data: Vec<u8>) -> Self {
+        Self { data, pool: None }
+    }
+}
+
+impl PartialEq for Buffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+    }
+}
+
+impl Eq for Buffer {}
+
+impl fmt::Debug for Buffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.data.fmt(f)
+    }
+}
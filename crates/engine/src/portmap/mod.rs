@@ -0,0 +1,319 @@
+//! Asking the home router to forward the listening port to us, so that
+//! peers on the internet can connect without the user setting up a forward
+//! by hand.
+//!
+//! Every enabled mechanism is tried at once: UPnP IGD, and NAT-PMP and PCP
+//! on the default gateway. Each keeps its mapping leased, renewing it well
+//! before the lease ends, and removes it when the session ends.
+
+mod natpmp;
+mod pcp;
+mod upnp;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::config::NetworkConfig;
+use crate::interface::{self, Interface};
+use crate::swarm::ExternalIp;
+
+/// How long each mapping is asked to be leased for. Routers may grant
+/// less; mappings are renewed at half of what they grant.
+const LEASE: Duration = Duration::from_secs(60 * 60);
+/// How long to wait before trying a mechanism again after it failed.
+const RETRY: Duration = Duration::from_secs(5 * 60);
+/// Renew at most this often, however short the lease.
+const MIN_RENEWAL: Duration = Duration::from_secs(60);
+/// Tries of a NAT-PMP or PCP request, which give up after almost 4 s. The
+/// RFCs allow 9, taking over a minute.
+const ATTEMPTS: u32 = 4;
+
+#[derive(Debug, Error)]
+pub enum PortMapError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("no default gateway found")]
+    NoGateway,
+    #[error("the router did not answer")]
+    Timeout,
+    #[error("the router refused: {0}")]
+    Refused(String),
+    #[error("malformed answer from the router: {0}")]
+    Malformed(&'static str),
+    #[error("{0}")]
+    Upnp(String),
+}
+
+/// A way of asking a router for a port forward.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mechanism {
+    Upnp,
+    NatPmp,
+    Pcp,
+}
+
+impl fmt::Display for Mechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Upnp => "UPnP",
+            Self::NatPmp => "NAT-PMP",
+            Self::Pcp => "PCP",
+        })
+    }
+}
+
+/// A forward a router has granted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortMapping {
+    pub mechanism: Mechanism,
+    /// The address and port peers on the internet reach us at, or an
+    /// unspecified address if the router did not say.
+    pub external: SocketAddr,
+}
+
+/// A router that has granted a mapping, which is renewed and removed
+/// through it.
+#[derive(Clone, Debug)]
+enum Router {
+    Upnp(upnp::Router),
+    NatPmp(natpmp::Router),
+    Pcp(pcp::Router),
+}
+
+/// What a router granted: the external address, and how long for.
+struct Grant {
+    external: SocketAddr,
+    lifetime: Duration,
+}
+
+impl Router {
+    async fn find(
+        mechanism: Mechanism,
+        gateway: Option<IpAddr>,
+        interface: Option<&Interface>,
+    ) -> Result<Self, PortMapError> {
+        let gateway = || {
+            gateway
+                .or_else(|| default_gateway(interface))
+                .ok_or(PortMapError::NoGateway)
+        };
+        let interface = interface.cloned();
+        Ok(match mechanism {
+            Mechanism::Upnp => Self::Upnp(upnp::Router::find().await?),
+            Mechanism::NatPmp => Self::NatPmp(natpmp::Router::new(gateway()?, interface)),
+            Mechanism::Pcp => Self::Pcp(pcp::Router::new(gateway()?, interface)),
+        })
+    }
+
+    async fn map(&self, port: u16) -> Result<Grant, PortMapError> {
+        match self {
+            Self::Upnp(router) => router.map(port, LEASE).await,
+            Self::NatPmp(router) => router.map(port, LEASE).await,
+            Self::Pcp(router) => router.map(port, LEASE).await,
+        }
+    }
+
+    async fn unmap(&self, port: u16) -> Result<(), PortMapError> {
+        match self {
+            Self::Upnp(router) => router.unmap(port).await,
+            Self::NatPmp(router) => router.unmap(port).await,
+            Self::Pcp(router) => router.unmap(port).await,
+        }
+    }
+}
+
+struct Active {
+    router: Router,
+    mapping: PortMapping,
+}
+
+type Mapped = Arc<Mutex<Vec<Active>>>;
+
+/// Keeps a port forwarded on the router, by every mechanism that works,
+/// until stopped.
+pub struct PortMapper {
+    port: u16,
+    tasks: Vec<JoinHandle<()>>,
+    mapped: Mapped,
+}
+
+impl PortMapper {
+    /// Starts mapping TCP `port` on the router to the same port here, by
+    /// the mechanisms `config` enables, recording the address the router
+    /// maps it on in `external`. UPnP is left out when traffic is kept to
+    /// an interface, since its requests cannot be.
+    pub fn start(port: u16, config: &NetworkConfig, external: ExternalIp) -> Self {
+        let mapped = Mapped::default();
+        let mut mechanisms = Vec::new();
+        if config.upnp && config.interface.is_none() {
+            mechanisms.push(Mechanism::Upnp);
+        }
+        if config.natpmp {
+            mechanisms.extend([Mechanism::NatPmp, Mechanism::Pcp]);
+        }
+        let tasks = mechanisms
+            .into_iter()
+            .map(|mechanism| {
+                tokio::spawn(keep_mapped(
+                    mechanism,
+                    config.gateway,
+                    config.interface.clone(),
+                    port,
+                    Arc::clone(&mapped),
+                    external.clone(),
+                ))
+            })
+            .collect();
+        Self {
+            port,
+            tasks,
+            mapped,
+        }
+    }
+
+    /// The forwards routers have granted so far.
+    pub fn mappings(&self) -> Vec<PortMapping> {
+        let mapped = self.mapped.lock().unwrap();
+        mapped.iter().map(|active| active.mapping).collect()
+    }
+
+    /// Stops renewing the mappings and removes them from the routers.
+    pub async fn stop(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        let mapped: Vec<Active> = self.mapped.lock().unwrap().drain(..).collect();
+        for active in mapped {
+            let mechanism = active.mapping.mechanism;
+            match active.router.unmap(self.port).await {
+                Ok(()) => debug!(port = self.port, "removed the {} port mapping", mechanism),
+                Err(e) => warn!(
+                    port = self.port,
+                    "cannot remove the {} port mapping: {}", mechanism, e
+                ),
+            }
+        }
+    }
+}
+
+async fn keep_mapped(
+    mechanism: Mechanism,
+    gateway: Option<IpAddr>,
+    interface: Option<Interface>,
+    port: u16,
+    mapped: Mapped,
+    external: ExternalIp,
+) {
+    loop {
+        let router = match Router::find(mechanism, gateway, interface.as_ref()).await {
+            Ok(router) => router,
+            Err(e) => {
+                debug!("no router answers {}: {}", mechanism, e);
+                tokio::time::sleep(RETRY).await;
+                continue;
+            }
+        };
+
+        // Renew with the same router until it stops answering, then look
+        // for one again.
+        let mut first = true;
+        loop {
+            let grant = match router.map(port).await {
+                Ok(grant) => grant,
+                Err(e) if first => {
+                    debug!("cannot map port {} with {}: {}", port, mechanism, e);
+                    break;
+                }
+                Err(e) => {
+                    warn!(
+                        "cannot renew the {} mapping of port {}: {}",
+                        mechanism, port, e
+                    );
+                    break;
+                }
+            };
+            let mapping = PortMapping {
+                mechanism,
+                external: grant.external,
+            };
+            if first {
+                info!(
+                    "the router forwards {} to port {} with {}",
+                    grant.external, port, mechanism
+                );
+                first = false;
+            } else {
+                debug!(port, "renewed the {} port mapping", mechanism);
+            }
+            external.set(grant.external.ip());
+            record(&mapped, Some((router.clone(), mapping)), mechanism);
+            tokio::time::sleep((grant.lifetime / 2).max(MIN_RENEWAL)).await;
+        }
+        record(&mapped, None, mechanism);
+        tokio::time::sleep(RETRY).await;
+    }
+}
+
+/// Replaces what `mechanism` has mapped.
+fn record(mapped: &Mapped, active: Option<(Router, PortMapping)>, mechanism: Mechanism) {
+    let mut mapped = mapped.lock().unwrap();
+    mapped.retain(|active| active.mapping.mechanism != mechanism);
+    if let Some((router, mapping)) = active {
+        mapped.push(Active { router, mapping });
+    }
+}
+
+/// The gateway of the default IPv4 route, from the kernel's routing table;
+/// the one through `interface` if it is named.
+fn default_gateway(interface: Option<&Interface>) -> Option<IpAddr> {
+    let device = match interface {
+        Some(Interface::Device(name)) => Some(name.as_str()),
+        _ => None,
+    };
+    let routes = fs::read_to_string("/proc/net/route").ok()?;
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [iface, "00000000", gateway, ..] if device.is_none_or(|name| name == *iface) => {
+                // The address is printed as a native-endian word.
+                let gateway = u32::from_str_radix(gateway, 16).ok()?;
+                Some(IpAddr::V4(Ipv4Addr::from(gateway.to_ne_bytes())))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// The address of the interface we reach `gateway` through, which is
+/// `interface` if there is one.
+async fn local_ip(gateway: SocketAddr, interface: Option<&Interface>) -> io::Result<IpAddr> {
+    let socket = interface::udp(interface, None, gateway.is_ipv6()).await?;
+    socket.connect(gateway).await?;
+    Ok(socket.local_addr()?.ip())
+}
+
+/// Sends `request` to `socket`'s peer until an answer comes, waiting
+/// 250 ms and then twice as long after each try, as NAT-PMP and PCP ask.
+async fn exchange(socket: &UdpSocket, request: &[u8]) -> Result<Vec<u8>, PortMapError> {
+    let mut wait = Duration::from_millis(250);
+    let mut buf = [0; 1100];
+    for _ in 0..ATTEMPTS {
+        socket.send(request).await?;
+        if let Ok(received) = tokio::time::timeout(wait, socket.recv(&mut buf)).await {
+            return Ok(buf[..received?].to_vec());
+        }
+        wait *= 2;
+    }
+    Err(PortMapError::Timeout)
+}
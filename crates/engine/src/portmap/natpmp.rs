@@ -0,0 +1,102 @@
+//! Port mapping with NAT-PMP (RFC 6886), asking the default gateway.
+
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use super::{exchange, Grant, PortMapError};
+use crate::interface::{self, Interface};
+
+/// The port NAT-PMP and PCP servers listen on.
+pub const SERVER_PORT: u16 = 5351;
+
+const VERSION: u8 = 0;
+const OP_EXTERNAL_ADDRESS: u8 = 0;
+const OP_MAP_TCP: u8 = 2;
+/// Added to the opcode in responses.
+const RESPONSE: u8 = 128;
+
+#[derive(Clone, Debug)]
+pub struct Router {
+    addr: SocketAddr,
+    interface: Option<Interface>,
+}
+
+impl Router {
+    pub fn new(gateway: IpAddr, interface: Option<Interface>) -> Self {
+        Self {
+            addr: SocketAddr::new(gateway, SERVER_PORT),
+            interface,
+        }
+    }
+
+    pub async fn map(&self, port: u16, lease: Duration) -> Result<Grant, PortMapError> {
+        let socket = self.open().await?;
+
+        let response = request(&socket, &[VERSION, OP_EXTERNAL_ADDRESS], 12).await?;
+        let external = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
+
+        let response = request(&socket, &map_request(port, port, lease), 16).await?;
+        let mapped_port = u16::from_be_bytes([response[10], response[11]]);
+        let lifetime = u32::from_be_bytes(response[12..16].try_into().unwrap());
+        Ok(Grant {
+            external: SocketAddr::new(external.into(), mapped_port),
+            lifetime: Duration::from_secs(lifetime.into()),
+        })
+    }
+
+    pub async fn unmap(&self, port: u16) -> Result<(), PortMapError> {
+        let socket = self.open().await?;
+        // A lifetime and suggested port of zero delete the mapping.
+        request(&socket, &map_request(port, 0, Duration::ZERO), 16).await?;
+        Ok(())
+    }
+
+    async fn open(&self) -> Result<UdpSocket, PortMapError> {
+        let socket = interface::udp(self.interface.as_ref(), None, self.addr.is_ipv6()).await?;
+        socket.connect(self.addr).await?;
+        Ok(socket)
+    }
+}
+
+fn map_request(internal: u16, external: u16, lease: Duration) -> Vec<u8> {
+    let mut request = Vec::with_capacity(12);
+    request.extend_from_slice(&[VERSION, OP_MAP_TCP, 0, 0]);
+    request.extend_from_slice(&internal.to_be_bytes());
+    request.extend_from_slice(&external.to_be_bytes());
+    request.extend_from_slice(&(lease.as_secs() as u32).to_be_bytes());
+    request
+}
+
+/// Sends `request` and checks that the answer is a successful response to
+/// it of at least `len` bytes.
+async fn request(socket: &UdpSocket, request: &[u8], len: usize) -> Result<Vec<u8>, PortMapError> {
+    let response = exchange(socket, request).await?;
+    if response.len() < 4 {
+        return Err(PortMapError::Malformed("short NAT-PMP response"));
+    }
+    if response[0] != VERSION || response[1] != RESPONSE + request[1] {
+        return Err(PortMapError::Malformed("not a NAT-PMP response"));
+    }
+    let result = u16::from_be_bytes([response[2], response[3]]);
+    if result != 0 {
+        return Err(PortMapError::Refused(result_text(result).to_string()));
+    }
+    if response.len() < len {
+        return Err(PortMapError::Malformed("short NAT-PMP response"));
+    }
+    Ok(response)
+}
+
+fn result_text(code: u16) -> &'static str {
+    match code {
+        1 => "unsupported version",
+        2 => "not authorized",
+        3 => "network failure",
+        4 => "out of resources",
+        5 => "unsupported opcode",
+        _ => "unknown result code",
+    }
+}
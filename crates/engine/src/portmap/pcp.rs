@@ -0,0 +1,116 @@
+//! Port mapping with PCP (RFC 6887), NAT-PMP's successor, asking the
+//! default gateway.
+
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use super::natpmp::SERVER_PORT;
+use super::{exchange, local_ip, Grant, PortMapError};
+use crate::interface::{self, Interface};
+
+const VERSION: u8 = 2;
+const OP_MAP: u8 = 1;
+/// Set on the opcode in responses.
+const RESPONSE: u8 = 0x80;
+const PROTOCOL_TCP: u8 = 6;
+/// Length of a MAP request or response: the common header and the MAP
+/// payload.
+const MAP_LEN: usize = 24 + 36;
+
+#[derive(Clone, Debug)]
+pub struct Router {
+    addr: SocketAddr,
+    interface: Option<Interface>,
+    /// Identifies our mapping to the router, which only lets the same
+    /// nonce renew or delete it.
+    nonce: [u8; 12],
+}
+
+impl Router {
+    pub fn new(gateway: IpAddr, interface: Option<Interface>) -> Self {
+        Self {
+            addr: SocketAddr::new(gateway, SERVER_PORT),
+            interface,
+            nonce: rand::random(),
+        }
+    }
+
+    pub async fn map(&self, port: u16, lease: Duration) -> Result<Grant, PortMapError> {
+        let response = self.request(port, lease).await?;
+        let lifetime = u32::from_be_bytes(response[4..8].try_into().unwrap());
+        let external_port = u16::from_be_bytes([response[42], response[43]]);
+        let external: [u8; 16] = response[44..60].try_into().unwrap();
+        let external = Ipv6Addr::from(external);
+        let external = match external.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(external),
+        };
+        Ok(Grant {
+            external: SocketAddr::new(external, external_port),
+            lifetime: Duration::from_secs(lifetime.into()),
+        })
+    }
+
+    pub async fn unmap(&self, port: u16) -> Result<(), PortMapError> {
+        // A lifetime of zero deletes the mapping.
+        self.request(port, Duration::ZERO).await?;
+        Ok(())
+    }
+
+    /// Asks for `port` to be mapped for `lease`, returning the successful
+    /// response.
+    async fn request(&self, port: u16, lease: Duration) -> Result<Vec<u8>, PortMapError> {
+        let socket = interface::udp(self.interface.as_ref(), None, self.addr.is_ipv6()).await?;
+        socket.connect(self.addr).await?;
+        let client = match local_ip(self.addr, self.interface.as_ref()).await? {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+
+        let mut request = Vec::with_capacity(MAP_LEN);
+        request.extend_from_slice(&[VERSION, OP_MAP, 0, 0]);
+        request.extend_from_slice(&(lease.as_secs() as u32).to_be_bytes());
+        request.extend_from_slice(&client.octets());
+        request.extend_from_slice(&self.nonce);
+        request.extend_from_slice(&[PROTOCOL_TCP, 0, 0, 0]);
+        request.extend_from_slice(&port.to_be_bytes());
+        // Suggest the same external port, on any IPv4 address.
+        request.extend_from_slice(&port.to_be_bytes());
+        request.extend_from_slice(&Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets());
+
+        let response = exchange(&socket, &request).await?;
+        if response.len() < 4 || response[0] != VERSION || response[1] != RESPONSE | OP_MAP {
+            return Err(PortMapError::Malformed("not a PCP MAP response"));
+        }
+        if response[3] != 0 {
+            return Err(PortMapError::Refused(result_text(response[3]).to_string()));
+        }
+        if response.len() < MAP_LEN {
+            return Err(PortMapError::Malformed("short PCP MAP response"));
+        }
+        if response[24..36] != self.nonce {
+            return Err(PortMapError::Malformed("PCP response for another mapping"));
+        }
+        Ok(response)
+    }
+}
+
+fn result_text(code: u8) -> &'static str {
+    match code {
+        1 => "unsupported version",
+        2 => "not authorized",
+        3 => "malformed request",
+        4 => "unsupported opcode",
+        5 => "unsupported option",
+        6 => "malformed option",
+        7 => "network failure",
+        8 => "no resources",
+        9 => "unsupported protocol",
+        10 => "user exceeded quota",
+        11 => "cannot provide external address",
+        12 => "address mismatch",
+        13 => "excessive remote peers",
+        _ => "unknown result code",
+    }
+}
@@ -0,0 +1,57 @@
+//! Port mapping with UPnP IGD, on whichever router answers a search.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use igd_next::aio::tokio::{search_gateway, Tokio};
+use igd_next::aio::Gateway;
+use igd_next::{PortMappingProtocol, SearchOptions};
+
+use super::{local_ip, Grant, PortMapError};
+
+const DESCRIPTION: &str = "rainyday";
+
+#[derive(Clone, Debug)]
+pub struct Router {
+    gateway: Gateway<Tokio>,
+}
+
+impl Router {
+    /// Searches the local network for a router.
+    pub async fn find() -> Result<Self, PortMapError> {
+        let gateway = search_gateway(SearchOptions::default())
+            .await
+            .map_err(|e| PortMapError::Upnp(e.to_string()))?;
+        Ok(Self { gateway })
+    }
+
+    pub async fn map(&self, port: u16, lease: Duration) -> Result<Grant, PortMapError> {
+        let local = SocketAddr::new(local_ip(self.gateway.addr, None).await?, port);
+        self.gateway
+            .add_port(
+                PortMappingProtocol::TCP,
+                port,
+                local,
+                lease.as_secs() as u32,
+                DESCRIPTION,
+            )
+            .await
+            .map_err(|e| PortMapError::Upnp(e.to_string()))?;
+        let external = self
+            .gateway
+            .get_external_ip()
+            .await
+            .unwrap_or(Ipv4Addr::UNSPECIFIED.into());
+        Ok(Grant {
+            external: SocketAddr::new(external, port),
+            lifetime: lease,
+        })
+    }
+
+    pub async fn unmap(&self, port: u16) -> Result<(), PortMapError> {
+        self.gateway
+            .remove_port(PortMappingProtocol::TCP, port)
+            .await
+            .map_err(|e| PortMapError::Upnp(e.to_string()))
+    }
+}
@@ -0,0 +1,146 @@
+//! Sending traffic through an HTTP or SOCKS5 proxy, as configured under
+//! `[proxy]`.
+//!
+//! Proxying fails closed: traffic that is meant to go through the proxy
+//! but cannot, such as UDP trackers and the DHT, is not sent at all.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::magnet::percent_encode;
+
+#[derive(Debug, Error)]
+pub enum ProxyError {
+    #[error("a {0} proxy needs a host and a port")]
+    MissingAddress(ProxyKind),
+    #[error("a proxy password needs a username")]
+    MissingUsername,
+    #[error("SOCKS5 usernames and passwords are limited to 255 bytes")]
+    CredentialsTooLong,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyKind {
+    /// Connect directly.
+    #[default]
+    None,
+    /// An HTTP proxy, tunnelling peer connections with `CONNECT`.
+    Http,
+    Socks5,
+}
+
+impl std::fmt::Display for ProxyKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ProxyKind::None => "none",
+            ProxyKind::Http => "HTTP",
+            ProxyKind::Socks5 => "SOCKS5",
+        })
+    }
+}
+
+/// The `[proxy]` section: the proxy, and which traffic goes through it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    #[serde(rename = "type")]
+    pub kind: ProxyKind,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Connect to peers through the proxy.
+    pub peers: bool,
+    /// Announce to and scrape trackers through the proxy. UDP trackers are
+    /// skipped, since neither kind of proxy carries them.
+    pub trackers: bool,
+    /// Send DHT traffic through the proxy. Neither kind of proxy carries
+    /// it, so this turns the DHT off.
+    pub dht: bool,
+    /// Have the proxy look up tracker host names, so no DNS queries leave
+    /// this machine. HTTP proxies always do.
+    pub hostnames: bool,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            kind: ProxyKind::None,
+            host: None,
+            port: None,
+            username: None,
+            password: None,
+            peers: true,
+            trackers: true,
+            dht: true,
+            hostnames: true,
+        }
+    }
+}
+
+impl ProxyConfig {
+    /// Checks that the settings describe a usable proxy.
+    pub fn validate(&self) -> Result<(), ProxyError> {
+        if self.kind == ProxyKind::None {
+            return Ok(());
+        }
+        if self.host.is_none() || self.port.is_none() {
+            return Err(ProxyError::MissingAddress(self.kind));
+        }
+        if self.password.is_some() && self.username.is_none() {
+            return Err(ProxyError::MissingUsername);
+        }
+        let too_long = |s: &Option<String>| s.as_ref().is_some_and(|s| s.len() > 255);
+        if self.kind == ProxyKind::Socks5 && (too_long(&self.username) || too_long(&self.password))
+        {
+            return Err(ProxyError::CredentialsTooLong);
+        }
+        Ok(())
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.kind != ProxyKind::None
+    }
+
+    pub fn proxies_peers(&self) -> bool {
+        self.is_enabled() && self.peers
+    }
+
+    pub fn proxies_trackers(&self) -> bool {
+        self.is_enabled() && self.trackers
+    }
+
+    /// Whether the DHT must stay off because its traffic may only go
+    /// through the proxy.
+    pub fn blocks_dht(&self) -> bool {
+        self.is_enabled() && self.dht
+    }
+
+    pub fn address(&self) -> (&str, u16) {
+        (
+            self.host.as_deref().unwrap_or_default(),
+            self.port.unwrap_or_default(),
+        )
+    }
+
+    /// The proxy as a URL for the HTTP client, credentials included.
+    pub fn url(&self) -> String {
+        let scheme = match (self.kind, self.hostnames) {
+            (ProxyKind::Socks5, true) => "socks5h",
+            (ProxyKind::Socks5, false) => "socks5",
+            _ => "http",
+        };
+        let credentials = match (&self.username, &self.password) {
+            (Some(user), Some(password)) => format!(
+                "{}:{}@",
+                percent_encode(user.as_bytes()),
+                percent_encode(password.as_bytes())
+            ),
+            (Some(user), None) => format!("{}@", percent_encode(user.as_bytes())),
+            _ => String::new(),
+        };
+        let (host, port) = self.address();
+        format!("{}://{}{}:{}", scheme, credentials, host, port)
+    }
+}
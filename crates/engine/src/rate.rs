@@ -0,0 +1,51 @@
+//! Transfer rates averaged over a short sliding window.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How far back rates look by default.
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Measures the rate at which bytes pass through something.
+#[derive(Clone, Debug)]
+pub struct RateMeter {
+    window: Duration,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl RateMeter {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, bytes: u64) {
+        let now = Instant::now();
+        self.expire(now);
+        self.samples.push_back((now, bytes));
+    }
+
+    /// Bytes per second over the window.
+    pub fn rate(&mut self) -> u64 {
+        self.expire(Instant::now());
+        let total: u64 = self.samples.iter().map(|(_, bytes)| bytes).sum();
+        total * 1000 / self.window.as_millis().max(1) as u64
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(at, _)) = self.samples.front() {
+            if now.duration_since(at) <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+}
+
+impl Default for RateMeter {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW)
+    }
+}
@@ -0,0 +1,111 @@
+//! Resume data: what we need to pick a torrent up where we left off.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::bencode::{self, BencodeError, Value};
+use crate::bitfield::Bitfield;
+use crate::info_hash::InfoHash;
+
+/// Version of the on-disk format written by this build.
+pub const RESUME_VERSION: i64 = 1;
+
+#[derive(Debug, Error)]
+pub enum ResumeError {
+    #[error("resume file is not valid bencode: {0}")]
+    Bencode(#[from] BencodeError),
+    #[error("resume file has unsupported version {0}")]
+    UnsupportedVersion(i64),
+    #[error("resume file field `{0}` is missing or invalid")]
+    InvalidField(&'static str),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResumeData {
+    pub info_hash: InfoHash,
+    /// Directory the torrent's data lives in.
+    pub save_path: PathBuf,
+    /// Pieces that have been verified and flushed to disk. The session
+    /// flushes each torrent's data before recording them.
+    pub have: Bitfield,
+    pub uploaded: u64,
+    pub downloaded: u64,
+}
+
+impl ResumeData {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut dict = BTreeMap::new();
+        dict.insert(b"version".to_vec(), Value::Integer(RESUME_VERSION));
+        dict.insert(
+            b"info-hash".to_vec(),
+            Value::Bytes(self.info_hash.as_bytes().to_vec()),
+        );
+        dict.insert(
+            b"save-path".to_vec(),
+            Value::from(self.save_path.to_string_lossy().as_ref()),
+        );
+        dict.insert(b"pieces".to_vec(), Value::Integer(self.have.len() as i64));
+        dict.insert(
+            b"have".to_vec(),
+            Value::Bytes(self.have.as_bytes().to_vec()),
+        );
+        dict.insert(b"uploaded".to_vec(), Value::Integer(self.uploaded as i64));
+        dict.insert(
+            b"downloaded".to_vec(),
+            Value::Integer(self.downloaded as i64),
+        );
+        Value::Dict(dict).encode()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ResumeError> {
+        let value = bencode::decode(bytes)?;
+
+        let version = value
+            .get("version")
+            .and_then(Value::as_int)
+            .ok_or(ResumeError::InvalidField("version"))?;
+        if version != RESUME_VERSION {
+            return Err(ResumeError::UnsupportedVersion(version));
+        }
+
+        let info_hash = value
+            .get("info-hash")
+            .and_then(Value::as_bytes)
+            .and_then(|bytes| <[u8; 20]>::try_from(bytes).ok())
+            .map(InfoHash)
+            .ok_or(ResumeError::InvalidField("info-hash"))?;
+        let save_path = value
+            .get("save-path")
+            .and_then(Value::as_str)
+            .map(PathBuf::from)
+            .ok_or(ResumeError::InvalidField("save-path"))?;
+        let pieces = value
+            .get("pieces")
+            .and_then(Value::as_int)
+            .and_then(|n| usize::try_from(n).ok())
+            .ok_or(ResumeError::InvalidField("pieces"))?;
+        let have = value
+            .get("have")
+            .and_then(Value::as_bytes)
+            .and_then(|bytes| Bitfield::from_bytes(bytes, pieces))
+            .ok_or(ResumeError::InvalidField("have"))?;
+        let counter = |key: &'static str| {
+            value
+                .get(key)
+                .and_then(Value::as_int)
+                .and_then(|n| u64::try_from(n).ok())
+                .ok_or(ResumeError::InvalidField(key))
+        };
+
+        Ok(Self {
+            info_hash,
+            save_path,
+            have,
+            uploaded: counter("uploaded")?,
+            downloaded: counter("downloaded")?,
+        })
+    }
+}
@@ -0,0 +1,278 @@
+//! Alternate rate limits that apply at certain times of the week, such as
+//! working hours, and times when the whole session is paused.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::limits::Limits;
+use crate::session::Session;
+
+/// How often the schedule is checked against the clock.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// The `[schedule]` section: limits to use instead of the usual ones
+/// during the given periods.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(default)]
+pub struct Schedule {
+    /// Most bytes per second to download during a period, or unlimited.
+    pub download_rate: Option<u64>,
+    /// Most bytes per second to upload during a period, or unlimited.
+    pub upload_rate: Option<u64>,
+    /// When the alternate limits apply, in local time.
+    pub periods: Vec<Period>,
+    /// When every torrent of the daemon is paused, in local time.
+    pub pause_periods: Vec<Period>,
+}
+
+/// A span of hours on some days of the week.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Period {
+    pub days: Days,
+    pub hours: Hours,
+}
+
+/// A set of weekdays, written as names and ranges such as `mon-fri,sun`,
+/// or `daily`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Days(u8);
+
+/// A span of the day, written `09:00-17:30` or just `9-17`. A span whose
+/// end is before its start, such as `22-6`, covers the start and end of
+/// each of its days.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Hours {
+    /// Minutes after midnight.
+    start: u16,
+    end: u16,
+}
+
+impl Schedule {
+    /// Whether the alternate limits apply on `weekday` (0 for Sunday) at
+    /// `minute` minutes after midnight.
+    pub fn is_active(&self, weekday: u8, minute: u16) -> bool {
+        covers(&self.periods, weekday, minute)
+    }
+
+    /// Whether the session should be paused now.
+    pub fn is_paused_now(&self) -> bool {
+        let (weekday, minute) = local_now();
+        covers(&self.pause_periods, weekday, minute)
+    }
+}
+
+impl Period {
+    pub fn contains(&self, weekday: u8, minute: u16) -> bool {
+        self.days.contains(weekday) && self.hours.contains(minute)
+    }
+}
+
+fn covers(periods: &[Period], weekday: u8, minute: u16) -> bool {
+    periods
+        .iter()
+        .any(|period| period.contains(weekday, minute))
+}
+
+impl Days {
+    pub fn contains(&self, weekday: u8) -> bool {
+        self.0 & (1 << weekday) != 0
+    }
+}
+
+impl Hours {
+    pub fn contains(&self, minute: u16) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl FromStr for Days {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid days {:?}; expected e.g. mon-fri,sun", s);
+        let day = |name: &str| {
+            let name = name.trim().to_ascii_lowercase();
+            DAY_NAMES
+                .iter()
+                .position(|day| name.get(..3) == Some(*day))
+                .ok_or_else(invalid)
+        };
+
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("daily") || s == "*" {
+            return Ok(Self(0x7f));
+        }
+        let mut days = 0u8;
+        for part in s.split(',') {
+            let (first, last) = match part.split_once('-') {
+                Some((first, last)) => (day(first)?, day(last)?),
+                None => (day(part)?, day(part)?),
+            };
+            // Ranges may wrap around the weekend, as in fri-mon.
+            let mut weekday = first;
+            loop {
+                days |= 1 << weekday;
+                if weekday == last {
+                    break;
+                }
+                weekday = (weekday + 1) % 7;
+            }
+        }
+        Ok(Self(days))
+    }
+}
+
+impl FromStr for Hours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid hours {:?}; expected e.g. 09:00-17:30", s);
+        let time = |t: &str| {
+            let (hour, minute) = t.trim().split_once(':').unwrap_or((t.trim(), "0"));
+            let hour: u16 = hour.parse().map_err(|_| invalid())?;
+            let minute: u16 = minute.parse().map_err(|_| invalid())?;
+            if hour > 24 || minute >= 60 || hour * 60 + minute > 24 * 60 {
+                return Err(invalid());
+            }
+            Ok(hour * 60 + minute)
+        };
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let (start, end) = (time(start)?, time(end)?);
+        if start == end {
+            return Err(invalid());
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl TryFrom<String> for Days {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl TryFrom<String> for Hours {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Days> for String {
+    fn from(days: Days) -> Self {
+        days.to_string()
+    }
+}
+
+impl From<Hours> for String {
+    fn from(hours: Hours) -> Self {
+        hours.to_string()
+    }
+}
+
+impl fmt::Display for Days {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == 0x7f {
+            return f.write_str("daily");
+        }
+        let names: Vec<&str> = (0..7)
+            .filter(|&weekday| self.contains(weekday))
+            .map(|weekday| DAY_NAMES[weekday as usize])
+            .collect();
+        f.write_str(&names.join(","))
+    }
+}
+
+impl fmt::Display for Hours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+/// The local weekday (0 for Sunday) and minutes after midnight.
+fn local_now() -> (u8, u16) {
+    // SAFETY: localtime_r and localtime_s only write to the tm we pass.
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        #[cfg(unix)]
+        libc::localtime_r(&now, &mut tm);
+        #[cfg(windows)]
+        libc::localtime_s(&mut tm, &now);
+        tm
+    };
+    (tm.tm_wday as u8, (tm.tm_hour * 60 + tm.tm_min) as u16)
+}
+
+/// Switches the session's rate limits between `normal` and the schedule's
+/// alternates as periods begin and end. Runs until the session ends.
+pub async fn run(schedule: Schedule, limits: Limits, normal: (Option<u64>, Option<u64>)) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut alternate = false;
+    loop {
+        interval.tick().await;
+        let (weekday, minute) = local_now();
+        let active = schedule.is_active(weekday, minute);
+        if active == alternate {
+            continue;
+        }
+        alternate = active;
+
+        let (download, upload) = if active {
+            info!("switching to the scheduled alternate rate limits");
+            (schedule.download_rate, schedule.upload_rate)
+        } else {
+            info!("switching back to the normal rate limits");
+            normal
+        };
+        limits.download.set_rate(download);
+        limits.upload.set_rate(upload);
+    }
+}
+
+/// Pauses the whole session as the schedule's pause periods begin, and
+/// resumes it as they end. Runs until the session ends.
+pub async fn pause_during(schedule: Schedule, session: Arc<Session>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut paused = session.is_paused();
+    loop {
+        interval.tick().await;
+        let pause = schedule.is_paused_now();
+        if pause == paused {
+            continue;
+        }
+        paused = pause;
+
+        if pause {
+            info!("pausing the session as scheduled");
+            session.pause().await;
+        } else {
+            info!("resuming the session as scheduled");
+            session.resume().await;
+        }
+    }
+}
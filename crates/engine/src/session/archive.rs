@@ -0,0 +1,95 @@
+//! A whole session in one file, for backing it up or moving it to another
+//! machine: every torrent's record, with its metainfo, settings,
+//! statistics and resume data, in a gzipped bencoded dictionary.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use thiserror::Error;
+
+use super::store::{StoreError, TorrentRecord};
+use crate::bencode::{self, BencodeError, Value};
+use crate::info_hash::InfoHash;
+
+/// Version of the archive format written by this build.
+pub const ARCHIVE_VERSION: i64 = 1;
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("session archive is not gzipped: {0}")]
+    Gzip(#[from] io::Error),
+    #[error("session archive is not valid bencode: {0}")]
+    Bencode(#[from] BencodeError),
+    #[error("unsupported session archive version {0}")]
+    Version(i64),
+    #[error("invalid session archive: field `{0}` is missing or invalid")]
+    InvalidField(&'static str),
+    #[error(transparent)]
+    Record(#[from] StoreError),
+}
+
+/// Packs `records` into an archive.
+pub fn to_bytes(records: &[TorrentRecord]) -> Vec<u8> {
+    let torrents = records
+        .iter()
+        .map(|record| {
+            let mut dict = BTreeMap::new();
+            dict.insert(
+                b"info-hash".to_vec(),
+                Value::Bytes(record.info_hash.as_bytes().to_vec()),
+            );
+            dict.insert(b"record".to_vec(), Value::Bytes(record.to_bytes()));
+            Value::Dict(dict)
+        })
+        .collect();
+
+    let mut dict = BTreeMap::new();
+    dict.insert(b"version".to_vec(), Value::Integer(ARCHIVE_VERSION));
+    dict.insert(b"torrents".to_vec(), Value::List(torrents));
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // Writing to a Vec cannot fail.
+    encoder
+        .write_all(&Value::Dict(dict).encode())
+        .expect("writing to memory");
+    encoder.finish().expect("writing to memory")
+}
+
+/// Unpacks the records of an archive written by [`to_bytes`].
+pub fn from_bytes(bytes: &[u8]) -> Result<Vec<TorrentRecord>, ArchiveError> {
+    let mut unpacked = Vec::new();
+    GzDecoder::new(bytes).read_to_end(&mut unpacked)?;
+    let value = bencode::decode(&unpacked)?;
+
+    let version = value
+        .get("version")
+        .and_then(Value::as_int)
+        .ok_or(ArchiveError::InvalidField("version"))?;
+    if version != ARCHIVE_VERSION {
+        return Err(ArchiveError::Version(version));
+    }
+
+    value
+        .get("torrents")
+        .and_then(Value::as_list)
+        .ok_or(ArchiveError::InvalidField("torrents"))?
+        .iter()
+        .map(|torrent| {
+            let info_hash = torrent
+                .get("info-hash")
+                .and_then(Value::as_bytes)
+                .and_then(|bytes| <[u8; 20]>::try_from(bytes).ok())
+                .map(InfoHash)
+                .ok_or(ArchiveError::InvalidField("info-hash"))?;
+            let record = torrent
+                .get("record")
+                .and_then(Value::as_bytes)
+                .ok_or(ArchiveError::InvalidField("record"))?;
+            Ok(TorrentRecord::from_bytes(info_hash, record)?)
+        })
+        .collect()
+}
@@ -0,0 +1,33 @@
+//! The set of torrents managed together by one client instance.
+
+pub mod archive;
+pub mod running;
+pub mod store;
+
+pub use running::{save_periodically, Session, SessionError};
+pub use store::{SessionStore, TorrentRecord};
+
+use std::path::PathBuf;
+
+use tracing::info;
+
+use crate::config::Config;
+use crate::storage::{MovableStorage, StorageError};
+
+/// Moves a finished torrent out of the incomplete directory, returning its
+/// new location if it had to move. Torrents stored anywhere else, e.g.
+/// because the user moved them, are left alone.
+pub fn move_completed(
+    storage: &MovableStorage,
+    config: &Config,
+) -> Result<Option<PathBuf>, StorageError> {
+    let destination = &config.storage.download_dir;
+    match &config.storage.incomplete_dir {
+        Some(incomplete) if storage.root() == *incomplete && incomplete != destination => {}
+        _ => return Ok(None),
+    }
+
+    info!("moving finished download to {}", destination.display());
+    storage.move_to(destination)?;
+    Ok(Some(destination.clone()))
+}
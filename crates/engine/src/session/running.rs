@@ -0,0 +1,826 @@
+//! The torrents running in one process, which the control interface can
+//! add to and remove from.
+//!
+//! The torrents share the listening ports, incoming peers being handed to
+//! the torrent their handshake asks for, and one set of [`Limits`].
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rand::seq::SliceRandom;
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::OnceCell;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use crate::bitfield::Bitfield;
+use crate::blocklist::{self, Blocklist};
+use crate::config::{Config, DEFAULT_LISTEN_PORT};
+use crate::engine::{
+    self, EngineError, Handle, Mode, Options, SeedGoal, Shared, State, Status, Summary,
+    CONNECT_TIMEOUT,
+};
+use crate::event::{Event, EventBus};
+use crate::info_hash::InfoHash;
+use crate::interface::{self, Endpoint, Interface};
+use crate::limits::Limits;
+use crate::metainfo::Metainfo;
+use crate::peer::connection::accept;
+use crate::peer::encryption::EncryptionPolicy;
+use crate::peer::id::PeerIdError;
+use crate::peer::transport;
+use crate::peer::PeerId;
+use crate::portmap::{PortMapper, PortMapping};
+use crate::resume::ResumeData;
+use crate::schedule;
+use crate::session::store::{
+    SessionStore, StoreError, TorrentOptions, TorrentRecord, TorrentStats,
+};
+use crate::stats::{Counters, Transferred};
+use crate::storage::StorageError;
+use crate::swarm::ExternalIp;
+
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("{0}")]
+    Engine(#[from] EngineError),
+    #[error("torrent {0} is already running")]
+    AlreadyRunning(InfoHash),
+    #[error("cannot listen for peers on {at}: {source}")]
+    Listen { at: String, source: io::Error },
+    #[error("invalid peer ID: {0}")]
+    PeerId(#[from] PeerIdError),
+    #[error("torrent {0} is not running")]
+    NotRunning(InfoHash),
+    #[error("{0}")]
+    Storage(#[from] StorageError),
+}
+
+struct Entry {
+    info_hash: InfoHash,
+    handle: Handle,
+    task: Option<JoinHandle<Result<Summary, EngineError>>>,
+    /// What the torrent had transferred when its progress was last
+    /// recorded, which the lifetime totals already include.
+    recorded: Transferred,
+}
+
+impl Entry {
+    /// What the torrent transferred since its progress was last recorded,
+    /// as of `status`, which is taken to be recorded now.
+    fn unrecorded(&mut self, status: &Status) -> Transferred {
+        let since = Transferred {
+            downloaded: status.downloaded.saturating_sub(self.recorded.downloaded),
+            uploaded: status.uploaded.saturating_sub(self.recorded.uploaded),
+        };
+        self.recorded = Transferred {
+            downloaded: status.downloaded,
+            uploaded: status.uploaded,
+        };
+        since
+    }
+}
+
+type Torrents = Arc<Mutex<Vec<Entry>>>;
+
+/// Starts torrents and keeps track of them until they stop.
+pub struct Session {
+    config: Config,
+    events: EventBus,
+    limits: Limits,
+    blocklist: Blocklist,
+    torrents: Torrents,
+    /// What every torrent transferred since the session started.
+    counters: Arc<Counters>,
+    /// Where peers connect to, once the first torrent has started, with
+    /// the ports actually bound. The first is the main one.
+    endpoints: OnceCell<Vec<Endpoint>>,
+    /// Keeps the port forwarded on the router, if asked to.
+    port_mapper: Mutex<Option<PortMapper>>,
+    /// Our public address, once a router, tracker or peer tells us.
+    external_ip: ExternalIp,
+    /// Who we are to peers and trackers, for as long as the session lasts.
+    peer_id: PeerId,
+    daemon: bool,
+    /// Where the daemon records its torrents and their settings.
+    store: Option<SessionStore>,
+    /// Set once the session is shutting down, when torrents stop because
+    /// they are told to.
+    closing: AtomicBool,
+    /// While the whole session is paused, the torrents it paused, which
+    /// are resumed with it.
+    held: Mutex<Option<Vec<InfoHash>>>,
+}
+
+impl Session {
+    /// A session for the torrents given on the command line, which ends
+    /// with them.
+    ///
+    /// Must be called within a Tokio runtime, which runs the session's
+    /// rate limit schedule and blocklist reloads.
+    pub fn new(config: Config, events: EventBus) -> Result<Self, SessionError> {
+        let peer_id = config.peer_id()?;
+        let limits = Limits::new(&config);
+        let blocklist = Blocklist::from_config(&config.blocklist);
+        tokio::spawn(blocklist::reload_periodically(
+            blocklist.clone(),
+            config.blocklist.clone(),
+        ));
+        if !config.schedule.periods.is_empty() {
+            let normal = (config.limits.download_rate, config.limits.upload_rate);
+            tokio::spawn(schedule::run(
+                config.schedule.clone(),
+                limits.clone(),
+                normal,
+            ));
+        }
+        Ok(Self {
+            limits,
+            blocklist,
+            config,
+            events,
+            torrents: Arc::default(),
+            counters: Arc::default(),
+            endpoints: OnceCell::new(),
+            port_mapper: Mutex::new(None),
+            external_ip: ExternalIp::default(),
+            peer_id,
+            daemon: false,
+            store: None,
+            closing: AtomicBool::new(false),
+            held: Mutex::new(None),
+        })
+    }
+
+    /// A long-lived session that torrents are added to over the control
+    /// interface.
+    pub fn daemon(config: Config, events: EventBus) -> Result<Self, SessionError> {
+        Ok(Self {
+            daemon: true,
+            ..Self::new(config, events)?
+        })
+    }
+
+    /// Records torrents added to the session in `store`.
+    pub fn with_store(mut self, store: SessionStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn is_daemon(&self) -> bool {
+        self.daemon
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// The peer ID all the session's torrents and metadata fetches use.
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Starts running `metainfo`, refusing a torrent that is already
+    /// running.
+    pub async fn add(
+        &self,
+        metainfo: Arc<Metainfo>,
+        options: &Options,
+        mode: Mode,
+    ) -> Result<Handle, SessionError> {
+        let info_hash = metainfo.info_hash;
+        if self.find(&info_hash).is_some() {
+            return Err(SessionError::AlreadyRunning(info_hash));
+        }
+        let endpoints = self.endpoints().await?;
+        let shared = Shared {
+            events: self.events.clone(),
+            port: endpoints[0].port,
+            endpoints: endpoints.to_vec(),
+            limits: self.limits.clone(),
+            blocklist: self.blocklist.clone(),
+            counters: Arc::clone(&self.counters),
+            external_ip: self.external_ip.clone(),
+            peer_id: self.peer_id,
+            transport: transport::from_config(&self.config),
+            #[cfg(feature = "faults")]
+            faults: None,
+        };
+        let (handle, task) = engine::start(metainfo, &self.config, options, &shared, mode).await?;
+        self.torrents.lock().unwrap().push(Entry {
+            info_hash,
+            handle: handle.clone(),
+            task: Some(task),
+            recorded: Transferred::default(),
+        });
+        if self.is_paused() {
+            handle.pause().await;
+            self.hold(info_hash);
+        }
+        Ok(handle)
+    }
+
+    /// What every torrent transferred since the session started, those
+    /// since removed included.
+    pub fn transferred(&self) -> Transferred {
+        self.counters.snapshot()
+    }
+
+    /// Whether the whole session is paused.
+    pub fn is_paused(&self) -> bool {
+        self.held.lock().unwrap().is_some()
+    }
+
+    /// Pauses every running torrent, and those added later until the
+    /// session is resumed. Returns the torrents this paused.
+    pub async fn pause(&self) -> Vec<Handle> {
+        self.held.lock().unwrap().get_or_insert_with(Vec::new);
+        let mut paused = Vec::new();
+        for handle in self.handles() {
+            match handle.status().await {
+                Some(status) if status.state != State::Paused => {
+                    handle.pause().await;
+                    self.hold(status.info_hash);
+                    paused.push(handle);
+                }
+                _ => {}
+            }
+        }
+        paused
+    }
+
+    /// Resumes the torrents that pausing the session paused. Returns those
+    /// still running.
+    pub async fn resume(&self) -> Vec<Handle> {
+        let held = self.held.lock().unwrap().take().unwrap_or_default();
+        let mut resumed = Vec::new();
+        for info_hash in held {
+            if let Some(handle) = self.find(&info_hash) {
+                handle.resume().await;
+                resumed.push(handle);
+            }
+        }
+        resumed
+    }
+
+    fn hold(&self, info_hash: InfoHash) {
+        if let Some(held) = self.held.lock().unwrap().as_mut() {
+            held.push(info_hash);
+        }
+    }
+
+    /// Leaves a torrent paused or resumed on its own as it is when the
+    /// session is resumed.
+    pub fn release(&self, info_hash: &InfoHash) {
+        if let Some(held) = self.held.lock().unwrap().as_mut() {
+            held.retain(|held| held != info_hash);
+        }
+    }
+
+    fn is_held(&self, info_hash: &InfoHash) -> bool {
+        self.held
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|held| held.contains(info_hash))
+    }
+
+    /// The main port peers connect to, listening first if no torrent has
+    /// yet.
+    pub async fn peer_port(&self) -> Result<u16, SessionError> {
+        Ok(self.endpoints().await?[0].port)
+    }
+
+    /// The main port peers connect to, if the session is listening yet.
+    pub fn bound_port(&self) -> Option<u16> {
+        self.endpoints.get().map(|endpoints| endpoints[0].port)
+    }
+
+    /// Where peers connect to, if the session is listening yet.
+    pub fn bound_endpoints(&self) -> Vec<Endpoint> {
+        self.endpoints.get().cloned().unwrap_or_default()
+    }
+
+    async fn endpoints(&self) -> Result<&[Endpoint], SessionError> {
+        Ok(self.endpoints.get_or_try_init(|| self.listen()).await?)
+    }
+
+    /// The forwards of the port routers have granted.
+    pub fn port_mappings(&self) -> Vec<PortMapping> {
+        self.port_mapper
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(PortMapper::mappings)
+            .unwrap_or_default()
+    }
+
+    /// Starts accepting peer connections for the session's torrents,
+    /// returning where. Only the main port is forwarded on the router.
+    async fn listen(&self) -> Result<Vec<Endpoint>, SessionError> {
+        let network = &self.config.network;
+        let store = self.store.as_ref().filter(|_| network.keep_port);
+        let kept = store.and_then(|store| match store.kept_port() {
+            Ok(port) => port,
+            Err(e) => {
+                warn!("cannot read the kept listen port: {}", e);
+                None
+            }
+        });
+        let bound = bind(&self.config, kept).await?;
+        if let Some(store) = store.filter(|_| network.listen.is_empty()) {
+            let port = bound[0].1.port;
+            if kept != Some(port) {
+                if let Err(e) = store.keep_port(port).and_then(|()| store.flush()) {
+                    warn!("cannot keep listen port {}: {}", port, e);
+                }
+            }
+        }
+        if network.upnp || network.natpmp {
+            let port = bound[0].1.port;
+            *self.port_mapper.lock().unwrap() =
+                Some(PortMapper::start(port, network, self.external_ip.clone()));
+        }
+
+        let single = bound.len() == 1 && network.listen.is_empty();
+        let mut endpoints = Vec::new();
+        for (listener, endpoint) in bound {
+            if single {
+                info!("listening for peers on port {}", endpoint.port);
+            } else {
+                info!("listening for peers on {}", endpoint);
+            }
+            let torrents = Arc::clone(&self.torrents);
+            let policy = network.encryption;
+            tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, addr)) => {
+                            tokio::spawn(route(stream, addr, Arc::clone(&torrents), policy));
+                        }
+                        Err(e) => warn!("failed to accept connection: {}", e),
+                    }
+                }
+            });
+            endpoints.push(endpoint);
+        }
+        Ok(endpoints)
+    }
+
+    /// The torrents still running, in the order they were added.
+    pub fn handles(&self) -> Vec<Handle> {
+        let mut torrents = self.torrents.lock().unwrap();
+        torrents.retain(|entry| entry.handle.is_running() || entry.task.is_some());
+        torrents
+            .iter()
+            .filter(|entry| entry.handle.is_running())
+            .map(|entry| entry.handle.clone())
+            .collect()
+    }
+
+    fn find(&self, info_hash: &InfoHash) -> Option<Handle> {
+        self.torrents
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|entry| entry.info_hash == *info_hash && entry.handle.is_running())
+            .map(|entry| entry.handle.clone())
+    }
+
+    /// Waits for a torrent to stop of its own accord, returning how it
+    /// went. Returns `None` if the torrent is unknown or already waited for.
+    pub async fn wait(&self, info_hash: &InfoHash) -> Option<Result<Summary, EngineError>> {
+        let task = self
+            .torrents
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|entry| entry.info_hash == *info_hash)?
+            .task
+            .take()?;
+        Some(task.await.expect("torrent task panicked"))
+    }
+
+    /// Stops a torrent and forgets it. Returns whether it was running.
+    pub async fn remove(&self, info_hash: &InfoHash) -> bool {
+        let entry = {
+            let mut torrents = self.torrents.lock().unwrap();
+            match torrents
+                .iter()
+                .position(|entry| entry.info_hash == *info_hash)
+            {
+                Some(index) => torrents.remove(index),
+                None => return false,
+            }
+        };
+        stop(entry).await;
+        self.events.publish(Event::TorrentRemoved {
+            info_hash: *info_hash,
+        });
+        true
+    }
+
+    /// Moves a running torrent's data to the same place under `dir`, and
+    /// records that it is kept there.
+    pub async fn move_torrent(
+        &self,
+        info_hash: &InfoHash,
+        dir: PathBuf,
+    ) -> Result<(), SessionError> {
+        let handle = self
+            .find(info_hash)
+            .ok_or(SessionError::NotRunning(*info_hash))?;
+        handle
+            .move_to(dir.clone())
+            .await
+            .ok_or(SessionError::NotRunning(*info_hash))??;
+        self.update_record(info_hash, |record| {
+            record.options.save_path = dir.clone();
+            if let Some(resume) = &mut record.resume {
+                resume.save_path = dir.clone();
+            }
+        });
+        Ok(())
+    }
+
+    /// Renames a running torrent's single file or top-level directory, and
+    /// records the new name.
+    pub async fn rename_torrent(
+        &self,
+        info_hash: &InfoHash,
+        name: String,
+    ) -> Result<(), SessionError> {
+        let handle = self
+            .find(info_hash)
+            .ok_or(SessionError::NotRunning(*info_hash))?;
+        handle
+            .rename(name.clone())
+            .await
+            .ok_or(SessionError::NotRunning(*info_hash))??;
+        self.update_record(info_hash, |record| record.options.name = Some(name.clone()));
+        Ok(())
+    }
+
+    /// Changes a torrent's record in the session store, if there is one.
+    /// The change is already made, so failing to record it is only logged.
+    fn update_record(&self, info_hash: &InfoHash, change: impl Fn(&mut TorrentRecord)) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        if let Err(e) = store.update(info_hash, change).and_then(|_| store.flush()) {
+            warn!(%info_hash, "cannot record the change in the session store: {}", e);
+        }
+    }
+
+    /// Records a torrent and the settings it was added with in the session
+    /// store, if there is one.
+    pub fn remember(
+        &self,
+        metainfo: &Metainfo,
+        options: &Options,
+        goal: SeedGoal,
+    ) -> Result<(), StoreError> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let added_at = unix_time();
+        let save_path = options
+            .download_dir
+            .clone()
+            .unwrap_or_else(|| self.config.storage.download_dir.clone());
+        store.insert(&TorrentRecord {
+            info_hash: metainfo.info_hash,
+            metainfo: metainfo.to_bytes(),
+            options: TorrentOptions {
+                save_path,
+                name: options.name.clone(),
+                paused: false,
+                file_priorities: options.file_priorities.clone(),
+                download_rate: options.download_rate,
+                upload_rate: options.upload_rate,
+                seed_goal: goal,
+                sequential: options.sequential,
+            },
+            stats: TorrentStats {
+                added_at,
+                ..TorrentStats::default()
+            },
+            resume: None,
+        })?;
+        store.flush()
+    }
+
+    /// Drops a torrent from the session store, if there is one.
+    pub fn forget(&self, info_hash: &InfoHash) -> Result<(), StoreError> {
+        match &self.store {
+            Some(store) => {
+                store.remove(info_hash)?;
+                store.flush()
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The torrents recorded in the session store, if there is one, to be
+    /// started again when the daemon starts.
+    pub fn records(&self) -> Result<Vec<TorrentRecord>, StoreError> {
+        match &self.store {
+            Some(store) => store.list(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Records that a torrent stopped of its own accord, such as once its
+    /// seeding goal was met, so that it is not started again with the
+    /// session.
+    pub fn stopped(&self, info_hash: &InfoHash) -> Result<(), StoreError> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        if self.closing.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        store.update(info_hash, |record| record.options.paused = true)?;
+        store.flush()
+    }
+
+    /// Records which pieces every running torrent has safely on disk,
+    /// whether it is paused, and what it transferred in the session store,
+    /// so that the next session carries on from here even if this one
+    /// ends abruptly.
+    pub async fn save(&self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        for handle in self.handles() {
+            if self.closing.load(Ordering::SeqCst) {
+                return;
+            }
+            let Some(status) = handle.status().await else {
+                continue;
+            };
+            let Some(have) = handle.checkpoint().await else {
+                continue;
+            };
+            let transferred = self
+                .torrents
+                .lock()
+                .unwrap()
+                .iter_mut()
+                .rev()
+                .find(|entry| entry.info_hash == status.info_hash)
+                .map(|entry| entry.unrecorded(&status));
+            if let Some(transferred) = transferred {
+                self.record(store, &status, have, transferred);
+            }
+        }
+        if let Err(e) = store.flush() {
+            warn!("cannot save the session: {}", e);
+        }
+    }
+
+    /// Records one torrent's progress: `have`, the pieces its flushed data
+    /// holds, and `transferred`, what it moved since last recorded.
+    fn record(
+        &self,
+        store: &SessionStore,
+        status: &Status,
+        have: Bitfield,
+        transferred: Transferred,
+    ) {
+        let now = unix_time();
+        let saved = store.update(&status.info_hash, |record| {
+            record.stats.uploaded += transferred.uploaded;
+            record.stats.downloaded += transferred.downloaded;
+            if status.done == status.wanted && record.stats.completed_at.is_none() {
+                record.stats.completed_at = Some(now);
+            }
+            // Those the session paused start with it next time.
+            record.options.paused =
+                status.state == State::Paused && !self.is_held(&status.info_hash);
+            record.resume = Some(ResumeData {
+                info_hash: status.info_hash,
+                save_path: record.options.save_path.clone(),
+                have: have.clone(),
+                uploaded: record.stats.uploaded,
+                downloaded: record.stats.downloaded,
+            });
+        });
+        if let Err(e) = saved {
+            warn!("cannot record the progress of {}: {}", status.name, e);
+        }
+    }
+
+    /// Stops every torrent, waiting for each to say goodbye to its trackers
+    /// and flush its data, then records its progress.
+    pub async fn shutdown(&self) {
+        self.closing.store(true, Ordering::SeqCst);
+        let entries: Vec<Entry> = self.torrents.lock().unwrap().drain(..).collect();
+        for mut entry in entries {
+            // Asked first, as a stopped torrent answers nothing.
+            let status = entry.handle.status().await;
+            let transferred = status.as_ref().map(|status| entry.unrecorded(status));
+            let have = stop(entry).await;
+            if let (Some(store), Some(status), Some(transferred), Some(have)) =
+                (&self.store, status, transferred, have)
+            {
+                self.record(store, &status, have, transferred);
+            }
+        }
+        if let Some(store) = &self.store {
+            if let Err(e) = store.flush() {
+                warn!("cannot save the session: {}", e);
+            }
+        }
+        self.unmap_port().await;
+    }
+
+    /// Removes the port forward from the router, if there is one.
+    pub async fn unmap_port(&self) {
+        let mapper = self.port_mapper.lock().unwrap().take();
+        if let Some(mapper) = mapper {
+            mapper.stop().await;
+        }
+    }
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Binds each endpoint the configuration lists, or otherwise the single
+/// port it asks for, returning the listeners with where they are bound.
+async fn bind(
+    config: &Config,
+    kept: Option<u16>,
+) -> Result<Vec<(TcpListener, Endpoint)>, SessionError> {
+    let mut bound = Vec::new();
+    for endpoint in &config.network.listen {
+        let listener = interface::listen(Some(&endpoint.interface), endpoint.port)
+            .await
+            .map_err(|source| SessionError::Listen {
+                at: endpoint.to_string(),
+                source,
+            })?;
+        let port = listener
+            .local_addr()
+            .map_err(|source| SessionError::Listen {
+                at: endpoint.to_string(),
+                source,
+            })?
+            .port();
+        let interface = endpoint.interface.clone();
+        bound.push((listener, Endpoint { interface, port }));
+    }
+    if bound.is_empty() {
+        let (listener, port) = bind_single(config, kept).await?;
+        let interface = config
+            .network
+            .interface
+            .clone()
+            .unwrap_or(Interface::Address(Ipv4Addr::UNSPECIFIED.into()));
+        bound.push((listener, Endpoint { interface, port }));
+        if config.network.dual_stack && config.network.interface.is_none() {
+            bound.extend(bind_ipv6(port).await);
+        }
+    }
+    Ok(bound)
+}
+
+/// Also listens on IPv6, on the same port, if the host has a public IPv6
+/// address. Failing to is not an error, as IPv4 is listened on already.
+async fn bind_ipv6(port: u16) -> Option<(TcpListener, Endpoint)> {
+    interface::public_addresses().1?;
+    let interface = Interface::Address(Ipv6Addr::UNSPECIFIED.into());
+    match interface::listen(Some(&interface), port).await {
+        Ok(listener) => Some((listener, Endpoint { interface, port })),
+        Err(e) => {
+            warn!("cannot listen for peers on IPv6 port {}: {}", port, e);
+            None
+        }
+    }
+}
+
+/// Binds the port the configuration asks for: one at random from the
+/// range if there is one, preferring the `kept` port if it is in it, the
+/// port if one was given, or otherwise the default port or any free one.
+async fn bind_single(
+    config: &Config,
+    kept: Option<u16>,
+) -> Result<(TcpListener, u16), SessionError> {
+    let interface = config.network.interface.as_ref();
+    if let Some(range) = config.network.port_range {
+        let mut ports: Vec<u16> = range.ports().collect();
+        ports.shuffle(&mut rand::thread_rng());
+        if let Some(index) = ports.iter().position(|&port| Some(port) == kept) {
+            ports.swap(0, index);
+        }
+        let mut last_error = None;
+        for port in ports {
+            match bind_port(interface, port).await {
+                Ok(bound) => return Ok(bound),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        return Err(SessionError::Listen {
+            at: format!("port {}", range),
+            source: last_error.expect("a port range is never empty"),
+        });
+    }
+
+    if let Some(port) = config.network.port {
+        return bind_port(interface, port)
+            .await
+            .map_err(|source| SessionError::Listen {
+                at: format!("port {}", port),
+                source,
+            });
+    }
+
+    match bind_port(interface, DEFAULT_LISTEN_PORT).await {
+        Ok(bound) => Ok(bound),
+        Err(e) => {
+            warn!("cannot listen on port {}: {}", DEFAULT_LISTEN_PORT, e);
+            bind_port(interface, 0)
+                .await
+                .map_err(|source| SessionError::Listen {
+                    at: "port 0".to_string(),
+                    source,
+                })
+        }
+    }
+}
+
+async fn bind_port(interface: Option<&Interface>, port: u16) -> io::Result<(TcpListener, u16)> {
+    let listener = interface::listen(interface, port).await?;
+    let port = listener.local_addr()?.port();
+    Ok((listener, port))
+}
+
+/// Reads an incoming peer's handshake and hands the connection to the
+/// torrent it asks for.
+async fn route(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    torrents: Torrents,
+    policy: EncryptionPolicy,
+) {
+    let span = info_span!("handshake", %addr, incoming = true);
+    let handshake = match timeout(CONNECT_TIMEOUT, accept(&mut stream, policy))
+        .instrument(span)
+        .await
+    {
+        Ok(Ok(handshake)) => handshake,
+        Ok(Err(e)) => return debug!(%addr, "incoming handshake failed: {}", e),
+        Err(_) => return debug!(%addr, "incoming handshake timed out"),
+    };
+    let handle = torrents
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|entry| entry.info_hash == handshake.info_hash && entry.handle.is_running())
+        .map(|entry| entry.handle.clone());
+    match handle {
+        Some(handle) => handle.incoming(Box::new(stream), addr, handshake).await,
+        None => {
+            debug!(%addr, info_hash = %handshake.info_hash, "incoming peer wants an unknown torrent")
+        }
+    }
+}
+
+/// Stops a torrent, returning the pieces its flushed data holds.
+async fn stop(entry: Entry) -> Option<Bitfield> {
+    let have = entry.handle.stop().await;
+    if let Some(task) = entry.task {
+        let _ = task.await;
+    }
+    have
+}
+
+/// Records the progress of the session's torrents every `save_interval`,
+/// so that a crash loses no more than that.
+pub async fn save_periodically(session: Arc<Session>) {
+    let seconds = session.config.save_interval;
+    if seconds == 0 || session.store.is_none() {
+        return;
+    }
+    let period = Duration::from_secs(seconds);
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+        session.save().await;
+    }
+}
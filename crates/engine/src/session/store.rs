@@ -0,0 +1,353 @@
+//! Persistent record of every torrent in a session, so restarting the
+//! client restores the session rather than starting from nothing.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::bencode::{self, BencodeError, Value};
+use crate::engine::SeedGoal;
+use crate::files::FilePriority;
+use crate::info_hash::InfoHash;
+use crate::resume::{ResumeData, ResumeError};
+
+/// Key of the kept listen port, in the database's default tree.
+const KEPT_PORT_KEY: &[u8] = b"kept-port";
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("session database error: {0}")]
+    Database(#[from] sled::Error),
+    #[error("corrupt session record: {0}")]
+    Bencode(#[from] BencodeError),
+    #[error("corrupt session record: field `{0}` is missing or invalid")]
+    InvalidField(&'static str),
+    #[error("corrupt resume data in session record: {0}")]
+    Resume(#[from] ResumeError),
+}
+
+/// Settings chosen for a torrent when it was added.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TorrentOptions {
+    /// Directory the torrent's data is stored under.
+    pub save_path: PathBuf,
+    /// Name of the top-level file or directory, if it differs from the
+    /// name in the metainfo.
+    pub name: Option<String>,
+    pub paused: bool,
+    /// Per-file priorities, indexed like the torrent's files. Empty means
+    /// every file is wanted.
+    pub file_priorities: Vec<FilePriority>,
+    /// The torrent's own rate limits, in bytes per second.
+    pub download_rate: Option<u64>,
+    pub upload_rate: Option<u64>,
+    /// When to stop seeding, instead of never.
+    pub seed_goal: SeedGoal,
+    /// Download pieces in order rather than rarest first.
+    pub sequential: bool,
+}
+
+/// Lifetime statistics kept across restarts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TorrentStats {
+    pub uploaded: u64,
+    pub downloaded: u64,
+    /// Unix timestamp of when the torrent was added.
+    pub added_at: i64,
+    /// Unix timestamp of when the download finished, if it has.
+    pub completed_at: Option<i64>,
+}
+
+/// Everything the session remembers about one torrent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TorrentRecord {
+    pub info_hash: InfoHash,
+    /// The original `.torrent` file.
+    pub metainfo: Vec<u8>,
+    pub options: TorrentOptions,
+    pub stats: TorrentStats,
+    pub resume: Option<ResumeData>,
+}
+
+impl TorrentRecord {
+    pub(super) fn to_bytes(&self) -> Vec<u8> {
+        let mut options = BTreeMap::new();
+        options.insert(
+            b"save-path".to_vec(),
+            Value::from(self.options.save_path.to_string_lossy().as_ref()),
+        );
+        if let Some(name) = &self.options.name {
+            options.insert(b"name".to_vec(), Value::from(name.as_str()));
+        }
+        options.insert(
+            b"paused".to_vec(),
+            Value::Integer(self.options.paused as i64),
+        );
+        options.insert(
+            b"file-priorities".to_vec(),
+            Value::List(
+                self.options
+                    .file_priorities
+                    .iter()
+                    .map(|&p| Value::Integer(encode_priority(p)))
+                    .collect(),
+            ),
+        );
+        if let Some(rate) = self.options.download_rate {
+            options.insert(b"download-rate".to_vec(), Value::Integer(rate as i64));
+        }
+        if let Some(rate) = self.options.upload_rate {
+            options.insert(b"upload-rate".to_vec(), Value::Integer(rate as i64));
+        }
+        if let Some(ratio) = self.options.seed_goal.ratio {
+            // Bencode has no floats; keep thousandths.
+            options.insert(
+                b"seed-ratio".to_vec(),
+                Value::Integer((ratio * 1000.0).round() as i64),
+            );
+        }
+        if let Some(time) = self.options.seed_goal.time {
+            options.insert(b"seed-time".to_vec(), Value::Integer(time.as_secs() as i64));
+        }
+        options.insert(
+            b"sequential".to_vec(),
+            Value::Integer(self.options.sequential as i64),
+        );
+
+        let mut stats = BTreeMap::new();
+        stats.insert(
+            b"uploaded".to_vec(),
+            Value::Integer(self.stats.uploaded as i64),
+        );
+        stats.insert(
+            b"downloaded".to_vec(),
+            Value::Integer(self.stats.downloaded as i64),
+        );
+        stats.insert(b"added-at".to_vec(), Value::Integer(self.stats.added_at));
+        if let Some(completed_at) = self.stats.completed_at {
+            stats.insert(b"completed-at".to_vec(), Value::Integer(completed_at));
+        }
+
+        let mut dict = BTreeMap::new();
+        dict.insert(b"metainfo".to_vec(), Value::Bytes(self.metainfo.clone()));
+        dict.insert(b"options".to_vec(), Value::Dict(options));
+        dict.insert(b"stats".to_vec(), Value::Dict(stats));
+        if let Some(resume) = &self.resume {
+            dict.insert(b"resume".to_vec(), Value::Bytes(resume.to_bytes()));
+        }
+        Value::Dict(dict).encode()
+    }
+
+    pub(super) fn from_bytes(info_hash: InfoHash, bytes: &[u8]) -> Result<Self, StoreError> {
+        let value = bencode::decode(bytes)?;
+        let int = |dict: &Value, key: &'static str| {
+            dict.get(key)
+                .and_then(Value::as_int)
+                .ok_or(StoreError::InvalidField(key))
+        };
+        let count = |dict: &Value, key: &'static str| {
+            int(dict, key).and_then(|n| u64::try_from(n).map_err(|_| StoreError::InvalidField(key)))
+        };
+
+        let metainfo = value
+            .get("metainfo")
+            .and_then(Value::as_bytes)
+            .ok_or(StoreError::InvalidField("metainfo"))?
+            .to_vec();
+
+        let options = value
+            .get("options")
+            .ok_or(StoreError::InvalidField("options"))?;
+        let options = TorrentOptions {
+            save_path: options
+                .get("save-path")
+                .and_then(Value::as_str)
+                .map(PathBuf::from)
+                .ok_or(StoreError::InvalidField("save-path"))?,
+            name: options
+                .get("name")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            paused: int(options, "paused")? != 0,
+            file_priorities: options
+                .get("file-priorities")
+                .and_then(Value::as_list)
+                .unwrap_or_default()
+                .iter()
+                .map(|p| p.as_int().and_then(decode_priority))
+                .collect::<Option<_>>()
+                .ok_or(StoreError::InvalidField("file-priorities"))?,
+            download_rate: optional_count(options, "download-rate")?,
+            upload_rate: optional_count(options, "upload-rate")?,
+            seed_goal: SeedGoal {
+                ratio: optional_count(options, "seed-ratio")?.map(|n| n as f64 / 1000.0),
+                time: optional_count(options, "seed-time")?.map(Duration::from_secs),
+            },
+            // Absent from records written before it existed.
+            sequential: options.get("sequential").and_then(Value::as_int) == Some(1),
+        };
+
+        let stats = value
+            .get("stats")
+            .ok_or(StoreError::InvalidField("stats"))?;
+        let stats = TorrentStats {
+            uploaded: count(stats, "uploaded")?,
+            downloaded: count(stats, "downloaded")?,
+            added_at: int(stats, "added-at")?,
+            completed_at: stats.get("completed-at").and_then(Value::as_int),
+        };
+
+        let resume = value
+            .get("resume")
+            .and_then(Value::as_bytes)
+            .map(ResumeData::from_bytes)
+            .transpose()?;
+
+        Ok(Self {
+            info_hash,
+            metainfo,
+            options,
+            stats,
+            resume,
+        })
+    }
+}
+
+/// A non-negative integer that may be left out of a record.
+fn optional_count(dict: &Value, key: &'static str) -> Result<Option<u64>, StoreError> {
+    dict.get(key)
+        .map(|value| {
+            value
+                .as_int()
+                .and_then(|n| u64::try_from(n).ok())
+                .ok_or(StoreError::InvalidField(key))
+        })
+        .transpose()
+}
+
+/// On-disk priority values. These are stable identifiers rather than an
+/// ordering, so new levels are appended.
+fn encode_priority(priority: FilePriority) -> i64 {
+    match priority {
+        FilePriority::Skip => 0,
+        FilePriority::Normal => 1,
+        FilePriority::Low => 2,
+        FilePriority::High => 3,
+    }
+}
+
+fn decode_priority(value: i64) -> Option<FilePriority> {
+    match value {
+        0 => Some(FilePriority::Skip),
+        1 => Some(FilePriority::Normal),
+        2 => Some(FilePriority::Low),
+        3 => Some(FilePriority::High),
+        _ => None,
+    }
+}
+
+/// A sled database holding one record per torrent, keyed by info hash.
+pub struct SessionStore {
+    db: sled::Db,
+    torrents: sled::Tree,
+}
+
+impl SessionStore {
+    pub fn open(path: &Path) -> Result<Self, StoreError> {
+        let db = sled::open(path)?;
+        let torrents = db.open_tree("torrents")?;
+        Ok(Self { db, torrents })
+    }
+
+    /// Adds or replaces a torrent's record.
+    pub fn insert(&self, record: &TorrentRecord) -> Result<(), StoreError> {
+        self.torrents
+            .insert(record.info_hash.as_bytes(), record.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn get(&self, info_hash: &InfoHash) -> Result<Option<TorrentRecord>, StoreError> {
+        self.torrents
+            .get(info_hash.as_bytes())?
+            .map(|bytes| TorrentRecord::from_bytes(*info_hash, &bytes))
+            .transpose()
+    }
+
+    pub fn remove(&self, info_hash: &InfoHash) -> Result<(), StoreError> {
+        self.torrents.remove(info_hash.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn contains(&self, info_hash: &InfoHash) -> Result<bool, StoreError> {
+        Ok(self.torrents.contains_key(info_hash.as_bytes())?)
+    }
+
+    /// Every torrent in the session, ordered by info hash.
+    pub fn list(&self) -> Result<Vec<TorrentRecord>, StoreError> {
+        self.torrents
+            .iter()
+            .map(|entry| {
+                let (key, bytes) = entry?;
+                let info_hash = <[u8; 20]>::try_from(key.as_ref())
+                    .map(InfoHash)
+                    .map_err(|_| StoreError::InvalidField("info-hash"))?;
+                TorrentRecord::from_bytes(info_hash, &bytes)
+            })
+            .collect()
+    }
+
+    /// Applies `update` to a torrent's record atomically, returning `false`
+    /// if the torrent is not in the session.
+    pub fn update(
+        &self,
+        info_hash: &InfoHash,
+        update: impl Fn(&mut TorrentRecord),
+    ) -> Result<bool, StoreError> {
+        let mut found = false;
+        let mut error = None;
+        self.torrents
+            .fetch_and_update(info_hash.as_bytes(), |old| {
+                let old = old?;
+                match TorrentRecord::from_bytes(*info_hash, old) {
+                    Ok(mut record) => {
+                        found = true;
+                        update(&mut record);
+                        Some(record.to_bytes())
+                    }
+                    Err(e) => {
+                        error = Some(e);
+                        Some(old.to_vec())
+                    }
+                }
+            })?;
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(found),
+        }
+    }
+
+    /// The listen port kept from an earlier session, if any.
+    pub fn kept_port(&self) -> Result<Option<u16>, StoreError> {
+        Ok(self
+            .db
+            .get(KEPT_PORT_KEY)?
+            .and_then(|bytes| <[u8; 2]>::try_from(bytes.as_ref()).ok())
+            .map(u16::from_be_bytes))
+    }
+
+    /// Remembers the listen port for later sessions.
+    pub fn keep_port(&self, port: u16) -> Result<(), StoreError> {
+        self.db.insert(KEPT_PORT_KEY, &port.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Blocks until every change has reached the disk.
+    pub fn flush(&self) -> Result<(), StoreError> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
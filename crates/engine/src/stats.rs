@@ -0,0 +1,58 @@
+//! Counters of the bytes transferred by the session, each torrent and each
+//! peer.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Live transfer counters, added to as blocks pass through.
+///
+/// Counters can have a parent that everything they count is added to as
+/// well: a peer's are the children of its torrent's, which are the
+/// children of the session's. Every level can then be read at any time,
+/// from any task, without a lock or asking the engine that counts.
+#[derive(Debug, Default)]
+pub struct Counters {
+    downloaded: AtomicU64,
+    uploaded: AtomicU64,
+    parent: Option<Arc<Counters>>,
+}
+
+/// A point-in-time copy of [`Counters`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Transferred {
+    pub downloaded: u64,
+    pub uploaded: u64,
+}
+
+impl Counters {
+    /// Counters that also add what they count to `self`.
+    pub fn child(self: &Arc<Self>) -> Self {
+        Self {
+            parent: Some(Arc::clone(self)),
+            ..Self::default()
+        }
+    }
+
+    pub fn record_download(&self, bytes: u64) {
+        let mut counters = Some(self);
+        while let Some(level) = counters {
+            level.downloaded.fetch_add(bytes, Ordering::Relaxed);
+            counters = level.parent.as_deref();
+        }
+    }
+
+    pub fn record_upload(&self, bytes: u64) {
+        let mut counters = Some(self);
+        while let Some(level) = counters {
+            level.uploaded.fetch_add(bytes, Ordering::Relaxed);
+            counters = level.parent.as_deref();
+        }
+    }
+
+    pub fn snapshot(&self) -> Transferred {
+        Transferred {
+            downloaded: self.downloaded.load(Ordering::Relaxed),
+            uploaded: self.uploaded.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! How space for a torrent's files is reserved up front.
+
+use std::fs::File;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AllocationMode {
+    /// Set each file's length without reserving blocks. Fast, but blocks are
+    /// allocated in download order, which fragments files and defers
+    /// running out of space until some arbitrary write.
+    #[default]
+    Sparse,
+    /// Reserve every block before downloading. Slower to start, but files
+    /// stay contiguous and a full disk is reported immediately. On Windows
+    /// and systems with no preallocation call, such as OpenBSD and NetBSD,
+    /// this writes zeroes over each file, which takes as long as writing
+    /// the whole torrent.
+    Full,
+    /// Create empty files and let them grow as data arrives.
+    None,
+}
+
+/// Prepares `file` to hold `length` bytes according to `mode`.
+pub fn allocate(file: &File, length: u64, mode: AllocationMode) -> io::Result<()> {
+    let current = file.metadata()?.len();
+
+    match mode {
+        AllocationMode::None => Ok(()),
+        AllocationMode::Sparse => {
+            if current < length {
+                file.set_len(length)?;
+            }
+            Ok(())
+        }
+        AllocationMode::Full => preallocate(file, length),
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "illumos",
+    target_os = "solaris"
+))]
+fn preallocate(file: &File, length: u64) -> io::Result<()> {
+    use std::convert::TryFrom;
+    use std::os::unix::io::AsRawFd;
+
+    if length == 0 {
+        return Ok(());
+    }
+
+    let len = libc::off_t::try_from(length)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large"))?;
+    // posix_fallocate reports errors through its return value, not errno.
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len) } {
+        0 => Ok(()),
+        libc::EINVAL | libc::EOPNOTSUPP => zero_fill(file, length),
+        err => Err(io::Error::from_raw_os_error(err)),
+    }
+}
+
+/// Reserves blocks with `F_PREALLOCATE`, contiguous ones if it can, and
+/// then sets the length, which the call leaves alone.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn preallocate(file: &File, length: u64) -> io::Result<()> {
+    use std::convert::TryFrom;
+    use std::os::unix::io::AsRawFd;
+
+    let current = file.metadata()?.len();
+    if current >= length {
+        return Ok(());
+    }
+
+    let len = libc::off_t::try_from(length - current)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large"))?;
+    let mut store = libc::fstore_t {
+        fst_flags: libc::F_ALLOCATECONTIG,
+        fst_posmode: libc::F_PEOFPOSMODE,
+        fst_offset: 0,
+        fst_length: len,
+        fst_bytesalloc: 0,
+    };
+    let fd = file.as_raw_fd();
+    // SAFETY: fcntl only reads and updates the fstore_t we pass it.
+    if unsafe { libc::fcntl(fd, libc::F_PREALLOCATE, &mut store as *mut libc::fstore_t) } == -1 {
+        store.fst_flags = libc::F_ALLOCATEALL;
+        if unsafe { libc::fcntl(fd, libc::F_PREALLOCATE, &mut store as *mut libc::fstore_t) } == -1
+        {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::EINVAL | libc::ENOTSUP) => zero_fill(file, length),
+                _ => Err(err),
+            };
+        }
+    }
+    file.set_len(length)
+}
+
+/// Elsewhere, including on Windows and the BSDs without
+/// `posix_fallocate`, "full" allocation writes zeroes over the file.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "illumos",
+    target_os = "solaris",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+fn preallocate(file: &File, length: u64) -> io::Result<()> {
+    zero_fill(file, length)
+}
+
+/// Writes zeroes over any part of the file not yet backed by data, for
+/// filesystems without a native preallocation call.
+fn zero_fill(file: &File, length: u64) -> io::Result<()> {
+    use std::io::{Seek, SeekFrom, Write};
+
+    let current = file.metadata()?.len();
+    if current >= length {
+        return Ok(());
+    }
+
+    let mut file = file;
+    file.seek(SeekFrom::Start(current))?;
+    let zeroes = vec![0u8; 1 << 16];
+    let mut remaining = length - current;
+    while remaining > 0 {
+        let n = remaining.min(zeroes.len() as u64) as usize;
+        file.write_all(&zeroes[..n])?;
+        remaining -= n as u64;
+    }
+    Ok(())
+}
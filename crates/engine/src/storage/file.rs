@@ -0,0 +1,331 @@
+//! Storage backed by ordinary files accessed with positioned reads and
+//! writes.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IoSlice};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use bytes::Bytes;
+use tracing::info_span;
+
+use super::allocation::{allocate, AllocationMode};
+use super::hints::{self, IoHints};
+use super::{DiskMetrics, Layout, Storage, StorageError};
+
+/// Default bound on file handles kept open at once, so torrents with
+/// thousands of files do not exhaust the descriptor limit.
+pub const DEFAULT_OPEN_FILES: usize = 128;
+
+pub struct FileStorage {
+    root: PathBuf,
+    layout: Layout,
+    open: Mutex<HashMap<usize, Arc<Handle>>>,
+    writable: bool,
+    hints: IoHints,
+    metrics: Arc<DiskMetrics>,
+}
+
+/// An open file, plus a direct I/O handle on it when that is enabled.
+struct Handle {
+    file: File,
+    direct: Option<File>,
+}
+
+impl FileStorage {
+    /// Creates the torrent's files under `root`, allocating them according
+    /// to `mode`. Existing files are kept, so partial downloads resume.
+    pub fn open(root: &Path, layout: Layout, mode: AllocationMode) -> Result<Self, StorageError> {
+        for slot in &layout.files {
+            let path = root.join(&slot.path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?;
+            allocate(&file, slot.length, mode)?;
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            layout,
+            open: Mutex::new(HashMap::new()),
+            writable: true,
+            hints: IoHints::default(),
+            metrics: Arc::default(),
+        })
+    }
+
+    /// Opens existing data under `root` for reading only, creating nothing.
+    /// Missing files read back as zeroes.
+    pub fn open_read_only(root: &Path, layout: Layout) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            layout,
+            open: Mutex::new(HashMap::new()),
+            writable: false,
+            hints: IoHints::default(),
+            metrics: Arc::default(),
+        }
+    }
+
+    /// Counts this storage's activity in `metrics` rather than its own.
+    pub fn with_metrics(mut self, metrics: Arc<DiskMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Applies page cache hints to subsequent I/O.
+    pub fn with_hints(mut self, hints: IoHints) -> Self {
+        self.hints = hints;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn file(&self, index: usize) -> io::Result<Arc<Handle>> {
+        let mut open = self.open.lock().unwrap();
+        if let Some(file) = open.get(&index) {
+            self.metrics.record_cache(true);
+            return Ok(Arc::clone(file));
+        }
+        self.metrics.record_cache(false);
+
+        if open.len() >= self.hints.open_files.max(1) {
+            let victim = *open.keys().next().unwrap();
+            open.remove(&victim);
+        }
+
+        let path = self.root.join(&self.layout.files[index].path);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(self.writable)
+            .open(&path)?;
+        let direct = if self.writable && self.hints.direct_io {
+            hints::open_direct(&path)
+        } else {
+            None
+        };
+
+        let handle = Arc::new(Handle { file, direct });
+        open.insert(index, Arc::clone(&handle));
+        Ok(handle)
+    }
+
+    fn check_range(&self, offset: u64, len: u64) -> Result<(), StorageError> {
+        if offset + len > self.layout.total_length {
+            Err(StorageError::OutOfRange { offset, len })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    fn metrics(&self) -> &DiskMetrics {
+        &self.metrics
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), StorageError> {
+        self.check_range(offset, data.len() as u64)?;
+        let _op = self.metrics.begin();
+
+        let mut written = 0;
+        for span in self.layout.spans(offset, data.len() as u64) {
+            let chunk = &data[written..written + span.len as usize];
+            let handle = self.file(span.file)?;
+            match &handle.direct {
+                Some(direct) if hints::is_aligned(span.file_offset, chunk.len()) => {
+                    hints::write_direct(direct, &[IoSlice::new(chunk)], span.file_offset)?
+                }
+                _ => write_all_at(&handle.file, chunk, span.file_offset)?,
+            }
+            written += span.len as usize;
+        }
+        self.metrics.record_write(data.len() as u64);
+        Ok(())
+    }
+
+    /// Writes the piece with one vectored write for each file it overlaps,
+    /// rather than one write for each block.
+    fn write_blocks(&self, piece: u32, blocks: &[Bytes]) -> Result<(), StorageError> {
+        let offset = self.layout.piece_offset(piece);
+        let len = blocks.iter().map(|block| block.len() as u64).sum();
+        self.check_range(offset, len)?;
+        let _op = self.metrics.begin();
+
+        let mut blocks = blocks.iter().map(|block| &block[..]);
+        let mut current: &[u8] = &[];
+        for span in self.layout.spans(offset, len) {
+            let mut parts = Vec::new();
+            let mut wanted = span.len as usize;
+            while wanted > 0 {
+                if current.is_empty() {
+                    match blocks.next() {
+                        Some(block) => current = block,
+                        None => break,
+                    }
+                }
+                let (part, rest) = current.split_at(wanted.min(current.len()));
+                parts.push(IoSlice::new(part));
+                wanted -= part.len();
+                current = rest;
+            }
+            let handle = self.file(span.file)?;
+            match &handle.direct {
+                Some(direct) if hints::is_aligned(span.file_offset, span.len as usize) => {
+                    hints::write_direct(direct, &parts, span.file_offset)?
+                }
+                _ => write_vectored_all_at(&handle.file, &mut parts, span.file_offset)?,
+            }
+        }
+        self.metrics.record_write(len);
+        Ok(())
+    }
+
+    fn read_into(&self, offset: u64, buf: &mut [u8]) -> Result<(), StorageError> {
+        let len = buf.len() as u64;
+        self.check_range(offset, len)?;
+        let _op = self.metrics.begin();
+
+        let mut read = 0;
+        for span in self.layout.spans(offset, len) {
+            let chunk = &mut buf[read..read + span.len as usize];
+            read += span.len as usize;
+            let handle = match self.file(span.file) {
+                Ok(handle) => handle,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            read_at_most(&handle.file, chunk, span.file_offset)?;
+        }
+        self.metrics.record_read(len);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        let _span = info_span!("flush").entered();
+        let _op = self.metrics.begin();
+        let started = Instant::now();
+        let open: Vec<Arc<Handle>> = self.open.lock().unwrap().values().cloned().collect();
+        for handle in open {
+            handle.file.sync_data()?;
+        }
+        self.metrics.record_flush(started.elapsed());
+        Ok(())
+    }
+
+    fn drop_cache(&self, offset: u64, len: u64) -> Result<(), StorageError> {
+        if !self.hints.drop_cache {
+            return Ok(());
+        }
+        for span in self.layout.spans(offset, len) {
+            match self.file(span.file) {
+                Ok(handle) => hints::drop_cache(&handle.file, span.file_offset, span.len)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+pub(super) fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+
+/// Most buffers passed to one `pwritev`, the least `IOV_MAX` allowed.
+#[cfg(unix)]
+const MAX_IOVECS: usize = 1024;
+
+#[cfg(unix)]
+fn write_vectored_all_at(
+    file: &File,
+    mut parts: &mut [IoSlice<'_>],
+    mut offset: u64,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    while !parts.is_empty() {
+        let count = parts.len().min(MAX_IOVECS);
+        // Safety: `IoSlice` has the layout of `iovec`, and each points into a
+        // live buffer.
+        let n = unsafe {
+            libc::pwritev(
+                file.as_raw_fd(),
+                parts.as_ptr().cast(),
+                count as libc::c_int,
+                offset as libc::off_t,
+            )
+        };
+        if n < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(error);
+        }
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        IoSlice::advance_slices(&mut parts, n as usize);
+        offset += n as u64;
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn write_vectored_all_at(file: &File, parts: &mut [IoSlice<'_>], offset: u64) -> io::Result<()> {
+    let mut data = Vec::new();
+    for part in parts.iter() {
+        data.extend_from_slice(part);
+    }
+    write_all_at(file, &data, offset)
+}
+
+#[cfg(windows)]
+pub(super) fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        let n = file.seek_write(buf, offset)?;
+        buf = &buf[n..];
+        offset += n as u64;
+    }
+    Ok(())
+}
+
+/// Fills as much of `buf` as the file holds; anything past its end is left
+/// zeroed.
+fn read_at_most(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    #[cfg(unix)]
+    use std::os::unix::fs::FileExt;
+    #[cfg(windows)]
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        #[cfg(unix)]
+        let n = file.read_at(buf, offset)?;
+        #[cfg(windows)]
+        let n = file.seek_read(buf, offset)?;
+        if n == 0 {
+            break;
+        }
+        buf = &mut buf[n..];
+        offset += n as u64;
+    }
+    Ok(())
+}
@@ -0,0 +1,150 @@
+//! Page cache hints for bulk torrent I/O.
+//!
+//! A seedbox moving terabytes through the page cache evicts everything else
+//! the machine had cached. Writing with `O_DIRECT` and dropping pieces from
+//! the cache once they are hashed keeps torrent traffic from crowding out
+//! other workloads. Both are only implemented on Linux; elsewhere they are
+//! ignored.
+
+use std::fs::File;
+use std::io::{self, IoSlice};
+use std::path::Path;
+
+use super::file::DEFAULT_OPEN_FILES;
+#[cfg(feature = "tokio")]
+use crate::config::Config;
+
+/// Alignment of offsets, lengths and buffers for direct I/O. Stricter than
+/// most devices need, but always sufficient.
+pub const DIRECT_ALIGN: usize = 4096;
+
+/// How a backend should treat the page cache and its own caches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoHints {
+    /// Write aligned blocks with `O_DIRECT`, bypassing the page cache.
+    pub direct_io: bool,
+    /// Drop data from the page cache once it has been hashed.
+    pub drop_cache: bool,
+    /// Most file handles to keep open at once.
+    pub open_files: usize,
+}
+
+impl Default for IoHints {
+    fn default() -> Self {
+        Self {
+            direct_io: false,
+            drop_cache: false,
+            open_files: DEFAULT_OPEN_FILES,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl From<&Config> for IoHints {
+    fn from(config: &Config) -> Self {
+        Self {
+            direct_io: config.storage.direct_io,
+            drop_cache: config.storage.drop_cache_after_hash,
+            open_files: config.storage.open_files,
+        }
+    }
+}
+
+/// Opens a second, write-only handle on `path` for direct I/O. Returns
+/// `None` where the platform or filesystem does not support it.
+#[cfg(target_os = "linux")]
+pub fn open_direct(path: &Path) -> Option<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+        .ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn open_direct(_path: &Path) -> Option<File> {
+    None
+}
+
+/// Whether a write at `offset` of `len` bytes can go through direct I/O.
+pub fn is_aligned(offset: u64, len: usize) -> bool {
+    offset.is_multiple_of(DIRECT_ALIGN as u64) && len.is_multiple_of(DIRECT_ALIGN) && len > 0
+}
+
+/// Writes `parts` through a direct I/O handle, copying them one after the
+/// other into a suitably aligned buffer first.
+pub fn write_direct(file: &File, parts: &[IoSlice<'_>], offset: u64) -> io::Result<()> {
+    let len = parts.iter().map(|part| part.len()).sum();
+    let mut buf = AlignedBuf::new(len);
+    let mut filled = 0;
+    for part in parts {
+        buf.as_mut_slice()[filled..filled + part.len()].copy_from_slice(part);
+        filled += part.len();
+    }
+    super::file::write_all_at(file, buf.as_slice(), offset)
+}
+
+/// Writes back and evicts `len` bytes at `offset` from the page cache.
+#[cfg(target_os = "linux")]
+pub fn drop_cache(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    // Dirty pages cannot be dropped, so push them to disk first.
+    let flags = libc::SYNC_FILE_RANGE_WAIT_BEFORE
+        | libc::SYNC_FILE_RANGE_WRITE
+        | libc::SYNC_FILE_RANGE_WAIT_AFTER;
+    // Safety: plain syscalls on a descriptor we own.
+    unsafe {
+        if libc::sync_file_range(fd, offset as i64, len as i64, flags) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        match libc::posix_fadvise(fd, offset as i64, len as i64, libc::POSIX_FADV_DONTNEED) {
+            0 => Ok(()),
+            errno => Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn drop_cache(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+    Ok(())
+}
+
+/// A heap buffer aligned to [`DIRECT_ALIGN`].
+struct AlignedBuf {
+    ptr: *mut u8,
+    layout: std::alloc::Layout,
+}
+
+impl AlignedBuf {
+    fn new(len: usize) -> Self {
+        let layout = std::alloc::Layout::from_size_align(len.max(1), DIRECT_ALIGN)
+            .expect("direct I/O buffer too large");
+        // Safety: the layout has a non-zero size.
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+        Self { ptr, layout }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        // Safety: `ptr` points to `layout.size()` initialised bytes.
+        unsafe { std::slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        // Safety: as above, and we hold the only reference.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        // Safety: allocated in `new` with the same layout.
+        unsafe { std::alloc::dealloc(self.ptr, self.layout) }
+    }
+}
@@ -0,0 +1,121 @@
+//! Mapping between the torrent's contiguous byte space and its files.
+
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::metainfo::Info;
+
+/// A file of the torrent positioned within the torrent's byte space.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileSlot {
+    /// Path relative to the download directory.
+    pub path: PathBuf,
+    pub length: u64,
+    /// Offset of the file's first byte within the torrent.
+    pub offset: u64,
+}
+
+/// The part of a torrent byte range that falls within one file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub file: usize,
+    pub file_offset: u64,
+    pub len: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub files: Vec<FileSlot>,
+    pub piece_length: u32,
+    pub total_length: u64,
+}
+
+impl Layout {
+    pub fn new(info: &Info) -> Self {
+        let mut offset = 0;
+        let files = info
+            .files
+            .iter()
+            .map(|file| {
+                let mut path = PathBuf::new();
+                if !info.single_file {
+                    path.push(&info.name);
+                }
+                path.push(file.path_buf());
+
+                let slot = FileSlot {
+                    path,
+                    length: file.length,
+                    offset,
+                };
+                offset += file.length;
+                slot
+            })
+            .collect();
+
+        Self {
+            files,
+            piece_length: info.piece_length,
+            total_length: offset,
+        }
+    }
+
+    /// Replaces the top-level name: the file itself for single-file
+    /// torrents, or the directory holding everything otherwise.
+    pub fn rename_root(&mut self, name: &str) {
+        for slot in &mut self.files {
+            let mut path = PathBuf::from(name);
+            path.extend(slot.path.components().skip(1));
+            slot.path = path;
+        }
+    }
+
+    pub fn piece_offset(&self, piece: u32) -> u64 {
+        piece as u64 * self.piece_length as u64
+    }
+
+    /// Splits the range `offset..offset + len` into per-file spans.
+    /// Zero-length files never appear in the result.
+    pub fn spans(&self, offset: u64, len: u64) -> Vec<Span> {
+        let end = (offset + len).min(self.total_length);
+        self.files
+            .iter()
+            .enumerate()
+            .filter(|(_, file)| file.length > 0)
+            .filter_map(|(index, file)| {
+                let start = offset.max(file.offset);
+                let stop = end.min(file.offset + file.length);
+                if start < stop {
+                    Some(Span {
+                        file: index,
+                        file_offset: start - file.offset,
+                        len: stop - start,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// The pieces holding any of file `index`'s data. Empty for zero-length
+    /// files.
+    pub fn pieces_for_file(&self, index: usize) -> Range<u32> {
+        let file = &self.files[index];
+        if file.length == 0 {
+            return 0..0;
+        }
+        let piece_length = self.piece_length as u64;
+        let first = file.offset / piece_length;
+        let last = (file.offset + file.length).div_ceil(piece_length);
+        first as u32..last as u32
+    }
+
+    /// Indices of the files overlapping `piece`.
+    pub fn files_for_piece(&self, piece: u32, piece_size: u32) -> Vec<usize> {
+        self.spans(self.piece_offset(piece), piece_size as u64)
+            .into_iter()
+            .map(|span| span.file)
+            .collect()
+    }
+}
@@ -0,0 +1,123 @@
+//! Counters describing a storage backend's disk activity.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Live disk I/O counters, updated by a backend as it works.
+///
+/// Shared through an `Arc` so the counts survive the backend being
+/// reopened, e.g. when a torrent's data is moved.
+#[derive(Debug, Default)]
+pub struct DiskMetrics {
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    reads: AtomicU64,
+    writes: AtomicU64,
+    in_flight: AtomicU64,
+    peak_in_flight: AtomicU64,
+    flushes: AtomicU64,
+    flush_micros: AtomicU64,
+    max_flush_micros: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+/// A point-in-time copy of [`DiskMetrics`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiskStats {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub reads: u64,
+    pub writes: u64,
+    /// Operations currently queued on or executing against the disk.
+    pub queue_depth: u64,
+    /// The deepest the queue has been.
+    pub peak_queue_depth: u64,
+    pub flushes: u64,
+    pub total_flush_time: Duration,
+    pub max_flush_time: Duration,
+    /// Lookups served by an already open file handle.
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+impl DiskMetrics {
+    /// Marks an operation as in flight until the returned guard is dropped.
+    pub fn begin(&self) -> InFlight<'_> {
+        let depth = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak_in_flight.fetch_max(depth, Ordering::Relaxed);
+        InFlight(self)
+    }
+
+    pub fn record_read(&self, bytes: u64) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_write(&self, bytes: u64) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_flush(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros() as u64;
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+        self.flush_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_flush_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    pub fn record_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> DiskStats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        DiskStats {
+            bytes_read: load(&self.bytes_read),
+            bytes_written: load(&self.bytes_written),
+            reads: load(&self.reads),
+            writes: load(&self.writes),
+            queue_depth: load(&self.in_flight),
+            peak_queue_depth: load(&self.peak_in_flight),
+            flushes: load(&self.flushes),
+            total_flush_time: Duration::from_micros(load(&self.flush_micros)),
+            max_flush_time: Duration::from_micros(load(&self.max_flush_micros)),
+            cache_hits: load(&self.cache_hits),
+            cache_misses: load(&self.cache_misses),
+        }
+    }
+}
+
+impl DiskStats {
+    pub fn mean_flush_time(&self) -> Option<Duration> {
+        if self.flushes == 0 {
+            None
+        } else {
+            Some(self.total_flush_time / self.flushes as u32)
+        }
+    }
+
+    /// Fraction of lookups served from cache, if the backend has a cache.
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let lookups = self.cache_hits + self.cache_misses;
+        if lookups == 0 {
+            None
+        } else {
+            Some(self.cache_hits as f64 / lookups as f64)
+        }
+    }
+}
+
+/// Guard returned by [`DiskMetrics::begin`].
+pub struct InFlight<'a>(&'a DiskMetrics);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
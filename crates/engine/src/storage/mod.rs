@@ -0,0 +1,148 @@
+//! Persisting torrent data to disk.
+
+pub mod allocation;
+pub mod file;
+pub mod hints;
+pub mod layout;
+pub mod metrics;
+pub mod mmap;
+pub mod part;
+pub mod relocate;
+pub mod sanitize;
+pub mod space;
+
+pub use allocation::AllocationMode;
+pub use file::FileStorage;
+pub use hints::IoHints;
+pub use layout::Layout;
+pub use metrics::{DiskMetrics, DiskStats};
+pub use mmap::MmapStorage;
+pub use relocate::MovableStorage;
+
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("I/O error: {0}")]
+    Io(io::Error),
+    #[error("no space left on device")]
+    OutOfSpace,
+    #[error("not enough disk space: {needed} bytes needed, {available} available")]
+    InsufficientSpace { needed: u64, available: u64 },
+    #[error("disk quota exceeded: {needed} bytes needed, {available} left in quota")]
+    QuotaExceeded { needed: u64, available: u64 },
+    #[error("range {offset}+{len} is outside the torrent")]
+    OutOfRange { offset: u64, len: u64 },
+}
+
+impl StorageError {
+    /// Whether the error means the torrent cannot continue until space is
+    /// freed, as opposed to a fault with the data or the disk.
+    pub fn is_out_of_space(&self) -> bool {
+        matches!(
+            self,
+            StorageError::OutOfSpace
+                | StorageError::InsufficientSpace { .. }
+                | StorageError::QuotaExceeded { .. }
+        )
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> Self {
+        #[cfg(unix)]
+        if e.raw_os_error() == Some(libc::ENOSPC) {
+            return StorageError::OutOfSpace;
+        }
+        StorageError::Io(e)
+    }
+}
+
+/// Which implementation of [`Storage`] a session uses.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageBackend {
+    /// Positioned reads and writes on ordinary files.
+    #[default]
+    File,
+    /// Memory-mapped files.
+    Mmap,
+}
+
+/// Opens a torrent's storage under `root` with the chosen backend, counting
+/// its activity in `metrics`. The memory-mapped backend ignores `hints`,
+/// as the page cache is its storage. Fails without creating anything if the rest
+/// of the torrent would not fit on the disk.
+pub fn open(
+    backend: StorageBackend,
+    root: &Path,
+    layout: Layout,
+    mode: AllocationMode,
+    hints: IoHints,
+    metrics: Arc<DiskMetrics>,
+) -> Result<Box<dyn Storage>, StorageError> {
+    space::ensure(root, &layout, None)?;
+    Ok(match backend {
+        StorageBackend::File => Box::new(
+            FileStorage::open(root, layout, mode)?
+                .with_hints(hints)
+                .with_metrics(metrics),
+        ),
+        StorageBackend::Mmap => {
+            Box::new(MmapStorage::open(root, layout, mode)?.with_metrics(metrics))
+        }
+    })
+}
+
+/// A backend holding a torrent's data, addressed by offset within the
+/// torrent's contiguous byte space.
+pub trait Storage: Send + Sync {
+    fn layout(&self) -> &Layout;
+
+    fn metrics(&self) -> &DiskMetrics;
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), StorageError>;
+
+    /// Fills `buf` with the bytes at `offset`. Regions never written read
+    /// back as zeroes.
+    fn read_into(&self, offset: u64, buf: &mut [u8]) -> Result<(), StorageError>;
+
+    fn flush(&self) -> Result<(), StorageError>;
+
+    /// Hints that the range will not be read again soon, e.g. because it
+    /// has just been hashed. Backends may ignore this.
+    fn drop_cache(&self, _offset: u64, _len: u64) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Reads `len` bytes into a new buffer.
+    fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>, StorageError> {
+        let mut buf = vec![0; len as usize];
+        self.read_into(offset, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn write_piece(&self, piece: u32, data: &[u8]) -> Result<(), StorageError> {
+        self.write(self.layout().piece_offset(piece), data)
+    }
+
+    /// Writes a piece held as consecutive blocks.
+    fn write_blocks(&self, piece: u32, blocks: &[Bytes]) -> Result<(), StorageError> {
+        let mut offset = self.layout().piece_offset(piece);
+        for block in blocks {
+            self.write(offset, block)?;
+            offset += block.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn read_piece(&self, piece: u32, len: u32) -> Result<Vec<u8>, StorageError> {
+        self.read(self.layout().piece_offset(piece), len as u64)
+    }
+}
@@ -0,0 +1,66 @@
+//! Marking unfinished files with a suffix such as `.part`.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use super::{Layout, MovableStorage, StorageError};
+use crate::bitfield::Bitfield;
+
+/// Adds `suffix` to every file of `layout` not already present under
+/// `root` by its real name, so data is written under the suffixed name.
+///
+/// Call before opening storage, then once the data has been checked call
+/// [`finish_files`] to rename the files that turn out to be complete.
+pub fn apply_suffix(root: &Path, layout: &mut Layout, suffix: &str) {
+    for slot in &mut layout.files {
+        if !root.join(&slot.path).exists() {
+            slot.path = with_suffix(&slot.path, suffix);
+        }
+    }
+}
+
+/// Renames files that have become complete back to their real names,
+/// returning the indices of the files renamed.
+pub fn finish_files(
+    storage: &MovableStorage,
+    suffix: &str,
+    have: &Bitfield,
+) -> Result<Vec<usize>, StorageError> {
+    let finished: Vec<(usize, PathBuf)> = {
+        let guard = storage.get();
+        let layout = guard.layout();
+        (0..layout.files.len())
+            .filter(|&index| is_complete(layout, index, have))
+            .filter_map(|index| {
+                let path = without_suffix(&layout.files[index].path, suffix)?;
+                Some((index, path))
+            })
+            .collect()
+    };
+
+    for (index, path) in &finished {
+        debug!("file {} complete, renaming to {}", index, path.display());
+        storage.rename_file(*index, path.clone())?;
+    }
+    Ok(finished.into_iter().map(|(index, _)| index).collect())
+}
+
+fn is_complete(layout: &Layout, index: usize, have: &Bitfield) -> bool {
+    layout
+        .pieces_for_file(index)
+        .all(|piece| have.has(piece as usize))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn without_suffix(path: &Path, suffix: &str) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let stripped = name.strip_suffix(suffix)?;
+    Some(path.with_file_name(stripped))
+}
@@ -0,0 +1,237 @@
+//! Moving and renaming a torrent's data while it is in use.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+use tracing::warn;
+
+#[cfg(feature = "faults")]
+use crate::faults::{Faults, FaultyStorage};
+
+use super::{
+    open, AllocationMode, DiskMetrics, DiskStats, IoHints, Layout, Storage, StorageBackend,
+    StorageError,
+};
+
+/// A storage whose location can change at runtime.
+///
+/// I/O goes through [`MovableStorage::get`], whose guard holds off moves
+/// until it is dropped; a move in turn blocks new I/O until the data has
+/// been relocated and the backend reopened at the new location.
+pub struct MovableStorage {
+    backend: StorageBackend,
+    mode: AllocationMode,
+    hints: IoHints,
+    metrics: Arc<DiskMetrics>,
+    #[cfg(feature = "faults")]
+    faults: Option<Arc<Faults>>,
+    state: RwLock<State>,
+}
+
+struct State {
+    root: PathBuf,
+    storage: Box<dyn Storage>,
+}
+
+impl MovableStorage {
+    pub fn open(
+        backend: StorageBackend,
+        root: &Path,
+        layout: Layout,
+        mode: AllocationMode,
+        hints: IoHints,
+    ) -> Result<Self, StorageError> {
+        let metrics = Arc::new(DiskMetrics::default());
+        Ok(Self {
+            backend,
+            mode,
+            hints,
+            #[cfg(feature = "faults")]
+            faults: None,
+            state: RwLock::new(State {
+                root: root.to_path_buf(),
+                storage: open(backend, root, layout, mode, hints, Arc::clone(&metrics))?,
+            }),
+            metrics,
+        })
+    }
+
+    /// Injects `faults` into the storage's I/O, wherever it moves to.
+    #[cfg(feature = "faults")]
+    pub fn with_faults(mut self, faults: Arc<Faults>) -> Self {
+        let state = self.state.get_mut().unwrap();
+        let placeholder = open_placeholder(state.storage.layout());
+        let storage = std::mem::replace(&mut state.storage, placeholder);
+        state.storage = Box::new(FaultyStorage::new(storage, Arc::clone(&faults)));
+        self.faults = Some(faults);
+        self
+    }
+
+    /// Borrows the underlying storage for I/O.
+    pub fn get(&self) -> StorageGuard<'_> {
+        StorageGuard(self.state.read().unwrap())
+    }
+
+    pub fn root(&self) -> PathBuf {
+        self.state.read().unwrap().root.clone()
+    }
+
+    /// Disk activity since the storage was opened, across any moves.
+    pub fn stats(&self) -> DiskStats {
+        self.metrics.snapshot()
+    }
+
+    /// Moves every file to the same relative location under `new_root`.
+    pub fn move_to(&self, new_root: &Path) -> Result<(), StorageError> {
+        self.relocate(|layout| (new_root.to_path_buf(), layout.clone()))
+    }
+
+    /// Renames one file; `new_path` is relative to the storage root.
+    pub fn rename_file(&self, index: usize, new_path: PathBuf) -> Result<(), StorageError> {
+        self.relocate(|layout| {
+            let mut layout = layout.clone();
+            if let Some(slot) = layout.files.get_mut(index) {
+                slot.path = new_path.clone();
+            }
+            (PathBuf::new(), layout)
+        })
+    }
+
+    /// Renames the torrent's top-level file or directory.
+    pub fn rename_root(&self, name: &str) -> Result<(), StorageError> {
+        self.relocate(|layout| {
+            let mut layout = layout.clone();
+            layout.rename_root(name);
+            (PathBuf::new(), layout)
+        })
+    }
+
+    /// Moves files from the current layout to the one `target` describes.
+    /// An empty root from `target` means "stay under the current root".
+    fn relocate(&self, target: impl Fn(&Layout) -> (PathBuf, Layout)) -> Result<(), StorageError> {
+        let mut state = self.state.write().unwrap();
+        let old_layout = state.storage.layout().clone();
+        let (mut new_root, new_layout) = target(&old_layout);
+        if new_root.as_os_str().is_empty() {
+            new_root = state.root.clone();
+        }
+
+        state.storage.flush()?;
+        let moves: Vec<(PathBuf, PathBuf)> = old_layout
+            .files
+            .iter()
+            .zip(&new_layout.files)
+            .map(|(old, new)| (state.root.join(&old.path), new_root.join(&new.path)))
+            .filter(|(from, to)| from != to)
+            .collect();
+
+        // Drop the old backend first so no handle or mapping outlives the
+        // rename; reopen the original location if anything goes wrong.
+        let placeholder = open_placeholder(&old_layout);
+        drop(std::mem::replace(&mut state.storage, placeholder));
+
+        if let Err(e) = move_all(&moves) {
+            state.storage = self.reopen(&state.root, old_layout)?;
+            return Err(e.into());
+        }
+
+        for (from, _) in &moves {
+            remove_empty_parents(from, &state.root);
+        }
+
+        state.storage = self.reopen(&new_root, new_layout)?;
+        state.root = new_root;
+        Ok(())
+    }
+
+    /// Opens the backend at `root`, with any faults injected.
+    fn reopen(&self, root: &Path, layout: Layout) -> Result<Box<dyn Storage>, StorageError> {
+        let storage = open(
+            self.backend,
+            root,
+            layout,
+            self.mode,
+            self.hints,
+            Arc::clone(&self.metrics),
+        )?;
+        #[cfg(feature = "faults")]
+        if let Some(faults) = &self.faults {
+            return Ok(Box::new(FaultyStorage::new(storage, Arc::clone(faults))));
+        }
+        Ok(storage)
+    }
+}
+
+/// A read guard over the current backend.
+pub struct StorageGuard<'a>(RwLockReadGuard<'a, State>);
+
+impl std::ops::Deref for StorageGuard<'_> {
+    type Target = dyn Storage;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0.storage
+    }
+}
+
+/// Moves each file, undoing earlier moves if a later one fails.
+fn move_all(moves: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+    for (done, (from, to)) in moves.iter().enumerate() {
+        if let Err(e) = move_file(from, to) {
+            for (from, to) in moves[..done].iter().rev() {
+                if let Err(e) = move_file(to, from) {
+                    warn!("could not move {} back: {}", to.display(), e);
+                }
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Renames `from` to `to`, copying when they are on different filesystems.
+/// Files that were never created, such as skipped ones, are ignored.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if !from.exists() {
+        return Ok(());
+    }
+    if to.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", to.display()),
+        ));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::File::open(to)?.sync_all()?;
+            fs::remove_file(from)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Removes directories left empty by a move, stopping at `root`.
+fn remove_empty_parents(path: &Path, root: &Path) {
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        if current == root || !current.starts_with(root) || fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+/// A backend that owns no files, held while the real one is closed.
+fn open_placeholder(layout: &Layout) -> Box<dyn Storage> {
+    Box::new(super::FileStorage::open_read_only(
+        Path::new(""),
+        layout.clone(),
+    ))
+}
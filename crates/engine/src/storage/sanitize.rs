@@ -0,0 +1,140 @@
+//! Making file paths from untrusted metainfo safe to create on disk.
+//!
+//! Anything that could escape the download directory is rejected outright.
+//! Names that are merely awkward on some platform are rewritten instead, so
+//! a torrent with an unlucky file name still downloads everywhere.
+
+use std::collections::HashSet;
+
+use thiserror::Error;
+
+/// Longest file name most filesystems accept, in bytes.
+pub const MAX_COMPONENT_LEN: usize = 255;
+
+/// Extensions longer than this are not worth preserving when truncating.
+const MAX_EXTENSION_LEN: usize = 16;
+
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum PathError {
+    #[error("empty path component")]
+    Empty,
+    #[error("path component {0:?} refers to a directory outside the torrent")]
+    Traversal(String),
+    #[error("path component {0:?} contains a path separator")]
+    Separator(String),
+}
+
+/// Checks and cleans one component of a path from a torrent.
+pub fn sanitize_component(component: &str) -> Result<String, PathError> {
+    match component {
+        "" => return Err(PathError::Empty),
+        "." | ".." => return Err(PathError::Traversal(component.to_string())),
+        _ => {}
+    }
+    if component.contains(['/', '\\']) {
+        return Err(PathError::Separator(component.to_string()));
+    }
+
+    let mut clean: String = component
+        .chars()
+        .map(|c| match c {
+            c if c.is_control() => '_',
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c => c,
+        })
+        .collect();
+
+    // Windows silently drops trailing dots and spaces, which would let two
+    // distinct names collide.
+    let trimmed = clean.trim_end_matches(['.', ' ']).len();
+    if trimmed < clean.len() {
+        clean.truncate(trimmed);
+        clean.push('_');
+    }
+
+    let stem = clean.split('.').next().unwrap_or_default();
+    if WINDOWS_RESERVED
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        clean.insert(0, '_');
+    }
+
+    if clean.len() > MAX_COMPONENT_LEN {
+        clean = truncate(&clean);
+    }
+
+    Ok(clean)
+}
+
+/// Sanitizes each component of a relative path.
+pub fn sanitize_path(path: &[String]) -> Result<Vec<String>, PathError> {
+    path.iter().map(|c| sanitize_component(c)).collect()
+}
+
+/// Renames files whose sanitized paths clash, with each other or with a
+/// directory another file is in, by numbering all but the first, as in
+/// `name (1).ext`, skipping numbers some other file already has. Distinct
+/// names such as `a:b` and `a_b` sanitize to the same one, and would
+/// otherwise be written over each other.
+pub fn disambiguate<'a>(paths: impl IntoIterator<Item = &'a mut Vec<String>>) {
+    let mut paths: Vec<&mut Vec<String>> = paths.into_iter().collect();
+    let dirs: HashSet<Vec<String>> = paths
+        .iter()
+        .flat_map(|path| (1..path.len()).map(move |len| path[..len].to_vec()))
+        .collect();
+    let named: HashSet<Vec<String>> = paths.iter().map(|path| path.to_vec()).collect();
+    let mut taken = HashSet::new();
+    for path in paths.iter_mut() {
+        let Some(name) = path.last().cloned() else {
+            continue;
+        };
+        let mut number = 0;
+        while dirs.contains(path.as_slice())
+            || taken.contains(path.as_slice())
+            || (number > 0 && named.contains(path.as_slice()))
+        {
+            number += 1;
+            *path.last_mut().unwrap() = numbered(&name, number);
+        }
+        taken.insert(path.clone());
+    }
+}
+
+/// `name` with `number` added before its extension.
+fn numbered(name: &str, number: u32) -> String {
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= MAX_EXTENSION_LEN => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let numbered = format!("{} ({}){}", stem, number, extension);
+    if numbered.len() <= MAX_COMPONENT_LEN {
+        return numbered;
+    }
+    let suffix = format!(" ({}){}", number, extension);
+    let mut end = MAX_COMPONENT_LEN - suffix.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &stem[..end], suffix)
+}
+
+/// Shortens a name to the length limit, keeping a short extension intact.
+fn truncate(name: &str) -> String {
+    let extension = name
+        .rfind('.')
+        .map(|dot| &name[dot..])
+        .filter(|ext| ext.len() <= MAX_EXTENSION_LEN)
+        .unwrap_or("");
+
+    let mut end = MAX_COMPONENT_LEN - extension.len();
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &name[..end], extension)
+}
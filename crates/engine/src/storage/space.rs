@@ -0,0 +1,89 @@
+//! Checking that a download will fit before committing to it.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{Layout, StorageError};
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+/// `path` need not exist yet; its nearest existing ancestor is queried.
+#[cfg(unix)]
+pub fn available(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path
+        .ancestors()
+        .find(|dir| dir.as_os_str().is_empty() || dir.exists())
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let c_path = CString::new(existing.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // Safety: `c_path` is NUL-terminated and `stat` is a valid out pointer.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Free space cannot be queried here, so it is assumed to be plentiful and
+/// running out is caught when a write fails.
+#[cfg(not(unix))]
+pub fn available(_path: &Path) -> io::Result<u64> {
+    Ok(u64::MAX)
+}
+
+/// Bytes of the torrent's data already occupying disk space under `root`.
+/// Sparse regions that were never written do not count.
+pub fn used(root: &Path, layout: &Layout) -> u64 {
+    layout
+        .files
+        .iter()
+        .filter_map(|slot| {
+            let meta = fs::metadata(root.join(&slot.path)).ok()?;
+            Some(on_disk(&meta).min(slot.length))
+        })
+        .sum()
+}
+
+/// Bytes the torrent still needs to claim to be fully stored under `root`.
+pub fn remaining(root: &Path, layout: &Layout) -> u64 {
+    layout.total_length - used(root, layout)
+}
+
+/// Fails if the rest of the torrent will not fit on the disk under `root`,
+/// or within `quota_left` bytes if a quota applies.
+pub fn ensure(root: &Path, layout: &Layout, quota_left: Option<u64>) -> Result<(), StorageError> {
+    let needed = remaining(root, layout);
+    if needed == 0 {
+        return Ok(());
+    }
+    if let Some(quota_left) = quota_left {
+        if needed > quota_left {
+            return Err(StorageError::QuotaExceeded {
+                needed,
+                available: quota_left,
+            });
+        }
+    }
+
+    let available = available(root)?;
+    if needed > available {
+        return Err(StorageError::InsufficientSpace { needed, available });
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn on_disk(meta: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn on_disk(meta: &fs::Metadata) -> u64 {
+    meta.len()
+}
@@ -0,0 +1,246 @@
+//! Bookkeeping for the set of peers we know about for a torrent.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::peer::canonical_priority;
+
+/// How long a peer that could not be dialled is left before it is tried
+/// again, doubling with each failure in a row.
+pub const RETRY_AFTER: Duration = Duration::from_secs(60);
+/// The longest a peer that could not be dialled is left.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Our public IP address, shared by a session's torrents, which BEP 40
+/// priorities are computed against.
+#[derive(Clone, Debug, Default)]
+pub struct ExternalIp(Arc<Mutex<Option<IpAddr>>>);
+
+impl ExternalIp {
+    pub fn get(&self) -> Option<IpAddr> {
+        *self.0.lock().unwrap()
+    }
+
+    /// Records the address a router mapped our port on, which is taken
+    /// over any other.
+    pub fn set(&self, ip: IpAddr) {
+        if is_public(ip) {
+            *self.0.lock().unwrap() = Some(ip);
+        }
+    }
+
+    /// Records the address a tracker or peer saw us at, unless one is
+    /// known already.
+    pub fn suggest(&self, ip: IpAddr) {
+        let mut known = self.0.lock().unwrap();
+        if known.is_none() && is_public(ip) {
+            *known = Some(ip);
+        }
+    }
+}
+
+/// Whether `ip` could be where the internet reaches us; routers that do
+/// not know say the unspecified address.
+fn is_public(ip: IpAddr) -> bool {
+    !ip.is_unspecified() && !ip.is_loopback()
+}
+
+/// Tracks known and connected peers and decides whom to dial next.
+#[derive(Clone, Debug)]
+pub struct Swarm {
+    local: Option<SocketAddr>,
+    known: Vec<SocketAddr>,
+    connected: HashSet<SocketAddr>,
+    max_connections: usize,
+    /// Connected peers that support holepunch relaying, in order.
+    relays: BTreeSet<SocketAddr>,
+    /// Peers we failed to dial directly.
+    unreachable: HashMap<SocketAddr, Unreachable>,
+}
+
+/// A peer that could not be dialled.
+#[derive(Clone, Copy, Debug)]
+struct Unreachable {
+    /// Failures in a row.
+    failures: u32,
+    /// When it may be dialled again.
+    retry_at: Instant,
+    /// Whether it is left until then, rather than due to be dialled.
+    waiting: bool,
+    /// Whether a relay has been asked to introduce us since it failed.
+    rendezvous: bool,
+}
+
+impl Swarm {
+    pub fn new(max_connections: usize) -> Self {
+        Self {
+            local: None,
+            known: Vec::new(),
+            connected: HashSet::new(),
+            max_connections,
+            relays: BTreeSet::new(),
+            unreachable: HashMap::new(),
+        }
+    }
+
+    /// Records our externally visible endpoint, which BEP 40 priorities are
+    /// computed against.
+    pub fn set_local_addr(&mut self, addr: SocketAddr) {
+        self.local = Some(addr);
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local
+    }
+
+    /// Adds a peer learned from a tracker, DHT or PEX. Duplicates are ignored.
+    pub fn add_peer(&mut self, addr: SocketAddr) {
+        if Some(addr) != self.local && !self.known.contains(&addr) {
+            self.known.push(addr);
+        }
+    }
+
+    pub fn remove_peer(&mut self, addr: &SocketAddr) {
+        self.known.retain(|known| known != addr);
+        self.mark_disconnected(addr);
+        self.unreachable.remove(addr);
+    }
+
+    pub fn mark_connected(&mut self, addr: SocketAddr) {
+        self.add_peer(addr);
+        self.connected.insert(addr);
+        self.unreachable.remove(&addr);
+    }
+
+    pub fn mark_disconnected(&mut self, addr: &SocketAddr) {
+        self.connected.remove(addr);
+        self.relays.remove(addr);
+    }
+
+    /// Records that dialling `addr` directly failed at `now`. It is not
+    /// dialled again until [`retry_unreachable`](Self::retry_unreachable)
+    /// finds it due, which is later the more often it failed.
+    pub fn mark_unreachable(&mut self, addr: SocketAddr, now: Instant) {
+        if self.connected.contains(&addr) {
+            return;
+        }
+        let failures = self
+            .unreachable
+            .get(&addr)
+            .map_or(1, |unreachable| unreachable.failures + 1);
+        let wait = RETRY_AFTER
+            .saturating_mul(1 << (failures - 1).min(16))
+            .min(MAX_RETRY_AFTER);
+        self.unreachable.insert(
+            addr,
+            Unreachable {
+                failures,
+                retry_at: now + wait,
+                waiting: true,
+                rendezvous: false,
+            },
+        );
+    }
+
+    /// Makes the peers that could not be dialled, and have waited long
+    /// enough, candidates for dialling again.
+    pub fn retry_unreachable(&mut self, now: Instant) {
+        for unreachable in self.unreachable.values_mut() {
+            if unreachable.retry_at <= now {
+                unreachable.waiting = false;
+            }
+        }
+    }
+
+    /// Records that a connected peer advertised holepunch support.
+    pub fn set_relay(&mut self, addr: SocketAddr) {
+        if self.connected.contains(&addr) {
+            self.relays.insert(addr);
+        }
+    }
+
+    pub fn is_relay(&self, addr: &SocketAddr) -> bool {
+        self.relays.contains(addr)
+    }
+
+    /// Peers that could not be dialled and are waiting to be tried again,
+    /// each paired with a connected relay to ask for a rendezvous, once
+    /// per failure. Without knowing which relay is connected to the peer,
+    /// each failure in a row asks the next relay.
+    pub fn take_holepunch_targets(&mut self) -> Vec<(SocketAddr, SocketAddr)> {
+        if self.relays.is_empty() {
+            return Vec::new();
+        }
+        let relays: Vec<SocketAddr> = self.relays.iter().copied().collect();
+        let mut targets: Vec<(SocketAddr, SocketAddr)> = self
+            .unreachable
+            .iter_mut()
+            .filter(|(_, unreachable)| unreachable.waiting && !unreachable.rendezvous)
+            .map(|(target, unreachable)| {
+                unreachable.rendezvous = true;
+                let relay = relays[(unreachable.failures - 1) as usize % relays.len()];
+                (relay, *target)
+            })
+            .collect();
+        targets.sort_unstable();
+        targets
+    }
+
+    /// The connected peer of lowest canonical priority, if `addr` outranks
+    /// it, which a full swarm drops to make room for `addr`.
+    pub fn outranked_by(&self, addr: SocketAddr) -> Option<SocketAddr> {
+        let local = self.local?;
+        let lowest = self
+            .connected
+            .iter()
+            .min_by_key(|peer| (canonical_priority(local, **peer), **peer))?;
+        (canonical_priority(local, *lowest) < canonical_priority(local, addr)).then_some(*lowest)
+    }
+
+    pub fn is_connected(&self, addr: &SocketAddr) -> bool {
+        self.connected.contains(addr)
+    }
+
+    pub fn known_peers(&self) -> &[SocketAddr] {
+        &self.known
+    }
+
+    pub fn num_connected(&self) -> usize {
+        self.connected.len()
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// Returns the unconnected peers worth dialing given the remaining
+    /// connection budget, highest canonical priority first.
+    ///
+    /// Until our own endpoint is known there is nothing to rank against, so
+    /// candidates are returned in the order they were learned.
+    pub fn dial_candidates(&self) -> Vec<SocketAddr> {
+        let slots = self.max_connections.saturating_sub(self.connected.len());
+        let mut candidates: Vec<SocketAddr> = self
+            .known
+            .iter()
+            .filter(|addr| {
+                !self.connected.contains(addr)
+                    && self
+                        .unreachable
+                        .get(addr)
+                        .is_none_or(|unreachable| !unreachable.waiting)
+            })
+            .copied()
+            .collect();
+
+        if let Some(local) = self.local {
+            candidates
+                .sort_by_cached_key(|addr| std::cmp::Reverse(canonical_priority(local, *addr)));
+        }
+
+        candidates.truncate(slots);
+        candidates
+    }
+}
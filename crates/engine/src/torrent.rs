@@ -0,0 +1,283 @@
+//! Per-torrent download state: assembling blocks into pieces, and acting on
+//! their verification.
+
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tracing::{debug, field, info, info_span, Span};
+
+use crate::event::{Event, Publish};
+use crate::files::{piece_priorities, FilePriority};
+use crate::metainfo::Metainfo;
+use crate::peer::state::PeerState;
+use crate::peer::PeerError;
+use crate::picker::{Picker, BLOCK_SIZE};
+use crate::protocol::{BlockRequest, Message};
+use crate::storage::{Layout, StorageError};
+
+/// Number of failed pieces a peer may contribute to before it is banned.
+pub const MAX_HASH_FAILURES: u32 = 3;
+
+#[derive(Debug)]
+struct PieceBuffer {
+    /// The blocks received so far, each still in the frame it arrived in.
+    blocks: Vec<Option<Bytes>>,
+    contributors: HashSet<IpAddr>,
+    /// Lasts from the piece's first block until it has been verified.
+    span: Span,
+}
+
+/// What became of a fully downloaded piece.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PieceOutcome {
+    /// The piece matched its hash; announce it to every peer.
+    Verified { have: Message },
+    /// The piece was corrupt and has been re-queued. Connections to the
+    /// listed peers, which are now banned, should be dropped.
+    Failed { banned: Vec<IpAddr>, failures: u32 },
+}
+
+pub struct Torrent {
+    pub metainfo: Arc<Metainfo>,
+    pub picker: Picker,
+    buffers: HashMap<u32, PieceBuffer>,
+    strikes: HashMap<IpAddr, u32>,
+    /// How often each piece has failed verification.
+    failures: HashMap<u32, u32>,
+    banned: HashSet<IpAddr>,
+    /// As last set, indexed like the torrent's files.
+    file_priorities: Vec<FilePriority>,
+    paused: bool,
+    /// Why the torrent was stopped, if it was stopped by an error.
+    error: Option<String>,
+    events: Arc<dyn Publish>,
+}
+
+impl Torrent {
+    pub fn new(metainfo: Arc<Metainfo>, events: Arc<dyn Publish>) -> Self {
+        let picker = Picker::new(metainfo.info.piece_length, metainfo.info.total_length());
+        Self {
+            metainfo,
+            picker,
+            buffers: HashMap::new(),
+            strikes: HashMap::new(),
+            failures: HashMap::new(),
+            banned: HashSet::new(),
+            file_priorities: Vec::new(),
+            paused: false,
+            error: None,
+            events,
+        }
+    }
+
+    /// Sets how eagerly each file is downloaded. Files beyond the end of
+    /// `priorities` keep normal priority.
+    pub fn set_file_priorities(&mut self, priorities: &[FilePriority]) {
+        let layout = Layout::new(&self.metainfo.info);
+        let pieces = piece_priorities(&layout, priorities, self.picker.num_pieces());
+        self.picker.set_priorities(pieces);
+        // Pieces of skipped files are no longer in progress; drop what was
+        // received of them, as abandoning them would.
+        let picker = &self.picker;
+        self.buffers
+            .retain(|&piece, _| picker.is_in_progress(piece));
+        self.file_priorities = priorities.to_vec();
+    }
+
+    pub fn file_priority(&self, index: usize) -> FilePriority {
+        self.file_priorities.get(index).copied().unwrap_or_default()
+    }
+
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.banned.contains(ip)
+    }
+
+    /// How many times `piece` has failed verification.
+    pub fn piece_failures(&self, piece: u32) -> u32 {
+        self.failures.get(&piece).copied().unwrap_or(0)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.error = None;
+    }
+
+    /// The error that paused the torrent, if any.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Reacts to a failed storage operation. Running out of disk space or
+    /// quota pauses the torrent rather than failing it; returns whether
+    /// that happened.
+    pub fn storage_failed(&mut self, error: &StorageError) -> bool {
+        if !error.is_out_of_space() {
+            return false;
+        }
+
+        info!("pausing torrent: {}", error);
+        self.paused = true;
+        self.error = Some(error.to_string());
+        self.events.publish(Event::DiskFull {
+            info_hash: self.metainfo.info_hash,
+            message: error.to_string(),
+        });
+        true
+    }
+
+    /// Has `peer` handle a message, counting the pieces it newly has or no
+    /// longer has towards their availability. Returns the requests the
+    /// message released, as [`PeerState::handle`] does.
+    pub fn peer_message(
+        &mut self,
+        peer: &mut PeerState,
+        message: &Message,
+    ) -> Result<Vec<BlockRequest>, PeerError> {
+        let had_piece = matches!(message, Message::Have(piece) if peer.has.has(*piece as usize));
+        let had = matches!(
+            message,
+            Message::Bitfield(_) | Message::HaveAll | Message::HaveNone
+        )
+        .then(|| peer.has.clone());
+        let released = peer.handle(message)?;
+        match (message, had) {
+            (Message::Have(piece), _) if !had_piece => self.picker.peer_has(*piece),
+            (_, Some(had)) => self.picker.peer_bitfield(&had, &peer.has),
+            _ => {}
+        }
+        Ok(released)
+    }
+
+    /// Stores a block received from `from`, returning the piece's blocks
+    /// in order once every one of them has arrived so it can be verified.
+    /// Only whole blocks of pieces in progress, as they are requested, are
+    /// taken; anything else would be held outside the picker's memory
+    /// budget.
+    pub fn block_received(
+        &mut self,
+        from: IpAddr,
+        piece: u32,
+        offset: u32,
+        data: Bytes,
+    ) -> Option<Vec<Bytes>> {
+        if piece as usize >= self.picker.num_pieces() {
+            debug!(%from, piece, offset, "ignoring out-of-range block");
+            return None;
+        }
+        let size = self.metainfo.info.piece_size(piece);
+        let expected = size.saturating_sub(offset).min(BLOCK_SIZE);
+        if !offset.is_multiple_of(BLOCK_SIZE) || data.len() != expected as usize || expected == 0 {
+            debug!(%from, piece, offset, len = data.len(), "ignoring misshapen block");
+            return None;
+        }
+        if !self.picker.is_in_progress(piece) {
+            debug!(%from, piece, offset, "ignoring block of a piece not in progress");
+            return None;
+        }
+
+        let num_blocks = self.picker.num_blocks(piece);
+        let buffer = self.buffers.entry(piece).or_insert_with(|| PieceBuffer {
+            blocks: (0..num_blocks).map(|_| None).collect(),
+            contributors: HashSet::new(),
+            span: info_span!(parent: None, "piece", piece, size, valid = field::Empty),
+        });
+        buffer.blocks[(offset / BLOCK_SIZE) as usize] = Some(data);
+        buffer.contributors.insert(from);
+
+        let request = BlockRequest {
+            piece,
+            offset,
+            length: expected,
+        };
+        if !self.picker.block_received(&request) {
+            return None;
+        }
+        // Keep the contributors and span until the piece is verified.
+        let buffer = self.buffers.get_mut(&piece)?;
+        buffer.blocks.iter_mut().map(Option::take).collect()
+    }
+
+    /// Gives up on a downloaded piece that could not be stored, dropping
+    /// its blocks, so that it is downloaded again.
+    pub fn abandon_piece(&mut self, piece: u32) {
+        self.buffers.remove(&piece);
+        self.picker.piece_failed(piece);
+    }
+
+    /// Number of pieces with blocks held in memory.
+    pub fn buffered(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// The span covering `piece` while it is being downloaded, under which
+    /// its verification and writing are traced.
+    pub fn piece_span(&self, piece: u32) -> Span {
+        self.buffers
+            .get(&piece)
+            .map_or_else(Span::none, |buffer| buffer.span.clone())
+    }
+
+    /// Records the result of verifying `piece`.
+    ///
+    /// A corrupt piece is quarantined: its blocks are discarded and it goes
+    /// back to the picker to be downloaded afresh, possibly from other
+    /// peers.
+    pub fn finish_piece(&mut self, piece: u32, valid: bool) -> PieceOutcome {
+        let contributors = match self.buffers.remove(&piece) {
+            Some(buffer) => {
+                buffer.span.record("valid", valid);
+                buffer.contributors
+            }
+            None => HashSet::new(),
+        };
+        let info_hash = self.metainfo.info_hash;
+
+        if valid {
+            self.picker.piece_complete(piece);
+            self.events
+                .publish(Event::PieceCompleted { info_hash, piece });
+            if self.picker.is_finished() {
+                self.events.publish(Event::TorrentFinished { info_hash });
+            }
+            return PieceOutcome::Verified {
+                have: Message::Have(piece),
+            };
+        }
+
+        let failures = self.failures.entry(piece).or_insert(0);
+        *failures += 1;
+        let failures = *failures;
+        info!(piece, failures, "piece failed hash check");
+        self.picker.piece_failed(piece);
+        self.events.publish(Event::PieceFailed {
+            info_hash,
+            piece,
+            failures,
+        });
+
+        // A peer that sent the whole piece alone is certainly at fault;
+        // otherwise every contributor is suspect until it fails repeatedly.
+        let sole = contributors.len() == 1;
+        let mut banned = Vec::new();
+        for ip in contributors {
+            let strikes = self.strikes.entry(ip).or_insert(0);
+            *strikes += 1;
+            if sole || *strikes >= MAX_HASH_FAILURES {
+                self.banned.insert(ip);
+                banned.push(ip);
+            }
+        }
+
+        PieceOutcome::Failed { banned, failures }
+    }
+}
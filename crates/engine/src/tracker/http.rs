@@ -0,0 +1,220 @@
+//! HTTP(S) tracker announces (BEP 3, with compact peer lists from BEP 23
+//! and IPv6 peers from BEP 7).
+
+use std::convert::TryFrom;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use reqwest::Url;
+
+use super::{
+    compact_peers, compact_peers_v4, compact_peers_v6, Announce, AnnounceEvent, AnnounceResponse,
+    ScrapeStats, TrackerError, DEFAULT_INTERVAL,
+};
+use crate::bencode::{self, Value};
+use crate::info_hash::InfoHash;
+use crate::magnet::percent_encode;
+
+pub async fn announce(
+    client: &reqwest::Client,
+    url: &str,
+    announce: &Announce,
+    pedantic: bool,
+) -> Result<AnnounceResponse, TrackerError> {
+    let url = announce_url(url, announce)?;
+    let response = client.get(url).send().await?;
+    if !response.status().is_success() {
+        return Err(TrackerError::Status(response.status().as_u16()));
+    }
+    parse_response(&response.bytes().await?, pedantic)
+}
+
+pub async fn scrape(
+    client: &reqwest::Client,
+    url: &str,
+    info_hash: &InfoHash,
+) -> Result<ScrapeStats, TrackerError> {
+    let mut scrape_url = scrape_url(url)?;
+    let query = format!("info_hash={}", percent_encode(info_hash.as_bytes()));
+    let query = match scrape_url.query() {
+        Some(existing) if !existing.is_empty() => format!("{}&{}", existing, query),
+        _ => query,
+    };
+    scrape_url.set_query(Some(&query));
+
+    let response = client.get(scrape_url).send().await?;
+    if !response.status().is_success() {
+        return Err(TrackerError::Status(response.status().as_u16()));
+    }
+    let value = bencode::decode(&response.bytes().await?)?;
+    if let Some(reason) = value.get("failure reason").and_then(Value::as_bytes) {
+        return Err(TrackerError::Failure(
+            String::from_utf8_lossy(reason).into_owned(),
+        ));
+    }
+
+    let stats = value
+        .get("files")
+        .and_then(Value::as_dict)
+        .and_then(|files| files.get(info_hash.as_bytes().as_ref()))
+        .ok_or(TrackerError::InvalidResponse("torrent missing from scrape"))?;
+    let count = |key: &str| {
+        stats
+            .get(key)
+            .and_then(Value::as_int)
+            .and_then(|n| u32::try_from(n).ok())
+            .unwrap_or(0)
+    };
+    Ok(ScrapeStats {
+        seeders: count("complete"),
+        completed: count("downloaded"),
+        leechers: count("incomplete"),
+    })
+}
+
+/// Derives the scrape URL by convention: the last path segment must start
+/// with `announce`, which is replaced by `scrape`.
+fn scrape_url(url: &str) -> Result<Url, TrackerError> {
+    let mut parsed = Url::parse(url).map_err(|_| TrackerError::InvalidUrl(url.to_string()))?;
+    let path = parsed.path().to_string();
+    let (dir, last) = path.rsplit_once('/').unwrap_or(("", &path));
+    match last.strip_prefix("announce") {
+        Some(rest) => parsed.set_path(&format!("{}/scrape{}", dir, rest)),
+        None => return Err(TrackerError::ScrapeUnsupported(url.to_string())),
+    }
+    Ok(parsed)
+}
+
+/// Appends the announce parameters to the tracker's URL, keeping any query
+/// the tracker already put there (private trackers often carry a passkey).
+fn announce_url(url: &str, announce: &Announce) -> Result<Url, TrackerError> {
+    let mut url = Url::parse(url).map_err(|_| TrackerError::InvalidUrl(url.to_string()))?;
+
+    let mut query = format!(
+        "info_hash={}&peer_id={}&port={}&uploaded={}&downloaded={}&left={}&compact=1",
+        percent_encode(announce.info_hash.as_bytes()),
+        percent_encode(announce.peer_id.as_bytes()),
+        announce.port,
+        announce.uploaded,
+        announce.downloaded,
+        announce.left,
+    );
+    if let Some(event) = announce.event {
+        let event = match event {
+            AnnounceEvent::Started => "started",
+            AnnounceEvent::Completed => "completed",
+            AnnounceEvent::Stopped => "stopped",
+        };
+        query.push_str("&event=");
+        query.push_str(event);
+    }
+    if let Some(num_want) = announce.num_want {
+        query.push_str(&format!("&numwant={}", num_want));
+    }
+    if let Some(ip) = announce.ipv4 {
+        query.push_str(&format!("&ipv4={}", ip));
+    }
+    if let Some(ip) = announce.ipv6 {
+        query.push_str(&format!(
+            "&ipv6={}",
+            percent_encode(ip.to_string().as_bytes())
+        ));
+    }
+
+    let query = match url.query() {
+        Some(existing) if !existing.is_empty() => format!("{}&{}", existing, query),
+        _ => query,
+    };
+    url.set_query(Some(&query));
+    Ok(url)
+}
+
+/// Parses an announce response. Leniently, a missing interval falls back
+/// to the default and malformed peers are skipped; `pedantic` rejects the
+/// response instead.
+fn parse_response(body: &[u8], pedantic: bool) -> Result<AnnounceResponse, TrackerError> {
+    let value = if pedantic {
+        bencode::decode_canonical(body)?
+    } else {
+        bencode::decode(body)?
+    };
+    if value.as_dict().is_none() {
+        return Err(TrackerError::InvalidResponse("not a dictionary"));
+    }
+    if let Some(reason) = value.get("failure reason") {
+        let reason = reason.as_bytes().unwrap_or_default();
+        return Err(TrackerError::Failure(
+            String::from_utf8_lossy(reason).into_owned(),
+        ));
+    }
+
+    let seconds = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_int)
+            .filter(|&s| s > 0)
+            .map(|s| Duration::from_secs(s as u64))
+    };
+    let count = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_int)
+            .and_then(|n| u32::try_from(n).ok())
+    };
+
+    let mut peers = match value.get("peers") {
+        Some(Value::Bytes(bytes)) => compact_peers(bytes, 6, pedantic, compact_peers_v4)?,
+        Some(Value::List(list)) if pedantic => list
+            .iter()
+            .map(|peer| dict_peer(peer).ok_or(TrackerError::InvalidResponse("malformed peer")))
+            .collect::<Result<_, _>>()?,
+        Some(Value::List(list)) => list.iter().filter_map(dict_peer).collect(),
+        _ if pedantic => return Err(TrackerError::InvalidResponse("missing peer list")),
+        _ => Vec::new(),
+    };
+    match value.get("peers6") {
+        Some(Value::Bytes(bytes)) => {
+            peers.extend(compact_peers(bytes, 18, pedantic, compact_peers_v6)?)
+        }
+        Some(_) if pedantic => return Err(TrackerError::InvalidResponse("malformed peers6")),
+        _ => {}
+    }
+
+    let interval = match seconds("interval") {
+        Some(interval) => interval,
+        None if pedantic => return Err(TrackerError::InvalidResponse("missing interval")),
+        None => DEFAULT_INTERVAL,
+    };
+
+    Ok(AnnounceResponse {
+        interval,
+        min_interval: seconds("min interval"),
+        peers,
+        seeders: count("complete"),
+        leechers: count("incomplete"),
+        warning: value
+            .get("warning message")
+            .and_then(Value::as_bytes)
+            .map(|w| String::from_utf8_lossy(w).into_owned()),
+        external_ip: value
+            .get("external ip")
+            .and_then(Value::as_bytes)
+            .and_then(external_ip),
+    })
+}
+
+/// Our address in a response's `external ip`, in network byte order.
+fn external_ip(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+        16 => Some(IpAddr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+        _ => None,
+    }
+}
+
+/// A peer from the original, non-compact list of dictionaries.
+fn dict_peer(peer: &Value) -> Option<SocketAddr> {
+    let ip: IpAddr = peer.get("ip")?.as_str()?.parse().ok()?;
+    let port = u16::try_from(peer.get("port")?.as_int()?).ok()?;
+    Some(SocketAddr::new(ip, port))
+}
@@ -0,0 +1,266 @@
+//! Announcing to trackers to learn about peers.
+
+pub mod http;
+pub mod udp;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use serde::Serialize;
+use thiserror::Error;
+use tracing::{debug, instrument};
+
+use crate::bencode::BencodeError;
+use crate::config::PortRange;
+use crate::info_hash::InfoHash;
+use crate::interface::Interface;
+use crate::peer::PeerId;
+use crate::proxy::ProxyConfig;
+
+/// How long to wait before re-announcing when a tracker does not say.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Error)]
+pub enum TrackerError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("tracker returned HTTP status {0}")]
+    Status(u16),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed tracker response: {0}")]
+    Bencode(#[from] BencodeError),
+    #[error("malformed tracker response: {0}")]
+    InvalidResponse(&'static str),
+    #[error("tracker refused the announce: {0}")]
+    Failure(String),
+    #[error("tracker did not respond")]
+    Timeout,
+    #[error("invalid tracker URL {0:?}")]
+    InvalidUrl(String),
+    #[error("unsupported tracker protocol in {0:?}")]
+    UnsupportedScheme(String),
+    #[error("tracker {0:?} does not support scraping")]
+    ScrapeUnsupported(String),
+    #[error("UDP tracker {0:?} cannot be reached through the proxy")]
+    Unproxied(String),
+}
+
+/// The `event` parameter of an announce. Regular re-announces carry none.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnnounceEvent {
+    Started,
+    Completed,
+    Stopped,
+}
+
+/// What we tell a tracker about ourselves and our progress.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Announce {
+    pub info_hash: InfoHash,
+    pub peer_id: PeerId,
+    pub port: u16,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub left: u64,
+    pub event: Option<AnnounceEvent>,
+    /// How many peers we would like; `None` leaves it to the tracker.
+    pub num_want: Option<u32>,
+    /// Our public addresses, which HTTP trackers hand out to peers along
+    /// with the one the announce came from (BEP 7).
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AnnounceResponse {
+    /// How long to wait before announcing again.
+    pub interval: Duration,
+    /// Announcing more often than this may get us refused.
+    pub min_interval: Option<Duration>,
+    pub peers: Vec<SocketAddr>,
+    pub seeders: Option<u32>,
+    pub leechers: Option<u32>,
+    pub warning: Option<String>,
+    /// The address the announce came from, as the tracker saw it (BEP 24).
+    pub external_ip: Option<IpAddr>,
+}
+
+impl AnnounceResponse {
+    /// Folds in another answer from the same tracker, such as to an
+    /// announce over the other IP family: every peer from either, the
+    /// sooner of their intervals and the larger of their counts.
+    pub fn merge(&mut self, other: AnnounceResponse) {
+        for peer in other.peers {
+            if !self.peers.contains(&peer) {
+                self.peers.push(peer);
+            }
+        }
+        self.interval = self.interval.min(other.interval);
+        self.min_interval = self.min_interval.max(other.min_interval);
+        self.seeders = self.seeders.max(other.seeders);
+        self.leechers = self.leechers.max(other.leechers);
+        self.warning = self.warning.take().or(other.warning);
+        self.external_ip = self.external_ip.or(other.external_ip);
+    }
+}
+
+/// A tracker's counts for one torrent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ScrapeStats {
+    pub seeders: u32,
+    /// How many peers have ever finished downloading.
+    pub completed: u32,
+    pub leechers: u32,
+}
+
+/// Announces to trackers over HTTP(S) and UDP.
+#[derive(Clone, Debug, Default)]
+pub struct Tracker {
+    http: reqwest::Client,
+    pedantic: bool,
+    /// Whether trackers may only be reached through a proxy.
+    proxied: bool,
+    /// The interface all announces are sent from.
+    interface: Option<Interface>,
+    /// The ports UDP announces and scrapes are sent from.
+    source_ports: Option<PortRange>,
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rejects responses that deviate from the specifications, instead of
+    /// making the best of them.
+    pub fn with_pedantic(mut self, pedantic: bool) -> Self {
+        self.pedantic = pedantic;
+        self
+    }
+
+    /// Sends UDP announces and scrapes from a port in `source_ports`, if
+    /// given. HTTP requests may come from any port.
+    pub fn with_source_ports(mut self, source_ports: Option<PortRange>) -> Self {
+        self.source_ports = source_ports;
+        self
+    }
+
+    /// Sends announces and scrapes through the proxy, if trackers are to
+    /// be proxied, in which case UDP trackers are refused; and from
+    /// `interface`, if there is one.
+    pub fn with_network(
+        mut self,
+        proxy: &ProxyConfig,
+        interface: Option<&Interface>,
+    ) -> Result<Self, TrackerError> {
+        let mut http = reqwest::Client::builder();
+        if proxy.proxies_trackers() {
+            http = http.proxy(reqwest::Proxy::all(proxy.url())?);
+            self.proxied = true;
+        }
+        if let Some(interface) = interface {
+            http = interface.configure(http)?;
+        }
+        self.http = http.build()?;
+        self.interface = interface.cloned();
+        Ok(self)
+    }
+
+    #[instrument(skip_all, fields(url, event = ?announce.event))]
+    pub async fn announce(
+        &self,
+        url: &str,
+        announce: &Announce,
+    ) -> Result<AnnounceResponse, TrackerError> {
+        debug!("announcing");
+        if url.starts_with("http://") || url.starts_with("https://") {
+            http::announce(&self.http, url, announce, self.pedantic).await
+        } else if url.starts_with("udp://") && self.proxied {
+            Err(TrackerError::Unproxied(url.to_string()))
+        } else if url.starts_with("udp://") {
+            let interface = self.interface.as_ref();
+            udp::announce(url, announce, self.pedantic, interface, self.source_ports).await
+        } else {
+            Err(TrackerError::UnsupportedScheme(url.to_string()))
+        }
+    }
+
+    /// Asks a tracker how many peers it knows for a torrent, without
+    /// joining the swarm.
+    #[instrument(skip_all, fields(url, %info_hash))]
+    pub async fn scrape(
+        &self,
+        url: &str,
+        info_hash: &InfoHash,
+    ) -> Result<ScrapeStats, TrackerError> {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            http::scrape(&self.http, url, info_hash).await
+        } else if url.starts_with("udp://") && self.proxied {
+            Err(TrackerError::Unproxied(url.to_string()))
+        } else if url.starts_with("udp://") {
+            udp::scrape(url, info_hash, self.interface.as_ref(), self.source_ports).await
+        } else {
+            Err(TrackerError::UnsupportedScheme(url.to_string()))
+        }
+    }
+
+    /// Announces to each tier in turn (BEP 12), stopping at the first
+    /// tracker that answers. Returns that tracker's URL with its response,
+    /// along with the errors from the trackers tried before it.
+    pub async fn announce_tiers(
+        &self,
+        tiers: &[Vec<String>],
+        announce: &Announce,
+    ) -> (
+        Option<(String, AnnounceResponse)>,
+        Vec<(String, TrackerError)>,
+    ) {
+        let mut errors = Vec::new();
+        for url in tiers.iter().flatten() {
+            match self.announce(url, announce).await {
+                Ok(response) => return (Some((url.clone(), response)), errors),
+                Err(e) => errors.push((url.clone(), e)),
+            }
+        }
+        (None, errors)
+    }
+}
+
+/// Parses a compact peer list of `entry`-byte entries, requiring that it
+/// holds a whole number of them if `pedantic` is set.
+fn compact_peers(
+    bytes: &[u8],
+    entry: usize,
+    pedantic: bool,
+    parse: fn(&[u8]) -> Vec<SocketAddr>,
+) -> Result<Vec<SocketAddr>, TrackerError> {
+    if pedantic && !bytes.len().is_multiple_of(entry) {
+        return Err(TrackerError::InvalidResponse("truncated compact peer list"));
+    }
+    Ok(parse(bytes))
+}
+
+/// Parses the compact IPv4 peer list: 4 address bytes and a port each.
+pub fn compact_peers_v4(bytes: &[u8]) -> Vec<SocketAddr> {
+    bytes
+        .chunks_exact(6)
+        .map(|c| {
+            let ip = Ipv4Addr::new(c[0], c[1], c[2], c[3]);
+            SocketAddr::new(IpAddr::V4(ip), u16::from_be_bytes([c[4], c[5]]))
+        })
+        .collect()
+}
+
+/// Parses the compact IPv6 peer list: 16 address bytes and a port each.
+pub fn compact_peers_v6(bytes: &[u8]) -> Vec<SocketAddr> {
+    bytes
+        .chunks_exact(18)
+        .map(|c| {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&c[..16]);
+            let ip = Ipv6Addr::from(octets);
+            SocketAddr::new(IpAddr::V6(ip), u16::from_be_bytes([c[16], c[17]]))
+        })
+        .collect()
+}
@@ -0,0 +1,207 @@
+//! UDP tracker announces (BEP 15).
+
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::time::timeout;
+
+use super::{
+    compact_peers, compact_peers_v4, compact_peers_v6, Announce, AnnounceEvent, AnnounceResponse,
+    ScrapeStats, TrackerError, DEFAULT_INTERVAL,
+};
+use crate::config::PortRange;
+use crate::info_hash::InfoHash;
+use crate::interface::{self, Interface};
+
+const PROTOCOL_ID: u64 = 0x0417_2710_1980;
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_SCRAPE: u32 = 2;
+const ACTION_ERROR: u32 = 3;
+
+/// BEP 15 retries with a timeout of 15 * 2^n seconds for n up to 8, which
+/// can take an hour. We give up far sooner and move on to the next tracker.
+const ATTEMPTS: u32 = 3;
+const BASE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Announces over UDP. A zero interval falls back to the default unless
+/// `pedantic` is set, as does a trailing partial peer entry.
+pub async fn announce(
+    url: &str,
+    announce: &Announce,
+    pedantic: bool,
+    interface: Option<&Interface>,
+    source_ports: Option<PortRange>,
+) -> Result<AnnounceResponse, TrackerError> {
+    let (socket, addr) = open(url, interface, source_ports).await?;
+    let connection_id = connect(&socket).await?;
+
+    let mut request = Vec::with_capacity(98);
+    request.extend_from_slice(&connection_id.to_be_bytes());
+    request.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+    let transaction_id: u32 = rand::thread_rng().gen();
+    request.extend_from_slice(&transaction_id.to_be_bytes());
+    request.extend_from_slice(announce.info_hash.as_bytes());
+    request.extend_from_slice(announce.peer_id.as_bytes());
+    request.extend_from_slice(&announce.downloaded.to_be_bytes());
+    request.extend_from_slice(&announce.left.to_be_bytes());
+    request.extend_from_slice(&announce.uploaded.to_be_bytes());
+    let event: u32 = match announce.event {
+        None => 0,
+        Some(AnnounceEvent::Completed) => 1,
+        Some(AnnounceEvent::Started) => 2,
+        Some(AnnounceEvent::Stopped) => 3,
+    };
+    request.extend_from_slice(&event.to_be_bytes());
+    request.extend_from_slice(&0u32.to_be_bytes()); // our IP: the sender's
+    let key: u32 = rand::thread_rng().gen();
+    request.extend_from_slice(&key.to_be_bytes());
+    let num_want = announce.num_want.map(|n| n as i32).unwrap_or(-1);
+    request.extend_from_slice(&num_want.to_be_bytes());
+    request.extend_from_slice(&announce.port.to_be_bytes());
+
+    let response = exchange(&socket, &request, ACTION_ANNOUNCE, transaction_id).await?;
+    if response.len() < 20 {
+        return Err(TrackerError::InvalidResponse("short announce response"));
+    }
+    let word = |at: usize| u32::from_be_bytes(response[at..at + 4].try_into().unwrap());
+    let peers = if addr.is_ipv4() {
+        compact_peers(&response[20..], 6, pedantic, compact_peers_v4)?
+    } else {
+        compact_peers(&response[20..], 18, pedantic, compact_peers_v6)?
+    };
+    let interval = match word(8) {
+        0 if pedantic => return Err(TrackerError::InvalidResponse("zero interval")),
+        0 => DEFAULT_INTERVAL,
+        seconds => Duration::from_secs(seconds as u64),
+    };
+
+    Ok(AnnounceResponse {
+        interval,
+        min_interval: None,
+        peers,
+        leechers: Some(word(12)),
+        seeders: Some(word(16)),
+        warning: None,
+        external_ip: None,
+    })
+}
+
+pub async fn scrape(
+    url: &str,
+    info_hash: &InfoHash,
+    interface: Option<&Interface>,
+    source_ports: Option<PortRange>,
+) -> Result<ScrapeStats, TrackerError> {
+    let (socket, _) = open(url, interface, source_ports).await?;
+    let connection_id = connect(&socket).await?;
+
+    let transaction_id: u32 = rand::thread_rng().gen();
+    let mut request = Vec::with_capacity(36);
+    request.extend_from_slice(&connection_id.to_be_bytes());
+    request.extend_from_slice(&ACTION_SCRAPE.to_be_bytes());
+    request.extend_from_slice(&transaction_id.to_be_bytes());
+    request.extend_from_slice(info_hash.as_bytes());
+
+    let response = exchange(&socket, &request, ACTION_SCRAPE, transaction_id).await?;
+    if response.len() < 20 {
+        return Err(TrackerError::InvalidResponse("short scrape response"));
+    }
+    let word = |at: usize| u32::from_be_bytes(response[at..at + 4].try_into().unwrap());
+    Ok(ScrapeStats {
+        seeders: word(8),
+        completed: word(12),
+        leechers: word(16),
+    })
+}
+
+/// Opens a socket connected to the tracker at `url`, on `interface` if
+/// there is one and a port in `source_ports` if given.
+async fn open(
+    url: &str,
+    interface: Option<&Interface>,
+    source_ports: Option<PortRange>,
+) -> Result<(UdpSocket, SocketAddr), TrackerError> {
+    let addr = resolve(url, interface).await?;
+    let socket = interface::udp(interface, source_ports, addr.is_ipv6()).await?;
+    socket.connect(addr).await?;
+    Ok((socket, addr))
+}
+
+/// Obtains a connection ID, proving to the tracker that our address is not
+/// spoofed.
+async fn connect(socket: &UdpSocket) -> Result<u64, TrackerError> {
+    let transaction_id: u32 = rand::thread_rng().gen();
+    let mut request = Vec::with_capacity(16);
+    request.extend_from_slice(&PROTOCOL_ID.to_be_bytes());
+    request.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+    request.extend_from_slice(&transaction_id.to_be_bytes());
+
+    let response = exchange(socket, &request, ACTION_CONNECT, transaction_id).await?;
+    if response.len() < 16 {
+        return Err(TrackerError::InvalidResponse("short connect response"));
+    }
+    Ok(u64::from_be_bytes(response[8..16].try_into().unwrap()))
+}
+
+/// Sends `request` until a response with the matching transaction ID
+/// arrives, retransmitting with an increasing timeout.
+async fn exchange(
+    socket: &UdpSocket,
+    request: &[u8],
+    action: u32,
+    transaction_id: u32,
+) -> Result<Vec<u8>, TrackerError> {
+    let mut buf = vec![0u8; 4096];
+    for attempt in 0..ATTEMPTS {
+        socket.send(request).await?;
+        let wait = BASE_TIMEOUT * 2u32.pow(attempt);
+        let deadline = tokio::time::Instant::now() + wait;
+
+        loop {
+            let len = match timeout(
+                deadline - tokio::time::Instant::now(),
+                socket.recv(&mut buf),
+            )
+            .await
+            {
+                Ok(received) => received?,
+                Err(_) => break,
+            };
+            if len < 8 {
+                continue;
+            }
+            let got_action = u32::from_be_bytes(buf[0..4].try_into().unwrap());
+            let got_transaction = u32::from_be_bytes(buf[4..8].try_into().unwrap());
+            if got_transaction != transaction_id {
+                continue;
+            }
+            if got_action == ACTION_ERROR {
+                let message = String::from_utf8_lossy(&buf[8..len]).into_owned();
+                return Err(TrackerError::Failure(message));
+            }
+            if got_action != action {
+                return Err(TrackerError::InvalidResponse("unexpected action"));
+            }
+            return Ok(buf[..len].to_vec());
+        }
+    }
+    Err(TrackerError::Timeout)
+}
+
+/// Looks up the tracker's address, preferring one `interface` can reach.
+async fn resolve(url: &str, interface: Option<&Interface>) -> Result<SocketAddr, TrackerError> {
+    let invalid = || TrackerError::InvalidUrl(url.to_string());
+    let rest = url.strip_prefix("udp://").ok_or_else(invalid)?;
+    let host_port = rest.split(['/', '?']).next().ok_or_else(invalid)?;
+    let addrs: Vec<SocketAddr> = lookup_host(host_port).await?.collect();
+    addrs
+        .iter()
+        .find(|addr| interface.is_none_or(|interface| interface.reaches(addr)))
+        .or(addrs.first())
+        .copied()
+        .ok_or_else(invalid)
+}
@@ -0,0 +1,59 @@
+//! Piece hashing.
+
+use bytes::Bytes;
+
+use crate::bitfield::Bitfield;
+use crate::hash::{self, Sha1};
+use crate::metainfo::Info;
+use crate::storage::{Storage, StorageError};
+
+pub fn piece_hash(data: &[u8]) -> [u8; 20] {
+    hash::sha1(data)
+}
+
+pub fn verify_piece(expected: &[u8; 20], data: &[u8]) -> bool {
+    &piece_hash(data) == expected
+}
+
+/// Hashes a piece held as consecutive blocks.
+pub fn blocks_hash(blocks: &[Bytes]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    for block in blocks {
+        hasher.update(block);
+    }
+    hasher.finish()
+}
+
+/// Verifies a piece on the blocking thread pool so hashing never stalls the
+/// peer tasks, handing its blocks back for writing.
+#[cfg(feature = "tokio")]
+pub async fn verify_piece_async(expected: [u8; 20], blocks: Vec<Bytes>) -> (bool, Vec<Bytes>) {
+    tokio::task::spawn_blocking(move || (blocks_hash(&blocks) == expected, blocks))
+        .await
+        .expect("piece hashing task panicked")
+}
+
+/// Hashes every piece present in `storage`, returning the ones that match.
+///
+/// `progress` is called after each piece with its index and validity.
+pub fn recheck(
+    storage: &dyn Storage,
+    info: &Info,
+    mut progress: impl FnMut(u32, bool),
+) -> Result<Bitfield, StorageError> {
+    let mut valid = Bitfield::new(info.num_pieces());
+
+    for (piece, expected) in info.pieces.iter().enumerate() {
+        let piece = piece as u32;
+        let size = info.piece_size(piece);
+        let data = storage.read_piece(piece, size)?;
+        let ok = verify_piece(expected, &data);
+        storage.drop_cache(storage.layout().piece_offset(piece), size as u64)?;
+        if ok {
+            valid.set(piece as usize);
+        }
+        progress(piece, ok);
+    }
+
+    Ok(valid)
+}
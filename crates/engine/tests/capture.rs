@@ -0,0 +1,163 @@
+//! Reading TCP connections back out of packet captures.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use rainyday_engine::capture::tcp::Event;
+use rainyday_engine::capture::{self, CaptureError, Direction, Reassembler, Segment};
+
+const SYN: u8 = 0x02;
+const ACK: u8 = 0x10;
+const PSH_ACK: u8 = 0x18;
+const FIN_ACK: u8 = 0x11;
+
+fn client() -> SocketAddr {
+    "10.0.0.1:40000".parse().unwrap()
+}
+
+fn server() -> SocketAddr {
+    "10.0.0.2:6881".parse().unwrap()
+}
+
+/// An Ethernet frame carrying a TCP segment over IPv4.
+fn frame(src: SocketAddr, dst: SocketAddr, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+    let ip = |addr: SocketAddr| match addr {
+        SocketAddr::V4(addr) => addr.ip().octets(),
+        SocketAddr::V6(_) => unreachable!(),
+    };
+    let mut tcp = Vec::new();
+    tcp.extend_from_slice(&src.port().to_be_bytes());
+    tcp.extend_from_slice(&dst.port().to_be_bytes());
+    tcp.extend_from_slice(&seq.to_be_bytes());
+    tcp.extend_from_slice(&[0, 0, 0, 0, 5 << 4, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    tcp.extend_from_slice(payload);
+
+    let mut frame = vec![0; 12];
+    frame.extend_from_slice(&[0x08, 0x00]);
+    frame.extend_from_slice(&[0x45, 0]);
+    frame.extend_from_slice(&(20 + tcp.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+    frame.extend_from_slice(&ip(src));
+    frame.extend_from_slice(&ip(dst));
+    frame.extend_from_slice(&tcp);
+    frame
+}
+
+/// A little-endian, microsecond pcap of `frames`, a millisecond apart.
+fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut pcap = Vec::new();
+    for field in [0xa1b2_c3d4u32, 0x0004_0002, 0, 0, 65535, 1] {
+        pcap.extend_from_slice(&field.to_le_bytes());
+    }
+    for (i, frame) in frames.iter().enumerate() {
+        let len = frame.len() as u32;
+        for field in [1_700_000_000, 1000 * i as u32, len, len] {
+            pcap.extend_from_slice(&field.to_le_bytes());
+        }
+        pcap.extend_from_slice(frame);
+    }
+    pcap
+}
+
+fn data(events: &[(Duration, Direction, Event)], direction: Direction) -> Vec<u8> {
+    events
+        .iter()
+        .filter(|(_, d, _)| *d == direction)
+        .filter_map(|(_, _, event)| match event {
+            Event::Data(data) => Some(&data[..]),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+#[test]
+fn reads_segments_from_a_pcap() {
+    let capture = pcap(&[
+        frame(client(), server(), 99, SYN, b""),
+        frame(client(), server(), 100, PSH_ACK, b"hello"),
+    ]);
+    let segments = capture::segments(&capture).unwrap();
+    assert_eq!(
+        segments[1],
+        Segment {
+            time: Duration::new(1_700_000_000, 1_000_000),
+            src: client(),
+            dst: server(),
+            seq: 100,
+            flags: PSH_ACK,
+            payload: b"hello".to_vec(),
+        }
+    );
+}
+
+#[test]
+fn rejects_what_is_not_a_capture() {
+    assert!(matches!(
+        capture::segments(b"GET / HTTP/1.1\r\n"),
+        Err(CaptureError::UnknownFormat)
+    ));
+    let capture = pcap(&[frame(client(), server(), 1, ACK, b"cut off")]);
+    assert!(matches!(
+        capture::segments(&capture[..capture.len() - 1]),
+        Err(CaptureError::Truncated(_))
+    ));
+}
+
+#[test]
+fn reassembles_reordered_and_repeated_segments() {
+    let capture = pcap(&[
+        frame(client(), server(), 99, SYN, b""),
+        frame(server(), client(), u32::MAX, SYN | ACK, b""),
+        frame(client(), server(), 105, PSH_ACK, b" world"),
+        frame(client(), server(), 100, PSH_ACK, b"hello"),
+        frame(client(), server(), 100, PSH_ACK, b"hello"),
+        frame(server(), client(), 0, PSH_ACK, b"wrapped"),
+        frame(client(), server(), 111, FIN_ACK, b""),
+    ]);
+    let mut reassembler = Reassembler::new();
+    for segment in capture::segments(&capture).unwrap() {
+        reassembler.push(segment);
+    }
+    let connections = reassembler.finish();
+    assert_eq!(connections.len(), 1);
+    let connection = &connections[0];
+    assert!(connection.opened);
+    assert_eq!(connection.initiator, client());
+    assert_eq!(
+        data(&connection.events, Direction::Outbound),
+        b"hello world"
+    );
+    assert_eq!(data(&connection.events, Direction::Inbound), b"wrapped");
+    assert!(matches!(
+        connection.events.last(),
+        Some((_, Direction::Outbound, Event::Fin))
+    ));
+}
+
+#[test]
+fn notes_what_was_not_captured() {
+    let capture = pcap(&[
+        frame(client(), server(), 100, PSH_ACK, b"before"),
+        frame(client(), server(), 110, PSH_ACK, b"after"),
+    ]);
+    let mut reassembler = Reassembler::new();
+    for segment in capture::segments(&capture).unwrap() {
+        reassembler.push(segment);
+    }
+    let connections = reassembler.finish();
+    assert!(!connections[0].opened);
+    let events = connections[0]
+        .events
+        .iter()
+        .map(|(_, _, event)| event.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        [
+            Event::Data(b"before".to_vec()),
+            Event::Gap(4),
+            Event::Data(b"after".to_vec()),
+        ]
+    );
+}
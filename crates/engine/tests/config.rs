@@ -0,0 +1,101 @@
+//! Loading the configuration.
+
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use rainyday_engine::config::{Config, ConfigError};
+use rainyday_engine::peer::encryption::EncryptionPolicy;
+
+/// Writes `text` to a configuration file of its own, named after `test`.
+fn file(test: &str, text: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("rainyday-config-{}-{}.toml", test, process::id()));
+    fs::write(&path, text).unwrap();
+    path
+}
+
+#[test]
+fn refuses_encryption_policies_it_cannot_honour() {
+    let path = file("disabled", "[network]\nencryption = \"disabled\"\n");
+    let config = Config::try_from(path.as_path()).unwrap();
+    assert_eq!(config.network.encryption, EncryptionPolicy::Disabled);
+
+    for policy in ["allow-incoming", "prefer-outgoing", "require"] {
+        let path = file(policy, &format!("[network]\nencryption = \"{}\"\n", policy));
+        let error = Config::try_from(path.as_path()).unwrap_err();
+        let _ = fs::remove_file(&path);
+        assert!(
+            matches!(error, ConfigError::EncryptionUnsupported(_)),
+            "{}",
+            error
+        );
+        assert!(error.to_string().contains(policy));
+    }
+    let _ = fs::remove_file(&path);
+}
+
+/// Environment variables, as `Config::load_with` takes them.
+fn vars(vars: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+    vars.iter()
+        .map(|(name, value)| (name.into(), value.into()))
+        .collect()
+}
+
+#[test]
+fn lets_the_environment_override_the_file_under_any_name() {
+    let path = file("legacy", "[network]\nport = 1000\nmax_peers = 10\n");
+    let env = vars(&[
+        ("RAINYDAY_LISTEN_PORT", "2000"),
+        ("RAINYDAY_NETWORK__MAX_PEERS", "20"),
+    ]);
+    let config = Config::load_with(Some(&path), None, env).unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(config.network.port, Some(2000));
+    assert_eq!(config.network.max_peers, 20);
+}
+
+#[test]
+fn lets_the_environment_set_keys_of_profiles() {
+    let path = file(
+        "profiles",
+        "[network]\nport = 1000\n[profile.work.network]\nport = 2000\nmax_peers = 10\n",
+    );
+    let env = vars(&[
+        ("RAINYDAY_PROFILE__WORK__NETWORK__MAX_PEERS", "20"),
+        ("RAINYDAY_PROFILE__HOME__NETWORK__PORT", "3000"),
+    ]);
+    let work = Config::load_with(Some(&path), Some("work"), env.clone()).unwrap();
+    let home = Config::load_with(Some(&path), Some("home"), env.clone()).unwrap();
+    let none = Config::load_with(Some(&path), None, env).unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(work.network.port, Some(2000));
+    assert_eq!(work.network.max_peers, 20);
+    assert_eq!(home.network.port, Some(3000));
+    assert_eq!(none.network.port, Some(1000));
+}
+
+#[test]
+fn ignores_variables_that_are_not_configuration_keys() {
+    let path = file("reserved", "[logging]\nkeep = 3\n");
+    let env = vars(&[
+        ("RAINYDAY_LOG", "debug"),
+        ("RAINYDAY_LOGGING", "debug"),
+        ("RAINYDAY_EVENT", "finished"),
+        ("RAINYDAY_NAME", "some torrent"),
+        ("RAINYDAY_PATH", "/tmp/some torrent"),
+        ("RAINYDAY_RATIO", "1.500"),
+    ]);
+    let config = Config::load_with(Some(&path), None, env).unwrap();
+    let expected = Config::load_with(Some(&path), None, Vec::new()).unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(config, expected);
+    assert_eq!(config.logging.keep, 3);
+    let env = vars(&[("RAINYDAY_LOGGING", "debug")]);
+    assert_eq!(
+        Config::load_with(None, None, env).unwrap(),
+        Config::default()
+    );
+}
@@ -0,0 +1,77 @@
+//! The fast extension (BEP 6): which pieces a choked peer may still have.
+
+use std::net::IpAddr;
+
+use rainyday_engine::bitfield::Bitfield;
+use rainyday_engine::info_hash::InfoHash;
+use rainyday_engine::peer::fast::allowed_fast_set;
+use rainyday_engine::peer::state::PeerState;
+use rainyday_engine::picker::BLOCK_SIZE;
+use rainyday_engine::protocol::BlockRequest;
+
+const NUM_PIECES: u32 = 1313;
+const INFO_HASH: InfoHash = InfoHash([0xaa; 20]);
+
+fn peer() -> IpAddr {
+    IpAddr::from([80, 4, 4, 200])
+}
+
+#[test]
+fn computes_the_sets_of_bep_6() {
+    assert_eq!(
+        allowed_fast_set(peer(), &INFO_HASH, NUM_PIECES, 7),
+        [1059, 431, 808, 1217, 287, 376, 1188]
+    );
+    assert_eq!(
+        allowed_fast_set(peer(), &INFO_HASH, NUM_PIECES, 9),
+        [1059, 431, 808, 1217, 287, 376, 1188, 353, 508]
+    );
+}
+
+#[test]
+fn has_the_same_set_for_every_address_in_a_slash_24() {
+    let neighbour = IpAddr::from([80, 4, 4, 17]);
+    assert_eq!(
+        allowed_fast_set(neighbour, &INFO_HASH, NUM_PIECES, 7),
+        allowed_fast_set(peer(), &INFO_HASH, NUM_PIECES, 7)
+    );
+    let ipv6 = "2001:db8::1".parse().unwrap();
+    assert!(allowed_fast_set(ipv6, &INFO_HASH, NUM_PIECES, 7).is_empty());
+}
+
+#[test]
+fn serves_a_choked_peer_only_its_allowed_fast_pieces() {
+    let mut state = PeerState::new(NUM_PIECES as usize, true, false);
+    state
+        .granted_fast
+        .extend(allowed_fast_set(peer(), &INFO_HASH, NUM_PIECES, 7));
+    let have = Bitfield::full(NUM_PIECES as usize);
+    let request = |piece| BlockRequest {
+        piece,
+        offset: 0,
+        length: 16 * 1024,
+    };
+
+    assert!(state.am_choking);
+    assert!(state.should_serve(&request(1059), &have));
+    assert!(!state.should_serve(&request(1060), &have));
+    assert!(!state.should_serve(&request(1059), &Bitfield::new(NUM_PIECES as usize)));
+    state.am_choking = false;
+    assert!(state.should_serve(&request(1060), &have));
+}
+
+#[test]
+fn serves_no_more_than_a_block_at_once() {
+    let mut state = PeerState::new(NUM_PIECES as usize, true, false);
+    state.am_choking = false;
+    let have = Bitfield::full(NUM_PIECES as usize);
+    let request = |length| BlockRequest {
+        piece: 7,
+        offset: 0,
+        length,
+    };
+
+    assert!(state.should_serve(&request(BLOCK_SIZE), &have));
+    assert!(!state.should_serve(&request(BLOCK_SIZE + 1), &have));
+    assert!(!state.should_serve(&request(2 * 1024 * 1024), &have));
+}
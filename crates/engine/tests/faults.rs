@@ -0,0 +1,74 @@
+//! Downloads with faults injected into the engine's connections and disk.
+
+mod sim;
+
+use std::time::Duration;
+
+use rainyday_engine::engine::EngineError;
+
+use sim::{Link, Sim, VirtualPeer};
+
+const SEED: u64 = 0x6661_756c_7473;
+const PIECE_LENGTH: u32 = 64 * 1024;
+/// Sixteen pieces and a short one.
+const SIZE: usize = 16 * PIECE_LENGTH as usize + 3000;
+const LIMIT: Duration = Duration::from_secs(3600);
+
+fn lan() -> Link {
+    Link::default().with_latency(Duration::from_millis(1))
+}
+
+#[tokio::test(start_paused = true)]
+async fn carries_on_without_a_peer_whose_connection_timed_out() {
+    let sim = Sim::new("connect-timeout", SEED, SIZE, PIECE_LENGTH);
+    let first = sim.add_peer(VirtualPeer::seed(), lan());
+    let second = sim.add_peer(VirtualPeer::seed(), lan());
+    sim.faults().time_out_connects(1);
+
+    let outcome = sim.run(LIMIT).await;
+    assert!(outcome.is_complete(), "{:?}", outcome);
+    assert!(sim.faults().is_spent());
+    assert_eq!(
+        first.connections() + second.connections(),
+        1,
+        "the attempt that timed out never arrived"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn reassembles_messages_from_short_reads() {
+    let sim = Sim::new("short-reads", SEED, SIZE, PIECE_LENGTH);
+    sim.add_peer(VirtualPeer::seed(), lan());
+    sim.faults().shorten_reads(7);
+
+    let outcome = sim.run(LIMIT).await;
+    assert!(outcome.is_complete(), "{:?}", outcome);
+}
+
+#[tokio::test(start_paused = true)]
+async fn fetches_a_piece_again_when_a_block_arrives_corrupt() {
+    let sim = Sim::new("corrupt-block", SEED, SIZE, PIECE_LENGTH);
+    let first = sim.add_peer(VirtualPeer::seed(), lan());
+    let second = sim.add_peer(VirtualPeer::seed(), lan());
+    sim.faults().corrupt_blocks(1);
+
+    let outcome = sim.run(LIMIT).await;
+    assert!(outcome.is_complete(), "{:?}", outcome);
+    assert!(sim.faults().is_spent());
+    assert!(first.blocks() + second.blocks() > SIZE.div_ceil(16 * 1024));
+}
+
+#[tokio::test(start_paused = true)]
+async fn stops_when_a_write_fails() {
+    let sim = Sim::new("write-error", SEED, SIZE, PIECE_LENGTH);
+    sim.add_peer(VirtualPeer::seed(), lan());
+    sim.faults().fail_writes(1);
+
+    let outcome = sim.run(LIMIT).await;
+    assert!(
+        matches!(outcome.result, Some(Err(EngineError::Storage(_)))),
+        "{:?}",
+        outcome
+    );
+    assert!(sim.faults().is_spent());
+}
@@ -0,0 +1,47 @@
+//! Relaying rendezvous between peers that cannot dial each other (BEP 55).
+
+use std::net::SocketAddr;
+
+use rainyday_engine::peer::holepunch::relay;
+use rainyday_engine::protocol::holepunch::{HolepunchError, HolepunchMessage};
+
+fn addr(s: &str) -> SocketAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn introduces_each_peer_to_the_other() {
+    let from = addr("198.51.100.20:50123");
+    let initiator = addr("198.51.100.20:6881");
+    let target = addr("203.0.113.9:6881");
+    let to = addr("203.0.113.9:41000");
+
+    assert_eq!(
+        relay(from, initiator, target, Some((to, true))),
+        [
+            (from, HolepunchMessage::Connect(target)),
+            (to, HolepunchMessage::Connect(initiator)),
+        ]
+    );
+}
+
+#[test]
+fn refuses_what_it_cannot_arrange() {
+    let from = addr("198.51.100.20:50123");
+    let initiator = addr("198.51.100.20:6881");
+    let target = addr("203.0.113.9:6881");
+    let error = |error| vec![(from, HolepunchMessage::Error(target, error))];
+
+    assert_eq!(
+        relay(from, initiator, target, None),
+        error(HolepunchError::NotConnected)
+    );
+    assert_eq!(
+        relay(from, initiator, target, Some((target, false))),
+        error(HolepunchError::NoSupport)
+    );
+    assert_eq!(
+        relay(from, target, target, Some((target, true))),
+        error(HolepunchError::NoSelf)
+    );
+}
@@ -0,0 +1,181 @@
+//! Torrents, magnet links and resume data read back as they were written.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use rainyday_engine::bencode::Value;
+use rainyday_engine::bitfield::Bitfield;
+use rainyday_engine::hash::sha1;
+use rainyday_engine::info_hash::InfoHash;
+use rainyday_engine::magnet::Magnet;
+use rainyday_engine::metainfo::{self, FileEntry, Info, Metainfo};
+use rainyday_engine::resume::ResumeData;
+
+/// Bencode integers are signed, so counts past this cannot be stored.
+const MAX_COUNT: u64 = i64::MAX as u64;
+
+/// A file or directory name that needs no sanitizing.
+fn component() -> impl Strategy<Value = String> {
+    "[a-z0-9][a-z0-9_.-]{0,11}[a-z0-9]"
+}
+
+fn info() -> impl Strategy<Value = Info> {
+    let single = (component(), 0..1u64 << 22).prop_map(|(name, length)| {
+        let files = vec![FileEntry {
+            path: vec![name.clone()],
+            length,
+        }];
+        (name, files, true)
+    });
+    let multi = (
+        component(),
+        vec((vec(component(), 1..4), 0..1u64 << 22), 1..5),
+    )
+        .prop_map(|(name, files)| {
+            let files = files
+                .into_iter()
+                .map(|(path, length)| FileEntry { path, length })
+                .collect();
+            (name, files, false)
+        });
+
+    (
+        prop_oneof![single, multi],
+        16 * 1024..=4u32 << 20,
+        any::<bool>(),
+    )
+        .prop_flat_map(|((name, files, single_file), piece_length, private)| {
+            let total: u64 = files.iter().map(|file: &FileEntry| file.length).sum();
+            let num_pieces = total.div_ceil(u64::from(piece_length)) as usize;
+            vec(any::<[u8; 20]>(), num_pieces).prop_map(move |pieces| Info {
+                name: name.clone(),
+                piece_length,
+                pieces,
+                private,
+                files: files.clone(),
+                single_file,
+            })
+        })
+}
+
+/// The info dictionary a torrent maker would write for `info`.
+fn encode_info(info: &Info) -> Vec<u8> {
+    let mut dict = BTreeMap::new();
+    dict.insert(b"name".to_vec(), Value::from(info.name.as_str()));
+    dict.insert(
+        b"piece length".to_vec(),
+        Value::Integer(i64::from(info.piece_length)),
+    );
+    dict.insert(b"pieces".to_vec(), Value::Bytes(info.pieces.concat()));
+    if info.private {
+        dict.insert(b"private".to_vec(), Value::Integer(1));
+    }
+    if info.single_file {
+        dict.insert(
+            b"length".to_vec(),
+            Value::Integer(info.total_length() as i64),
+        );
+    } else {
+        let files = info
+            .files
+            .iter()
+            .map(|file| {
+                let mut entry = BTreeMap::new();
+                entry.insert(b"length".to_vec(), Value::Integer(file.length as i64));
+                let path = file.path.iter().map(|c| Value::from(c.as_str())).collect();
+                entry.insert(b"path".to_vec(), Value::List(path));
+                Value::Dict(entry)
+            })
+            .collect();
+        dict.insert(b"files".to_vec(), Value::List(files));
+    }
+    Value::Dict(dict).encode()
+}
+
+fn metainfo() -> impl Strategy<Value = Metainfo> {
+    let text = || ".{0,24}";
+    (
+        proptest::option::of(text()),
+        vec(vec(text(), 1..3), 0..3),
+        proptest::option::of(text()),
+        proptest::option::of(text()),
+        proptest::option::of(any::<i64>()),
+        vec(text(), 0..3),
+        info(),
+    )
+        .prop_map(
+            |(announce, announce_list, comment, created_by, creation_date, url_list, info)| {
+                let info_bytes = encode_info(&info);
+                Metainfo {
+                    announce,
+                    announce_list,
+                    comment,
+                    created_by,
+                    creation_date,
+                    url_list,
+                    info,
+                    info_hash: InfoHash(sha1(&info_bytes)),
+                    info_bytes,
+                }
+            },
+        )
+}
+
+fn bitfield() -> impl Strategy<Value = Bitfield> {
+    vec(any::<bool>(), 0..200).prop_map(|bits| {
+        let mut bitfield = Bitfield::new(bits.len());
+        for (i, _) in bits.iter().enumerate().filter(|(_, &bit)| bit) {
+            bitfield.set(i);
+        }
+        bitfield
+    })
+}
+
+proptest! {
+    #[test]
+    fn info_dictionaries_parse_to_what_they_describe(info in info()) {
+        let parsed = Metainfo::from_info_bytes(encode_info(&info), Vec::new(), Vec::new())?;
+        prop_assert_eq!(parsed.info, info);
+    }
+
+    #[test]
+    fn torrents_roundtrip(metainfo in metainfo()) {
+        let bytes = metainfo.to_bytes();
+        prop_assert_eq!(Metainfo::from_bytes(&bytes)?, metainfo.clone());
+        prop_assert_eq!(Metainfo::parse(&bytes, true)?, metainfo);
+    }
+
+    #[test]
+    fn torrents_keep_their_info_dictionary_when_rewritten(metainfo in metainfo()) {
+        let bytes = metainfo.to_bytes();
+        let root = rainyday_engine::bencode::decode(&bytes)?;
+        let root = root.as_dict().unwrap();
+        prop_assert_eq!(metainfo::encode(root, &metainfo.info_bytes), bytes);
+    }
+
+    #[test]
+    fn magnet_links_roundtrip(
+        info_hash in any::<[u8; 20]>().prop_map(InfoHash),
+        name in proptest::option::of(".{0,24}"),
+        trackers in vec(".{0,24}", 0..3),
+        web_seeds in vec(".{0,24}", 0..3),
+    ) {
+        let magnet = Magnet { info_hash, name, trackers, web_seeds };
+        prop_assert_eq!(magnet.to_string().parse::<Magnet>()?, magnet);
+    }
+
+    #[test]
+    fn resume_data_roundtrips(
+        info_hash in any::<[u8; 20]>().prop_map(InfoHash),
+        save_path in ".{0,24}".prop_map(PathBuf::from),
+        have in bitfield(),
+        uploaded in 0..=MAX_COUNT,
+        downloaded in 0..=MAX_COUNT,
+    ) {
+        let resume = ResumeData { info_hash, save_path, have, uploaded, downloaded };
+        prop_assert_eq!(ResumeData::from_bytes(&resume.to_bytes())?, resume);
+    }
+}
@@ -0,0 +1,96 @@
+//! Making paths from untrusted metainfo safe to create on disk.
+
+use rainyday_engine::metainfo::Metainfo;
+use rainyday_engine::storage::sanitize::{
+    disambiguate, sanitize_component, sanitize_path, PathError,
+};
+
+fn path(components: &[&str]) -> Vec<String> {
+    components.iter().map(|c| c.to_string()).collect()
+}
+
+#[test]
+fn refuses_to_leave_the_download_directory() {
+    assert_eq!(
+        sanitize_component(".."),
+        Err(PathError::Traversal("..".to_string()))
+    );
+    assert_eq!(
+        sanitize_path(&path(&["a", "..", "..", "etc"])),
+        Err(PathError::Traversal("..".to_string()))
+    );
+    // An absolute path splits into an empty first component.
+    assert_eq!(
+        sanitize_path(&path(&["", "etc", "passwd"])),
+        Err(PathError::Empty)
+    );
+    assert_eq!(
+        sanitize_component("/etc/passwd"),
+        Err(PathError::Separator("/etc/passwd".to_string()))
+    );
+    assert_eq!(
+        sanitize_component("C:\\Windows"),
+        Err(PathError::Separator("C:\\Windows".to_string()))
+    );
+}
+
+#[test]
+fn renames_reserved_names() {
+    assert_eq!(sanitize_component("CON").unwrap(), "_CON");
+    assert_eq!(sanitize_component("nul.txt").unwrap(), "_nul.txt");
+    assert_eq!(sanitize_component("console").unwrap(), "console");
+}
+
+#[test]
+fn keeps_trailing_dots_and_spaces_from_vanishing() {
+    assert_eq!(sanitize_component("notes.").unwrap(), "notes_");
+    assert_eq!(sanitize_component("notes ..").unwrap(), "notes_");
+    assert_eq!(sanitize_component("a:b?").unwrap(), "a_b_");
+}
+
+#[test]
+fn numbers_files_that_sanitize_to_the_same_path() {
+    let mut paths: Vec<Vec<String>> = [["dir", "a:b.txt"], ["dir", "a_b.txt"], ["dir", "a?b.txt"]]
+        .iter()
+        .map(|raw| sanitize_path(&path(raw)).unwrap())
+        .collect();
+    disambiguate(&mut paths);
+    assert_eq!(
+        paths,
+        [
+            path(&["dir", "a_b.txt"]),
+            path(&["dir", "a_b (1).txt"]),
+            path(&["dir", "a_b (2).txt"]),
+        ]
+    );
+}
+
+#[test]
+fn numbers_a_file_in_the_way_of_a_directory() {
+    let mut paths = vec![
+        sanitize_path(&path(&["notes."])).unwrap(),
+        sanitize_path(&path(&["notes_", "today"])).unwrap(),
+        path(&["notes_ (1)"]),
+    ];
+    disambiguate(&mut paths);
+    assert_eq!(
+        paths,
+        [
+            path(&["notes_ (2)"]),
+            path(&["notes_", "today"]),
+            path(&["notes_ (1)"]),
+        ]
+    );
+}
+
+#[test]
+fn reads_a_torrent_whose_file_names_clash_into_distinct_files() {
+    let mut torrent = b"d4:infod5:filesl".to_vec();
+    torrent.extend_from_slice(b"d6:lengthi1e4:pathl7:a:b.txteed6:lengthi1e4:pathl7:a_b.txtee");
+    torrent.extend_from_slice(b"e4:name3:dir12:piece lengthi16384e6:pieces20:");
+    torrent.extend_from_slice(&[0; 20]);
+    torrent.extend_from_slice(b"ee");
+    let metainfo = Metainfo::from_bytes(&torrent).unwrap();
+    let paths: Vec<_> = metainfo.info.files.iter().map(|file| &file.path).collect();
+    assert_eq!(paths, [&path(&["a_b.txt"]), &path(&["a_b (1).txt"])]);
+}
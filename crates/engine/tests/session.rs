@@ -0,0 +1,109 @@
+//! Torrents running together in one session.
+
+use std::fs;
+use std::process;
+use std::sync::Arc;
+
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+
+use rainyday_engine::config::Config;
+use rainyday_engine::create::{create, CreateOptions};
+use rainyday_engine::engine::{Mode, Options, SeedGoal};
+use rainyday_engine::event::EventBus;
+use rainyday_engine::metainfo::Metainfo;
+use rainyday_engine::protocol::handshake::HANDSHAKE_LEN;
+use rainyday_engine::protocol::Handshake;
+use rainyday_engine::session::{Session, SessionStore};
+
+#[tokio::test]
+async fn introduces_every_torrent_with_the_same_peer_id() {
+    let dir = std::env::temp_dir().join(format!("rainyday-session-id-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let mut config = Config::default();
+    config.storage.download_dir = dir.join("download");
+    config.dht.enabled = false;
+    config.network.port = Some(0);
+    config.network.upnp = false;
+    config.network.natpmp = false;
+    let session = Session::new(config, EventBus::default()).unwrap();
+
+    // Someone every torrent knows of, who notes who dials it.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let peer = listener.local_addr().unwrap();
+    for name in ["one", "two"] {
+        let source = dir.join(name);
+        fs::write(&source, name.repeat(1000)).unwrap();
+        let torrent = create(&source, &CreateOptions::default()).unwrap();
+        let metainfo = Arc::new(Metainfo::from_bytes(&torrent).unwrap());
+        let handle = session
+            .add(metainfo, &Options::default(), Mode::Download)
+            .await
+            .unwrap();
+        handle.add_peers(vec![peer]).await;
+    }
+
+    let mut peer_ids = Vec::new();
+    for _ in 0..2 {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0; HANDSHAKE_LEN];
+        stream.read_exact(&mut buf).await.unwrap();
+        peer_ids.push(Handshake::from_bytes(&buf).unwrap().peer_id);
+    }
+    session.shutdown().await;
+    let _ = fs::remove_dir_all(&dir);
+
+    assert_eq!(peer_ids[0], session.peer_id());
+    assert_eq!(peer_ids[1], session.peer_id());
+}
+
+#[tokio::test]
+async fn moves_and_renames_a_running_torrent_and_remembers_where() {
+    let dir = std::env::temp_dir().join(format!("rainyday-session-move-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let downloads = dir.join("download");
+    fs::create_dir_all(&downloads).unwrap();
+    let source = downloads.join("data.bin");
+    fs::write(&source, vec![3; 100_000]).unwrap();
+    let metainfo = Metainfo::from_bytes(&create(&source, &CreateOptions::default()).unwrap());
+    let metainfo = Arc::new(metainfo.unwrap());
+
+    let mut config = Config::default();
+    config.storage.download_dir = downloads.clone();
+    config.dht.enabled = false;
+    config.network.port = Some(0);
+    config.network.upnp = false;
+    config.network.natpmp = false;
+    let store = SessionStore::open(&dir.join("state")).unwrap();
+    let session = Session::daemon(config, EventBus::default())
+        .unwrap()
+        .with_store(store);
+    let options = Options::default();
+    let goal = SeedGoal::default();
+    session.remember(&metainfo, &options, goal).unwrap();
+    session
+        .add(Arc::clone(&metainfo), &options, Mode::Seed(goal))
+        .await
+        .unwrap();
+    let info_hash = metainfo.info_hash;
+
+    let moved = dir.join("moved");
+    session
+        .move_torrent(&info_hash, moved.clone())
+        .await
+        .unwrap();
+    assert!(!source.exists());
+    assert!(moved.join("data.bin").is_file());
+    session
+        .rename_torrent(&info_hash, "renamed.bin".to_string())
+        .await
+        .unwrap();
+    assert!(moved.join("renamed.bin").is_file());
+
+    let records = session.records().unwrap();
+    session.shutdown().await;
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(records[0].options.save_path, moved);
+    assert_eq!(records[0].options.name.as_deref(), Some("renamed.bin"));
+}
@@ -0,0 +1,190 @@
+//! A deterministic simulation of a swarm, for running whole downloads in
+//! tests.
+//!
+//! The engine runs as it would in the client, but dials virtual peers
+//! over an in-memory network instead of TCP. Run on a paused Tokio clock
+//! (`#[tokio::test(start_paused = true)]`), time only passes while
+//! everything waits on a timer, so minutes of simulated transfer take
+//! moments, and the torrent, the links and their losses all follow from
+//! the scenario's seed.
+
+// Each test target uses only some of what is here.
+#![allow(dead_code)]
+
+mod network;
+mod peer;
+
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use tokio::time::{timeout, Instant};
+
+use rainyday_engine::blocklist::Blocklist;
+use rainyday_engine::config::Config;
+use rainyday_engine::create::{create, CreateOptions};
+use rainyday_engine::engine::{self, EngineError, Mode, Options, Shared, Summary};
+use rainyday_engine::event::EventBus;
+#[cfg(feature = "faults")]
+use rainyday_engine::faults::{Faults, FaultyTransport};
+use rainyday_engine::limits::Limits;
+use rainyday_engine::metainfo::Metainfo;
+use rainyday_engine::peer::transport::PeerTransport;
+use rainyday_engine::peer::PeerId;
+use rainyday_engine::swarm::ExternalIp;
+
+pub use network::Link;
+pub use peer::{PeerLog, VirtualPeer};
+
+use network::Network;
+use peer::Content;
+
+/// Port every virtual peer listens on.
+const PORT: u16 = 6881;
+
+/// One scenario: a torrent, the peers that have it, and a directory to
+/// download it into.
+pub struct Sim {
+    dir: PathBuf,
+    metainfo: Arc<Metainfo>,
+    content: Arc<Content>,
+    network: Arc<Network>,
+    #[cfg(feature = "faults")]
+    faults: Arc<Faults>,
+}
+
+/// How a run ended.
+#[derive(Debug)]
+pub struct Outcome {
+    /// What the engine returned, or `None` if it ran out of time.
+    pub result: Option<Result<Summary, EngineError>>,
+    /// Simulated time the run took.
+    pub elapsed: Duration,
+    /// Whether the downloaded file matches the torrent's data.
+    pub intact: bool,
+}
+
+impl Outcome {
+    pub fn is_complete(&self) -> bool {
+        self.intact && matches!(&self.result, Some(Ok(summary)) if summary.complete)
+    }
+}
+
+impl Sim {
+    /// A torrent of `size` bytes of data drawn from `seed`, with pieces of
+    /// `piece_length`. `name` keeps the scenario's files apart from those
+    /// of others running at the same time.
+    pub fn new(name: &str, seed: u64, size: usize, piece_length: u32) -> Self {
+        let dir = std::env::temp_dir().join(format!("rainyday-sim-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut data = vec![0; size];
+        StdRng::seed_from_u64(seed).fill_bytes(&mut data);
+        let source = dir.join("data.bin");
+        fs::write(&source, &data).unwrap();
+        let options = CreateOptions {
+            piece_length: Some(piece_length),
+            ..CreateOptions::default()
+        };
+        let torrent = create(&source, &options).unwrap();
+        let metainfo = Arc::new(Metainfo::from_bytes(&torrent).unwrap());
+
+        let content = Arc::new(Content {
+            info_hash: metainfo.info_hash,
+            piece_length,
+            data,
+        });
+        Self {
+            network: Arc::new(Network::new(seed, Arc::clone(&content))),
+            #[cfg(feature = "faults")]
+            faults: Arc::default(),
+            dir,
+            metainfo,
+            content,
+        }
+    }
+
+    pub fn num_pieces(&self) -> u32 {
+        self.content.num_pieces()
+    }
+
+    /// Faults to inject into the engine's connections and storage.
+    #[cfg(feature = "faults")]
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+
+    /// Adds a peer behind `link`, returning the log of what happens to it.
+    pub fn add_peer(&self, peer: VirtualPeer, link: Link) -> Arc<PeerLog> {
+        let host = self.network.addrs().len() as u8 + 1;
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, host)), PORT);
+        self.network.add(addr, peer, link)
+    }
+
+    /// Downloads the torrent from the peers, giving up after `limit` of
+    /// simulated time.
+    pub async fn run(&self, limit: Duration) -> Outcome {
+        let download = self.dir.join("download");
+        let _ = fs::remove_dir_all(&download);
+        let mut config = Config::default();
+        config.storage.download_dir = download.clone();
+        config.dht.enabled = false;
+
+        let transport: Arc<dyn PeerTransport> = Arc::clone(&self.network) as _;
+        #[cfg(feature = "faults")]
+        let transport: Arc<dyn PeerTransport> =
+            Arc::new(FaultyTransport::new(transport, Arc::clone(&self.faults)));
+        let shared = Shared {
+            events: EventBus::default(),
+            port: PORT,
+            endpoints: Vec::new(),
+            limits: Limits::new(&config),
+            blocklist: Blocklist::default(),
+            counters: Arc::default(),
+            external_ip: ExternalIp::default(),
+            peer_id: PeerId::generate("-RD0100-").unwrap(),
+            transport,
+            #[cfg(feature = "faults")]
+            faults: Some(Arc::clone(&self.faults)),
+        };
+        let started = Instant::now();
+        let metainfo = Arc::clone(&self.metainfo);
+        let (handle, task) = engine::start(
+            metainfo,
+            &config,
+            &Options::default(),
+            &shared,
+            Mode::Download,
+        )
+        .await
+        .expect("the engine starts");
+        handle.add_peers(self.network.addrs()).await;
+
+        let result = match timeout(limit, task).await {
+            Ok(result) => Some(result.expect("the engine doesn't panic")),
+            Err(_) => {
+                handle.shutdown().await;
+                None
+            }
+        };
+        let elapsed = started.elapsed();
+        let downloaded = fs::read(download.join(&self.metainfo.info.name));
+        Outcome {
+            result,
+            elapsed,
+            intact: downloaded.is_ok_and(|data| data == self.content.data),
+        }
+    }
+}
+
+impl Drop for Sim {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
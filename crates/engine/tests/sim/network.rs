@@ -0,0 +1,229 @@
+//! The simulated network: links with latency, loss and bandwidth between
+//! the engine and virtual peers, all on Tokio's clock.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::future::{self, BoxFuture};
+use futures::FutureExt;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, sleep_until, Instant};
+
+use rainyday_engine::peer::transport::{PeerStream, PeerTransport};
+
+use super::peer::{Content, PeerLog, VirtualPeer};
+
+/// How long a lost segment takes to be sent again, Linux's minimum.
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(200);
+/// Most bytes a link carries as one segment, as on Ethernet.
+const MSS: usize = 1460;
+/// Bytes buffered at either end of a link.
+const BUFFER: usize = 64 * 1024;
+
+/// The conditions on the path to one peer, the same in both directions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Link {
+    /// One-way delay of every segment.
+    pub latency: Duration,
+    /// Chance of each segment being lost. TCP hides the loss, so it shows
+    /// as the segment, and all behind it, arriving a retransmission later.
+    pub loss: f64,
+    /// Bytes per second; unlimited without it.
+    pub bandwidth: Option<u64>,
+}
+
+impl Link {
+    pub fn with_latency(self, latency: Duration) -> Self {
+        Self { latency, ..self }
+    }
+
+    pub fn with_loss(self, loss: f64) -> Self {
+        Self { loss, ..self }
+    }
+
+    pub fn with_bandwidth(self, bandwidth: u64) -> Self {
+        Self {
+            bandwidth: Some(bandwidth),
+            ..self
+        }
+    }
+}
+
+struct Host {
+    peer: VirtualPeer,
+    link: Link,
+    log: Arc<PeerLog>,
+}
+
+/// Virtual peers by address, reached through their links.
+///
+/// Every connection draws its losses from a generator seeded by the
+/// network's seed, the peer and how many times it was dialled before, so a
+/// link behaves the same from one run to the next however the engine's
+/// connections interleave.
+pub struct Network {
+    seed: u64,
+    content: Arc<Content>,
+    hosts: Mutex<BTreeMap<SocketAddr, Host>>,
+}
+
+impl Network {
+    pub fn new(seed: u64, content: Arc<Content>) -> Self {
+        Self {
+            seed,
+            content,
+            hosts: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn add(&self, addr: SocketAddr, peer: VirtualPeer, link: Link) -> Arc<PeerLog> {
+        let log = Arc::new(PeerLog::default());
+        let host = Host {
+            peer,
+            link,
+            log: Arc::clone(&log),
+        };
+        self.hosts.lock().unwrap().insert(addr, host);
+        log
+    }
+
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        self.hosts.lock().unwrap().keys().copied().collect()
+    }
+
+    async fn dial(&self, addr: SocketAddr) -> io::Result<PeerStream> {
+        let found = self.hosts.lock().unwrap().get(&addr).map(|host| {
+            let attempt = host.log.connect();
+            let seed = self.seed ^ hash(addr) ^ (attempt as u64).rotate_left(32);
+            (
+                host.peer.clone(),
+                host.link,
+                Arc::clone(&host.log),
+                seed,
+                attempt,
+            )
+        });
+        let (peer, link, log, seed, attempt) = match found {
+            Some(found) => found,
+            None => return Err(io::ErrorKind::ConnectionRefused.into()),
+        };
+        sleep(link.latency * 2).await;
+        if peer.is_offline(attempt) {
+            return Err(io::ErrorKind::ConnectionRefused.into());
+        }
+
+        let (ours, near) = tokio::io::duplex(BUFFER);
+        let (far, theirs) = tokio::io::duplex(BUFFER);
+        let (near_read, near_write) = tokio::io::split(near);
+        let (far_read, far_write) = tokio::io::split(far);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let upstream = StdRng::seed_from_u64(rng.gen());
+        let downstream = StdRng::seed_from_u64(rng.gen());
+        tokio::spawn(carry(near_read, far_write, link, upstream));
+        tokio::spawn(carry(far_read, near_write, link, downstream));
+        tokio::spawn(peer.serve(theirs, Arc::clone(&self.content), log));
+        Ok(Box::new(ours))
+    }
+}
+
+impl fmt::Debug for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Network")
+            .field("seed", &self.seed)
+            .field("hosts", &self.addrs())
+            .finish()
+    }
+}
+
+impl PeerTransport for Network {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<PeerStream>> {
+        self.dial(addr).boxed()
+    }
+}
+
+/// A stable hash of `addr`, unlike the standard library's.
+fn hash(addr: SocketAddr) -> u64 {
+    addr.to_string()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3)
+        })
+}
+
+/// Carries one direction of a connection over `link`, delivering what is
+/// sent in order once its delay is up. Closing either end closes the other.
+///
+/// Losses are drawn for each segment of the stream as TCP would cut it,
+/// however the bytes were written, so that they fall on the same bytes from
+/// one run to the next.
+async fn carry(
+    mut from: ReadHalf<DuplexStream>,
+    mut to: WriteHalf<DuplexStream>,
+    link: Link,
+    mut rng: StdRng,
+) {
+    let (segments, mut arriving) = mpsc::unbounded_channel::<(Instant, Bytes)>();
+
+    let send = async move {
+        let mut buf = vec![0; BUFFER];
+        // Bytes carried so far, and whether the segment they end in is lost.
+        let mut carried = 0;
+        let mut lost = false;
+        // When the link is next free to send, and when the last segment
+        // arrives; none overtakes another.
+        let mut free = Instant::now();
+        let mut last = Instant::now();
+        loop {
+            let len = match from.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(len) => len,
+            };
+            let now = Instant::now();
+            let mut read = &buf[..len];
+            while !read.is_empty() {
+                if carried % MSS == 0 {
+                    lost = rng.gen_bool(link.loss);
+                }
+                let part = read.len().min(MSS - carried % MSS);
+                let mut sent = now;
+                if let Some(bandwidth) = link.bandwidth {
+                    free = free.max(now) + Duration::from_secs_f64(part as f64 / bandwidth as f64);
+                    sent = free;
+                }
+                let mut arrives = sent + link.latency;
+                if lost {
+                    arrives += RETRANSMIT_TIMEOUT;
+                }
+                last = last.max(arrives);
+                if segments
+                    .send((last, Bytes::copy_from_slice(&read[..part])))
+                    .is_err()
+                {
+                    return;
+                }
+                carried += part;
+                read = &read[part..];
+            }
+        }
+    };
+
+    let deliver = async move {
+        while let Some((at, segment)) = arriving.recv().await {
+            sleep_until(at).await;
+            if to.write_all(&segment).await.is_err() {
+                return;
+            }
+        }
+        let _ = to.shutdown().await;
+    };
+
+    future::join(send, deliver).await;
+}
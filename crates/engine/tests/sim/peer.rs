@@ -0,0 +1,216 @@
+//! Virtual peers: other clients reduced to a script of what they have and
+//! how they treat us.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio_util::codec::Framed;
+
+use rainyday_engine::info_hash::InfoHash;
+use rainyday_engine::peer::PeerId;
+use rainyday_engine::protocol::handshake::HANDSHAKE_LEN;
+use rainyday_engine::protocol::{BlockRequest, Handshake, Message, MessageCodec, ProtocolError};
+
+/// The torrent the peers share.
+#[derive(Debug)]
+pub struct Content {
+    pub info_hash: InfoHash,
+    pub piece_length: u32,
+    pub data: Vec<u8>,
+}
+
+impl Content {
+    pub fn num_pieces(&self) -> u32 {
+        self.data.len().div_ceil(self.piece_length as usize) as u32
+    }
+
+    fn block(&self, request: BlockRequest) -> Option<&[u8]> {
+        let start = request.piece as usize * self.piece_length as usize + request.offset as usize;
+        self.data.get(start..start + request.length as usize)
+    }
+}
+
+/// What a peer does, from the moment it is dialled. By default it has
+/// every piece and serves whoever asks.
+#[derive(Clone, Debug, Default)]
+pub struct VirtualPeer {
+    /// The pieces it has, or all of them.
+    pieces: Option<Vec<u32>>,
+    choking: bool,
+    stalling: bool,
+    corrupt: bool,
+    disconnect_after: Option<usize>,
+    offline: bool,
+    /// Connections to refuse before accepting any.
+    refuse: usize,
+}
+
+impl VirtualPeer {
+    pub fn seed() -> Self {
+        Self::default()
+    }
+
+    /// A peer with only `pieces`.
+    pub fn with_pieces(pieces: impl IntoIterator<Item = u32>) -> Self {
+        Self {
+            pieces: Some(pieces.into_iter().collect()),
+            ..Self::default()
+        }
+    }
+
+    /// Never unchokes us.
+    pub fn choking(self) -> Self {
+        Self {
+            choking: true,
+            ..self
+        }
+    }
+
+    /// Unchokes us, then never answers a request.
+    pub fn stalling(self) -> Self {
+        Self {
+            stalling: true,
+            ..self
+        }
+    }
+
+    /// Sends blocks with their first byte flipped.
+    pub fn corrupt(self) -> Self {
+        Self {
+            corrupt: true,
+            ..self
+        }
+    }
+
+    /// Hangs up after sending `blocks` blocks.
+    pub fn disconnecting_after(self, blocks: usize) -> Self {
+        Self {
+            disconnect_after: Some(blocks),
+            ..self
+        }
+    }
+
+    /// Refuses every connection.
+    pub fn offline(self) -> Self {
+        Self {
+            offline: true,
+            ..self
+        }
+    }
+
+    /// Refuses the first `connections` connections.
+    pub fn refusing(self, connections: usize) -> Self {
+        Self {
+            refuse: connections,
+            ..self
+        }
+    }
+
+    /// Whether it refuses the connection made after `attempt` others.
+    pub fn is_offline(&self, attempt: usize) -> bool {
+        self.offline || attempt < self.refuse
+    }
+
+    fn has(&self, piece: u32) -> bool {
+        self.pieces
+            .as_ref()
+            .is_none_or(|pieces| pieces.contains(&piece))
+    }
+
+    fn bitfield(&self, num_pieces: u32) -> Vec<u8> {
+        let mut bits = vec![0u8; num_pieces.div_ceil(8) as usize];
+        for piece in (0..num_pieces).filter(|&piece| self.has(piece)) {
+            bits[piece as usize / 8] |= 0x80 >> (piece % 8);
+        }
+        bits
+    }
+
+    /// Plays the peer's side of a connection until either side hangs up.
+    pub async fn serve(
+        self,
+        mut stream: DuplexStream,
+        content: Arc<Content>,
+        log: Arc<PeerLog>,
+    ) -> Result<(), ProtocolError> {
+        let mut buf = [0u8; HANDSHAKE_LEN];
+        stream.read_exact(&mut buf).await?;
+        if Handshake::from_bytes(&buf)?.info_hash != content.info_hash {
+            return Ok(());
+        }
+        let ours = Handshake::new(content.info_hash, PeerId(*b"-SM0001-virtualpeer!"));
+        stream.write_all(&ours.to_bytes()).await?;
+
+        let mut framed = Framed::new(stream, MessageCodec::default());
+        let bitfield = self.bitfield(content.num_pieces());
+        framed.send(Message::Bitfield(bitfield)).await?;
+        let mut choked = true;
+        let mut sent = 0;
+        while let Some(message) = framed.next().await {
+            match message? {
+                Message::Interested if !self.choking && choked => {
+                    choked = false;
+                    framed.send(Message::Unchoke).await?;
+                }
+                Message::Request(request) => {
+                    log.requests.fetch_add(1, Ordering::Relaxed);
+                    if choked || self.stalling || !self.has(request.piece) {
+                        continue;
+                    }
+                    let mut block = match content.block(request) {
+                        Some(block) => block.to_vec(),
+                        None => return Ok(()),
+                    };
+                    if self.corrupt {
+                        block[0] ^= 0xff;
+                    }
+                    let piece = Message::Piece {
+                        piece: request.piece,
+                        offset: request.offset,
+                        data: Bytes::from(block),
+                    };
+                    framed.send(piece).await?;
+                    log.blocks.fetch_add(1, Ordering::Relaxed);
+                    sent += 1;
+                    if Some(sent) == self.disconnect_after {
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What happened to one virtual peer.
+#[derive(Debug, Default)]
+pub struct PeerLog {
+    connections: AtomicUsize,
+    requests: AtomicUsize,
+    blocks: AtomicUsize,
+}
+
+impl PeerLog {
+    /// Counts a connection attempt, returning how many came before it.
+    pub fn connect(&self) -> usize {
+        self.connections.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Times the engine dialled the peer.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    /// Blocks the engine asked the peer for.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Blocks the peer sent.
+    pub fn blocks(&self) -> usize {
+        self.blocks.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,131 @@
+//! Whole downloads against simulated swarms.
+
+mod sim;
+
+use std::time::Duration;
+
+use rainyday_engine::swarm::RETRY_AFTER;
+
+use sim::{Link, Sim, VirtualPeer};
+
+const SEED: u64 = 0x7261_696e_7964_6179;
+const PIECE_LENGTH: u32 = 64 * 1024;
+/// Forty pieces and a short one.
+const SIZE: usize = 40 * PIECE_LENGTH as usize + 5000;
+const LIMIT: Duration = Duration::from_secs(3600);
+
+fn lan() -> Link {
+    Link::default().with_latency(Duration::from_millis(1))
+}
+
+fn internet() -> Link {
+    Link::default()
+        .with_latency(Duration::from_millis(60))
+        .with_loss(0.02)
+        .with_bandwidth(2 * 1024 * 1024)
+}
+
+#[tokio::test(start_paused = true)]
+async fn downloads_from_a_seed() {
+    let sim = Sim::new("seed", SEED, SIZE, PIECE_LENGTH);
+    let seed = sim.add_peer(VirtualPeer::seed(), lan());
+
+    let outcome = sim.run(LIMIT).await;
+    assert!(outcome.is_complete(), "{:?}", outcome);
+    assert_eq!(seed.connections(), 1);
+    assert_eq!(seed.blocks(), SIZE.div_ceil(16 * 1024));
+}
+
+#[tokio::test(start_paused = true)]
+async fn assembles_the_torrent_from_peers_with_parts_of_it() {
+    let sim = Sim::new("parts", SEED, SIZE, PIECE_LENGTH);
+    let pieces = sim.num_pieces();
+    let peers: Vec<_> = (0..3)
+        .map(|part| {
+            let pieces = (0..pieces).filter(move |piece| piece % 3 != part);
+            sim.add_peer(VirtualPeer::with_pieces(pieces), internet())
+        })
+        .collect();
+
+    let outcome = sim.run(LIMIT).await;
+    assert!(outcome.is_complete(), "{:?}", outcome);
+    for peer in &peers {
+        assert!(peer.blocks() > 0);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn bans_a_peer_that_sends_corrupt_data() {
+    let sim = Sim::new("corrupt", SEED, SIZE, PIECE_LENGTH);
+    let corrupt = sim.add_peer(VirtualPeer::seed().corrupt(), lan());
+    let honest = sim.add_peer(VirtualPeer::seed(), internet());
+
+    let outcome = sim.run(LIMIT).await;
+    assert!(outcome.is_complete(), "{:?}", outcome);
+    assert!(corrupt.blocks() > 0);
+    assert_eq!(
+        corrupt.connections(),
+        1,
+        "a banned peer is not dialled again"
+    );
+    assert!(honest.blocks() > 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn gets_past_peers_that_choke_stall_hang_up_or_are_gone() {
+    let sim = Sim::new("unhelpful", SEED, SIZE, PIECE_LENGTH);
+    let choking = sim.add_peer(VirtualPeer::seed().choking(), lan());
+    let stalling = sim.add_peer(VirtualPeer::seed().stalling(), lan());
+    let hanging_up = sim.add_peer(VirtualPeer::seed().disconnecting_after(5), lan());
+    let offline = sim.add_peer(VirtualPeer::seed().offline(), lan());
+    let honest = sim.add_peer(VirtualPeer::seed(), internet());
+
+    let outcome = sim.run(LIMIT).await;
+    assert!(outcome.is_complete(), "{:?}", outcome);
+    assert_eq!(choking.requests(), 0);
+    assert!(stalling.requests() > 0);
+    assert_eq!(stalling.blocks(), 0);
+    assert!(hanging_up.blocks() >= 5);
+    assert!(offline.connections() >= 1);
+    assert!(honest.blocks() > 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn dials_a_peer_again_after_it_refused() {
+    let sim = Sim::new("refusing", SEED, SIZE, PIECE_LENGTH);
+    let seed = sim.add_peer(VirtualPeer::seed().refusing(2), lan());
+
+    let outcome = sim.run(LIMIT).await;
+    assert!(outcome.is_complete(), "{:?}", outcome);
+    assert_eq!(seed.connections(), 3);
+    assert!(
+        outcome.elapsed >= RETRY_AFTER * 3,
+        "waits longer after each refusal"
+    );
+}
+
+/// Each run has a runtime of its own, so that nothing left over from one
+/// can affect the other.
+#[test]
+fn runs_the_same_way_every_time() {
+    let runs: Vec<_> = (0..2)
+        .map(|run| {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .start_paused(true)
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                let sim = Sim::new(&format!("repeat{}", run), SEED, SIZE, PIECE_LENGTH);
+                let peers: Vec<_> = (0..4)
+                    .map(|_| sim.add_peer(VirtualPeer::seed(), internet()))
+                    .collect();
+                let outcome = sim.run(LIMIT).await;
+                assert!(outcome.is_complete(), "{:?}", outcome);
+                let blocks: Vec<usize> = peers.iter().map(|peer| peer.blocks()).collect();
+                (outcome.elapsed, blocks)
+            })
+        })
+        .collect();
+    assert_eq!(runs[0], runs[1]);
+}
@@ -0,0 +1,126 @@
+//! Choosing whom to dial and whom to keep.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+
+use rainyday_engine::peer::canonical_priority;
+use rainyday_engine::swarm::{ExternalIp, Swarm, RETRY_AFTER};
+
+fn addr(s: &str) -> SocketAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn computes_the_priorities_of_bep_40() {
+    let priority = canonical_priority(addr("123.213.32.10:6881"), addr("98.76.54.32:6881"));
+    assert_eq!(priority, 0xec2d_7224);
+    let priority = canonical_priority(addr("123.213.32.10:6881"), addr("123.213.32.234:6881"));
+    assert_eq!(priority, 0x9956_8189);
+}
+
+#[test]
+fn dials_in_order_of_priority_once_our_address_is_known() {
+    let peers = [
+        "98.76.54.32:6881",
+        "123.213.32.234:6881",
+        "10.0.0.7:51413", // 0x82c131f8
+        "203.0.113.9:6881",
+        "123.213.40.1:6881",
+    ];
+    let mut swarm = Swarm::new(10);
+    for peer in peers {
+        swarm.add_peer(addr(peer));
+    }
+    let learned: Vec<SocketAddr> = peers.iter().map(|peer| addr(peer)).collect();
+    assert_eq!(swarm.dial_candidates(), learned);
+
+    swarm.set_local_addr(addr("123.213.32.10:6881"));
+    let expected: Vec<SocketAddr> = [
+        "98.76.54.32:6881",    // 0xec2d7224
+        "123.213.40.1:6881",   // 0xcbbe95d9
+        "123.213.32.234:6881", // 0x99568189
+        "10.0.0.7:51413",      // 0x82c131f8
+        "203.0.113.9:6881",    // 0x143bdce1
+    ]
+    .iter()
+    .map(|peer| addr(peer))
+    .collect();
+    assert_eq!(swarm.dial_candidates(), expected);
+}
+
+#[test]
+fn makes_room_only_for_a_peer_of_higher_priority() {
+    let mut swarm = Swarm::new(2);
+    // Of priority 0xec2d7224 and 0x99568189 against the address below.
+    swarm.mark_connected(addr("98.76.54.32:6881"));
+    swarm.mark_connected(addr("123.213.32.234:6881"));
+    let higher = addr("123.213.40.1:6881"); // 0xcbbe95d9
+    let lower = addr("198.51.100.20:6881"); // 0x7892b9f8
+    assert_eq!(swarm.outranked_by(higher), None, "nothing to rank against");
+
+    swarm.set_local_addr(addr("123.213.32.10:6881"));
+    assert_eq!(
+        swarm.outranked_by(higher),
+        Some(addr("123.213.32.234:6881"))
+    );
+    assert_eq!(swarm.outranked_by(lower), None);
+}
+
+#[test]
+fn prefers_the_router_over_trackers_and_peers() {
+    let external = ExternalIp::default();
+    external.suggest(IpAddr::from([0, 0, 0, 0]));
+    assert_eq!(external.get(), None);
+    external.suggest(IpAddr::from([198, 51, 100, 1]));
+    external.suggest(IpAddr::from([198, 51, 100, 2]));
+    assert_eq!(external.get(), Some(IpAddr::from([198, 51, 100, 1])));
+    external.set(IpAddr::from([203, 0, 113, 5]));
+    external.suggest(IpAddr::from([198, 51, 100, 1]));
+    assert_eq!(external.get(), Some(IpAddr::from([203, 0, 113, 5])));
+}
+
+#[test]
+fn dials_an_unreachable_peer_again_later_and_later() {
+    let peer = addr("198.51.100.20:6881");
+    let mut swarm = Swarm::new(10);
+    swarm.add_peer(peer);
+    let start = Instant::now();
+
+    swarm.mark_unreachable(peer, start);
+    swarm.retry_unreachable(start + RETRY_AFTER / 2);
+    assert!(swarm.dial_candidates().is_empty());
+    swarm.retry_unreachable(start + RETRY_AFTER);
+    assert_eq!(swarm.dial_candidates(), [peer]);
+
+    let again = start + RETRY_AFTER;
+    swarm.mark_unreachable(peer, again);
+    swarm.retry_unreachable(again + RETRY_AFTER);
+    assert!(swarm.dial_candidates().is_empty(), "waits twice as long");
+    swarm.retry_unreachable(again + RETRY_AFTER * 2);
+    assert_eq!(swarm.dial_candidates(), [peer]);
+
+    swarm.mark_unreachable(peer, again);
+    swarm.mark_connected(peer);
+    swarm.mark_disconnected(&peer);
+    assert_eq!(swarm.dial_candidates(), [peer], "forgiven once reached");
+}
+
+#[test]
+fn asks_the_next_relay_after_each_failure() {
+    let peer = addr("198.51.100.20:6881");
+    let relays = [addr("203.0.113.1:6881"), addr("203.0.113.2:6881")];
+    let mut swarm = Swarm::new(10);
+    swarm.set_relay(relays[0]);
+    assert!(!swarm.is_relay(&relays[0]), "only connected peers relay");
+    for relay in relays {
+        swarm.mark_connected(relay);
+        swarm.set_relay(relay);
+    }
+    let now = Instant::now();
+
+    swarm.mark_unreachable(peer, now);
+    assert_eq!(swarm.take_holepunch_targets(), [(relays[0], peer)]);
+    assert!(swarm.take_holepunch_targets().is_empty(), "asks once");
+    swarm.mark_unreachable(peer, now);
+    assert_eq!(swarm.take_holepunch_targets(), [(relays[1], peer)]);
+}
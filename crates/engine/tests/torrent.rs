@@ -0,0 +1,108 @@
+//! Assembling received blocks into pieces.
+
+use std::fs;
+use std::net::IpAddr;
+use std::process;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use rainyday_engine::create::{create, CreateOptions};
+use rainyday_engine::event::EventBus;
+use rainyday_engine::files::FilePriority;
+use rainyday_engine::metainfo::Metainfo;
+use rainyday_engine::peer::state::PeerState;
+use rainyday_engine::picker::BLOCK_SIZE;
+use rainyday_engine::protocol::Message;
+use rainyday_engine::torrent::Torrent;
+
+const PIECE_LENGTH: u32 = 2 * BLOCK_SIZE;
+
+/// A torrent of three pieces, none of them downloaded.
+fn torrent(test: &str) -> Torrent {
+    let path = std::env::temp_dir().join(format!("rainyday-torrent-{}-{}", test, process::id()));
+    fs::write(&path, vec![7; 3 * PIECE_LENGTH as usize]).unwrap();
+    let options = CreateOptions {
+        piece_length: Some(PIECE_LENGTH),
+        ..CreateOptions::default()
+    };
+    let bytes = create(&path, &options).unwrap();
+    fs::remove_file(&path).unwrap();
+    let metainfo = Arc::new(Metainfo::from_bytes(&bytes).unwrap());
+    Torrent::new(metainfo, Arc::new(EventBus::default()))
+}
+
+fn block() -> Bytes {
+    Bytes::from(vec![7; BLOCK_SIZE as usize])
+}
+
+fn peer() -> IpAddr {
+    IpAddr::from([198, 51, 100, 20])
+}
+
+#[test]
+fn ignores_blocks_of_pieces_not_in_progress() {
+    let mut torrent = torrent("unstarted");
+    assert_eq!(torrent.block_received(peer(), 1, 0, block()), None);
+    assert_eq!(torrent.block_received(peer(), 1, BLOCK_SIZE, block()), None);
+    assert_eq!(torrent.buffered(), 0);
+}
+
+#[test]
+fn frees_the_blocks_of_an_abandoned_piece() {
+    let mut torrent = torrent("abandoned");
+    let mut state = PeerState::new(3, false, false);
+    state.has.set(1);
+    state.peer_choking = false;
+    let request = torrent.picker.pick(&state).unwrap();
+    assert_eq!(request.piece, 1);
+
+    assert_eq!(torrent.block_received(peer(), 1, 0, block()), None);
+    assert_eq!(torrent.buffered(), 1);
+    torrent.abandon_piece(1);
+    assert_eq!(torrent.buffered(), 0);
+    assert!(!torrent.picker.is_in_progress(1));
+}
+
+#[test]
+fn frees_the_blocks_of_pieces_of_a_skipped_file() {
+    let mut torrent = torrent("skipped");
+    let mut state = PeerState::new(3, false, false);
+    state.has.set(1);
+    state.peer_choking = false;
+    torrent.picker.pick(&state).unwrap();
+    assert_eq!(torrent.block_received(peer(), 1, 0, block()), None);
+    assert_eq!(torrent.buffered(), 1);
+
+    torrent.set_file_priorities(&[FilePriority::Skip]);
+    assert_eq!(torrent.buffered(), 0);
+    torrent.set_file_priorities(&[FilePriority::Normal]);
+    torrent.picker.pick(&state).unwrap();
+    assert_eq!(torrent.block_received(peer(), 1, 0, block()), None);
+    assert_eq!(torrent.buffered(), 1);
+}
+
+#[test]
+fn counts_each_piece_a_peer_has_once() {
+    let mut torrent = torrent("availability");
+    let mut state = PeerState::new(3, true, false);
+    let availability = |torrent: &Torrent| -> Vec<u32> {
+        (0..3)
+            .map(|piece| torrent.picker.availability(piece))
+            .collect()
+    };
+
+    for message in [Message::Have(0), Message::Have(0), Message::Have(2)] {
+        torrent.peer_message(&mut state, &message).unwrap();
+    }
+    assert_eq!(availability(&torrent), [1, 0, 1]);
+    torrent
+        .peer_message(&mut state, &Message::Bitfield(vec![0b1100_0000]))
+        .unwrap();
+    assert_eq!(availability(&torrent), [1, 1, 0]);
+    torrent.peer_message(&mut state, &Message::HaveAll).unwrap();
+    assert_eq!(availability(&torrent), [1, 1, 1]);
+
+    torrent.picker.peer_lost(&state.has);
+    assert_eq!(availability(&torrent), [0, 0, 0]);
+}
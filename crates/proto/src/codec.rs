@@ -0,0 +1,79 @@
+use core::convert::TryInto;
+
+use bytes::{Buf, BytesMut};
+#[cfg(feature = "tokio")]
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::debug;
+
+use super::{Message, ProtocolError, MAX_FRAME_LEN};
+
+/// Frames peer wire messages on a byte stream after the handshake.
+///
+/// By default messages with unknown IDs are skipped; a pedantic codec
+/// treats them as an error. With the `tokio` feature it is also a
+/// `tokio_util` codec, for framing a socket.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessageCodec {
+    pedantic: bool,
+}
+
+impl MessageCodec {
+    pub fn new(pedantic: bool) -> Self {
+        Self { pedantic }
+    }
+
+    /// Takes the next whole message off the front of `src`, if it has
+    /// arrived.
+    pub fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, ProtocolError> {
+        loop {
+            if src.len() < 4 {
+                return Ok(None);
+            }
+
+            let len = u32::from_be_bytes(src[..4].try_into().unwrap()) as usize;
+            if len > MAX_FRAME_LEN {
+                return Err(ProtocolError::FrameTooLarge(len));
+            }
+
+            if src.len() < 4 + len {
+                src.reserve(4 + len - src.len());
+                return Ok(None);
+            }
+
+            src.advance(4);
+            let frame = src.split_to(len).freeze();
+            match Message::decode(frame, self.pedantic) {
+                Err(ProtocolError::UnknownMessage(id)) if !self.pedantic => {
+                    debug!(id, len, "skipping unknown message");
+                }
+                result => return result.map(Some),
+            }
+        }
+    }
+
+    /// Appends `item`, framed, to `dst`.
+    pub fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), ProtocolError> {
+        dst.reserve(4 + item.encoded_len());
+        item.encode(dst);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = ProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, ProtocolError> {
+        MessageCodec::decode(self, src)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Encoder<Message> for MessageCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), ProtocolError> {
+        MessageCodec::encode(self, item, dst)
+    }
+}
@@ -0,0 +1,129 @@
+//! The extension protocol (BEP 10).
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use rainyday_bencode::{self as bencode, Value};
+
+use super::ProtocolError;
+
+/// Extended message ID reserved for the extension handshake itself.
+pub const HANDSHAKE_ID: u8 = 0;
+
+/// The dictionary exchanged in the extension handshake.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExtendedHandshake {
+    /// Extension names mapped to the message IDs the sender wants for them.
+    pub extensions: BTreeMap<String, u8>,
+    /// Client name and version, e.g. `rainyday 0.0.1`.
+    pub version: Option<String>,
+    /// The sender's listen port.
+    pub port: Option<u16>,
+    /// Our address as seen by the sender.
+    pub your_ip: Option<IpAddr>,
+    /// Number of outstanding requests the sender will queue.
+    pub request_queue: Option<u32>,
+    /// Size of the info dictionary, for metadata exchange.
+    pub metadata_size: Option<u64>,
+}
+
+impl ExtendedHandshake {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut dict = BTreeMap::new();
+
+        let m = self
+            .extensions
+            .iter()
+            .map(|(name, id)| (name.as_bytes().to_vec(), Value::Integer(*id as i64)))
+            .collect();
+        dict.insert(b"m".to_vec(), Value::Dict(m));
+
+        if let Some(version) = &self.version {
+            dict.insert(b"v".to_vec(), Value::from(version.as_str()));
+        }
+        if let Some(port) = self.port {
+            dict.insert(b"p".to_vec(), Value::Integer(port as i64));
+        }
+        if let Some(ip) = self.your_ip {
+            let bytes = match ip {
+                IpAddr::V4(ip) => ip.octets().to_vec(),
+                IpAddr::V6(ip) => ip.octets().to_vec(),
+            };
+            dict.insert(b"yourip".to_vec(), Value::Bytes(bytes));
+        }
+        if let Some(reqq) = self.request_queue {
+            dict.insert(b"reqq".to_vec(), Value::Integer(reqq as i64));
+        }
+        if let Some(size) = self.metadata_size {
+            dict.insert(b"metadata_size".to_vec(), Value::Integer(size as i64));
+        }
+
+        Value::Dict(dict).encode()
+    }
+
+    /// Parses a handshake payload. Unknown keys and values of the wrong type
+    /// are ignored, as the extension protocol asks.
+    pub fn from_bytes(payload: &[u8]) -> Result<Self, ProtocolError> {
+        let value = bencode::decode(payload)?;
+        if value.as_dict().is_none() {
+            return Err(ProtocolError::InvalidExtendedHandshake);
+        }
+
+        let extensions = value
+            .get("m")
+            .and_then(Value::as_dict)
+            .map(|m| {
+                m.iter()
+                    .filter_map(|(name, id)| {
+                        let name = String::from_utf8(name.clone()).ok()?;
+                        let id = u8::try_from(id.as_int()?).ok()?;
+                        Some((name, id))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let int = |key| value.get(key).and_then(Value::as_int);
+
+        Ok(Self {
+            extensions,
+            version: value
+                .get("v")
+                .and_then(Value::as_bytes)
+                .map(|v| String::from_utf8_lossy(v).into_owned()),
+            port: int("p").and_then(|p| u16::try_from(p).ok()),
+            your_ip: value
+                .get("yourip")
+                .and_then(Value::as_bytes)
+                .and_then(parse_ip),
+            request_queue: int("reqq").and_then(|r| u32::try_from(r).ok()),
+            metadata_size: int("metadata_size").and_then(|s| u64::try_from(s).ok()),
+        })
+    }
+
+    /// The message ID the sender wants for `extension`, if it supports it.
+    /// An ID of zero means the extension was disabled.
+    pub fn id_of(&self, extension: &str) -> Option<u8> {
+        self.extensions
+            .get(extension)
+            .copied()
+            .filter(|&id| id != 0)
+    }
+}
+
+fn parse_ip(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(IpAddr::V4(Ipv4Addr::new(
+            bytes[0], bytes[1], bytes[2], bytes[3],
+        ))),
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(bytes);
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
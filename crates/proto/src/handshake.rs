@@ -0,0 +1,82 @@
+use super::ProtocolError;
+use crate::info_hash::InfoHash;
+use crate::peer_id::PeerId;
+
+pub const PROTOCOL: &[u8; 19] = b"BitTorrent protocol";
+
+/// Length of a handshake on the wire.
+pub const HANDSHAKE_LEN: usize = 1 + PROTOCOL.len() + 8 + 20 + 20;
+
+/// Reserved bit advertising the fast extension (BEP 6).
+const FAST_BIT: (usize, u8) = (7, 0x04);
+
+/// Reserved bit advertising the extension protocol (BEP 10).
+const EXTENSION_BIT: (usize, u8) = (5, 0x10);
+
+/// Reserved bits given a meaning by BEP 4; peers setting any other are off
+/// spec, though harmlessly so.
+const ASSIGNED_BITS: [u8; 8] = [0x80, 0, 0x08, 0, 0, 0x13, 0, 0x1f];
+
+/// The fixed-size greeting exchanged before any other message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Handshake {
+    pub reserved: [u8; 8],
+    pub info_hash: InfoHash,
+    pub peer_id: PeerId,
+}
+
+impl Handshake {
+    pub fn new(info_hash: InfoHash, peer_id: PeerId) -> Self {
+        Self {
+            reserved: [0; 8],
+            info_hash,
+            peer_id,
+        }
+    }
+
+    pub fn supports_fast(&self) -> bool {
+        self.reserved[FAST_BIT.0] & FAST_BIT.1 != 0
+    }
+
+    pub fn set_fast(&mut self) {
+        self.reserved[FAST_BIT.0] |= FAST_BIT.1;
+    }
+
+    pub fn supports_extensions(&self) -> bool {
+        self.reserved[EXTENSION_BIT.0] & EXTENSION_BIT.1 != 0
+    }
+
+    pub fn set_extensions(&mut self) {
+        self.reserved[EXTENSION_BIT.0] |= EXTENSION_BIT.1;
+    }
+
+    /// Whether every reserved bit the peer set has an assigned meaning.
+    pub fn reserved_bits_assigned(&self) -> bool {
+        self.reserved
+            .iter()
+            .zip(ASSIGNED_BITS)
+            .all(|(bits, assigned)| bits & !assigned == 0)
+    }
+
+    pub fn to_bytes(&self) -> [u8; HANDSHAKE_LEN] {
+        let mut buf = [0u8; HANDSHAKE_LEN];
+        buf[0] = PROTOCOL.len() as u8;
+        buf[1..20].copy_from_slice(PROTOCOL);
+        buf[20..28].copy_from_slice(&self.reserved);
+        buf[28..48].copy_from_slice(self.info_hash.as_bytes());
+        buf[48..68].copy_from_slice(self.peer_id.as_bytes());
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8; HANDSHAKE_LEN]) -> Result<Self, ProtocolError> {
+        if buf[0] as usize != PROTOCOL.len() || &buf[1..20] != PROTOCOL {
+            return Err(ProtocolError::InvalidProtocol);
+        }
+
+        let mut handshake = Self::new(InfoHash::default(), PeerId([0; 20]));
+        handshake.reserved.copy_from_slice(&buf[20..28]);
+        handshake.info_hash.0.copy_from_slice(&buf[28..48]);
+        handshake.peer_id.0.copy_from_slice(&buf[48..68]);
+        Ok(handshake)
+    }
+}
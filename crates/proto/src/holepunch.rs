@@ -0,0 +1,128 @@
+//! Messages of the holepunch extension (BEP 55).
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use super::ProtocolError;
+
+/// Name under which the extension is advertised in the extension handshake.
+pub const EXTENSION_NAME: &str = "ut_holepunch";
+
+/// The extended message ID we ask peers to send holepunch messages with.
+pub const LOCAL_ID: u8 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HolepunchError {
+    /// The target is not a valid peer.
+    NoSuchPeer,
+    /// The relay is not connected to the target.
+    NotConnected,
+    /// The target does not support holepunching.
+    NoSupport,
+    /// The target is the initiator itself.
+    NoSelf,
+    Unknown(u32),
+}
+
+impl HolepunchError {
+    fn code(self) -> u32 {
+        match self {
+            HolepunchError::NoSuchPeer => 1,
+            HolepunchError::NotConnected => 2,
+            HolepunchError::NoSupport => 3,
+            HolepunchError::NoSelf => 4,
+            HolepunchError::Unknown(code) => code,
+        }
+    }
+
+    fn from_code(code: u32) -> Self {
+        match code {
+            1 => HolepunchError::NoSuchPeer,
+            2 => HolepunchError::NotConnected,
+            3 => HolepunchError::NoSupport,
+            4 => HolepunchError::NoSelf,
+            code => HolepunchError::Unknown(code),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HolepunchMessage {
+    /// Sent to a relay, asking it to introduce us to `addr`.
+    Rendezvous(SocketAddr),
+    /// Sent by a relay, telling us to connect to `addr` right away.
+    Connect(SocketAddr),
+    /// Sent by a relay when a rendezvous for `addr` cannot be arranged.
+    Error(SocketAddr, HolepunchError),
+}
+
+impl HolepunchMessage {
+    pub fn addr(&self) -> SocketAddr {
+        match self {
+            HolepunchMessage::Rendezvous(addr)
+            | HolepunchMessage::Connect(addr)
+            | HolepunchMessage::Error(addr, _) => *addr,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (kind, code) = match self {
+            HolepunchMessage::Rendezvous(_) => (0, 0),
+            HolepunchMessage::Connect(_) => (1, 0),
+            HolepunchMessage::Error(_, error) => (2, error.code()),
+        };
+
+        let addr = self.addr();
+        let mut buf = vec![kind];
+        match addr.ip() {
+            IpAddr::V4(ip) => {
+                buf.push(0);
+                buf.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                buf.push(1);
+                buf.extend_from_slice(&ip.octets());
+            }
+        }
+        buf.extend_from_slice(&addr.port().to_be_bytes());
+        buf.extend_from_slice(&code.to_be_bytes());
+        buf
+    }
+
+    pub fn from_bytes(payload: &[u8]) -> Result<Self, ProtocolError> {
+        let invalid = || ProtocolError::InvalidHolepunch;
+
+        let (&kind, rest) = payload.split_first().ok_or_else(invalid)?;
+        let (&addr_type, rest) = rest.split_first().ok_or_else(invalid)?;
+        let (ip, rest) = match addr_type {
+            0 if rest.len() >= 4 => {
+                let octets: [u8; 4] = rest[..4].try_into().unwrap();
+                (IpAddr::V4(Ipv4Addr::from(octets)), &rest[4..])
+            }
+            1 if rest.len() >= 16 => {
+                let octets: [u8; 16] = rest[..16].try_into().unwrap();
+                (IpAddr::V6(Ipv6Addr::from(octets)), &rest[16..])
+            }
+            _ => return Err(invalid()),
+        };
+        if rest.len() != 6 {
+            return Err(invalid());
+        }
+
+        let port = u16::from_be_bytes([rest[0], rest[1]]);
+        let code = u32::from_be_bytes(rest[2..6].try_into().unwrap());
+        let addr = SocketAddr::new(ip, port);
+
+        match kind {
+            0 => Ok(HolepunchMessage::Rendezvous(addr)),
+            1 => Ok(HolepunchMessage::Connect(addr)),
+            2 => Ok(HolepunchMessage::Error(
+                addr,
+                HolepunchError::from_code(code),
+            )),
+            _ => Err(invalid()),
+        }
+    }
+}
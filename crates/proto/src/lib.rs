@@ -0,0 +1,52 @@
+//! The BitTorrent peer wire protocol (BEP 3).
+//!
+//! Only `alloc` is needed without the default `std` feature, which brings
+//! I/O errors and [`PeerId::generate`](peer_id::PeerId::generate). The
+//! `tokio` feature makes [`MessageCodec`] a Tokio codec as well.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod codec;
+pub mod extension;
+pub mod handshake;
+pub mod holepunch;
+pub mod info_hash;
+pub mod message;
+pub mod metadata;
+pub mod peer_id;
+
+pub use codec::MessageCodec;
+pub use extension::ExtendedHandshake;
+pub use handshake::Handshake;
+pub use message::{BlockRequest, Message};
+
+use thiserror::Error;
+
+/// Largest frame we are willing to buffer. Comfortably above a 16 KiB block
+/// plus header, and large enough for bitfields of very big torrents.
+pub const MAX_FRAME_LEN: usize = 1 << 21;
+
+#[derive(Debug, Error)]
+pub enum ProtocolError {
+    #[error("unknown message id {0}")]
+    UnknownMessage(u8),
+    #[error("message id {id} has invalid length {len}")]
+    InvalidLength { id: u8, len: usize },
+    #[error("frame of {0} bytes exceeds the maximum frame length")]
+    FrameTooLarge(usize),
+    #[error("invalid protocol string in handshake")]
+    InvalidProtocol,
+    #[error("malformed bencoded payload: {0}")]
+    Bencode(#[from] rainyday_bencode::BencodeError),
+    #[error("extension handshake is not a dictionary")]
+    InvalidExtendedHandshake,
+    #[error("malformed holepunch message")]
+    InvalidHolepunch,
+    #[error("malformed metadata message")]
+    InvalidMetadataMessage,
+    #[cfg(feature = "std")]
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
@@ -0,0 +1,186 @@
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use bytes::{BufMut, Bytes};
+
+use super::ProtocolError;
+
+/// Identifies a block within a piece.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BlockRequest {
+    pub piece: u32,
+    pub offset: u32,
+    pub length: u32,
+}
+
+/// A single peer wire message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    KeepAlive,
+    Choke,
+    Unchoke,
+    Interested,
+    NotInterested,
+    Have(u32),
+    Bitfield(Vec<u8>),
+    Request(BlockRequest),
+    Piece {
+        piece: u32,
+        offset: u32,
+        /// Shares the frame it was received in, or the buffer it was read
+        /// into from disk.
+        data: Bytes,
+    },
+    Cancel(BlockRequest),
+    Port(u16),
+    Suggest(u32),
+    HaveAll,
+    HaveNone,
+    Reject(BlockRequest),
+    AllowedFast(u32),
+    Extended {
+        id: u8,
+        payload: Vec<u8>,
+    },
+}
+
+impl Message {
+    pub fn id(&self) -> Option<u8> {
+        match self {
+            Message::KeepAlive => None,
+            Message::Choke => Some(0),
+            Message::Unchoke => Some(1),
+            Message::Interested => Some(2),
+            Message::NotInterested => Some(3),
+            Message::Have(_) => Some(4),
+            Message::Bitfield(_) => Some(5),
+            Message::Request(_) => Some(6),
+            Message::Piece { .. } => Some(7),
+            Message::Cancel(_) => Some(8),
+            Message::Port(_) => Some(9),
+            Message::Suggest(_) => Some(13),
+            Message::HaveAll => Some(14),
+            Message::HaveNone => Some(15),
+            Message::Reject(_) => Some(16),
+            Message::AllowedFast(_) => Some(17),
+            Message::Extended { .. } => Some(20),
+        }
+    }
+
+    /// Length of the wire form, not counting the length prefix.
+    pub fn encoded_len(&self) -> usize {
+        let payload = match self {
+            Message::Have(_) | Message::Suggest(_) | Message::AllowedFast(_) => 4,
+            Message::Bitfield(bits) => bits.len(),
+            Message::Request(_) | Message::Cancel(_) | Message::Reject(_) => 12,
+            Message::Piece { data, .. } => 8 + data.len(),
+            Message::Port(_) => 2,
+            Message::Extended { payload, .. } => 1 + payload.len(),
+            _ => 0,
+        };
+        usize::from(self.id().is_some()) + payload
+    }
+
+    /// Appends the length-prefixed wire form of this message to `buf`.
+    pub fn encode(&self, buf: &mut impl BufMut) {
+        buf.put_u32(self.encoded_len() as u32);
+
+        if let Some(id) = self.id() {
+            buf.put_u8(id);
+        }
+
+        match self {
+            Message::Have(piece) | Message::Suggest(piece) | Message::AllowedFast(piece) => {
+                buf.put_u32(*piece)
+            }
+            Message::Bitfield(bits) => buf.put_slice(bits),
+            Message::Request(block) | Message::Cancel(block) | Message::Reject(block) => {
+                encode_block(block, buf)
+            }
+            Message::Piece {
+                piece,
+                offset,
+                data,
+            } => {
+                buf.put_u32(*piece);
+                buf.put_u32(*offset);
+                buf.put_slice(data);
+            }
+            Message::Port(port) => buf.put_u16(*port),
+            Message::Extended { id, payload } => {
+                buf.put_u8(*id);
+                buf.put_slice(payload);
+            }
+            _ => {}
+        }
+    }
+
+    /// Decodes a message from a frame, excluding its length prefix. A
+    /// piece's data is a slice of the frame, not a copy.
+    ///
+    /// Fixed-size messages with trailing bytes are accepted, ignoring the
+    /// excess, unless `pedantic` is set.
+    pub fn decode(frame: Bytes, pedantic: bool) -> Result<Self, ProtocolError> {
+        let (id, payload) = match frame.split_first() {
+            Some((id, payload)) => (*id, payload),
+            None => return Ok(Message::KeepAlive),
+        };
+        let invalid = || ProtocolError::InvalidLength {
+            id,
+            len: payload.len(),
+        };
+        let wrong_size = |size: usize| payload.len() < size || (pedantic && payload.len() > size);
+
+        match id {
+            0..=3 | 14 | 15 if wrong_size(0) => Err(invalid()),
+            0 => Ok(Message::Choke),
+            1 => Ok(Message::Unchoke),
+            2 => Ok(Message::Interested),
+            3 => Ok(Message::NotInterested),
+            4 | 13 | 17 if wrong_size(4) => Err(invalid()),
+            4 => Ok(Message::Have(read_u32(payload, 0))),
+            5 => Ok(Message::Bitfield(payload.to_vec())),
+            6 | 8 | 16 if wrong_size(12) => Err(invalid()),
+            6 => Ok(Message::Request(decode_block(payload))),
+            8 => Ok(Message::Cancel(decode_block(payload))),
+            7 if payload.len() < 8 => Err(invalid()),
+            7 => Ok(Message::Piece {
+                piece: read_u32(payload, 0),
+                offset: read_u32(payload, 4),
+                data: frame.slice(9..),
+            }),
+            9 if wrong_size(2) => Err(invalid()),
+            9 => Ok(Message::Port(u16::from_be_bytes([payload[0], payload[1]]))),
+            13 => Ok(Message::Suggest(read_u32(payload, 0))),
+            14 => Ok(Message::HaveAll),
+            15 => Ok(Message::HaveNone),
+            16 => Ok(Message::Reject(decode_block(payload))),
+            17 => Ok(Message::AllowedFast(read_u32(payload, 0))),
+            20 if payload.is_empty() => Err(invalid()),
+            20 => Ok(Message::Extended {
+                id: payload[0],
+                payload: payload[1..].to_vec(),
+            }),
+            _ => Err(ProtocolError::UnknownMessage(id)),
+        }
+    }
+}
+
+fn encode_block(block: &BlockRequest, buf: &mut impl BufMut) {
+    buf.put_u32(block.piece);
+    buf.put_u32(block.offset);
+    buf.put_u32(block.length);
+}
+
+fn decode_block(payload: &[u8]) -> BlockRequest {
+    BlockRequest {
+        piece: read_u32(payload, 0),
+        offset: read_u32(payload, 4),
+        length: read_u32(payload, 8),
+    }
+}
+
+/// Reads a big-endian integer; callers check the payload length first.
+fn read_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(buf[at..at + 4].try_into().unwrap())
+}
@@ -0,0 +1,90 @@
+//! Messages of the metadata exchange extension (BEP 9).
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+use rainyday_bencode::{self as bencode, Value};
+
+use super::ProtocolError;
+
+/// Name under which the extension is advertised in the extension handshake.
+pub const EXTENSION_NAME: &str = "ut_metadata";
+
+/// The extended message ID we ask peers to send metadata messages with.
+pub const LOCAL_ID: u8 = 1;
+
+/// The info dictionary is exchanged in pieces of this size; only the last
+/// may be shorter.
+pub const PIECE_SIZE: usize = 16 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MetadataMessage {
+    Request {
+        piece: u32,
+    },
+    Data {
+        piece: u32,
+        total_size: u64,
+        data: Vec<u8>,
+    },
+    /// The sender does not have, or will not share, the requested piece.
+    Reject {
+        piece: u32,
+    },
+}
+
+impl MetadataMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut dict = BTreeMap::new();
+        let (kind, piece) = match self {
+            MetadataMessage::Request { piece } => (0, piece),
+            MetadataMessage::Data { piece, .. } => (1, piece),
+            MetadataMessage::Reject { piece } => (2, piece),
+        };
+        dict.insert(b"msg_type".to_vec(), Value::Integer(kind));
+        dict.insert(b"piece".to_vec(), Value::Integer(*piece as i64));
+
+        match self {
+            MetadataMessage::Data {
+                total_size, data, ..
+            } => {
+                dict.insert(b"total_size".to_vec(), Value::Integer(*total_size as i64));
+                let mut buf = Value::Dict(dict).encode();
+                buf.extend_from_slice(data);
+                buf
+            }
+            _ => Value::Dict(dict).encode(),
+        }
+    }
+
+    /// Parses a message. Data follows the dictionary directly, without a
+    /// length prefix of its own.
+    pub fn from_bytes(payload: &[u8]) -> Result<Self, ProtocolError> {
+        let invalid = || ProtocolError::InvalidMetadataMessage;
+
+        let (value, used) = bencode::decode_prefix(payload)?;
+        let int = |key| value.get(key).and_then(Value::as_int);
+        let piece = int("piece")
+            .and_then(|piece| u32::try_from(piece).ok())
+            .ok_or_else(invalid)?;
+
+        match int("msg_type") {
+            Some(0) => Ok(MetadataMessage::Request { piece }),
+            Some(1) => Ok(MetadataMessage::Data {
+                piece,
+                total_size: int("total_size")
+                    .and_then(|size| u64::try_from(size).ok())
+                    .ok_or_else(invalid)?,
+                data: payload[used..].to_vec(),
+            }),
+            Some(2) => Ok(MetadataMessage::Reject { piece }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Number of pieces an info dictionary of `size` bytes is split into.
+pub fn num_pieces(size: u64) -> u32 {
+    size.div_ceil(PIECE_SIZE as u64) as u32
+}
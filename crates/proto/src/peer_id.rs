@@ -0,0 +1,81 @@
+//! Our own peer ID and the conventions around it.
+
+use core::fmt;
+
+#[cfg(feature = "std")]
+use rand::distributions::Alphanumeric;
+#[cfg(feature = "std")]
+use rand::Rng;
+use thiserror::Error;
+
+/// Azureus-style prefix identifying this client and version.
+pub const DEFAULT_PREFIX: &str = "-RD0100-";
+
+pub const PEER_ID_LEN: usize = 20;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PeerIdError {
+    #[error("peer ID prefix is {0} bytes long, but at most 20 are allowed")]
+    PrefixTooLong(usize),
+    #[error("peer ID prefix must be printable ASCII")]
+    NonAsciiPrefix,
+}
+
+/// The 20-byte identifier a peer presents in its handshake.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerId(pub [u8; PEER_ID_LEN]);
+
+impl PeerId {
+    /// Generates a fresh ID: `prefix` followed by random alphanumerics.
+    ///
+    /// Alphanumerics keep the ID readable in tracker logs and avoid bytes
+    /// that sloppy trackers fail to URL-encode correctly.
+    #[cfg(feature = "std")]
+    pub fn generate(prefix: &str) -> Result<Self, PeerIdError> {
+        if prefix.len() > PEER_ID_LEN {
+            return Err(PeerIdError::PrefixTooLong(prefix.len()));
+        }
+        if !prefix.bytes().all(|b| b.is_ascii_graphic()) {
+            return Err(PeerIdError::NonAsciiPrefix);
+        }
+
+        let mut id = [0u8; PEER_ID_LEN];
+        id[..prefix.len()].copy_from_slice(prefix.as_bytes());
+        let mut rng = rand::thread_rng();
+        for byte in &mut id[prefix.len()..] {
+            *byte = rng.sample(Alphanumeric);
+        }
+
+        Ok(Self(id))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; PEER_ID_LEN] {
+        &self.0
+    }
+}
+
+impl From<[u8; PEER_ID_LEN]> for PeerId {
+    fn from(bytes: [u8; PEER_ID_LEN]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl fmt::Debug for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PeerId({})", self)
+    }
+}
+
+/// Printable bytes are shown as-is and everything else as `\xNN`.
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &byte in &self.0 {
+            if byte.is_ascii_graphic() {
+                write!(f, "{}", byte as char)?;
+            } else {
+                write!(f, "\\x{:02x}", byte)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+//! Every message decodes to what was encoded.
+
+use std::net::{IpAddr, SocketAddr};
+
+use bytes::{Bytes, BytesMut};
+use proptest::collection::{btree_map, vec};
+use proptest::prelude::*;
+
+use rainyday_proto::holepunch::{HolepunchError, HolepunchMessage};
+use rainyday_proto::info_hash::InfoHash;
+use rainyday_proto::metadata::MetadataMessage;
+use rainyday_proto::peer_id::PeerId;
+use rainyday_proto::{BlockRequest, ExtendedHandshake, Handshake, Message, MessageCodec};
+
+/// Bencode integers are signed, so sizes past this cannot be sent.
+const MAX_SIZE: u64 = i64::MAX as u64;
+
+fn block() -> impl Strategy<Value = BlockRequest> {
+    (any::<u32>(), any::<u32>(), any::<u32>()).prop_map(|(piece, offset, length)| BlockRequest {
+        piece,
+        offset,
+        length,
+    })
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    prop_oneof![
+        Just(Message::KeepAlive),
+        Just(Message::Choke),
+        Just(Message::Unchoke),
+        Just(Message::Interested),
+        Just(Message::NotInterested),
+        any::<u32>().prop_map(Message::Have),
+        vec(any::<u8>(), 0..64).prop_map(Message::Bitfield),
+        block().prop_map(Message::Request),
+        (any::<u32>(), any::<u32>(), vec(any::<u8>(), 0..256)).prop_map(|(piece, offset, data)| {
+            Message::Piece {
+                piece,
+                offset,
+                data: Bytes::from(data),
+            }
+        }),
+        block().prop_map(Message::Cancel),
+        any::<u16>().prop_map(Message::Port),
+        any::<u32>().prop_map(Message::Suggest),
+        Just(Message::HaveAll),
+        Just(Message::HaveNone),
+        block().prop_map(Message::Reject),
+        any::<u32>().prop_map(Message::AllowedFast),
+        (any::<u8>(), vec(any::<u8>(), 0..64))
+            .prop_map(|(id, payload)| Message::Extended { id, payload }),
+    ]
+}
+
+fn handshake() -> impl Strategy<Value = Handshake> {
+    (any::<[u8; 8]>(), any::<[u8; 20]>(), any::<[u8; 20]>()).prop_map(
+        |(reserved, info_hash, peer_id)| Handshake {
+            reserved,
+            info_hash: InfoHash(info_hash),
+            peer_id: PeerId(peer_id),
+        },
+    )
+}
+
+fn extended_handshake() -> impl Strategy<Value = ExtendedHandshake> {
+    (
+        btree_map(".{0,12}", any::<u8>(), 0..6),
+        proptest::option::of(".{0,24}"),
+        proptest::option::of(any::<u16>()),
+        proptest::option::of(any::<IpAddr>()),
+        proptest::option::of(any::<u32>()),
+        proptest::option::of(0..=MAX_SIZE),
+    )
+        .prop_map(
+            |(extensions, version, port, your_ip, request_queue, metadata_size)| {
+                ExtendedHandshake {
+                    extensions,
+                    version,
+                    port,
+                    your_ip,
+                    request_queue,
+                    metadata_size,
+                }
+            },
+        )
+}
+
+fn metadata_message() -> impl Strategy<Value = MetadataMessage> {
+    prop_oneof![
+        any::<u32>().prop_map(|piece| MetadataMessage::Request { piece }),
+        (any::<u32>(), 0..=MAX_SIZE, vec(any::<u8>(), 0..256)).prop_map(
+            |(piece, total_size, data)| MetadataMessage::Data {
+                piece,
+                total_size,
+                data,
+            }
+        ),
+        any::<u32>().prop_map(|piece| MetadataMessage::Reject { piece }),
+    ]
+}
+
+fn holepunch_message() -> impl Strategy<Value = HolepunchMessage> {
+    let addr = (any::<IpAddr>(), any::<u16>()).prop_map(|(ip, port)| SocketAddr::new(ip, port));
+    let error = prop_oneof![
+        Just(HolepunchError::NoSuchPeer),
+        Just(HolepunchError::NotConnected),
+        Just(HolepunchError::NoSupport),
+        Just(HolepunchError::NoSelf),
+        // The codes with names of their own decode as those names.
+        any::<u32>()
+            .prop_filter("named code", |code| !(1..=4).contains(code))
+            .prop_map(HolepunchError::Unknown),
+    ];
+    prop_oneof![
+        addr.clone().prop_map(HolepunchMessage::Rendezvous),
+        addr.clone().prop_map(HolepunchMessage::Connect),
+        (addr, error).prop_map(|(addr, error)| HolepunchMessage::Error(addr, error)),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn messages_survive_framing(messages in vec(message(), 0..16), pedantic in any::<bool>()) {
+        let mut codec = MessageCodec::new(pedantic);
+        let mut buf = BytesMut::new();
+        for message in &messages {
+            codec.encode(message.clone(), &mut buf)?;
+        }
+        let mut decoded = Vec::new();
+        while let Some(message) = codec.decode(&mut buf)? {
+            decoded.push(message);
+        }
+        prop_assert_eq!(decoded, messages);
+        prop_assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn messages_arrive_whole_however_the_stream_is_cut(
+        messages in vec(message(), 1..8),
+        cut in any::<prop::sample::Index>(),
+    ) {
+        let mut codec = MessageCodec::default();
+        let mut encoded = BytesMut::new();
+        for message in &messages {
+            codec.encode(message.clone(), &mut encoded)?;
+        }
+        let mut buf = encoded.split_to(cut.index(encoded.len()));
+        let mut decoded = Vec::new();
+        while let Some(message) = codec.decode(&mut buf)? {
+            decoded.push(message);
+        }
+        buf.extend_from_slice(&encoded);
+        while let Some(message) = codec.decode(&mut buf)? {
+            decoded.push(message);
+        }
+        prop_assert_eq!(decoded, messages);
+    }
+
+    #[test]
+    fn message_lengths_match_their_encodings(message in message()) {
+        let mut buf = BytesMut::new();
+        message.encode(&mut buf);
+        prop_assert_eq!(buf.len(), 4 + message.encoded_len());
+    }
+
+    #[test]
+    fn handshakes_roundtrip(handshake in handshake()) {
+        prop_assert_eq!(Handshake::from_bytes(&handshake.to_bytes())?, handshake);
+    }
+
+    #[test]
+    fn info_hashes_roundtrip_through_hex(info_hash in any::<[u8; 20]>().prop_map(InfoHash)) {
+        prop_assert_eq!(InfoHash::from_hex(&info_hash.to_string()), Some(info_hash));
+    }
+
+    #[test]
+    fn extended_handshakes_roundtrip(handshake in extended_handshake()) {
+        prop_assert_eq!(ExtendedHandshake::from_bytes(&handshake.to_bytes())?, handshake);
+    }
+
+    #[test]
+    fn metadata_messages_roundtrip(message in metadata_message()) {
+        prop_assert_eq!(MetadataMessage::from_bytes(&message.to_bytes())?, message);
+    }
+
+    #[test]
+    fn holepunch_messages_roundtrip(message in holepunch_message()) {
+        prop_assert_eq!(HolepunchMessage::from_bytes(&message.to_bytes())?, message);
+    }
+}
@@ -0,0 +1,153 @@
+//! `rainyday benchmark`: measure how fast this machine hashes pieces,
+//! writes them to disk and frames peer messages, to tell whether a slow
+//! download is held back by the CPU, the disk or the network.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+use indicatif::HumanBytes;
+use rand::Rng;
+
+use rainyday_engine::config::Config;
+use rainyday_engine::hash;
+use rainyday_engine::pool::BLOCKS;
+use rainyday_engine::protocol::{Message, MessageCodec};
+use rainyday_engine::storage::layout::FileSlot;
+use rainyday_engine::storage::{self, DiskMetrics, IoHints, Layout};
+use rainyday_engine::verify::piece_hash;
+
+/// Piece length used throughout, typical of large torrents.
+const PIECE_LENGTH: usize = 1024 * 1024;
+/// Block size peers request and send.
+const BLOCK_LENGTH: usize = 16 * 1024;
+
+/// Processes `size` bytes in each benchmark, writing into a scratch
+/// directory under `dir`.
+pub fn run(config: &Config, size: u64, dir: &Path) -> Result<bool, Box<dyn Error>> {
+    let pieces = size.div_ceil(PIECE_LENGTH as u64).max(1) as usize;
+    let mut piece = vec![0u8; PIECE_LENGTH];
+    rand::thread_rng().fill(&mut piece[..]);
+    let total = (pieces * PIECE_LENGTH) as u64;
+
+    let elapsed = time(|| {
+        for _ in 0..pieces {
+            piece_hash(&piece);
+        }
+    });
+    println!(
+        "hashing:  {}/s (SHA-1 from {}, {} pieces)",
+        HumanBytes(rate(total, elapsed)),
+        hash::BACKEND,
+        HumanBytes(PIECE_LENGTH as u64)
+    );
+
+    let scratch = dir.join(format!(".rainyday-benchmark-{}", process::id()));
+    let written = write(config, &scratch, &piece, pieces);
+    let _ = fs::remove_dir_all(&scratch);
+    println!(
+        "disk:     {}/s write ({:?} backend, {:?} allocation, in {})",
+        HumanBytes(rate(total, written?)),
+        config.storage.backend,
+        config.storage.allocation,
+        dir.display()
+    );
+
+    let (encoded, decoded, messages) = codec(&piece, pieces)?;
+    println!(
+        "encode:   {}/s ({:.0} blocks/s)",
+        HumanBytes(rate(total, encoded)),
+        messages as f64 / encoded.as_secs_f64()
+    );
+    println!(
+        "decode:   {}/s ({:.0} blocks/s)",
+        HumanBytes(rate(total, decoded)),
+        messages as f64 / decoded.as_secs_f64()
+    );
+    Ok(true)
+}
+
+/// Writes `pieces` copies of `piece` with the configured storage backend,
+/// returning how long it took to get them onto the disk.
+fn write(
+    config: &Config,
+    root: &Path,
+    piece: &[u8],
+    pieces: usize,
+) -> Result<Duration, Box<dyn Error>> {
+    let length = (pieces * piece.len()) as u64;
+    let layout = Layout {
+        files: vec![FileSlot {
+            path: PathBuf::from("data"),
+            length,
+            offset: 0,
+        }],
+        piece_length: piece.len() as u32,
+        total_length: length,
+    };
+    fs::create_dir_all(root)?;
+    let storage = storage::open(
+        config.storage.backend,
+        root,
+        layout,
+        config.storage.allocation,
+        IoHints::from(config),
+        Arc::new(DiskMetrics::default()),
+    )?;
+
+    // Written a block at a time, as pieces arrive from peers.
+    let blocks: Vec<Bytes> = piece
+        .chunks(BLOCK_LENGTH)
+        .map(Bytes::copy_from_slice)
+        .collect();
+    let start = Instant::now();
+    for index in 0..pieces {
+        storage.write_blocks(index as u32, &blocks)?;
+    }
+    storage.flush()?;
+    Ok(start.elapsed())
+}
+
+/// Frames `pieces` pieces' worth of blocks and parses them back, returning
+/// the time spent on each and the number of messages.
+fn codec(piece: &[u8], pieces: usize) -> Result<(Duration, Duration, usize), Box<dyn Error>> {
+    let blocks: Vec<&[u8]> = piece.chunks(BLOCK_LENGTH).collect();
+    let mut codec = MessageCodec::default();
+    let mut buffer = BytesMut::new();
+    let (mut encoding, mut decoding, mut messages) = (Duration::ZERO, Duration::ZERO, 0);
+
+    for index in 0..pieces {
+        let start = Instant::now();
+        for (block, data) in blocks.iter().enumerate() {
+            let message = Message::Piece {
+                piece: index as u32,
+                offset: (block * BLOCK_LENGTH) as u32,
+                data: BLOCKS.copy_from(data).into_bytes(),
+            };
+            codec.encode(message, &mut buffer)?;
+        }
+        encoding += start.elapsed();
+
+        let start = Instant::now();
+        while codec.decode(&mut buffer)?.is_some() {
+            messages += 1;
+        }
+        decoding += start.elapsed();
+    }
+    Ok((encoding, decoding, messages))
+}
+
+fn time(f: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+/// Bytes per second.
+fn rate(bytes: u64, elapsed: Duration) -> u64 {
+    (bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON)) as u64
+}
@@ -0,0 +1,262 @@
+//! `rainyday config`: manage the configuration file.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use rainyday_engine::config;
+
+/// Every setting with its default; those without one are commented out.
+const TEMPLATE: &str = r#"# rainyday configuration. Every setting is optional; the values shown are
+# the defaults. Any key can be overridden with an environment variable such
+# as RAINYDAY_NETWORK__PORT=6881, and command-line options override both.
+
+# Reject torrents, tracker responses and peer messages that deviate from the
+# specifications, instead of tolerating the common slips.
+pedantic = false
+
+# Prefix of our peer ID, which trackers and peers use to identify the client.
+# Override it only if a tracker insists on a particular client.
+peer_id_prefix = "-RD0100-"
+
+# Directory holding the session database, from which the daemon restarts its
+# torrents where they left off. Without one, nothing about the session
+# survives a restart.
+# state_dir = "/var/lib/rainyday"
+
+# Seconds between the daemon recording its torrents' progress in state_dir,
+# which bounds what a crash loses. 0 records it only at shutdown.
+save_interval = 300
+
+# Unix socket the CLI uses to talk to a running rainyday. Defaults to one in
+# the user's runtime directory.
+# control_socket = "/run/rainyday/control.sock"
+
+# Address the daemon serves the gRPC control API on, in builds with the grpc
+# feature. Off unless set.
+# grpc_address = "127.0.0.1:50051"
+
+# Address the daemon serves the REST API on, and the bearer token REST and
+# gRPC clients must send. Off unless set; without a token, the daemon makes
+# one up and writes it to http-token beside the control socket.
+# http_address = "127.0.0.1:8080"
+# http_token = "secret"
+
+# Offer the daemon's D-Bus interface on the session bus, in builds with the
+# dbus feature.
+dbus = false
+
+# Show a desktop notification when a download run from a terminal finishes
+# or fails.
+notifications = false
+
+[network]
+# Port to accept peer connections on; 0 for any free port. Without one, 6881
+# is tried and any free port used if it is taken.
+# port = 6881
+
+# Range to pick the listen port from at random, instead of port.
+# port_range = "6881-6999"
+
+# Keep using the port picked from port_range in later sessions, rather than
+# picking a new one at each start. The daemon remembers it in state_dir.
+keep_port = false
+
+# Addresses or interfaces to listen on, each with its own port, instead of
+# port and port_range. Each is announced to trackers separately, from its
+# own address, so trackers hand it out to peers that can reach it.
+# listen = ["0.0.0.0:6881", "[::]:6882", "wg0:51413"]
+
+# Where this host has a public IPv6 address as well as IPv4, also listen on
+# IPv6 with the same port and announce to trackers over both, so rainyday is
+# in both of their peer lists. Not used with listen or interface.
+dual_stack = true
+
+# When to use encrypted peer connections. Only "disabled" is accepted until
+# rainyday speaks message stream encryption.
+encryption = "disabled"
+
+# Most peers to be connected to per torrent, and across all torrents.
+max_peers = 50
+max_connections = 200
+
+# Seconds of send inactivity after which a keep-alive is sent, and of
+# receive inactivity after which a peer is dropped.
+keepalive_interval = 100
+peer_timeout = 180
+
+# Ask the router to forward the listen port with UPnP, and with NAT-PMP or
+# PCP, renewing the mappings while rainyday runs and removing them when it
+# stops.
+upnp = true
+natpmp = true
+
+# The router to ask with NAT-PMP and PCP, instead of the default gateway.
+# gateway = "192.168.1.1"
+
+# Send and receive all traffic on this interface, named like "wg0" or by one
+# of its addresses, such as a VPN's. While it is missing nothing is sent,
+# rather than going out another way. UPnP is not used with an interface.
+# interface = "wg0"
+
+# Open outgoing peer connections, UDP tracker requests and DHT lookups from
+# ports in this range, for firewalls whose egress rules require it. HTTP
+# trackers are still reached from any port.
+# source_ports = "40000-40999"
+
+[storage]
+# Where finished downloads are kept.
+download_dir = "."
+
+# Where downloads in progress are kept, if different. Torrents are moved to
+# download_dir when they finish.
+# incomplete_dir = "/srv/torrents/incomplete"
+
+# Suffix added to file names until the file has been fully downloaded and
+# verified, so other programs do not pick up half-finished files.
+# part_suffix = ".part"
+
+# Whether torrent data is accessed through files ("file") or memory maps
+# ("mmap").
+backend = "file"
+
+# How disk space is reserved: "sparse", "full" or "none". On Windows, "full"
+# writes zeroes over each file.
+allocation = "sparse"
+
+# Most file handles to keep open per torrent.
+open_files = 128
+
+# Write piece data with O_DIRECT, bypassing the page cache, and evict piece
+# data from the page cache once it has been hashed.
+direct_io = false
+drop_cache_after_hash = false
+
+[dht]
+# Look for peers in the DHT as well as asking trackers.
+enabled = true
+
+# host:port of the nodes lookups start from.
+bootstrap_nodes = [
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+]
+
+[tracker]
+# How many peers to ask trackers for; without it, trackers decide.
+# num_want = 50
+
+[limits]
+# Most bytes per second to download and upload, across all torrents.
+# download_rate = 1000000
+# upload_rate = 500000
+
+# Most bytes of torrent data to keep on disk, across all torrents. Torrents
+# that would exceed it are paused.
+# disk_quota = 500000000000
+
+# Most bytes of piece data to hold in memory, across all torrents, until
+# pieces are written to disk. No new pieces are started while it is reached.
+# memory_budget = 268435456
+
+[schedule]
+# Alternate limits used instead of those in [limits] during the periods
+# below, given in local time. Days are names and ranges such as "mon-fri,sun"
+# or "daily"; hours are spans such as "09:00-17:30".
+# download_rate = 100000
+# upload_rate = 20000
+periods = []
+# periods = [{ days = "mon-fri", hours = "09:00-17:00" }]
+# When the daemon pauses every torrent, resuming them as each period ends.
+pause_periods = []
+# pause_periods = [{ days = "daily", hours = "18:00-23:00" }]
+
+[blocklist]
+# Addresses never to connect to or accept peers from, as PeerGuardian .p2p
+# or eMule .dat files, optionally gzipped.
+files = []
+# files = ["/etc/rainyday/level1.p2p.gz"]
+
+# Seconds between reloads of the files, or 0 to load them only once.
+reload_interval = 86400
+
+[proxy]
+# Proxy to send traffic through: "none", "http" or "socks5".
+type = "none"
+# host = "127.0.0.1"
+# port = 1080
+# username = "rainyday"
+# password = "secret"
+
+# Which traffic goes through the proxy. Traffic that should but cannot, such
+# as UDP trackers and the DHT, is not sent at all.
+peers = true
+trackers = true
+dht = true
+
+# Have the proxy look up tracker host names, so no DNS queries leave this
+# machine.
+hostnames = true
+
+[hooks]
+# Commands run with sh -c when a torrent is added, finishes, stops or fails.
+# The torrent is described by RAINYDAY_* environment variables.
+# added = "logger added $RAINYDAY_NAME"
+# finished = "logger finished $RAINYDAY_NAME"
+# stopped = "logger stopped $RAINYDAY_NAME"
+# error = "logger failed $RAINYDAY_NAME: $RAINYDAY_ERROR"
+
+[logging]
+# File to also log to, as one JSON object per line.
+# file = "/var/log/rainyday/rainyday.log"
+
+# Least severe messages written to the file, whatever -v says.
+file_level = "info"
+
+# Start a new file once it reaches this many bytes (0 for never), and at
+# midnight UTC if daily is set. Older files are kept as rainyday.log.1 and
+# so on, up to keep of them.
+max_size = 10485760
+daily = false
+keep = 5
+
+# Levels for particular modules, or for the subsystems protocol, tracker,
+# dht and storage, on the console and in the file.
+# [logging.levels]
+# tracker = "debug"
+# "rainyday::session" = "trace"
+
+# Profiles selected with --profile override any of the settings above.
+# [profile.seedbox.network]
+# max_connections = 1000
+"#;
+
+/// Writes the commented default configuration to `path`, or to the file
+/// rainyday looks for by default. An existing file is only replaced if
+/// `force` is set.
+pub fn init(path: Option<&Path>, force: bool) -> Result<bool, Box<dyn Error>> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => {
+            config::default_path().ok_or("cannot find a configuration directory; give a path")?
+        }
+    };
+    if path.exists() && !force {
+        return Err(format!(
+            "{} already exists; use --force to replace it",
+            path.display()
+        )
+        .into());
+    }
+
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, TEMPLATE)?;
+    println!("wrote {}", path.display());
+    Ok(true)
+}
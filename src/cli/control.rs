@@ -0,0 +1,40 @@
+//! Commands that ask a running torrent to do something, such as
+//! `rainyday pause` and `rainyday announce`.
+
+use std::error::Error;
+use std::fs;
+
+use rainyday_engine::config::Config;
+use rainyday_engine::control::{client, Request, Response};
+use rainyday_engine::magnet;
+
+/// Sends `request` and reports each torrent it was carried out for with
+/// `doing`, e.g. "announcing".
+pub fn run(config: &Config, request: Request, doing: &str) -> Result<bool, Box<dyn Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    match runtime.block_on(client::request(&config.control_socket(), &request))? {
+        Response::Done { torrents } => {
+            for torrent in torrents {
+                println!("{}: {}", torrent.name, doing);
+            }
+            Ok(true)
+        }
+        response => Err(format!("unexpected response {:?}", response).into()),
+    }
+}
+
+/// Makes a torrent path absolute, so the daemon finds the same file
+/// whatever its working directory. URLs and magnet links pass unchanged.
+pub fn absolute(torrent: String) -> Result<String, Box<dyn Error>> {
+    if torrent.starts_with(magnet::SCHEME)
+        || torrent.starts_with("http://")
+        || torrent.starts_with("https://")
+    {
+        return Ok(torrent);
+    }
+    if torrent == "-" {
+        return Err("cannot add a torrent from standard input to a daemon".into());
+    }
+    let path = fs::canonicalize(&torrent).map_err(|e| format!("{}: {}", torrent, e))?;
+    Ok(path.to_string_lossy().into_owned())
+}
@@ -0,0 +1,39 @@
+//! `rainyday convert`: turn a `.torrent` into a magnet link, or a magnet
+//! link back into a `.torrent` by fetching its metadata from peers.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rainyday_engine::config::Config;
+use rainyday_engine::input;
+use rainyday_engine::magnet::{self, Magnet};
+
+pub fn run(config: &Config, torrent: &str, output: Option<&Path>) -> Result<bool, Box<dyn Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let is_magnet = torrent.starts_with(magnet::SCHEME);
+    if is_magnet {
+        println!("fetching metadata from peers");
+    }
+    let metainfo = runtime.block_on(input::load(torrent, config, config.peer_id()?))?;
+
+    if !is_magnet {
+        let link = Magnet::from_metainfo(&metainfo).to_string();
+        match output {
+            Some(output) => {
+                fs::write(output, link + "\n")?;
+                println!("wrote {}", output.display());
+            }
+            None => println!("{}", link),
+        }
+        return Ok(true);
+    }
+
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => PathBuf::from(format!("{}.torrent", metainfo.info.name)),
+    };
+    fs::write(&output, metainfo.to_bytes())?;
+    println!("wrote {}", output.display());
+    Ok(true)
+}
@@ -0,0 +1,31 @@
+//! `rainyday create`: make a `.torrent` from a file or directory.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rainyday_engine::create::{create, CreateOptions};
+
+pub fn run(
+    path: &Path,
+    output: Option<&Path>,
+    options: &CreateOptions,
+) -> Result<bool, Box<dyn Error>> {
+    let torrent = create(path, options)?;
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => default_output(path),
+    };
+    fs::write(&output, torrent)?;
+    println!("wrote {}", output.display());
+    Ok(true)
+}
+
+/// `<name>.torrent` in the current directory.
+fn default_output(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "torrent".to_string());
+    PathBuf::from(format!("{}.torrent", name))
+}
@@ -0,0 +1,147 @@
+//! `rainyday daemon`: run headless, taking torrents over the control
+//! socket until told to stop. Under systemd, it can run as a service of
+//! `Type=notify`; see [`super::systemd`].
+
+use std::error::Error;
+use std::sync::Arc;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::info;
+
+use super::systemd;
+use rainyday_engine::config::Config;
+use rainyday_engine::control;
+use rainyday_engine::event::EventBus;
+use rainyday_engine::schedule;
+use rainyday_engine::session::{self, Session, SessionStore};
+
+/// Listens for peers, restores the torrents of the last session, and serves
+/// the control socket until interrupted or terminated, then stops every
+/// torrent.
+pub fn run(config: &Config) -> Result<bool, Box<dyn Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    runtime.block_on(async {
+        let mut session = Session::daemon(config.clone(), EventBus::default())?;
+        if let Some(state_dir) = &config.state_dir {
+            session = session.with_store(SessionStore::open(state_dir)?);
+        }
+        let session = Arc::new(session);
+        session.peer_port().await?;
+        if !config.schedule.pause_periods.is_empty() {
+            // Paused first, so that restored torrents start paused too.
+            if config.schedule.is_paused_now() {
+                info!("pausing the session as scheduled");
+                session.pause().await;
+            }
+            tokio::spawn(schedule::pause_during(
+                config.schedule.clone(),
+                Arc::clone(&session),
+            ));
+        }
+        control::server::restore(&session).await;
+        tokio::spawn(session::save_periodically(Arc::clone(&session)));
+        let socket = config.control_socket();
+        let mut terminate = signal(SignalKind::terminate())?;
+        let control_error = |e| format!("control socket {}: {}", socket.display(), e);
+        let listener = control::server::listen(socket.clone())
+            .await
+            .map_err(control_error)?;
+        info!("listening for commands on {}", socket.display());
+        // One token for every network API, made up only if one is served.
+        let token = if config.http_address.is_some() || config.grpc_address.is_some() {
+            api_token(config)?
+        } else {
+            String::new()
+        };
+        systemd::ready();
+
+        tokio::select! {
+            served = listener.serve(Arc::clone(&session)) => served.map_err(control_error)?,
+            served = serve_grpc(config, &token, Arc::clone(&session)) => served?,
+            served = serve_http(config, &token, Arc::clone(&session)) => served?,
+            served = serve_dbus(config, Arc::clone(&session)) => served?,
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+            _ = systemd::watchdog() => {}
+        }
+
+        info!("shutting down");
+        systemd::stopping();
+        session.shutdown().await;
+        Ok(true)
+    })
+}
+
+/// The token REST and gRPC clients must send.
+fn api_token(config: &Config) -> Result<String, Box<dyn Error>> {
+    let path = config.http_token_path();
+    let token = control::token(config).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if config.http_token.is_none() {
+        info!("API clients must send the token in {}", path.display());
+    }
+    Ok(token)
+}
+
+/// Serves the REST API if an address is configured, and otherwise never
+/// returns.
+async fn serve_http(
+    config: &Config,
+    token: &str,
+    session: Arc<Session>,
+) -> Result<(), Box<dyn Error>> {
+    let addr = match config.http_address {
+        Some(addr) => addr,
+        None => std::future::pending().await,
+    };
+    info!("serving the REST API on {}", addr);
+    control::rest::serve(addr, token.to_owned(), session)
+        .await
+        .map_err(|e| format!("REST API on {}: {}", addr, e).into())
+}
+
+/// Serves the gRPC API if an address is configured, and otherwise never
+/// returns.
+async fn serve_grpc(
+    config: &Config,
+    token: &str,
+    session: Arc<Session>,
+) -> Result<(), Box<dyn Error>> {
+    match config.grpc_address {
+        #[cfg(feature = "grpc")]
+        Some(addr) => {
+            info!("serving the gRPC API on {}", addr);
+            control::grpc::serve(addr, token.to_owned(), session)
+                .await
+                .map_err(|e| format!("gRPC API on {}: {}", addr, e).into())
+        }
+        #[cfg(not(feature = "grpc"))]
+        Some(_) => {
+            drop((token, session));
+            tracing::warn!("grpc_address is set but rainyday was built without gRPC");
+            std::future::pending().await
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Serves the D-Bus interface if it is enabled, and otherwise never
+/// returns.
+async fn serve_dbus(config: &Config, session: Arc<Session>) -> Result<(), Box<dyn Error>> {
+    if !config.dbus {
+        return std::future::pending().await;
+    }
+    #[cfg(feature = "dbus")]
+    {
+        info!("serving the D-Bus interface as {}", control::dbus::BUS_NAME);
+        control::dbus::serve(session)
+            .await
+            .map_err(|e| format!("D-Bus interface: {}", e).into())
+    }
+    #[cfg(not(feature = "dbus"))]
+    {
+        drop(session);
+        tracing::warn!("dbus is set but rainyday was built without D-Bus");
+        std::future::pending().await
+    }
+}
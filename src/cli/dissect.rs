@@ -0,0 +1,344 @@
+//! `rainyday dissect`: decode the BitTorrent connections in a packet
+//! capture, to see what was said when talking to another client goes wrong.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use bytes::BytesMut;
+
+use rainyday_engine::capture::tcp::Event;
+use rainyday_engine::capture::{self, Connection, Direction, Reassembler};
+use rainyday_engine::peer::client::ClientInfo;
+use rainyday_engine::protocol::extension::HANDSHAKE_ID;
+use rainyday_engine::protocol::handshake::{HANDSHAKE_LEN, PROTOCOL};
+use rainyday_engine::protocol::holepunch::{self, HolepunchMessage};
+use rainyday_engine::protocol::metadata::{self, MetadataMessage};
+use rainyday_engine::protocol::{
+    ExtendedHandshake, Handshake, Message, MessageCodec, ProtocolError,
+};
+
+pub fn run(capture: &Path) -> Result<bool, Box<dyn Error>> {
+    let bytes = fs::read(capture)?;
+    let segments = capture::segments(&bytes)?;
+    let Some(start) = segments.iter().map(|segment| segment.time).min() else {
+        println!("no TCP traffic captured");
+        return Ok(true);
+    };
+    let mut reassembler = Reassembler::new();
+    for segment in segments {
+        reassembler.push(segment);
+    }
+
+    let connections = reassembler.finish();
+    let mut shown = 0;
+    for connection in &connections {
+        if let Some(transcript) = transcript(connection, start) {
+            if shown > 0 {
+                println!();
+            }
+            print!("{}", transcript);
+            shown += 1;
+        }
+    }
+    let skipped = connections.len() - shown;
+    if skipped > 0 {
+        if shown > 0 {
+            println!();
+        }
+        println!(
+            "skipped {} TCP connection{} without a BitTorrent handshake \
+             (other protocols, or encrypted)",
+            skipped,
+            if skipped == 1 { "" } else { "s" }
+        );
+    }
+    Ok(true)
+}
+
+/// How far one side of a connection has been decoded.
+#[derive(Default)]
+struct Side {
+    buf: BytesMut,
+    handshaken: bool,
+    /// Nothing more can be made of what this side sends.
+    lost: bool,
+    /// The extended message IDs this side asked to be sent, and what for.
+    extensions: BTreeMap<u8, String>,
+}
+
+/// The annotated transcript of `connection`, if it is BitTorrent.
+fn transcript(connection: &Connection, start: Duration) -> Option<String> {
+    let mut sides = [Side::default(), Side::default()];
+    let mut out = String::new();
+    let mut recognized = false;
+    let opened = if connection.opened {
+        ""
+    } else {
+        ", opened before the capture began"
+    };
+    writeln!(
+        out,
+        "{} > {}{}",
+        connection.initiator, connection.acceptor, opened
+    )
+    .unwrap();
+
+    for (time, direction, event) in &connection.events {
+        let (from, to) = match direction {
+            Direction::Outbound => (0, 1),
+            Direction::Inbound => (1, 0),
+        };
+        let mark = match direction {
+            Direction::Outbound => '>',
+            Direction::Inbound => '<',
+        };
+        let mut line = |text: &str| {
+            let time = time.saturating_sub(start).as_secs_f64();
+            writeln!(out, "{:>12.6} {} {}", time, mark, text).unwrap();
+        };
+
+        match event {
+            Event::Data(data) => {
+                if sides[from].lost {
+                    continue;
+                }
+                sides[from].buf.extend_from_slice(data);
+                let receiver = sides[to].extensions.clone();
+                let side = &mut sides[from];
+                if !side.handshaken {
+                    match handshake(&mut side.buf) {
+                        Ok(None) => continue,
+                        Ok(Some(handshake)) => {
+                            recognized = true;
+                            side.handshaken = true;
+                            line(&describe_handshake(&handshake));
+                        }
+                        Err(_) => {
+                            side.lost = true;
+                            line("not a BitTorrent handshake");
+                            continue;
+                        }
+                    }
+                }
+                loop {
+                    match MessageCodec::new(true).decode(&mut side.buf) {
+                        Ok(Some(message)) => {
+                            if let Message::Extended {
+                                id: HANDSHAKE_ID,
+                                payload,
+                            } = &message
+                            {
+                                if let Ok(handshake) = ExtendedHandshake::from_bytes(payload) {
+                                    side.extensions = handshake
+                                        .extensions
+                                        .iter()
+                                        .filter(|&(_, &id)| id != 0)
+                                        .map(|(name, &id)| (id, name.clone()))
+                                        .collect();
+                                }
+                            }
+                            line(&describe(&message, &receiver));
+                        }
+                        Ok(None) => break,
+                        Err(ProtocolError::FrameTooLarge(len)) => {
+                            side.lost = true;
+                            line(&format!(
+                                "frame of {} bytes is too large; giving up on this side",
+                                len
+                            ));
+                            break;
+                        }
+                        Err(e) => line(&format!("malformed message: {}", e)),
+                    }
+                }
+            }
+            Event::Gap(len) => {
+                let side = &mut sides[from];
+                if !side.lost {
+                    side.lost = true;
+                    line(&format!(
+                        "{} bytes were not captured; giving up on this side",
+                        len
+                    ));
+                }
+            }
+            Event::Fin => {
+                let pending = sides[from].buf.len();
+                if pending > 0 && !sides[from].lost {
+                    line(&format!(
+                        "closed partway through a message, {} bytes in",
+                        pending
+                    ));
+                } else {
+                    line("closed");
+                }
+            }
+            Event::Reset => line("reset"),
+        }
+    }
+    recognized.then_some(out)
+}
+
+/// Takes the handshake off the front of `buf`, once it has arrived.
+fn handshake(buf: &mut BytesMut) -> Result<Option<Handshake>, ProtocolError> {
+    let header = 1 + PROTOCOL.len();
+    let expected = [&[PROTOCOL.len() as u8][..], &PROTOCOL[..]].concat();
+    let seen = buf.len().min(header);
+    if buf[..seen] != expected[..seen] {
+        return Err(ProtocolError::InvalidProtocol);
+    }
+    if buf.len() < HANDSHAKE_LEN {
+        return Ok(None);
+    }
+    let bytes = buf.split_to(HANDSHAKE_LEN);
+    Handshake::from_bytes(bytes[..].try_into().unwrap()).map(Some)
+}
+
+fn describe_handshake(handshake: &Handshake) -> String {
+    let mut text = format!(
+        "handshake for {}, peer id {}",
+        handshake.info_hash, handshake.peer_id
+    );
+    if let Some(client) = ClientInfo::from_peer_id(&handshake.peer_id) {
+        write!(text, " ({})", client).unwrap();
+    }
+    let mut supports = Vec::new();
+    if handshake.supports_extensions() {
+        supports.push("extensions");
+    }
+    if handshake.supports_fast() {
+        supports.push("fast");
+    }
+    if handshake.reserved[7] & 0x01 != 0 {
+        supports.push("dht");
+    }
+    if !supports.is_empty() {
+        write!(text, ", supports {}", supports.join(", ")).unwrap();
+    }
+    if !handshake.reserved_bits_assigned() {
+        write!(text, ", reserved bits {}", hex(&handshake.reserved)).unwrap();
+    }
+    text
+}
+
+/// What `message` says. `extensions` are the extended message IDs its
+/// receiver asked for.
+fn describe(message: &Message, extensions: &BTreeMap<u8, String>) -> String {
+    match message {
+        Message::KeepAlive => "keep-alive".to_string(),
+        Message::Choke => "choke".to_string(),
+        Message::Unchoke => "unchoke".to_string(),
+        Message::Interested => "interested".to_string(),
+        Message::NotInterested => "not interested".to_string(),
+        Message::Have(piece) => format!("have piece {}", piece),
+        Message::Bitfield(bits) => format!(
+            "bitfield, {} pieces set",
+            bits.iter().map(|byte| byte.count_ones()).sum::<u32>()
+        ),
+        Message::Request(block) => format!(
+            "request piece {} offset {} length {}",
+            block.piece, block.offset, block.length
+        ),
+        Message::Piece {
+            piece,
+            offset,
+            data,
+        } => format!("piece {} offset {} length {}", piece, offset, data.len()),
+        Message::Cancel(block) => format!(
+            "cancel piece {} offset {} length {}",
+            block.piece, block.offset, block.length
+        ),
+        Message::Port(port) => format!("dht port {}", port),
+        Message::Suggest(piece) => format!("suggest piece {}", piece),
+        Message::HaveAll => "have all".to_string(),
+        Message::HaveNone => "have none".to_string(),
+        Message::Reject(block) => format!(
+            "reject piece {} offset {} length {}",
+            block.piece, block.offset, block.length
+        ),
+        Message::AllowedFast(piece) => format!("allowed fast piece {}", piece),
+        Message::Extended {
+            id: HANDSHAKE_ID,
+            payload,
+        } => match ExtendedHandshake::from_bytes(payload) {
+            Ok(handshake) => describe_extended_handshake(&handshake),
+            Err(e) => format!("extension handshake: {}", e),
+        },
+        Message::Extended { id, payload } => match extensions.get(id).map(String::as_str) {
+            Some(metadata::EXTENSION_NAME) => match MetadataMessage::from_bytes(payload) {
+                Ok(MetadataMessage::Request { piece }) => {
+                    format!("ut_metadata request piece {}", piece)
+                }
+                Ok(MetadataMessage::Data {
+                    piece,
+                    total_size,
+                    data,
+                }) => format!(
+                    "ut_metadata piece {} length {} of {} bytes",
+                    piece,
+                    data.len(),
+                    total_size
+                ),
+                Ok(MetadataMessage::Reject { piece }) => {
+                    format!("ut_metadata reject piece {}", piece)
+                }
+                Err(e) => format!("ut_metadata: {}", e),
+            },
+            Some(holepunch::EXTENSION_NAME) => match HolepunchMessage::from_bytes(payload) {
+                Ok(HolepunchMessage::Rendezvous(addr)) => {
+                    format!("ut_holepunch rendezvous with {}", addr)
+                }
+                Ok(HolepunchMessage::Connect(addr)) => {
+                    format!("ut_holepunch connect to {}", addr)
+                }
+                Ok(HolepunchMessage::Error(addr, error)) => {
+                    format!("ut_holepunch error for {}: {:?}", addr, error)
+                }
+                Err(e) => format!("ut_holepunch: {}", e),
+            },
+            Some(name) => format!("{} message, {} bytes", name, payload.len()),
+            None => format!(
+                "extended message {}, {} bytes, with an ID the receiver never asked for",
+                id,
+                payload.len()
+            ),
+        },
+    }
+}
+
+fn describe_extended_handshake(handshake: &ExtendedHandshake) -> String {
+    let mut text = "extension handshake".to_string();
+    if let Some(version) = &handshake.version {
+        write!(text, " from {:?}", version).unwrap();
+    }
+    let extensions = handshake
+        .extensions
+        .iter()
+        .map(|(name, id)| format!("{}={}", name, id))
+        .collect::<Vec<_>>();
+    if !extensions.is_empty() {
+        write!(text, ", extensions {}", extensions.join(" ")).unwrap();
+    }
+    if let Some(port) = handshake.port {
+        write!(text, ", port {}", port).unwrap();
+    }
+    if let Some(ip) = handshake.your_ip {
+        write!(text, ", sees us as {}", ip).unwrap();
+    }
+    if let Some(queue) = handshake.request_queue {
+        write!(text, ", queues {} requests", queue).unwrap();
+    }
+    if let Some(size) = handshake.metadata_size {
+        write!(text, ", metadata {} bytes", size).unwrap();
+    }
+    text
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,201 @@
+//! `rainyday download` and `rainyday seed`: run a single torrent in the
+//! foreground.
+
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use indicatif::HumanBytes;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use super::exit::Exit;
+use super::notify::{self, Notifier};
+use super::progress::{self, Progress};
+use super::tui;
+use rainyday_engine::config::Config;
+use rainyday_engine::control;
+use rainyday_engine::engine::{Mode, Options};
+use rainyday_engine::event::{Event, EventBus};
+use rainyday_engine::files::Selection;
+use rainyday_engine::input;
+use rainyday_engine::magnet;
+use rainyday_engine::session::Session;
+
+/// Runs the torrents named by `inputs` side by side until `mode` says to
+/// stop or the user presses Ctrl-C, showing the dashboard instead of
+/// progress bars if `tui` is set. Only the files in `selection` are
+/// downloaded.
+pub fn run(
+    config: &Config,
+    inputs: &[String],
+    options: &Options,
+    selection: &Selection,
+    mode: Mode,
+    tui: bool,
+) -> Result<Exit, Box<dyn Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    runtime.block_on(async {
+        if inputs.iter().any(|input| input.starts_with(magnet::SCHEME)) {
+            println!("fetching metadata from peers");
+        }
+        let events = EventBus::default();
+        let session = Arc::new(Session::new(config.clone(), events.clone())?);
+        let loads = inputs
+            .iter()
+            .map(|input| input::load(input, config, session.peer_id()));
+        let metainfos = futures::future::try_join_all(loads).await?;
+        let mut torrents = Vec::new();
+        for metainfo in metainfos {
+            let mut options = options.clone();
+            if !selection.is_empty() {
+                options.file_priorities = selection.priorities(&metainfo.info)?;
+            }
+            let name = options
+                .name
+                .clone()
+                .unwrap_or_else(|| metainfo.info.name.clone());
+            let info_hash = metainfo.info_hash;
+            let handle = session.add(Arc::new(metainfo), &options, mode).await?;
+            torrents.push((info_hash, name, handle));
+        }
+
+        let progress = Progress::many(torrents.len());
+        if !tui {
+            tokio::spawn(report(events.clone(), progress[0].clone()));
+        }
+        let notifier = notify::enabled(config).then(|| {
+            let names = torrents
+                .iter()
+                .map(|(info_hash, name, _)| (*info_hash, name.clone()))
+                .collect();
+            Notifier::start(&events, names)
+        });
+        let (socket, server) = (config.control_socket(), Arc::clone(&session));
+        tokio::spawn(async move {
+            if let Err(e) = control::server::serve(socket.clone(), server).await {
+                warn!("control socket {} unavailable: {}", socket.display(), e);
+            }
+        });
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let (interrupt, flag) = (Arc::clone(&session), Arc::clone(&interrupted));
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                flag.store(true, Ordering::SeqCst);
+                for handle in interrupt.handles() {
+                    handle.shutdown().await;
+                }
+            }
+        });
+        let handles = torrents.iter().map(|(_, _, handle)| handle.clone());
+        if tui {
+            if tui::run(handles.collect(), &events).await? {
+                interrupted.store(true, Ordering::SeqCst);
+            }
+        } else {
+            for (handle, progress) in handles.zip(&progress) {
+                tokio::spawn(progress::watch(handle, progress.clone()));
+            }
+        }
+
+        let mut results = Vec::new();
+        for (info_hash, name, _) in &torrents {
+            let result = session
+                .wait(info_hash)
+                .await
+                .expect("the torrent was just added");
+            results.push((name, result));
+        }
+        session.unmap_port().await;
+        if let Some(notifier) = notifier {
+            notifier.finish().await;
+            for (name, result) in &results {
+                if let Err(e) = result {
+                    notify::failed(name, e).await;
+                }
+            }
+        }
+        for progress in &progress {
+            progress.finish();
+        }
+
+        let several = results.len() > 1;
+        let mut exit = Exit::Success;
+        for (name, result) in results {
+            let summary = match result {
+                Ok(summary) => summary,
+                Err(e) if !several => return Err(e.into()),
+                Err(e) => {
+                    eprintln!("{}: error: {}", name, e);
+                    if exit == Exit::Success {
+                        exit = Exit::of(&e);
+                    }
+                    continue;
+                }
+            };
+            println!(
+                "{}{}: downloaded {} bytes, uploaded {} bytes, saved in {}",
+                if several {
+                    format!("{}: ", name)
+                } else {
+                    String::new()
+                },
+                if summary.complete { "done" } else { "stopped" },
+                summary.downloaded,
+                summary.uploaded,
+                summary.save_path.display()
+            );
+            if !summary.complete && exit == Exit::Success {
+                exit = if interrupted.load(Ordering::SeqCst) {
+                    Exit::Interrupted
+                } else {
+                    Exit::Failure
+                };
+            }
+        }
+        Ok(exit)
+    })
+}
+
+/// Prints the files of each torrent named by `inputs` with the indices
+/// `--files` takes.
+pub fn list_files(config: &Config, inputs: &[String]) -> Result<bool, Box<dyn Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let peer_id = config.peer_id()?;
+    for input in inputs {
+        let metainfo = runtime.block_on(input::load(input, config, peer_id))?;
+        if inputs.len() > 1 {
+            println!("{}:", metainfo.info.name);
+        }
+        for (index, file) in metainfo.info.files.iter().enumerate() {
+            println!(
+                "{:>4}  {:>10}  {}",
+                index,
+                HumanBytes(file.length).to_string(),
+                file.path_buf().display()
+            );
+        }
+    }
+    Ok(true)
+}
+
+/// Prints anything that goes wrong.
+async fn report(events: EventBus, progress: Progress) {
+    let mut events = events.subscribe();
+
+    loop {
+        match events.recv().await {
+            Ok(Event::TrackerError { url, message, .. }) => {
+                progress.println(&format!("tracker {}: {}", url, message));
+            }
+            Ok(Event::PieceFailed { piece, .. }) => progress.println(&format!(
+                "piece {} failed verification; downloading it again",
+                piece
+            )),
+            Ok(Event::DiskFull { message, .. }) => progress.println(&message),
+            Ok(_) | Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
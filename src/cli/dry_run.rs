@@ -0,0 +1,198 @@
+//! `--dry-run`: say what `download` or `seed` would do without touching
+//! the network or the disk.
+
+use std::error::Error;
+
+use indicatif::HumanBytes;
+
+use super::exit::Exit;
+use rainyday_engine::config::{Config, DEFAULT_LISTEN_PORT};
+use rainyday_engine::engine::{Mode, Options};
+use rainyday_engine::files::Selection;
+use rainyday_engine::input;
+use rainyday_engine::interface::Endpoint;
+use rainyday_engine::magnet::{self, Magnet};
+use rainyday_engine::metainfo::Metainfo;
+use rainyday_engine::storage::{part, space, Layout};
+
+/// Checks the configuration, loads each of `inputs` that can be loaded
+/// offline and prints where its data would go. Magnet links and URLs are
+/// only described, since resolving them needs the network.
+pub fn run(
+    config: &Config,
+    inputs: &[String],
+    options: &Options,
+    selection: &Selection,
+    mode: Mode,
+) -> Result<Exit, Box<dyn Error>> {
+    let peer_id = config.peer_id()?;
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    let mut exit = Exit::Success;
+    for input in inputs {
+        if input.starts_with(magnet::SCHEME) {
+            describe_magnet(&input.parse()?);
+        } else if input.starts_with("http://") || input.starts_with("https://") {
+            println!("would download the torrent from {}", input);
+        } else {
+            let metainfo = runtime.block_on(input::load(input, config, peer_id))?;
+            if let Err(e) = plan(config, &metainfo, options, selection) {
+                println!("  problem:      {}", e);
+                if exit == Exit::Success {
+                    exit = Exit::of(e.as_ref());
+                }
+            }
+        }
+        println!();
+    }
+
+    print_settings(config, mode);
+    Ok(exit)
+}
+
+fn describe_magnet(magnet: &Magnet) {
+    let name = magnet.name.as_deref().unwrap_or("(unnamed)");
+    println!("{}: magnet link", name);
+    println!("  info hash:    {}", magnet.info_hash);
+    println!("  would fetch the metadata from peers before planning the layout");
+    for url in &magnet.trackers {
+        println!("  tracker:      {}", url);
+    }
+}
+
+/// Prints the plan for one torrent, failing if it could not be carried
+/// out as things stand.
+fn plan(
+    config: &Config,
+    metainfo: &Metainfo,
+    options: &Options,
+    selection: &Selection,
+) -> Result<(), Box<dyn Error>> {
+    let info = &metainfo.info;
+    let priorities = if selection.is_empty() {
+        Vec::new()
+    } else {
+        selection.priorities(info)?
+    };
+    let mut layout = Layout::new(info);
+    if let Some(name) = &options.name {
+        layout.rename_root(name);
+    }
+
+    // The same choice the engine makes when the torrent is added.
+    let root = if space::used(&config.storage.download_dir, &layout) > 0 {
+        config.storage.download_dir.clone()
+    } else {
+        config.storage.initial_dir().to_path_buf()
+    };
+    if let Some(suffix) = &config.storage.part_suffix {
+        part::apply_suffix(&root, &mut layout, suffix);
+    }
+
+    println!("{}", info.name);
+    println!("  info hash:    {}", metainfo.info_hash);
+    println!(
+        "  size:         {} in {} pieces of {}",
+        HumanBytes(info.total_length()),
+        info.num_pieces(),
+        HumanBytes(u64::from(info.piece_length))
+    );
+    println!("  save to:      {}", root.display());
+    if root != config.storage.download_dir {
+        println!("  then move to: {}", config.storage.download_dir.display());
+    }
+
+    println!("  files:");
+    for (index, slot) in layout.files.iter().enumerate() {
+        let wanted = priorities.get(index).is_none_or(|p| p.is_wanted());
+        println!(
+            "    {:<6}  {:>10}  {}",
+            if wanted { "get" } else { "skip" },
+            HumanBytes(slot.length).to_string(),
+            root.join(&slot.path).display()
+        );
+    }
+
+    for (tier, urls) in metainfo.trackers().iter().enumerate() {
+        for url in urls {
+            println!("  tracker:      [{}] {}", tier, url);
+        }
+    }
+    for url in &metainfo.url_list {
+        println!("  web seed:     {}", url);
+    }
+
+    let existing = space::used(&root, &layout);
+    let needed = layout.total_length - existing;
+    println!(
+        "  on disk:      {} already, {} still to claim",
+        HumanBytes(existing),
+        HumanBytes(needed)
+    );
+    space::ensure(&root, &layout, config.limits.disk_quota)?;
+    Ok(())
+}
+
+fn print_settings(config: &Config, mode: Mode) {
+    let listen: Vec<String> = config
+        .network
+        .listen
+        .iter()
+        .map(Endpoint::to_string)
+        .collect();
+    match (config.network.port_range, config.network.port) {
+        _ if !listen.is_empty() => println!("listen on:      {}", listen.join(", ")),
+        (Some(range), _) if config.network.keep_port => {
+            println!("listen on:      a port in {}, kept across restarts", range)
+        }
+        (Some(range), _) => println!("listen on:      a port in {}", range),
+        (None, Some(0)) => println!("listen on:      any free port"),
+        (None, Some(port)) => println!("listen on:      port {}", port),
+        (None, None) => println!(
+            "listen on:      port {}, or any free port if taken",
+            DEFAULT_LISTEN_PORT
+        ),
+    }
+    if let Some(interface) = &config.network.interface {
+        println!("interface:      {}", interface);
+    }
+    if let Some(range) = config.network.source_ports {
+        println!("source ports:   {}", range);
+    }
+    let rate = |limit: Option<u64>| match limit {
+        Some(bytes) => format!("{}/s", HumanBytes(bytes)),
+        None => "unlimited".to_string(),
+    };
+    println!("download rate:  {}", rate(config.limits.download_rate));
+    println!("upload rate:    {}", rate(config.limits.upload_rate));
+    if let Some(budget) = config.limits.memory_budget {
+        println!("piece memory:   {} at most", HumanBytes(budget));
+    }
+    println!(
+        "peers:          {} per torrent, {} in all",
+        config.network.max_peers, config.network.max_connections
+    );
+    let dht = match (config.dht.enabled, config.proxy.blocks_dht()) {
+        (false, _) => "off",
+        (true, true) => "off, since it cannot go through the proxy",
+        (true, false) => "on",
+    };
+    println!("dht:            {}", dht);
+    match mode {
+        Mode::Download => println!("when done:      stop"),
+        Mode::Seed(goal) => {
+            let mut until = Vec::new();
+            if let Some(ratio) = goal.ratio {
+                until.push(format!("ratio {}", ratio));
+            }
+            if let Some(time) = goal.time {
+                until.push(format!("{}s seeded", time.as_secs()));
+            }
+            if until.is_empty() {
+                println!("when done:      seed until interrupted");
+            } else {
+                println!("when done:      seed until {}", until.join(" or "));
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! `rainyday edit`: change a `.torrent` file's trackers, web seeds,
+//! comment or private flag.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use rainyday_engine::edit::{edit, Edit};
+use rainyday_engine::metainfo::Metainfo;
+
+pub fn run(torrent: &Path, output: Option<&Path>, changes: &Edit) -> Result<bool, Box<dyn Error>> {
+    let bytes = fs::read(torrent).map_err(|e| format!("{}: {}", torrent.display(), e))?;
+    let before = Metainfo::from_bytes(&bytes)?.info_hash;
+    let edited = edit(&bytes, changes)?;
+    let after = Metainfo::from_bytes(&edited)?.info_hash;
+
+    let output = output.unwrap_or(torrent);
+    fs::write(output, edited)?;
+    println!("wrote {}", output.display());
+    if after != before {
+        println!("info hash changed from {} to {}", before, after);
+    }
+    Ok(true)
+}
@@ -0,0 +1,94 @@
+//! Exit statuses, so that scripts can tell what went wrong.
+
+use std::error::Error;
+
+use rainyday_engine::config::ConfigError;
+use rainyday_engine::edit::EditError;
+use rainyday_engine::engine::EngineError;
+use rainyday_engine::files::SelectionError;
+use rainyday_engine::input::InputError;
+use rainyday_engine::magnet::MagnetError;
+use rainyday_engine::metainfo::MetainfoError;
+use rainyday_engine::session::SessionError;
+use rainyday_engine::storage::StorageError;
+use rainyday_engine::tracker::TrackerError;
+
+/// The exit statuses, as listed by `--help`.
+pub const HELP: &str = "\
+Exit status:
+  0    success
+  1    other failure, e.g. data that failed verification
+  2    invalid usage
+  3    configuration error
+  4    the torrent could not be loaded or parsed
+  5    no tracker could be reached
+  6    disk error
+  130  interrupted before the download was complete";
+
+/// How a command ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+    Success,
+    Failure,
+    Usage,
+    Config,
+    Metainfo,
+    Tracker,
+    Disk,
+    Interrupted,
+}
+
+impl Exit {
+    pub fn code(self) -> i32 {
+        match self {
+            Exit::Success => 0,
+            Exit::Failure => 1,
+            Exit::Usage => 2,
+            Exit::Config => 3,
+            Exit::Metainfo => 4,
+            Exit::Tracker => 5,
+            Exit::Disk => 6,
+            // 128 plus SIGINT, as shells report it.
+            Exit::Interrupted => 130,
+        }
+    }
+
+    /// The status for a command that failed with `error`.
+    pub fn of(error: &(dyn Error + 'static)) -> Exit {
+        if error.is::<SelectionError>() {
+            Exit::Usage
+        } else if error.is::<ConfigError>() {
+            Exit::Config
+        } else if error.is::<InputError>()
+            || error.is::<MetainfoError>()
+            || error.is::<MagnetError>()
+        {
+            Exit::Metainfo
+        } else if let Some(EditError::Metainfo(_) | EditError::Bencode(_)) = error.downcast_ref() {
+            Exit::Metainfo
+        } else if error.is::<TrackerError>() {
+            Exit::Tracker
+        } else if error.is::<StorageError>() {
+            Exit::Disk
+        } else if let Some(SessionError::Engine(error)) = error.downcast_ref() {
+            Exit::of(error)
+        } else if let Some(error) = error.downcast_ref::<EngineError>() {
+            match error {
+                EngineError::Storage(_) | EngineError::DiskFull(_) => Exit::Disk,
+                _ => Exit::Failure,
+            }
+        } else {
+            Exit::Failure
+        }
+    }
+}
+
+impl From<bool> for Exit {
+    fn from(success: bool) -> Self {
+        if success {
+            Exit::Success
+        } else {
+            Exit::Failure
+        }
+    }
+}
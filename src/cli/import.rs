@@ -0,0 +1,160 @@
+//! `rainyday import`: adopt data downloaded by another client, or every
+//! torrent of a qBittorrent or Transmission session.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rainyday_engine::config::Config;
+use rainyday_engine::import::{self, Client};
+use rainyday_engine::metainfo::Metainfo;
+use rainyday_engine::resume::ResumeData;
+use rainyday_engine::session::store::{TorrentOptions, TorrentStats};
+use rainyday_engine::session::{SessionStore, TorrentRecord};
+use rainyday_engine::storage::{FileStorage, Layout};
+use rainyday_engine::verify::recheck;
+
+/// Suffixes other clients give files they have not finished: qBittorrent's
+/// and Transmission's.
+const PARTIAL_SUFFIXES: &[&str] = &[".!qB", ".part"];
+
+pub fn run(config: &Config, torrent: &Path, data: &Path) -> Result<bool, Box<dyn Error>> {
+    let state_dir = state_dir(config)?;
+    let metainfo_bytes = fs::read(torrent)?;
+    let metainfo = Metainfo::try_from(torrent)?;
+    let info = &metainfo.info;
+
+    let name = data
+        .file_name()
+        .ok_or("data path has no file name")?
+        .to_string_lossy()
+        .into_owned();
+    let root = data.parent().unwrap_or_else(|| Path::new(""));
+    let mut layout = Layout::new(info);
+    layout.rename_root(&name);
+
+    for slot in &layout.files {
+        adopt_partial(&root.join(&slot.path))?;
+    }
+
+    let storage = FileStorage::open_read_only(root, layout);
+    let have = recheck(&storage, info, |_, _| {})?;
+    println!(
+        "{}: {}/{} pieces valid",
+        info.name,
+        have.count(),
+        info.num_pieces()
+    );
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let save_path = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let record = TorrentRecord {
+        info_hash: metainfo.info_hash,
+        metainfo: metainfo_bytes,
+        options: TorrentOptions {
+            save_path: save_path.clone(),
+            name: Some(name).filter(|name| *name != info.name),
+            ..TorrentOptions::default()
+        },
+        stats: TorrentStats {
+            added_at: now,
+            completed_at: Some(now).filter(|_| have.is_full()),
+            ..TorrentStats::default()
+        },
+        resume: Some(ResumeData {
+            info_hash: metainfo.info_hash,
+            save_path,
+            have: have.clone(),
+            uploaded: 0,
+            downloaded: 0,
+        }),
+    };
+
+    let store = SessionStore::open(state_dir)?;
+    let replaced = store.contains(&record.info_hash)?;
+    store.insert(&record)?;
+    store.flush()?;
+
+    let state = if have.is_full() {
+        "seeded"
+    } else {
+        "completed"
+    };
+    println!(
+        "{} {}; it will be {} when the session starts",
+        if replaced { "updated" } else { "imported" },
+        metainfo.info_hash,
+        state
+    );
+    Ok(true)
+}
+
+/// Imports every torrent `client` keeps resume data for in `dir`, with
+/// the settings it had and the pieces it verified.
+pub fn from_client(config: &Config, client: Client, dir: &Path) -> Result<bool, Box<dyn Error>> {
+    let state_dir = state_dir(config)?;
+    let torrents = import::read_dir(client, dir)
+        .map_err(|e| format!("cannot read {}: {}", dir.display(), e))?;
+    if torrents.is_empty() {
+        return Err(format!("no {} resume data in {}", client, dir.display()).into());
+    }
+
+    let store = SessionStore::open(state_dir)?;
+    let mut all_imported = true;
+    for (path, record) in torrents {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                all_imported = false;
+                continue;
+            }
+        };
+        let replaced = store.contains(&record.info_hash)?;
+        store.insert(&record)?;
+        let name = Metainfo::from_bytes(&record.metainfo)?.info.name;
+        let pieces = match &record.resume {
+            Some(resume) => format!("{}/{} pieces valid", resume.have.count(), resume.have.len()),
+            None => "pieces to be checked".to_string(),
+        };
+        println!(
+            "{} {} ({}): {}",
+            if replaced { "updated" } else { "imported" },
+            name,
+            record.info_hash,
+            pieces
+        );
+    }
+    store.flush()?;
+    Ok(all_imported)
+}
+
+fn state_dir(config: &Config) -> Result<&Path, Box<dyn Error>> {
+    Ok(config
+        .state_dir
+        .as_deref()
+        .ok_or("importing needs `state_dir` set in the configuration")?)
+}
+
+/// Gives a file another client left with a partial suffix its real name,
+/// unless a file by that name already exists.
+fn adopt_partial(path: &Path) -> Result<(), Box<dyn Error>> {
+    if path.exists() {
+        return Ok(());
+    }
+    for suffix in PARTIAL_SUFFIXES {
+        let mut partial = PathBuf::from(path);
+        partial.as_mut_os_string().push(suffix);
+        if partial.is_file() {
+            println!("renaming {} to {}", partial.display(), path.display());
+            fs::rename(&partial, path)?;
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! `rainyday inspect`: print what a `.torrent` file describes.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::path::Path;
+
+use serde_json::json;
+
+use rainyday_engine::metainfo::Metainfo;
+
+pub fn run(torrent: &Path, json: bool) -> Result<bool, Box<dyn Error>> {
+    let metainfo = Metainfo::try_from(torrent)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&to_json(&metainfo))?);
+        return Ok(true);
+    }
+
+    let info = &metainfo.info;
+    println!("name:         {}", info.name);
+    println!("info hash:    {}", metainfo.info_hash);
+    println!("size:         {} bytes", info.total_length());
+    println!(
+        "pieces:       {} x {} bytes",
+        info.num_pieces(),
+        info.piece_length
+    );
+    println!("private:      {}", if info.private { "yes" } else { "no" });
+    if let Some(comment) = &metainfo.comment {
+        println!("comment:      {}", comment);
+    }
+    if let Some(created_by) = &metainfo.created_by {
+        println!("created by:   {}", created_by);
+    }
+    if let Some(date) = metainfo.creation_date {
+        println!("created:      {}", date);
+    }
+
+    let trackers = metainfo.trackers();
+    if !trackers.is_empty() {
+        println!("trackers:");
+        for (tier, urls) in trackers.iter().enumerate() {
+            for url in urls {
+                println!("  [{}] {}", tier, url);
+            }
+        }
+    }
+    if !metainfo.url_list.is_empty() {
+        println!("web seeds:");
+        for url in &metainfo.url_list {
+            println!("  {}", url);
+        }
+    }
+
+    println!("files:");
+    for file in &info.files {
+        println!("  {:>14}  {}", file.length, file.path_buf().display());
+    }
+    Ok(true)
+}
+
+fn to_json(metainfo: &Metainfo) -> serde_json::Value {
+    let info = &metainfo.info;
+    let files: Vec<_> = info
+        .files
+        .iter()
+        .map(|file| json!({ "path": file.path_buf(), "length": file.length }))
+        .collect();
+    json!({
+        "name": info.name,
+        "info_hash": metainfo.info_hash,
+        "length": info.total_length(),
+        "piece_length": info.piece_length,
+        "pieces": info.num_pieces(),
+        "private": info.private,
+        "comment": metainfo.comment,
+        "created_by": metainfo.created_by,
+        "creation_date": metainfo.creation_date,
+        "trackers": metainfo.trackers(),
+        "web_seeds": metainfo.url_list,
+        "files": files,
+    })
+}
@@ -0,0 +1,35 @@
+//! `rainyday list`: one line for each torrent of a running rainyday.
+
+use std::error::Error;
+
+use indicatif::HumanBytes;
+
+use rainyday_engine::config::Config;
+use rainyday_engine::control::{client, Request, Response};
+
+pub fn run(config: &Config, json: bool) -> Result<bool, Box<dyn Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let request = Request::Status { torrent: None };
+    let torrents = match runtime.block_on(client::request(&config.control_socket(), &request))? {
+        Response::Status { torrents } => torrents,
+        response => return Err(format!("unexpected response {:?}", response).into()),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&torrents)?);
+        return Ok(true);
+    }
+    for status in &torrents {
+        let hash = status.info_hash.to_string();
+        println!(
+            "{}  {:<11} {:>5.1}%  down {:>10}/s  up {:>10}/s  {}",
+            &hash[..8],
+            status.state.to_string(),
+            100.0 * status.progress(),
+            HumanBytes(status.download_rate).to_string(),
+            HumanBytes(status.upload_rate).to_string(),
+            status.name
+        );
+    }
+    Ok(true)
+}
@@ -0,0 +1,189 @@
+//! Diagnostic logging to standard error, and to a file if configured.
+//!
+//! How much is logged is set with `-v`/`-q`, and can be tuned per subsystem
+//! with `--log` or `RAINYDAY_LOG`, e.g. `--log tracker=debug,protocol=trace`.
+//! The `[logging]` section sets levels for the console and the file alike;
+//! see [`rainyday_engine::logfile`]. Builds with the `otlp` feature can also export
+//! spans; see [`super::otlp`].
+
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use thiserror::Error;
+use tracing::level_filters::LevelFilter;
+use tracing::Subscriber;
+use tracing_subscriber::filter::ParseError;
+use tracing_subscriber::layer::SubscriberExt as _;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt as _;
+use tracing_subscriber::{EnvFilter, Layer};
+
+#[cfg(feature = "otlp")]
+use super::otlp;
+use rainyday_engine::logfile::{Level, LoggingConfig, RotatingFile};
+
+/// Environment variable read when `--log` is not given.
+pub const ENV_VAR: &str = "RAINYDAY_LOG";
+
+/// Names that can be used in a filter in place of module paths.
+const SUBSYSTEMS: &[(&str, &[&str])] = &[
+    ("protocol", &["rainyday::protocol", "rainyday::peer"]),
+    ("tracker", &["rainyday::tracker"]),
+    ("dht", &["rainyday::dht"]),
+    ("storage", &["rainyday::storage", "rainyday::files"]),
+];
+
+/// Spans exported over OTLP, whatever is logged.
+#[cfg(feature = "otlp")]
+const SPAN_FILTER: &str = "rainyday=info";
+
+#[derive(Debug, Error)]
+pub enum LoggingError {
+    #[error("invalid log filter: {0}")]
+    Filter(#[from] ParseError),
+    #[error("cannot open log file {}: {source}", path.display())]
+    File { path: PathBuf, source: io::Error },
+}
+
+/// Keeps the logger installed. Spans not yet exported are sent when it is
+/// dropped.
+pub struct Logging {
+    #[cfg(feature = "otlp")]
+    _exporter: Option<otlp::Exporter>,
+}
+
+/// A logger for the console alone, for use until the configuration has
+/// been loaded.
+pub fn console(
+    verbosity: i8,
+    filter: Option<&str>,
+) -> Result<impl Subscriber + Send + Sync, LoggingError> {
+    let console = console_layer(verbosity, filter, &BTreeMap::new())?;
+    Ok(tracing_subscriber::registry().with(console))
+}
+
+/// Installs the logger. `verbosity` counts `-v` up and `-q` down from the
+/// default of warnings only; the levels in `config` and then `filter` are
+/// added on top.
+pub fn init(
+    verbosity: i8,
+    filter: Option<&str>,
+    config: &LoggingConfig,
+) -> Result<Logging, LoggingError> {
+    let console = console_layer(verbosity, filter, &config.levels)?;
+    let file = match &config.file {
+        Some(path) => {
+            let writer = RotatingFile::open(path, config).map_err(|source| LoggingError::File {
+                path: path.clone(),
+                source,
+            })?;
+            let mut directives = base_directives(config.file_level.0);
+            directives.extend(configured(&config.levels));
+            let layer = tracing_subscriber::fmt::layer()
+                .json()
+                .with_ansi(false)
+                .with_writer(Mutex::new(writer))
+                .with_filter(EnvFilter::builder().parse(directives.join(","))?);
+            Some(layer)
+        }
+        None => None,
+    };
+    let registry = tracing_subscriber::registry().with(console).with(file);
+
+    #[cfg(feature = "otlp")]
+    {
+        let (layer, exporter, error) = match otlp::layer() {
+            Ok(Some((layer, exporter))) => (Some(layer), Some(exporter), None),
+            Ok(None) => (None, None, None),
+            Err(e) => (None, None, Some(e)),
+        };
+        let spans = EnvFilter::builder().parse(SPAN_FILTER)?;
+        registry.with(layer.with_filter(spans)).init();
+        if let Some(e) = error {
+            tracing::warn!("not exporting spans: {}", e);
+        }
+        Ok(Logging {
+            _exporter: exporter,
+        })
+    }
+    #[cfg(not(feature = "otlp"))]
+    {
+        registry.init();
+        Ok(Logging {})
+    }
+}
+
+fn console_layer<S>(
+    verbosity: i8,
+    filter: Option<&str>,
+    levels: &BTreeMap<String, Level>,
+) -> Result<impl Layer<S>, ParseError>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let level = match verbosity {
+        i8::MIN..=-2 => LevelFilter::OFF,
+        -1 => LevelFilter::ERROR,
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let mut directives = base_directives(level);
+    directives.extend(configured(levels));
+    let from_env = env::var(ENV_VAR).ok();
+    if let Some(filter) = filter.or(from_env.as_deref()) {
+        directives.extend(expand(filter));
+    }
+
+    let filter = EnvFilter::builder().parse(directives.join(","))?;
+    Ok(tracing_subscriber::fmt::layer()
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .with_filter(filter))
+}
+
+/// Logs rainyday's messages at `level`. Other crates' logs are only
+/// interesting once something goes wrong.
+fn base_directives(level: LevelFilter) -> Vec<String> {
+    vec![
+        LevelFilter::WARN.min(level).to_string(),
+        format!("rainyday={}", level),
+    ]
+}
+
+/// Directives for the levels set in the configuration file. Later
+/// directives for the same module win, so these come before `--log`.
+fn configured(levels: &BTreeMap<String, Level>) -> Vec<String> {
+    levels
+        .iter()
+        .flat_map(|(target, level)| expand(&format!("{}={}", target, level)))
+        .collect()
+}
+
+/// Rewrites the subsystem names in `filter` into the modules they cover,
+/// leaving everything else alone.
+fn expand(filter: &str) -> Vec<String> {
+    let mut directives = Vec::new();
+    for directive in filter.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        let (target, level) = match directive.split_once('=') {
+            Some((target, level)) => (target, Some(level)),
+            None => (directive, None),
+        };
+        match SUBSYSTEMS.iter().find(|(name, _)| *name == target) {
+            Some((_, modules)) => {
+                for module in modules.iter() {
+                    directives.push(match level {
+                        Some(level) => format!("{}={}", module, level),
+                        None => module.to_string(),
+                    });
+                }
+            }
+            None => directives.push(directive.to_string()),
+        }
+    }
+    directives
+}
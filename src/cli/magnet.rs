@@ -0,0 +1,14 @@
+//! `rainyday magnet`: print the magnet link for a `.torrent`.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::path::Path;
+
+use rainyday_engine::magnet::Magnet;
+use rainyday_engine::metainfo::Metainfo;
+
+pub fn run(torrent: &Path) -> Result<bool, Box<dyn Error>> {
+    let metainfo = Metainfo::try_from(torrent)?;
+    println!("{}", Magnet::from_metainfo(&metainfo));
+    Ok(true)
+}
@@ -0,0 +1,607 @@
+//! Command-line interface.
+
+pub mod benchmark;
+pub mod config;
+pub mod control;
+pub mod convert;
+pub mod create;
+pub mod daemon;
+pub mod dissect;
+pub mod download;
+pub mod dry_run;
+pub mod edit;
+pub mod exit;
+pub mod import;
+pub mod inspect;
+pub mod list;
+pub mod logging;
+pub mod magnet;
+pub mod notify;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod peers;
+pub mod progress;
+pub mod scrape;
+pub mod session;
+pub mod status;
+pub mod systemd;
+pub mod tui;
+pub mod verify;
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{ArgAction, Args, Parser, Subcommand};
+use glob::Pattern;
+use tracing::debug;
+
+use rainyday_engine::config::{Config, ConfigError, PortRange};
+use rainyday_engine::engine::SeedGoal;
+use rainyday_engine::files::{parse_indices, Selection, SelectionError};
+use rainyday_engine::import::Client;
+use rainyday_engine::storage::sanitize::{sanitize_component, PathError};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "rainyday",
+    version,
+    about = "A minimalist BitTorrent client",
+    after_long_help = exit::HELP,
+    arg_required_else_help = true
+)]
+pub struct Opts {
+    /// Path to the configuration file, instead of `config.toml` in the
+    /// user's configuration directory (`$XDG_CONFIG_HOME/rainyday` on Linux)
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Apply the settings of this `[profile.<name>]` table of the
+    /// configuration file over the rest of it
+    #[arg(short, long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Log more; repeat for even more detail
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Log less; repeat to log nothing at all
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "verbose")]
+    pub quiet: u8,
+
+    /// Log levels per subsystem (protocol, tracker, dht, storage) or
+    /// module, e.g. `tracker=debug,protocol=trace`; defaults to
+    /// $RAINYDAY_LOG
+    #[arg(long, global = true, value_name = "FILTER")]
+    pub log: Option<String>,
+
+    /// Print the configuration in effect and exit: the defaults, overridden
+    /// by the configuration file, then the environment, then the command's
+    /// own options
+    #[arg(long, global = true)]
+    pub print_config: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Opts {
+    /// How far `-v` and `-q` move the log level from its default.
+    pub fn verbosity(&self) -> i8 {
+        self.verbose.min(i8::MAX as u8) as i8 - self.quiet.min(i8::MAX as u8) as i8
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Download a torrent and exit once it is complete
+    Download {
+        /// The .torrent files (`-` for standard input), their URLs, or
+        /// magnet links to download; several run side by side
+        #[arg(required = true)]
+        torrents: Vec<String>,
+        /// Save the download here instead of the download directory
+        #[arg(short, long, value_name = "DIR")]
+        out: Option<PathBuf>,
+        /// Save the top-level file or directory under this name instead of
+        /// the one chosen by the torrent's author; one torrent only
+        #[arg(long, value_parser = parse_name)]
+        name: Option<String>,
+        /// Keep unfinished downloads here, moving them once complete
+        #[arg(long)]
+        incomplete_dir: Option<PathBuf>,
+        /// Show a full-screen dashboard instead of a progress bar
+        #[arg(long)]
+        tui: bool,
+        #[command(flatten)]
+        files: FileChoice,
+        /// Keep seeding once the download is complete
+        #[arg(long)]
+        seed: bool,
+        /// Download pieces in order, so files can be used before they are
+        /// complete
+        #[arg(long)]
+        sequential: bool,
+        #[command(flatten)]
+        limits: SeedLimits,
+        #[command(flatten)]
+        rates: RateLimits,
+        #[command(flatten)]
+        listen: Listen,
+        /// Print what would be done without connecting to anyone or
+        /// writing anything
+        #[arg(long, conflicts_with = "tui")]
+        dry_run: bool,
+    },
+    /// Serve a torrent's data to other peers until interrupted or a
+    /// seeding limit is reached
+    Seed {
+        /// The .torrent files (`-` for standard input), their URLs, or
+        /// magnet links to seed
+        #[arg(required = true)]
+        torrents: Vec<String>,
+        /// Directory containing the data, instead of the download directory
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        /// Name of the top-level file or directory, if it differs from the
+        /// torrent's; one torrent only
+        #[arg(long, value_parser = parse_name)]
+        name: Option<String>,
+        /// Show a full-screen dashboard instead of a progress bar
+        #[arg(long)]
+        tui: bool,
+        #[command(flatten)]
+        limits: SeedLimits,
+        #[command(flatten)]
+        rates: RateLimits,
+        #[command(flatten)]
+        listen: Listen,
+        /// Print what would be done without connecting to anyone or
+        /// writing anything
+        #[arg(long, conflicts_with = "tui")]
+        dry_run: bool,
+    },
+    /// Make a .torrent file from a file or directory
+    Create {
+        /// The file or directory to share
+        path: PathBuf,
+        /// Tracker URL; repeat for backups, separate a tier's URLs with commas
+        #[arg(short, long = "tracker")]
+        trackers: Vec<String>,
+        /// Web seed URL (BEP 19)
+        #[arg(long = "web-seed")]
+        web_seeds: Vec<String>,
+        /// Piece length in bytes, chosen from the total size by default
+        #[arg(long)]
+        piece_length: Option<u32>,
+        /// Only allow peers from the trackers
+        #[arg(long)]
+        private: bool,
+        #[arg(long)]
+        comment: Option<String>,
+        /// Where to write the torrent, `<name>.torrent` by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Change a .torrent file's trackers, web seeds, comment or private
+    /// flag, keeping its info hash
+    Edit {
+        torrent: PathBuf,
+        /// Tracker URL to add; repeat for more, separate a tier's URLs with
+        /// commas
+        #[arg(long = "add-tracker", value_name = "URL")]
+        add_trackers: Vec<String>,
+        /// Tracker URL to remove
+        #[arg(long = "remove-tracker", value_name = "URL")]
+        remove_trackers: Vec<String>,
+        /// Web seed URL (BEP 19) to add
+        #[arg(long = "add-web-seed", value_name = "URL")]
+        add_web_seeds: Vec<String>,
+        /// Web seed URL to remove
+        #[arg(long = "remove-web-seed", value_name = "URL")]
+        remove_web_seeds: Vec<String>,
+        /// Replace the comment; an empty one removes it
+        #[arg(long)]
+        comment: Option<String>,
+        /// Only allow peers from the trackers (changes the info hash)
+        #[arg(long, conflicts_with = "public")]
+        private: bool,
+        /// Allow peers from anywhere (changes the info hash)
+        #[arg(long)]
+        public: bool,
+        /// Make changes even if they alter the info hash
+        #[arg(long)]
+        force: bool,
+        /// Where to write the torrent, instead of overwriting it
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Show what a .torrent file describes
+    Inspect {
+        torrent: PathBuf,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check existing data against a torrent's piece hashes
+    Verify {
+        /// The .torrent file describing the data
+        torrent: PathBuf,
+        /// The downloaded file, or directory for multi-file torrents
+        data: PathBuf,
+    },
+    /// Ask a torrent's trackers for its seeder and leecher counts
+    Scrape {
+        torrent: PathBuf,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Measure piece hashing, disk write and message framing speeds, to
+    /// find what limits transfers
+    Benchmark {
+        /// How much data each measurement processes
+        #[arg(long, default_value = "256MiB", value_parser = parse_size)]
+        size: u64,
+        /// Where to write the scratch data, instead of the download
+        /// directory
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+    /// Print the magnet link for a .torrent file
+    Magnet { torrent: PathBuf },
+    /// Decode the BitTorrent connections in a pcap or pcapng capture, to
+    /// debug talking to other clients
+    Dissect { capture: PathBuf },
+    /// Turn a .torrent into a magnet link, or fetch a magnet link's
+    /// metadata from peers and write it out as a .torrent
+    Convert {
+        /// The .torrent file (`-` for standard input), its URL, or a magnet
+        /// link
+        torrent: String,
+        /// Where to write the result; for a magnet link, `<name>.torrent` by
+        /// default, otherwise standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Run in the background, taking torrents over the control socket
+    Daemon {
+        #[command(flatten)]
+        rates: RateLimits,
+        #[command(flatten)]
+        listen: Listen,
+    },
+    /// Add a torrent to a running daemon
+    Add {
+        /// The .torrent file, its URL, or a magnet link
+        torrent: String,
+        /// Save the download here instead of the daemon's download directory
+        #[arg(short, long, value_name = "DIR")]
+        out: Option<PathBuf>,
+        /// Most to download per second for this torrent, within the
+        /// daemon's limit
+        #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+        max_download: Option<u64>,
+        /// Most to upload per second for this torrent, within the daemon's
+        /// limit
+        #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+        max_upload: Option<u64>,
+        #[command(flatten)]
+        limits: SeedLimits,
+        /// Download pieces in order, so files can be used before they are
+        /// complete
+        #[arg(long)]
+        sequential: bool,
+    },
+    /// Stop a torrent in a running daemon and forget it
+    Remove {
+        /// The torrent, by name or info hash prefix
+        torrent: String,
+    },
+    /// List the torrents of a running rainyday
+    List {
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the state and statistics of the torrents a running rainyday
+    /// is handling
+    Status {
+        /// Only show this torrent, by name or info hash prefix
+        torrent: Option<String>,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Make a running torrent announce to its trackers now
+    Announce {
+        /// The torrent, by name or info hash prefix
+        torrent: String,
+    },
+    /// Pause a running torrent, disconnecting from its peers
+    Pause {
+        /// The torrent, by name or info hash prefix
+        torrent: String,
+    },
+    /// Resume a paused torrent
+    Resume {
+        /// The torrent, by name or info hash prefix
+        torrent: String,
+    },
+    /// Pause every torrent of a running rainyday, and those added later,
+    /// until resume-session
+    PauseSession,
+    /// Resume the torrents pause-session paused
+    ResumeSession,
+    /// Make a running torrent verify its data on disk again
+    Recheck {
+        /// The torrent, by name or info hash prefix
+        torrent: String,
+    },
+    /// Move a running torrent's data to another directory without stopping
+    /// it
+    Move {
+        /// The torrent, by name or info hash prefix
+        torrent: String,
+        /// The directory to keep the data in from now on
+        dir: PathBuf,
+    },
+    /// Rename a running torrent's file, or its top-level directory
+    Rename {
+        /// The torrent, by name or info hash prefix
+        torrent: String,
+        /// The new name
+        #[arg(value_parser = parse_name)]
+        name: String,
+    },
+    /// List the peers a running rainyday is connected to
+    Peers {
+        /// Only show this torrent, by name or info hash prefix
+        torrent: Option<String>,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Adopt data downloaded by another client and add it to the session
+    Import {
+        /// The .torrent file describing the data, or with --from, the
+        /// directory holding the other client's resume data: qBittorrent's
+        /// BT_backup, or Transmission's configuration directory
+        torrent: PathBuf,
+        /// The downloaded file, or directory for multi-file torrents
+        #[arg(required_unless_present = "from", conflicts_with = "from")]
+        data: Option<PathBuf>,
+        /// Import every torrent of another client, qbittorrent or
+        /// transmission, keeping its settings and the pieces it verified
+        #[arg(long, value_name = "CLIENT")]
+        from: Option<Client>,
+    },
+    /// Write every torrent of the session, with its settings and resume
+    /// data, to one file
+    ExportSession {
+        /// The file to write
+        path: PathBuf,
+    },
+    /// Add the torrents of a file written by export-session to the session
+    ImportSession {
+        /// The file to read
+        path: PathBuf,
+        /// Where the torrents' data is on this machine, if not where it
+        /// was on the one they were exported from
+        #[arg(long, value_name = "DIR")]
+        save_path: Option<PathBuf>,
+    },
+    /// Manage the configuration file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Write a commented configuration file with every default
+    Init {
+        /// Where to write it, instead of the file rainyday reads by default
+        path: Option<PathBuf>,
+        /// Replace the file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Which of a torrent's files to download.
+#[derive(Debug, Args)]
+pub struct FileChoice {
+    /// Download only these files, by index, e.g. `0,2,5-7`; one torrent
+    /// only
+    #[arg(long, value_name = "LIST")]
+    pub files: Option<String>,
+    /// Download only files whose path matches this glob; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<Pattern>,
+    /// Skip files whose path matches this glob; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<Pattern>,
+    /// List the torrent's files with their indices and exit
+    #[arg(long)]
+    pub list_files: bool,
+}
+
+impl FileChoice {
+    pub fn selection(&self) -> Result<Selection, SelectionError> {
+        Ok(Selection {
+            indices: match &self.files {
+                Some(list) => parse_indices(list)?,
+                None => Vec::new(),
+            },
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+        })
+    }
+}
+
+/// When to stop seeding.
+#[derive(Debug, Args)]
+pub struct SeedLimits {
+    /// Stop seeding once this much has been uploaded per byte downloaded
+    #[arg(long, value_name = "RATIO")]
+    pub seed_ratio: Option<f64>,
+    /// Stop seeding after this long, e.g. `90m` or `2days`
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub seed_time: Option<Duration>,
+}
+
+impl SeedLimits {
+    pub fn is_set(&self) -> bool {
+        self.seed_ratio.is_some() || self.seed_time.is_some()
+    }
+
+    pub fn goal(&self) -> SeedGoal {
+        SeedGoal {
+            ratio: self.seed_ratio,
+            time: self.seed_time,
+        }
+    }
+}
+
+impl Command {
+    /// Applies the options that override the configuration, so that they
+    /// take precedence over the file and the environment.
+    pub fn apply(&self, config: &mut Config) {
+        match self {
+            Command::Download {
+                out,
+                incomplete_dir,
+                rates,
+                listen,
+                ..
+            } => {
+                rates.apply(config);
+                listen.apply(config);
+                if let Some(out) = out {
+                    config.storage.download_dir = out.clone();
+                }
+                if incomplete_dir.is_some() {
+                    config.storage.incomplete_dir = incomplete_dir.clone();
+                }
+            }
+            Command::Seed {
+                dir, rates, listen, ..
+            } => {
+                rates.apply(config);
+                listen.apply(config);
+                if let Some(dir) = dir {
+                    config.storage.download_dir = dir.clone();
+                    config.storage.incomplete_dir = None;
+                }
+            }
+            Command::Daemon { rates, listen } => {
+                rates.apply(config);
+                listen.apply(config);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Bandwidth limits for this run, overriding the configuration file.
+#[derive(Debug, Args)]
+pub struct RateLimits {
+    /// Most to download per second across all torrents, e.g. `2MiB` or
+    /// `500k`; `0` for no limit
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    pub max_download: Option<u64>,
+    /// Most to upload per second across all torrents; `0` for no limit
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    pub max_upload: Option<u64>,
+}
+
+impl RateLimits {
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(rate) = self.max_download {
+            config.limits.download_rate = Some(rate).filter(|&rate| rate > 0);
+        }
+        if let Some(rate) = self.max_upload {
+            config.limits.upload_rate = Some(rate).filter(|&rate| rate > 0);
+        }
+    }
+}
+
+/// Where to accept peer connections, overriding the configuration file.
+#[derive(Debug, Args)]
+pub struct Listen {
+    /// Port to accept peer connections on; `0` for any free port
+    #[arg(long, conflicts_with = "port_range")]
+    pub port: Option<u16>,
+    /// Accept peer connections on a port picked at random from this range,
+    /// e.g. `6881-6999`
+    #[arg(long, value_name = "FIRST-LAST")]
+    pub port_range: Option<PortRange>,
+}
+
+impl Listen {
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(port) = self.port {
+            config.network.port = Some(port);
+            config.network.port_range = None;
+        }
+        if let Some(range) = self.port_range {
+            config.network.port = None;
+            config.network.port_range = Some(range);
+        }
+    }
+}
+
+/// Bytes per second, such as `1500`, `500k`, `2MiB` or `1.5MB/s`.
+fn parse_rate(rate: &str) -> Result<u64, String> {
+    let lower = rate.trim().to_ascii_lowercase();
+    parse_size(lower.strip_suffix("/s").unwrap_or(&lower))
+}
+
+/// A number of bytes, such as `1500`, `500k` or `2MiB`. Units ending in `i`
+/// are powers of 1024, the others powers of 1000.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid size {:?}; expected e.g. 500k or 2MiB", size);
+    let lower = size.trim().to_ascii_lowercase();
+    let split = lower
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let unit = unit.trim_start();
+    let unit = unit.strip_suffix('b').unwrap_or(unit);
+    let (prefix, base) = match unit.strip_suffix('i') {
+        Some(prefix) => (prefix, 1024f64),
+        None => (unit, 1000f64),
+    };
+    let exponent = match prefix {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        _ => return Err(invalid()),
+    };
+    if prefix.is_empty() && base == 1024.0 {
+        return Err(invalid());
+    }
+    Ok((number * base.powi(exponent)).round() as u64)
+}
+
+/// A name for a download, which must be a single path component.
+fn parse_name(name: &str) -> Result<String, PathError> {
+    sanitize_component(name)
+}
+
+/// Loads the configuration file if one was given, or else the one in the
+/// user's configuration directory if there is one, with any overrides
+/// from the environment applied.
+pub fn load_config(path: Option<&Path>, profile: Option<&str>) -> Result<Config, ConfigError> {
+    let path = match path {
+        Some(path) => Some(path.to_path_buf()),
+        None => rainyday_engine::config::default_path().filter(|path| path.is_file()),
+    };
+    if let Some(path) = &path {
+        debug!("using configuration file {}", path.display());
+    }
+    Config::load(path.as_deref(), profile)
+}
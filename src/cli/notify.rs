@@ -0,0 +1,93 @@
+//! Desktop notifications when a download run from a terminal finishes or
+//! fails, so it need not be watched.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, IsTerminal};
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use rainyday_engine::config::Config;
+use rainyday_engine::event::{Event, EventBus};
+use rainyday_engine::info_hash::InfoHash;
+
+/// Whether to notify: asked for in the config, built in, and running
+/// interactively.
+pub fn enabled(config: &Config) -> bool {
+    if !config.notifications || !io::stdout().is_terminal() {
+        return false;
+    }
+    if cfg!(not(feature = "notifications")) {
+        warn!("notifications are enabled but rainyday was built without them");
+        return false;
+    }
+    true
+}
+
+/// Notifies about the torrents of a session until told to finish.
+pub struct Notifier {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Notifier {
+    /// Starts watching for the torrents in `names`.
+    pub fn start(events: &EventBus, names: HashMap<InfoHash, String>) -> Self {
+        let (stop, stopped) = oneshot::channel();
+        let task = tokio::spawn(watch(events.subscribe(), names, stopped));
+        Self { stop, task }
+    }
+
+    /// Sends the notifications still due.
+    pub async fn finish(self) {
+        let _ = self.stop.send(());
+        let _ = self.task.await;
+    }
+}
+
+/// Notifies that the torrent called `name` stopped because of `error`.
+pub async fn failed(name: &str, error: &dyn Display) {
+    show(format!("{} failed", name), error.to_string()).await;
+}
+
+async fn watch(
+    mut events: broadcast::Receiver<Event>,
+    names: HashMap<InfoHash, String>,
+    mut stopped: oneshot::Receiver<()>,
+) {
+    loop {
+        // Events published before the torrent stopped are still delivered.
+        tokio::select! {
+            biased;
+            event = events.recv() => match event {
+                Ok(Event::TorrentFinished { info_hash }) => {
+                    if let Some(name) = names.get(&info_hash) {
+                        show("Download finished".to_string(), name.clone()).await;
+                    }
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            },
+            _ = &mut stopped => break,
+        }
+    }
+}
+
+#[cfg(feature = "notifications")]
+async fn show(summary: String, body: String) {
+    let notification = notify_rust::Notification::new()
+        .appname("rainyday")
+        .summary(&summary)
+        .body(&body)
+        .finalize();
+    // Talking to the notification daemon blocks.
+    if let Ok(Err(e)) = tokio::task::spawn_blocking(move || notification.show()).await {
+        warn!("cannot show notification: {}", e);
+    }
+}
+
+#[cfg(not(feature = "notifications"))]
+async fn show(_summary: String, _body: String) {}
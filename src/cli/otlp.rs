@@ -0,0 +1,56 @@
+//! Exporting tracing spans to an OpenTelemetry collector over OTLP/HTTP.
+//!
+//! Export is set up with the standard environment variables, chiefly
+//! `OTEL_EXPORTER_OTLP_ENDPOINT`, and is off unless an endpoint is given.
+//! The service is called `rainyday` unless `OTEL_SERVICE_NAME` says
+//! otherwise.
+
+use std::env;
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+const ENDPOINT_VARS: &[&str] = &[
+    "OTEL_EXPORTER_OTLP_ENDPOINT",
+    "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+];
+
+/// Sends the spans still buffered when dropped.
+pub struct Exporter {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("warning: cannot export the last spans: {}", e);
+        }
+    }
+}
+
+/// A layer exporting every span it sees, if an endpoint is configured.
+pub fn layer<S>() -> Result<Option<(impl Layer<S>, Exporter)>, ExporterBuildError>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    if !ENDPOINT_VARS.iter().any(|var| env::var_os(var).is_some()) {
+        return Ok(None);
+    }
+    let exporter = SpanExporter::builder().with_http().build()?;
+    let mut resource = Resource::builder();
+    if env::var_os("OTEL_SERVICE_NAME").is_none() {
+        resource = resource.with_service_name("rainyday");
+    }
+    let provider = SdkTracerProvider::builder()
+        .with_resource(resource.build())
+        .with_batch_exporter(exporter)
+        .build();
+    let tracer = provider.tracer("rainyday");
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer);
+    Ok(Some((layer, Exporter { provider })))
+}
@@ -0,0 +1,37 @@
+//! `rainyday peers`: list the peers a running rainyday is connected to.
+
+use std::error::Error;
+
+use indicatif::HumanBytes;
+
+use rainyday_engine::config::Config;
+use rainyday_engine::control::{client, Request, Response};
+
+pub fn run(config: &Config, torrent: Option<String>, json: bool) -> Result<bool, Box<dyn Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let request = Request::Peers { torrent };
+    let torrents = match runtime.block_on(client::request(&config.control_socket(), &request))? {
+        Response::Peers { torrents } => torrents,
+        response => return Err(format!("unexpected response {:?}", response).into()),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&torrents)?);
+        return Ok(true);
+    }
+    for torrent in &torrents {
+        println!("{} ({} peers)", torrent.name, torrent.peers.len());
+        for peer in &torrent.peers {
+            println!(
+                "  {:<40} {:<20} {:<5} {:>4.0}% down {:>10}/s up {:>10}/s",
+                peer.addr.to_string(),
+                peer.client.as_deref().unwrap_or("unknown"),
+                peer.flags(),
+                100.0 * peer.progress,
+                HumanBytes(peer.download_rate).to_string(),
+                HumanBytes(peer.upload_rate).to_string(),
+            );
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,123 @@
+//! Showing how a running torrent is getting on.
+
+use std::io::{self, IsTerminal};
+use std::time::Duration;
+
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use tokio::time::interval;
+
+use rainyday_engine::engine::{Handle, State, Status};
+
+/// How often the progress bar is redrawn.
+const BAR_INTERVAL: Duration = Duration::from_secs(1);
+/// How often a plain progress line is printed when stdout is not a terminal.
+const LINE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A progress bar on a terminal, or periodic lines of text otherwise.
+#[derive(Clone, Debug)]
+pub enum Progress {
+    Bar(ProgressBar),
+    Lines,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::many(1).remove(0)
+    }
+
+    /// One progress display for each of `count` torrents, with the bars
+    /// drawn together.
+    pub fn many(count: usize) -> Vec<Self> {
+        if !io::stdout().is_terminal() {
+            return vec![Progress::Lines; count];
+        }
+        let bars = MultiProgress::new();
+        (0..count)
+            .map(|_| {
+                let bar = bars.add(ProgressBar::new(0));
+                bar.set_style(
+                    ProgressStyle::with_template("{prefix} [{bar:30}] {percent:>3}% {msg}")
+                        .expect("progress template is valid")
+                        .progress_chars("=> "),
+                );
+                Progress::Bar(bar)
+            })
+            .collect()
+    }
+
+    /// Prints a message without disturbing the progress bar.
+    pub fn println(&self, message: &str) {
+        match self {
+            Progress::Bar(bar) => bar.println(message),
+            Progress::Lines => eprintln!("{}", message),
+        }
+    }
+
+    pub fn update(&self, status: &Status) {
+        match self {
+            Progress::Bar(bar) => {
+                bar.set_prefix(status.name.clone());
+                bar.set_length(status.wanted);
+                bar.set_position(status.done);
+                bar.set_message(summary(status));
+            }
+            Progress::Lines => println!(
+                "{}: {:.1}% {}",
+                status.name,
+                100.0 * status.progress(),
+                summary(status)
+            ),
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Progress::Bar(bar) = self {
+            bar.finish_and_clear();
+        }
+    }
+
+    fn interval(&self) -> Duration {
+        match self {
+            Progress::Bar(_) => BAR_INTERVAL,
+            Progress::Lines => LINE_INTERVAL,
+        }
+    }
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Updates `progress` from the torrent until it stops.
+pub async fn watch(handle: Handle, progress: Progress) {
+    let mut ticks = interval(progress.interval());
+    loop {
+        ticks.tick().await;
+        match handle.status().await {
+            Some(status) => progress.update(&status),
+            None => break,
+        }
+    }
+}
+
+/// Rates, peers and time left, e.g. `down 1.2 MiB/s, up 40 KiB/s, 7 peers,
+/// ETA 3 minutes`.
+fn summary(status: &Status) -> String {
+    let eta = match (status.state, status.eta()) {
+        (State::Paused, _) => "paused".to_string(),
+        (State::Checking, _) => "checking".to_string(),
+        (State::Error, _) => "error".to_string(),
+        (State::Seeding, _) => "seeding".to_string(),
+        (State::Downloading, Some(eta)) => format!("ETA {}", HumanDuration(eta)),
+        (State::Downloading, None) => "ETA unknown".to_string(),
+    };
+    format!(
+        "down {}/s, up {}/s, {} peers, {}",
+        HumanBytes(status.download_rate),
+        HumanBytes(status.upload_rate),
+        status.peers,
+        eta
+    )
+}
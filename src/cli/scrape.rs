@@ -0,0 +1,56 @@
+//! `rainyday scrape`: ask a torrent's trackers how busy it is.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::path::Path;
+
+use serde_json::json;
+
+use super::exit::Exit;
+use rainyday_engine::config::Config;
+use rainyday_engine::metainfo::Metainfo;
+use rainyday_engine::tracker::Tracker;
+
+/// Succeeds if at least one tracker answered.
+pub fn run(config: &Config, torrent: &Path, json: bool) -> Result<Exit, Box<dyn Error>> {
+    let metainfo = Metainfo::try_from(torrent)?;
+    let trackers: Vec<String> = metainfo.trackers().into_iter().flatten().collect();
+    if trackers.is_empty() {
+        return Err("torrent has no trackers".into());
+    }
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let tracker = Tracker::new()
+        .with_source_ports(config.network.source_ports)
+        .with_network(&config.proxy, config.network.interface.as_ref())?;
+    let mut answered = false;
+    let mut results = Vec::new();
+    runtime.block_on(async {
+        for url in &trackers {
+            match tracker.scrape(url, &metainfo.info_hash).await {
+                Ok(stats) => {
+                    answered = true;
+                    if json {
+                        results.push(json!({ "url": url, "stats": stats }));
+                    } else {
+                        println!(
+                            "{}: {} seeders, {} leechers, {} completed",
+                            url, stats.seeders, stats.leechers, stats.completed
+                        );
+                    }
+                }
+                Err(e) if json => results.push(json!({ "url": url, "error": e.to_string() })),
+                Err(e) => eprintln!("{}: {}", url, e),
+            }
+        }
+    });
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    }
+    Ok(if answered {
+        Exit::Success
+    } else {
+        Exit::Tracker
+    })
+}
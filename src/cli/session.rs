@@ -0,0 +1,75 @@
+//! `rainyday export-session` and `import-session`: the session in
+//! `state_dir` as one file, to back it up or carry it to another machine.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rainyday_engine::config::Config;
+use rainyday_engine::fsutil::write_atomic;
+use rainyday_engine::metainfo::Metainfo;
+use rainyday_engine::session::store::StoreError;
+use rainyday_engine::session::{archive, SessionStore};
+
+pub fn export(config: &Config, path: &Path) -> Result<bool, Box<dyn Error>> {
+    let store = open(config)?;
+    let records = store.list()?;
+    write_atomic(path, &archive::to_bytes(&records))
+        .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+    println!(
+        "exported {} torrent{} to {}",
+        records.len(),
+        if records.len() == 1 { "" } else { "s" },
+        path.display()
+    );
+    Ok(true)
+}
+
+/// Adds every torrent in the archive at `path` to the session, replacing
+/// any it already has. With `save_path`, their data is looked for there
+/// instead of where it was on the machine they were exported from.
+pub fn import(
+    config: &Config,
+    path: &Path,
+    save_path: Option<PathBuf>,
+) -> Result<bool, Box<dyn Error>> {
+    let bytes = fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let records = archive::from_bytes(&bytes)?;
+
+    let store = open(config)?;
+    for mut record in records {
+        if let Some(save_path) = &save_path {
+            record.options.save_path = save_path.clone();
+            if let Some(resume) = &mut record.resume {
+                resume.save_path = save_path.clone();
+            }
+        }
+        let replaced = store.contains(&record.info_hash)?;
+        store.insert(&record)?;
+        println!(
+            "{} {} ({}) in {}",
+            if replaced { "updated" } else { "imported" },
+            Metainfo::from_bytes(&record.metainfo)?.info.name,
+            record.info_hash,
+            record.options.save_path.display()
+        );
+    }
+    store.flush()?;
+    Ok(true)
+}
+
+fn open(config: &Config) -> Result<SessionStore, Box<dyn Error>> {
+    let state_dir = config
+        .state_dir
+        .as_deref()
+        .ok_or("the session needs `state_dir` set in the configuration")?;
+    match SessionStore::open(state_dir) {
+        // sled says only this when another process has the database open.
+        Err(StoreError::Database(sled::Error::Io(e)))
+            if e.to_string().starts_with("could not acquire lock") =>
+        {
+            Err("the session is in use; stop the daemon first".into())
+        }
+        result => Ok(result?),
+    }
+}
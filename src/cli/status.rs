@@ -0,0 +1,103 @@
+//! `rainyday status`: show what each torrent of a running rainyday is
+//! doing, and, for all of them, whether its port is forwarded.
+
+use std::error::Error;
+
+use indicatif::{HumanBytes, HumanDuration};
+
+use rainyday_engine::config::Config;
+use rainyday_engine::control::{client, Request, Response, SessionStatus};
+use rainyday_engine::engine::{State, Status};
+use rainyday_engine::interface::Endpoint;
+
+pub fn run(config: &Config, torrent: Option<String>, json: bool) -> Result<bool, Box<dyn Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let socket = config.control_socket();
+    let all = torrent.is_none();
+    let request = Request::Status { torrent };
+    let torrents = match runtime.block_on(client::request(&socket, &request))? {
+        Response::Status { torrents } => torrents,
+        response => return Err(format!("unexpected response {:?}", response).into()),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&torrents)?);
+        return Ok(true);
+    }
+    if all {
+        match runtime.block_on(client::request(&socket, &Request::Session))? {
+            Response::Session { session } => print_session(&session),
+            response => return Err(format!("unexpected response {:?}", response).into()),
+        }
+    }
+    for status in &torrents {
+        print(status);
+    }
+    Ok(true)
+}
+
+fn print_session(session: &SessionStatus) {
+    let port = match session.port {
+        Some(_) if session.endpoints.len() > 1 => {
+            let endpoints: Vec<String> =
+                session.endpoints.iter().map(Endpoint::to_string).collect();
+            format!("listening on {}", endpoints.join(", "))
+        }
+        Some(port) => format!("listening on port {}", port),
+        None => "not listening".to_string(),
+    };
+    let forwards: Vec<String> = session
+        .port_mappings
+        .iter()
+        .map(|mapping| format!("{} from {}", mapping.mechanism, mapping.external))
+        .collect();
+    let forwarded = if forwards.is_empty() {
+        "not forwarded".to_string()
+    } else {
+        format!("forwarded by {}", forwards.join(", "))
+    };
+    println!(
+        "session: {}, {}, down {}, up {}{}",
+        port,
+        forwarded,
+        HumanBytes(session.downloaded),
+        HumanBytes(session.uploaded),
+        if session.paused { ", paused" } else { "" }
+    );
+}
+
+fn print(status: &Status) {
+    println!("{} ({})", status.name, status.info_hash);
+    match &status.error {
+        Some(error) => println!("  state:    {}: {}", status.state, error),
+        None => println!("  state:    {}", status.state),
+    }
+    println!(
+        "  progress: {:.1}% of {}",
+        100.0 * status.progress(),
+        HumanBytes(status.wanted)
+    );
+    println!(
+        "  rates:    down {}/s, up {}/s",
+        HumanBytes(status.download_rate),
+        HumanBytes(status.upload_rate)
+    );
+    println!("  peers:    {} ({} seeds)", status.peers, status.seeds);
+    if status.blocked > 0 {
+        println!("  blocked:  {} (on the blocklist)", status.blocked);
+    }
+    println!(
+        "  ratio:    {} (down {}, up {})",
+        status
+            .ratio()
+            .map_or_else(|| "-".to_string(), |ratio| format!("{:.2}", ratio)),
+        HumanBytes(status.downloaded),
+        HumanBytes(status.uploaded)
+    );
+    let eta = match (status.state, status.eta()) {
+        (State::Downloading, Some(eta)) => HumanDuration(eta).to_string(),
+        (State::Downloading, None) => "unknown".to_string(),
+        _ => "-".to_string(),
+    };
+    println!("  eta:      {}", eta);
+}
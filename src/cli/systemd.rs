@@ -0,0 +1,40 @@
+//! Telling systemd how the daemon is doing, so it can run as a service of
+//! `Type=notify` and be restarted by the watchdog if it hangs. Nothing is
+//! sent unless systemd started the daemon.
+
+use std::time::Duration;
+
+use sd_notify::NotifyState;
+use tracing::{debug, warn};
+
+/// Tells systemd the daemon is ready to take commands.
+pub fn ready() {
+    notify(&[NotifyState::Ready]);
+}
+
+/// Tells systemd the daemon is stopping its torrents.
+pub fn stopping() {
+    notify(&[NotifyState::Stopping]);
+}
+
+/// Pets the watchdog, if `WatchdogSec=` is set, twice as often as systemd
+/// asks, and otherwise never returns.
+pub async fn watchdog() {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return std::future::pending().await;
+    }
+    let period = Duration::from_micros(usec) / 2;
+    debug!("petting the systemd watchdog every {:?}", period);
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        notify(&[NotifyState::Watchdog]);
+    }
+}
+
+fn notify(state: &[NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        warn!("cannot notify systemd: {}", e);
+    }
+}
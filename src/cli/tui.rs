@@ -0,0 +1,320 @@
+//! `--tui`: a full-screen dashboard showing pieces, peers, trackers and
+//! recent events for each running torrent.
+
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+
+use indicatif::{HumanBytes, HumanDuration};
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, List, ListItem, Paragraph, Row, Table, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::broadcast::{self, error::TryRecvError};
+use tokio::time::interval;
+
+use rainyday_engine::bitfield::Bitfield;
+use rainyday_engine::engine::{Handle, PeerInfo, State, Status, TrackerInfo, TrackerState};
+use rainyday_engine::event::{Event, EventBus};
+
+/// How often the screen is redrawn.
+const REFRESH: Duration = Duration::from_millis(500);
+/// Lines kept in the log pane.
+const LOG_LINES: usize = 200;
+
+/// What is shown for one torrent.
+#[derive(Default)]
+struct View {
+    status: Option<Status>,
+    pieces: Option<Bitfield>,
+    peers: Vec<PeerInfo>,
+    trackers: Vec<TrackerInfo>,
+}
+
+/// Runs the dashboard until every torrent has stopped or the user quits
+/// with `q` or Ctrl-C, in which case the torrents are shut down. Returns
+/// whether the user quit.
+pub async fn run(handles: Vec<Handle>, events: &EventBus) -> io::Result<bool> {
+    let mut terminal = ratatui::init();
+    let result = dashboard(&mut terminal, &handles, events.subscribe()).await;
+    ratatui::restore();
+
+    if let Ok(true) = result {
+        for handle in &handles {
+            handle.shutdown().await;
+        }
+    }
+    result
+}
+
+/// Returns whether the user asked to quit.
+async fn dashboard(
+    terminal: &mut DefaultTerminal,
+    handles: &[Handle],
+    mut events: broadcast::Receiver<Event>,
+) -> io::Result<bool> {
+    let mut selected = 0;
+    let mut log = VecDeque::new();
+    let mut ticks = interval(REFRESH);
+
+    loop {
+        ticks.tick().await;
+
+        loop {
+            match events.try_recv() {
+                Ok(event) => {
+                    if let Some(line) = describe(&event) {
+                        if log.len() == LOG_LINES {
+                            log.pop_front();
+                        }
+                        log.push_back(line);
+                    }
+                }
+                Err(TryRecvError::Lagged(_)) => {}
+                Err(_) => break,
+            }
+        }
+
+        let handle = &handles[selected];
+        let view = View {
+            status: handle.status().await,
+            pieces: handle.pieces().await,
+            peers: handle.peers().await.unwrap_or_default(),
+            trackers: handle.trackers().await.unwrap_or_default(),
+        };
+        if view.status.is_none() && all_stopped(handles).await {
+            return Ok(false);
+        }
+
+        terminal.draw(|frame| draw(frame, &view, &log, selected, handles.len()))?;
+
+        while event::poll(Duration::ZERO)? {
+            if let TermEvent::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('q') => return Ok(true),
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(true)
+                    }
+                    KeyCode::Char('a') => handles[selected].announce().await,
+                    KeyCode::Char('p') => match view.status.as_ref().map(|s| s.state) {
+                        Some(State::Paused) => handles[selected].resume().await,
+                        Some(_) => handles[selected].pause().await,
+                        None => {}
+                    },
+                    KeyCode::Tab => selected = (selected + 1) % handles.len(),
+                    KeyCode::BackTab => selected = (selected + handles.len() - 1) % handles.len(),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+async fn all_stopped(handles: &[Handle]) -> bool {
+    for handle in handles {
+        if handle.status().await.is_some() {
+            return false;
+        }
+    }
+    true
+}
+
+fn draw(frame: &mut Frame, view: &View, log: &VecDeque<String>, selected: usize, count: usize) {
+    let [header, pieces, middle, log_area, help] = Layout::vertical([
+        Constraint::Length(4),
+        Constraint::Length(6),
+        Constraint::Min(6),
+        Constraint::Length(8),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [peers, trackers] =
+        Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)]).areas(middle);
+
+    draw_header(frame, header, view.status.as_ref(), selected, count);
+    draw_pieces(frame, pieces, view.pieces.as_ref());
+    draw_peers(frame, peers, &view.peers);
+    draw_trackers(frame, trackers, &view.trackers);
+
+    let lines: Vec<ListItem> = log
+        .iter()
+        .rev()
+        .take(log_area.height as usize)
+        .map(|line| ListItem::new(line.as_str()))
+        .collect();
+    frame.render_widget(
+        List::new(lines).block(Block::bordered().title("Log")),
+        log_area,
+    );
+    frame.render_widget(
+        Paragraph::new("q quit  tab next torrent  a announce now  p pause/resume")
+            .style(Style::new().add_modifier(Modifier::DIM)),
+        help,
+    );
+}
+
+fn draw_header(
+    frame: &mut Frame,
+    area: Rect,
+    status: Option<&Status>,
+    selected: usize,
+    count: usize,
+) {
+    let status = match status {
+        Some(status) => status,
+        None => {
+            let block = Block::bordered().title("stopped");
+            frame.render_widget(block, area);
+            return;
+        }
+    };
+
+    let eta = match status.eta() {
+        Some(eta) => HumanDuration(eta).to_string(),
+        None => "unknown".to_string(),
+    };
+    let label = format!(
+        "{:.1}%  down {}/s  up {}/s  {} peers ({} seeds)  ETA {}",
+        100.0 * status.progress(),
+        HumanBytes(status.download_rate),
+        HumanBytes(status.upload_rate),
+        status.peers,
+        status.seeds,
+        eta
+    );
+    let title = format!(
+        "[{}/{}] {} ({})",
+        selected + 1,
+        count,
+        status.name,
+        status.state
+    );
+    let gauge = Gauge::default()
+        .block(Block::bordered().title(title))
+        .ratio(status.progress().clamp(0.0, 1.0))
+        .label(label);
+    frame.render_widget(gauge, area);
+}
+
+/// Draws one cell per group of pieces: full when all of them are on disk,
+/// shaded when some are, empty otherwise.
+fn draw_pieces(frame: &mut Frame, area: Rect, pieces: Option<&Bitfield>) {
+    let block = Block::bordered().title("Pieces");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let pieces = match pieces {
+        Some(pieces) if !pieces.is_empty() => pieces,
+        _ => return,
+    };
+
+    let cells = (inner.width as usize * inner.height as usize).max(1);
+    let per_cell = pieces.len().div_ceil(cells);
+    let map: String = (0..pieces.len())
+        .step_by(per_cell)
+        .map(|start| {
+            let end = (start + per_cell).min(pieces.len());
+            let have = (start..end).filter(|&piece| pieces.has(piece)).count();
+            if have == end - start {
+                '█'
+            } else if have > 0 {
+                '▒'
+            } else {
+                '·'
+            }
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(map).wrap(Wrap { trim: false }), inner);
+}
+
+fn draw_peers(frame: &mut Frame, area: Rect, peers: &[PeerInfo]) {
+    let rows = peers.iter().map(|peer| {
+        Row::new(vec![
+            peer.addr.to_string(),
+            peer.client.clone().unwrap_or_default(),
+            peer.flags(),
+            format!("{:.0}%", 100.0 * peer.progress),
+            format!("{}/s", HumanBytes(peer.download_rate)),
+            format!("{}/s", HumanBytes(peer.upload_rate)),
+        ])
+    });
+    let widths = [
+        Constraint::Length(22),
+        Constraint::Min(12),
+        Constraint::Length(5),
+        Constraint::Length(5),
+        Constraint::Length(12),
+        Constraint::Length(12),
+    ];
+    let table = Table::new(rows, widths)
+        .header(
+            Row::new(vec!["Address", "Client", "Flags", "Has", "Down", "Up"])
+                .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::bordered().title(format!("Peers ({})", peers.len())));
+    frame.render_widget(table, area);
+}
+
+fn draw_trackers(frame: &mut Frame, area: Rect, trackers: &[TrackerInfo]) {
+    let lines: Vec<Line> = trackers
+        .iter()
+        .flat_map(|tracker| {
+            let state = match &tracker.state {
+                TrackerState::Idle => "idle".to_string(),
+                TrackerState::Working => format!(
+                    "working, {} peers, {} seeders, {} leechers",
+                    tracker.peers,
+                    count(tracker.seeders),
+                    count(tracker.leechers)
+                ),
+                TrackerState::Failed(message) => format!("error: {}", message),
+            };
+            vec![
+                Line::from(tracker.url.clone()),
+                Line::from(format!("  {}", state)),
+            ]
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().title("Trackers")),
+        area,
+    );
+}
+
+fn count(value: Option<u32>) -> String {
+    value.map_or_else(|| "?".to_string(), |value| value.to_string())
+}
+
+/// A log line for the events worth showing.
+fn describe(event: &Event) -> Option<String> {
+    match event {
+        Event::PeerConnected { addr, .. } => Some(format!("{} connected", addr)),
+        Event::PeerDisconnected { addr, reason, .. } => Some(match reason {
+            Some(reason) => format!("{} disconnected: {}", addr, reason),
+            None => format!("{} disconnected", addr),
+        }),
+        Event::PieceFailed {
+            piece, failures, ..
+        } => Some(format!(
+            "piece {} failed verification ({} times)",
+            piece, failures
+        )),
+        Event::TrackerAnnounced { url, peers, .. } => {
+            Some(format!("announced to {}: {} peers", url, peers))
+        }
+        Event::TrackerError { url, message, .. } => Some(format!("tracker {}: {}", url, message)),
+        Event::DiskFull { message, .. } => Some(message.clone()),
+        Event::TorrentFinished { .. } => Some("download finished".to_string()),
+        Event::Rechecked { valid, pieces, .. } => Some(format!(
+            "recheck finished: {} of {} pieces valid",
+            valid, pieces
+        )),
+        _ => None,
+    }
+}
@@ -0,0 +1,57 @@
+//! `rainyday verify`: hash on-disk data against a torrent.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::path::Path;
+
+use rainyday_engine::metainfo::Metainfo;
+use rainyday_engine::storage::{FileStorage, Layout};
+use rainyday_engine::verify::recheck;
+
+pub fn run(torrent: &Path, data: &Path) -> Result<bool, Box<dyn Error>> {
+    let metainfo = Metainfo::try_from(torrent)?;
+    let info = &metainfo.info;
+
+    let mut layout = Layout::new(info);
+    let name = data
+        .file_name()
+        .ok_or("data path has no file name")?
+        .to_string_lossy();
+    layout.rename_root(&name);
+    let root = data.parent().unwrap_or_else(|| Path::new(""));
+    let storage = FileStorage::open_read_only(root, layout.clone());
+
+    let valid = recheck(&storage, info, |_, _| {})?;
+
+    println!(
+        "{}: {}/{} pieces valid ({:.1}%)",
+        info.name,
+        valid.count(),
+        info.num_pieces(),
+        100.0 * valid.count() as f64 / info.num_pieces().max(1) as f64
+    );
+
+    for (index, slot) in layout.files.iter().enumerate() {
+        let pieces = pieces_of_file(&layout, index, info.piece_length);
+        let good = pieces.clone().filter(|&p| valid.has(p as usize)).count();
+        let status = if good == pieces.len() {
+            "complete"
+        } else {
+            "incomplete"
+        };
+        println!("  {} {}", status, slot.path.display());
+    }
+
+    Ok(valid.is_full())
+}
+
+fn pieces_of_file(layout: &Layout, index: usize, piece_length: u32) -> std::ops::Range<u32> {
+    let slot = &layout.files[index];
+    let first = slot.offset / piece_length as u64;
+    let end = if slot.length == 0 {
+        first
+    } else {
+        (slot.offset + slot.length).div_ceil(piece_length as u64)
+    };
+    first as u32..end as u32
+}
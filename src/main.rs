@@ -1,3 +1,274 @@
+mod cli;
+
+use std::error::Error;
+use std::process;
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use tracing::dispatcher::{self, Dispatch};
+
+use rainyday_engine::control::{AddOptions, Request};
+use rainyday_engine::create::CreateOptions;
+use rainyday_engine::edit::Edit;
+use rainyday_engine::engine::{Mode, Options};
+use rainyday_engine::files::Selection;
+
+use cli::exit::Exit;
+use cli::{Command, ConfigCommand, Opts};
+
 fn main() {
-    println!("Hello, world!");
+    let opts = Opts::parse();
+
+    let exit = match run(opts) {
+        Ok(exit) => exit,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            Exit::of(e.as_ref())
+        }
+    };
+    process::exit(exit.code());
+}
+
+fn run(opts: Opts) -> Result<Exit, Box<dyn Error>> {
+    // The dashboard owns the terminal and shows its own log.
+    let tui = matches!(
+        opts.command,
+        Some(Command::Download { tui: true, .. } | Command::Seed { tui: true, .. })
+    );
+    // Writing a fresh configuration must work even if the old one is broken.
+    if let Some(Command::Config {
+        command: ConfigCommand::Init { path, force },
+    }) = &opts.command
+    {
+        return cli::config::init(path.as_deref(), *force).map(Exit::from);
+    }
+    // Until the configuration says otherwise, log to the console alone.
+    let console = if tui {
+        Dispatch::none()
+    } else {
+        Dispatch::new(cli::logging::console(
+            opts.verbosity(),
+            opts.log.as_deref(),
+        )?)
+    };
+    let mut config = dispatcher::with_default(&console, || {
+        cli::load_config(opts.config.as_deref(), opts.profile.as_deref())
+    })?;
+    if let Some(command) = &opts.command {
+        command.apply(&mut config);
+    }
+    if opts.print_config {
+        print!("{}", toml::to_string(&config)?);
+        return Ok(Exit::Success);
+    }
+    let _logging = if tui {
+        None
+    } else {
+        let logging = cli::logging::init(opts.verbosity(), opts.log.as_deref(), &config.logging)?;
+        Some(logging)
+    };
+    let Some(command) = opts.command else {
+        Opts::command()
+            .error(ErrorKind::MissingSubcommand, "a subcommand is required")
+            .exit()
+    };
+
+    match command {
+        Command::Download {
+            torrents,
+            name,
+            tui,
+            files,
+            seed,
+            sequential,
+            limits,
+            dry_run,
+            ..
+        } => {
+            if files.list_files {
+                return cli::download::list_files(&config, &torrents).map(Exit::from);
+            }
+            if torrents.len() > 1 && (name.is_some() || files.files.is_some()) {
+                one_torrent_only("--name and --files");
+            }
+            let mode = if seed || limits.is_set() {
+                Mode::Seed(limits.goal())
+            } else {
+                Mode::Download
+            };
+            let options = Options {
+                name,
+                sequential,
+                ..Options::default()
+            };
+            let selection = files.selection()?;
+            if dry_run {
+                return cli::dry_run::run(&config, &torrents, &options, &selection, mode);
+            }
+            cli::download::run(&config, &torrents, &options, &selection, mode, tui)
+        }
+        Command::Seed {
+            torrents,
+            name,
+            tui,
+            limits,
+            dry_run,
+            ..
+        } => {
+            if torrents.len() > 1 && name.is_some() {
+                one_torrent_only("--name");
+            }
+            let options = Options {
+                name,
+                ..Options::default()
+            };
+            let mode = Mode::Seed(limits.goal());
+            let selection = Selection::default();
+            if dry_run {
+                return cli::dry_run::run(&config, &torrents, &options, &selection, mode);
+            }
+            cli::download::run(&config, &torrents, &options, &selection, mode, tui)
+        }
+        Command::Create {
+            path,
+            trackers,
+            web_seeds,
+            piece_length,
+            private,
+            comment,
+            output,
+        } => {
+            let options = CreateOptions {
+                trackers: trackers
+                    .iter()
+                    .map(|tier| tier.split(',').map(str::to_string).collect())
+                    .collect(),
+                web_seeds,
+                piece_length,
+                private,
+                comment,
+                name: None,
+            };
+            cli::create::run(&path, output.as_deref(), &options).map(Exit::from)
+        }
+        Command::Inspect { torrent, json } => cli::inspect::run(&torrent, json).map(Exit::from),
+        Command::Verify { torrent, data } => cli::verify::run(&torrent, &data).map(Exit::from),
+        Command::Scrape { torrent, json } => cli::scrape::run(&config, &torrent, json),
+        Command::Magnet { torrent } => cli::magnet::run(&torrent).map(Exit::from),
+        Command::Dissect { capture } => cli::dissect::run(&capture).map(Exit::from),
+        Command::Edit {
+            torrent,
+            add_trackers,
+            remove_trackers,
+            add_web_seeds,
+            remove_web_seeds,
+            comment,
+            private,
+            public,
+            force,
+            output,
+        } => {
+            let changes = Edit {
+                add_trackers: add_trackers
+                    .iter()
+                    .map(|tier| tier.split(',').map(str::to_string).collect())
+                    .collect(),
+                remove_trackers,
+                add_web_seeds,
+                remove_web_seeds,
+                comment,
+                private: match (private, public) {
+                    (true, _) => Some(true),
+                    (_, true) => Some(false),
+                    _ => None,
+                },
+                force,
+            };
+            cli::edit::run(&torrent, output.as_deref(), &changes).map(Exit::from)
+        }
+        Command::Benchmark { size, dir } => {
+            let dir = dir.unwrap_or_else(|| config.storage.download_dir.clone());
+            cli::benchmark::run(&config, size, &dir).map(Exit::from)
+        }
+        Command::Convert { torrent, output } => {
+            cli::convert::run(&config, &torrent, output.as_deref()).map(Exit::from)
+        }
+        Command::Daemon { .. } => cli::daemon::run(&config).map(Exit::from),
+        Command::Add {
+            torrent,
+            out,
+            max_download,
+            max_upload,
+            limits,
+            sequential,
+        } => {
+            let torrent = cli::control::absolute(torrent)?;
+            let options = AddOptions {
+                download_dir: out.map(std::path::absolute).transpose()?,
+                download_rate: max_download.filter(|&rate| rate > 0),
+                upload_rate: max_upload.filter(|&rate| rate > 0),
+                seed_ratio: limits.seed_ratio,
+                seed_time: limits.seed_time.map(|time| time.as_secs()),
+                sequential,
+            };
+            let request = Request::Add { torrent, options };
+            cli::control::run(&config, request, "added").map(Exit::from)
+        }
+        Command::Remove { torrent } => {
+            cli::control::run(&config, Request::Remove { torrent }, "removed").map(Exit::from)
+        }
+        Command::List { json } => cli::list::run(&config, json).map(Exit::from),
+        Command::Status { torrent, json } => {
+            cli::status::run(&config, torrent, json).map(Exit::from)
+        }
+        Command::Announce { torrent } => {
+            cli::control::run(&config, Request::Announce { torrent }, "announcing").map(Exit::from)
+        }
+        Command::Pause { torrent } => {
+            cli::control::run(&config, Request::Pause { torrent }, "pausing").map(Exit::from)
+        }
+        Command::Resume { torrent } => {
+            cli::control::run(&config, Request::Resume { torrent }, "resuming").map(Exit::from)
+        }
+        Command::PauseSession => {
+            cli::control::run(&config, Request::PauseSession, "pausing").map(Exit::from)
+        }
+        Command::ResumeSession => {
+            cli::control::run(&config, Request::ResumeSession, "resuming").map(Exit::from)
+        }
+        Command::Recheck { torrent } => {
+            cli::control::run(&config, Request::Recheck { torrent }, "rechecking").map(Exit::from)
+        }
+        Command::Move { torrent, dir } => {
+            let path = std::path::absolute(dir)?;
+            cli::control::run(&config, Request::Move { torrent, path }, "moved").map(Exit::from)
+        }
+        Command::Rename { torrent, name } => {
+            cli::control::run(&config, Request::Rename { torrent, name }, "renamed").map(Exit::from)
+        }
+        Command::Peers { torrent, json } => cli::peers::run(&config, torrent, json).map(Exit::from),
+        Command::Import {
+            torrent,
+            data,
+            from,
+        } => match (from, data) {
+            (Some(client), _) => cli::import::from_client(&config, client, &torrent),
+            (None, Some(data)) => cli::import::run(&config, &torrent, &data),
+            (None, None) => unreachable!("clap requires the data without --from"),
+        }
+        .map(Exit::from),
+        Command::ExportSession { path } => cli::session::export(&config, &path).map(Exit::from),
+        Command::ImportSession { path, save_path } => {
+            cli::session::import(&config, &path, save_path).map(Exit::from)
+        }
+        Command::Config { .. } => unreachable!("handled before loading the configuration"),
+    }
+}
+
+/// Exits with a usage error for options given alongside several torrents.
+fn one_torrent_only(options: &str) -> ! {
+    let message = format!("{} can only be used with a single torrent", options);
+    Opts::command()
+        .error(ErrorKind::ArgumentConflict, message)
+        .exit()
 }